@@ -0,0 +1,249 @@
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+use crate::{
+    ast::{self, Node},
+    ir::CompileError,
+    lexer, Instruction,
+};
+
+/// What one pass through a loop body does to a cell, relative to the body's
+/// starting position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    /// Adds this much to the value the cell had.
+    Delta(u8),
+    /// Leaves this value whatever the cell had.
+    Const(u8),
+    /// Leaves whatever was last read from input, or the old value at end of input.
+    Input,
+    Unknown,
+}
+
+/// The effect of a body. `shift` is `None` when a nested loop moves the
+/// pointer by an amount that depends on the tape.
+struct Summary {
+    shift: Option<isize>,
+    cells: BTreeMap<isize, Cell>,
+}
+
+fn summarize(nodes: &[Node]) -> Summary {
+    let mut pointer = 0;
+    let mut cells = BTreeMap::new();
+
+    for node in nodes {
+        match node {
+            Node::Instruction(Instruction::ShiftLeft, _) => pointer -= 1,
+            Node::Instruction(Instruction::ShiftRight, _) => pointer += 1,
+            Node::Instruction(
+                instruction @ (Instruction::Increment | Instruction::Decrement),
+                _,
+            ) => {
+                let step = match instruction {
+                    Instruction::Increment => 1,
+                    _ => u8::MAX,
+                };
+                let cell = cells.entry(pointer).or_insert(Cell::Delta(0));
+                *cell = match *cell {
+                    Cell::Delta(d) => Cell::Delta(d.wrapping_add(step)),
+                    Cell::Const(c) => Cell::Const(c.wrapping_add(step)),
+                    cell => cell,
+                };
+            }
+            Node::Instruction(Instruction::Input, _) => {
+                cells.insert(pointer, Cell::Input);
+            }
+            Node::Instruction(..) => (),
+            Node::Loop(body, _) => {
+                let inner = summarize(body);
+                if inner.shift != Some(0) {
+                    return Summary { shift: None, cells };
+                }
+                for (offset, cell) in inner.cells {
+                    if cell != Cell::Delta(0) {
+                        cells.insert(pointer + offset, Cell::Unknown);
+                    }
+                }
+                cells.insert(pointer, Cell::Const(0));
+            }
+        }
+    }
+
+    Summary {
+        shift: Some(pointer),
+        cells,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// The loop runs forever once entered.
+    Never,
+    /// The loop runs forever for some tape contents or inputs.
+    Maybe,
+}
+
+/// A loop that does not always terminate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminationWarning {
+    pub termination: Termination,
+    pub reason: String,
+    /// Byte range of the whole loop, `Happy Happy` through `New New`.
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for TerminationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = match self.termination {
+            Termination::Never => "never terminates once entered",
+            Termination::Maybe => "may not terminate",
+        };
+        write!(
+            f,
+            "{}:{}: warning: loop {}: {}",
+            self.line, self.column, verdict, self.reason
+        )
+    }
+}
+
+/// Flags loops whose bodies leave the pointer where it started and either
+/// never bring the tested cell to zero, or only do so for some values. Loops
+/// that move the pointer, or whose tested cell is changed by a nested loop,
+/// are not judged.
+pub fn termination(source: &str, lenient: bool) -> Result<Vec<TerminationWarning>, CompileError> {
+    let mut warnings = Vec::new();
+    check_loops(&ast::parse(source, lenient)?, source, &mut warnings);
+    Ok(warnings)
+}
+
+fn check_loops(nodes: &[Node], source: &str, warnings: &mut Vec<TerminationWarning>) {
+    for node in nodes {
+        let Node::Loop(body, span) = node else {
+            continue;
+        };
+        if let Some((termination, reason)) = judge(&summarize(body)) {
+            let (line, column) = lexer::line_col(source, span.start);
+            warnings.push(TerminationWarning {
+                termination,
+                reason,
+                span: span.clone(),
+                line,
+                column,
+            });
+        }
+        check_loops(body, source, warnings);
+    }
+}
+
+fn judge(body: &Summary) -> Option<(Termination, String)> {
+    if body.shift != Some(0) {
+        return None;
+    }
+    match body.cells.get(&0).copied().unwrap_or(Cell::Delta(0)) {
+        Cell::Delta(0) => Some((
+            Termination::Never,
+            "the body never changes the tested cell".to_string(),
+        )),
+        Cell::Const(0) | Cell::Unknown => None,
+        Cell::Const(c) => Some((
+            Termination::Never,
+            format!("the body always leaves {} in the tested cell", c),
+        )),
+        Cell::Delta(d) if d % 2 == 1 => None,
+        Cell::Delta(d) => {
+            let step = 1u16 << d.trailing_zeros();
+            Some((
+                Termination::Maybe,
+                format!(
+                    "the tested cell changes by {} each time, so it only reaches zero from multiples of {}",
+                    d as i8, step
+                ),
+            ))
+        }
+        Cell::Input => Some((
+            Termination::Maybe,
+            "the tested cell is read from input and keeps its value at end of input".to_string(),
+        )),
+    }
+}
+
+/// Loops nested deeper than this are worth a warning: the interpreter runs
+/// each nested loop on a copy of its body, one stack frame per level.
+pub const DEFAULT_LOOP_DEPTH_WARNING: usize = 64;
+
+/// The most deeply nested loop of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopDepth {
+    /// How many loops enclose the innermost one, itself included.
+    pub depth: usize,
+    /// Byte range of the innermost loop.
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Finds the most deeply nested loop, the first one if several tie, or `None`
+/// for a program without loops.
+pub fn loop_depth(source: &str, lenient: bool) -> Result<Option<LoopDepth>, CompileError> {
+    fn deepest(nodes: &[Node], depth: usize, best: &mut Option<(usize, Range<usize>)>) {
+        for node in nodes {
+            if let Node::Loop(body, span) = node {
+                if best.as_ref().is_none_or(|(max, _)| depth + 1 > *max) {
+                    *best = Some((depth + 1, span.clone()));
+                }
+                deepest(body, depth + 1, best);
+            }
+        }
+    }
+
+    let mut best = None;
+    deepest(&ast::parse(source, lenient)?, 0, &mut best);
+    Ok(best.map(|(depth, span)| {
+        let (line, column) = lexer::line_col(source, span.start);
+        LoopDepth {
+            depth,
+            span,
+            line,
+            column,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    fn warnings(brainfuck: &str) -> Vec<(Termination, usize)> {
+        let source = brainfuck_to_hny(brainfuck).output;
+        termination(&source, false)
+            .unwrap()
+            .into_iter()
+            .map(|warning| (warning.termination, warning.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_termination() {
+        assert_eq!(warnings("+[-]+[->+<]>[>]"), []);
+        assert_eq!(warnings("+[.]"), [(Termination::Never, 12)]);
+        assert_eq!(warnings("+[[-]+]"), [(Termination::Never, 12)]);
+        assert_eq!(warnings("+[--]"), [(Termination::Maybe, 12)]);
+        assert_eq!(warnings(",[.,]"), [(Termination::Maybe, 11)]);
+        assert_eq!(warnings("+[>[+]<]"), [(Termination::Never, 12)]);
+    }
+
+    #[test]
+    fn test_loop_depth() {
+        let depth = |brainfuck| {
+            let source = brainfuck_to_hny(brainfuck).output;
+            loop_depth(&source, false)
+                .unwrap()
+                .map(|depth| (depth.depth, depth.column))
+        };
+        assert_eq!(depth("+-."), None);
+        assert_eq!(depth("[]"), Some((1, 1)));
+        assert_eq!(depth("[[]][[[]]][[[-]]]"), Some((3, 65)));
+    }
+}
@@ -0,0 +1,393 @@
+use std::{error::Error, fmt, ops::Range};
+
+use crate::{ir::CompileError, lexer, Instruction};
+
+/// A program as written: instructions and nested loops, each with the byte
+/// range it covers in the source. Unlike [`Program`](crate::Program) nothing is
+/// folded, so analyses can point back at the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// Any instruction but a loop bracket.
+    Instruction(Instruction, Range<usize>),
+    /// A loop, from its `Happy Happy` through its `New New`.
+    Loop(Vec<Node>, Range<usize>),
+}
+
+impl Node {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Node::Instruction(_, span) | Node::Loop(_, span) => span.clone(),
+        }
+    }
+}
+
+/// A mistake in the source and the byte range it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub error: CompileError,
+    pub span: Range<usize>,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl Error for SyntaxError {}
+
+/// Parses `source` into a tree of nodes. A trailing unpaired word is ignored,
+/// as the interpreter does.
+pub fn parse(source: &str, lenient: bool) -> Result<Vec<Node>, CompileError> {
+    let (nodes, errors) = parse_recovering(source, lenient);
+    match errors.into_iter().next() {
+        Some(error) => Err(error.error),
+        None => Ok(nodes),
+    }
+}
+
+/// Parses `source` as [`parse`] does, but carries on past mistakes so all of
+/// them are found in one pass, in the order they are found. A run of invalid
+/// pairs is skipped as one error, a loop end without a start is dropped and
+/// loops still open at the end are closed there, the innermost reported
+/// first. The tree is of what's left.
+pub fn parse_recovering(source: &str, lenient: bool) -> (Vec<Node>, Vec<SyntaxError>) {
+    let tokens = lexer::tokens(source, lenient).collect::<Vec<_>>();
+    let mut stack = vec![(Vec::new(), 0, 0..0)];
+    let mut errors = Vec::new();
+    let mut skipped: Option<SyntaxError> = None;
+
+    for (pair, words) in tokens.chunks_exact(2).enumerate() {
+        let span = words[0].span.start..words[1].span.end;
+        let Some(instruction) = Instruction::from_pair((words[0].text, words[1].text)) else {
+            match &mut skipped {
+                Some(error) => error.span.end = span.end,
+                None => {
+                    skipped = Some(SyntaxError {
+                        error: CompileError::InvalidToken { pair },
+                        span,
+                    })
+                }
+            }
+            continue;
+        };
+        errors.extend(skipped.take());
+        match instruction {
+            Instruction::LoopStart => stack.push((Vec::new(), pair, span)),
+            Instruction::LoopEnd if stack.len() == 1 => errors.push(SyntaxError {
+                error: CompileError::UnmatchedLoopEnd { pair },
+                span,
+            }),
+            Instruction::LoopEnd => {
+                let (body, _, start) = stack.pop().expect("checked above");
+                let parent = &mut stack.last_mut().expect("checked above").0;
+                parent.push(Node::Loop(body, start.start..span.end));
+            }
+            instruction => {
+                let parent = &mut stack.last_mut().expect("the top level").0;
+                parent.push(Node::Instruction(instruction, span));
+            }
+        }
+    }
+    errors.extend(skipped);
+
+    let end = tokens.last().map_or(0, |token| token.span.end);
+    while stack.len() > 1 {
+        let (body, pair, span) = stack.pop().expect("checked above");
+        errors.push(SyntaxError {
+            error: CompileError::UnmatchedLoopStart { pair },
+            span: span.clone(),
+        });
+        let parent = &mut stack.last_mut().expect("checked above").0;
+        parent.push(Node::Loop(body, span.start..end));
+    }
+    let (nodes, _, _) = stack.pop().expect("the top level");
+    (nodes, errors)
+}
+
+/// A source kept parsed as it is edited, as an editor or language server
+/// needs. While the source parses cleanly an edit reparses only the words
+/// around it, down to the innermost loop holding it; otherwise it is all
+/// reparsed, so the errors are always those a fresh parse would find.
+#[derive(Debug, Clone)]
+pub struct Document {
+    source: String,
+    lenient: bool,
+    nodes: Vec<Node>,
+    errors: Vec<SyntaxError>,
+}
+
+impl Document {
+    pub fn new(source: impl Into<String>, lenient: bool) -> Document {
+        let source = source.into();
+        let (nodes, errors) = parse_recovering(&source, lenient);
+        Document {
+            source,
+            lenient,
+            nodes,
+            errors,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+
+    /// Replaces the bytes in `range` with `text`.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) {
+        let edit = Edit {
+            delta: text.len() as isize - range.len() as isize,
+            range,
+        };
+        // An edit to the first line may start or end a `#!` line, hiding or
+        // showing the words after it up to the line's end.
+        let on_first_line = edit.range.start <= lexer::shebang_len(&self.source).max(1);
+        self.source.replace_range(edit.range.clone(), text);
+        let reparsed = self.errors.is_empty()
+            && !on_first_line
+            && edit.reparse(&mut self.nodes, &self.source, self.lenient, true);
+        if !reparsed {
+            (self.nodes, self.errors) = parse_recovering(&self.source, self.lenient);
+        }
+    }
+}
+
+/// A replaced range of the source, and how much longer the source became.
+struct Edit {
+    range: Range<usize>,
+    delta: isize,
+}
+
+impl Edit {
+    /// Reparses the nodes in `nodes` the edit touched from the edited
+    /// `source`, with the text between them and their untouched neighbours.
+    /// Nodes in a loop are only reparsed between neighbours, so the loop's
+    /// brackets stay out of it; at the top level the text before the first
+    /// node and after the last may be reparsed too. Fails, changing nothing,
+    /// if the words reparsed hold an error or an odd word, either of which
+    /// would change how the words after them pair up.
+    fn reparse(&self, nodes: &mut Vec<Node>, source: &str, lenient: bool, top: bool) -> bool {
+        let first = nodes.partition_point(|node| node.span().end < self.range.start);
+        let last = nodes.partition_point(|node| node.span().start <= self.range.end);
+        if last == first + 1 {
+            if let Node::Loop(body, span) = &mut nodes[first] {
+                if span.start < self.range.start
+                    && self.range.end < span.end
+                    && self.reparse(body, source, lenient, false)
+                {
+                    span.end = self.moved(span.end);
+                    shift(&mut nodes[last..], self.delta);
+                    return true;
+                }
+            }
+        }
+
+        let start = match first {
+            0 if top => 0,
+            0 => return false,
+            _ => nodes[first - 1].span().end,
+        };
+        let end = match nodes.get(last) {
+            Some(node) => self.moved(node.span().start),
+            None if top => source.len(),
+            None => return false,
+        };
+        let region = &source[start..end];
+        if start > 0 && region.starts_with("#!") || lexer::tokens(region, lenient).count() % 2 == 1
+        {
+            return false;
+        }
+        let (mut reparsed, errors) = parse_recovering(region, lenient);
+        if !errors.is_empty() {
+            return false;
+        }
+        shift(&mut reparsed, start as isize);
+        shift(&mut nodes[last..], self.delta);
+        nodes.splice(first..last, reparsed);
+        true
+    }
+
+    /// Where a position after the edit has moved to.
+    fn moved(&self, position: usize) -> usize {
+        position.wrapping_add_signed(self.delta)
+    }
+}
+
+/// Moves the spans of `nodes` and everything in them by `by` bytes.
+fn shift(nodes: &mut [Node], by: isize) {
+    for node in nodes {
+        match node {
+            Node::Instruction(_, span) => *span = shifted(span, by),
+            Node::Loop(body, span) => {
+                *span = shifted(span, by);
+                shift(body, by);
+            }
+        }
+    }
+}
+
+fn shifted(span: &Range<usize>, by: isize) -> Range<usize> {
+    span.start.wrapping_add_signed(by)..span.end.wrapping_add_signed(by)
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    /// Checks `document` holds what a fresh parse of its source gives.
+    fn assert_fresh(document: &Document) {
+        let fresh = Document::new(document.source(), document.lenient);
+        assert_eq!(document.nodes(), fresh.nodes(), "{:?}", document.source());
+        assert_eq!(document.errors(), fresh.errors(), "{:?}", document.source());
+    }
+
+    #[test]
+    fn test_parse() {
+        let nodes = parse("Year Happy Happy Happy Happy Year New New", false).unwrap();
+        assert_eq!(
+            nodes,
+            [
+                Node::Instruction(Instruction::Increment, 0..10),
+                Node::Loop(
+                    vec![Node::Instruction(Instruction::Decrement, 23..33)],
+                    11..41
+                ),
+            ]
+        );
+        assert_eq!(
+            parse("Happy Happy", false),
+            Err(CompileError::UnmatchedLoopStart { pair: 0 })
+        );
+        assert_eq!(
+            parse("New New", false),
+            Err(CompileError::UnmatchedLoopEnd { pair: 0 })
+        );
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let source = "New New Year Hapy Year Year Year Happy Happy Happy Happy Happy Year Happy";
+        let (nodes, errors) = parse_recovering(source, false);
+        assert_eq!(
+            errors,
+            [
+                SyntaxError {
+                    error: CompileError::UnmatchedLoopEnd { pair: 0 },
+                    span: 0..7,
+                },
+                SyntaxError {
+                    error: CompileError::InvalidToken { pair: 1 },
+                    span: 8..27,
+                },
+                SyntaxError {
+                    error: CompileError::UnmatchedLoopStart { pair: 5 },
+                    span: 51..62,
+                },
+                SyntaxError {
+                    error: CompileError::UnmatchedLoopStart { pair: 4 },
+                    span: 39..50,
+                },
+            ]
+        );
+        assert_eq!(
+            nodes,
+            [
+                Node::Instruction(Instruction::Increment, 28..38),
+                Node::Loop(
+                    vec![Node::Loop(
+                        vec![Node::Instruction(Instruction::Increment, 63..73)],
+                        51..73
+                    )],
+                    39..73
+                ),
+            ]
+        );
+        assert_eq!(
+            parse(source, false),
+            Err(CompileError::UnmatchedLoopEnd { pair: 0 })
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        // +[>[-]<-]>.
+        let mut document = Document::new(brainfuck_to_hny("+[>[-]<-]>.").output, false);
+        let find = |document: &Document, text| document.source().find(text).unwrap();
+
+        // Into the inner loop, then between its new neighbours.
+        let at = find(&document, "Happy Year New New");
+        document.edit(at..at, "Year Happy Year Happy ");
+        assert_fresh(&document);
+        let at = find(&document, "Happy Year New New");
+        document.edit(at..at + 10, "New Year");
+        assert_fresh(&document);
+        assert!(document.errors().is_empty());
+
+        // A loop end dropped, then put back.
+        let at = find(&document, "New New Happy New");
+        document.edit(at..at + 8, "");
+        assert_fresh(&document);
+        assert_eq!(document.errors().len(), 1);
+        document.edit(at..at, "New New ");
+        assert_fresh(&document);
+        assert!(document.errors().is_empty());
+
+        // A word split in two, and joined again.
+        document.edit(2..2, " ");
+        assert_fresh(&document);
+        document.edit(2..3, "");
+        assert_fresh(&document);
+        assert!(document.errors().is_empty());
+    }
+
+    /// Mostly whole pairs inserted between words, which keep the source
+    /// parsing, and now and then something that breaks it.
+    fn edit() -> impl Strategy<Value = (usize, usize, String)> {
+        let pair = proptest::sample::select(
+            &[
+                " Year Happy ",
+                " Happy Year ",
+                " New Year ",
+                " Happy Happy New New ",
+            ][..],
+        );
+        let other = proptest::sample::select(&["", " ", "Happy", "New New", "#!"][..]);
+        let text = prop_oneof![4 => pair, 1 => other].prop_map(String::from);
+        (
+            0..400usize,
+            prop_oneof![4 => Just(0usize), 1 => 0..20usize],
+            text,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn edits_match_a_fresh_parse(
+            parts in proptest::collection::vec("[-+<>.,]{0,6}", 5),
+            edits in proptest::collection::vec(edit(), 1..8),
+            lenient in any::<bool>(),
+        ) {
+            let brainfuck = format!("{}[{}[{}]{}]{}", parts[0], parts[1], parts[2], parts[3], parts[4]);
+            let mut document = Document::new(brainfuck_to_hny(&brainfuck).output, lenient);
+            for (at, len, text) in edits {
+                // Between words, where most edits are made.
+                let start = at.min(document.source().len());
+                let start = document.source()[..start].rfind(' ').unwrap_or(0);
+                let end = (start + len).min(document.source().len());
+                document.edit(start..end, &text);
+                assert_fresh(&document);
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! An append-only log of the programs a server runs, one JSON object per line,
+//! for investigating abuse. Once the file reaches its size limit it is
+//! renamed aside, `audit.jsonl` to `audit.jsonl.1` and so on, and a new one
+//! started, with the oldest past the number kept deleted.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::limits::{Exit, Limits};
+
+/// Where and how much to log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Audit {
+    pub path: PathBuf,
+    /// Bytes the file may grow to before it is rotated.
+    pub max_bytes: u64,
+    /// Rotated files kept besides the current one.
+    pub keep: usize,
+}
+
+impl Audit {
+    pub fn new(path: impl Into<PathBuf>) -> Audit {
+        Audit {
+            path: path.into(),
+            max_bytes: 100 << 20,
+            keep: 5,
+        }
+    }
+}
+
+/// One request to run a program.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    /// Milliseconds since the Unix epoch when the request was answered.
+    pub timestamp_ms: u64,
+    pub client: Option<IpAddr>,
+    /// `run` or `session`.
+    pub route: &'static str,
+    /// A hash of the program's source, as [`source_hash`] gives, if the
+    /// request had one.
+    pub source_hash: Option<String>,
+    /// The limits the program ran under.
+    pub limits: Option<Limits>,
+    pub status: u16,
+    /// Why the program stopped, if it ran.
+    pub exit: Option<Exit>,
+    pub steps: Option<u64>,
+    /// Time spent answering, including starting a worker.
+    pub duration_ms: f64,
+    pub error: Option<String>,
+}
+
+/// An open audit log, safe to write from many threads.
+#[derive(Debug)]
+pub struct AuditLog {
+    audit: Audit,
+    file: Mutex<(File, u64)>,
+}
+
+impl AuditLog {
+    pub fn open(audit: Audit) -> io::Result<AuditLog> {
+        let file = append(&audit.path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditLog {
+            audit,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    /// Appends `record`, first rotating the file if it would grow past its
+    /// limit.
+    pub fn write(&self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record).expect("records serialize");
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let (current, size) = &mut *file;
+        if *size > 0 && *size + line.len() as u64 > self.audit.max_bytes {
+            self.rotate()?;
+            *current = append(&self.audit.path)?;
+            *size = 0;
+        }
+        current.write_all(&line)?;
+        *size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        let path = &self.audit.path;
+        if self.audit.keep == 0 {
+            return fs::remove_file(path);
+        }
+        remove_if_exists(&rotated(path, self.audit.keep))?;
+        for n in (1..self.audit.keep).rev() {
+            rename_if_exists(&rotated(path, n), &rotated(path, n + 1))?;
+        }
+        fs::rename(path, rotated(path, 1))
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `path` with `.n` on the end.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// FNV-1a of `source`, in hex, to tell whether requests sent the same program
+/// without logging the program itself.
+pub fn source_hash(source: &str) -> String {
+    let hash = source.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("hnyfuck-audit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let record = Record {
+            timestamp_ms: 0,
+            client: None,
+            route: "run",
+            source_hash: Some(source_hash("")),
+            limits: None,
+            status: 200,
+            exit: Some(Exit::Finished),
+            steps: Some(0),
+            duration_ms: 1.0,
+            error: None,
+        };
+        let length = serde_json::to_string(&record).unwrap().len() as u64 + 1;
+        let log = AuditLog::open(Audit {
+            path: path.clone(),
+            max_bytes: length * 2,
+            keep: 2,
+        })
+        .unwrap();
+        for _ in 0..7 {
+            log.write(&record).unwrap();
+        }
+
+        let lines = |path: &Path| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&rotated(&path, 1)), 2);
+        assert_eq!(lines(&rotated(&path, 2)), 2);
+        assert!(!rotated(&path, 3).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
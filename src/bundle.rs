@@ -0,0 +1,198 @@
+//! `.hnypkg` bundles: a program packed into one tar archive with the input it
+//! reads, the output it should write and a manifest saying how to run it, so
+//! a runnable example can be shared as a single file.
+//!
+//! The archive holds `manifest.toml`, `program.hny` and, if given, `input`
+//! and `expected-output`.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ir::{CompileError, Program},
+    limits::{self, Execution, Limits},
+};
+
+const MANIFEST: &str = "manifest.toml";
+const PROGRAM: &str = "program.hny";
+const INPUT: &str = "input";
+const EXPECTED: &str = "expected-output";
+
+/// How a bundled program is meant to be run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    pub name: Option<String>,
+    pub lenient: bool,
+    pub max_steps: Option<u64>,
+    pub max_cells: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+}
+
+impl Manifest {
+    /// The limits the program runs under, with none where the manifest sets
+    /// none.
+    pub fn limits(&self) -> Limits {
+        Limits {
+            steps: self.max_steps.unwrap_or(Limits::NONE.steps),
+            cells: self.max_cells.unwrap_or(Limits::NONE.cells),
+            output: self.max_output_bytes.unwrap_or(Limits::NONE.output),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    pub manifest: Manifest,
+    pub program: String,
+    /// What the program reads, if it comes with input.
+    pub input: Option<Vec<u8>>,
+    /// What the program should write, if the bundle says.
+    pub expected: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub enum BundleError {
+    Io(io::Error),
+    /// The archive has no file of this name.
+    Missing(&'static str),
+    Manifest(toml::de::Error),
+    /// The program isn't UTF-8.
+    NotUtf8,
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BundleError::Io(e) => write!(f, "{}", e),
+            BundleError::Missing(name) => write!(f, "the bundle has no {}", name),
+            BundleError::Manifest(e) => write!(f, "bad {}: {}", MANIFEST, e),
+            BundleError::NotUtf8 => write!(f, "{} isn't valid UTF-8", PROGRAM),
+        }
+    }
+}
+
+impl Error for BundleError {}
+
+impl From<io::Error> for BundleError {
+    fn from(e: io::Error) -> Self {
+        BundleError::Io(e)
+    }
+}
+
+impl Bundle {
+    /// Whether `bytes` look like a tar archive, and so perhaps a bundle.
+    pub fn detect(bytes: &[u8]) -> bool {
+        bytes.get(257..262) == Some(b"ustar")
+    }
+
+    /// Packs the bundle into a tar archive. Entries carry no timestamps or
+    /// owners, so packing the same bundle twice gives the same bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let manifest = toml::to_string(&self.manifest).expect("manifests serialize");
+        let mut files = vec![
+            (MANIFEST, manifest.as_bytes()),
+            (PROGRAM, self.program.as_bytes()),
+        ];
+        files.extend(self.input.as_deref().map(|input| (INPUT, input)));
+        files.extend(self.expected.as_deref().map(|output| (EXPECTED, output)));
+
+        let mut archive = tar::Builder::new(Vec::new());
+        for (name, contents) in files {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(&mut header, name, contents)
+                .expect("writing to memory doesn't fail");
+        }
+        archive
+            .into_inner()
+            .expect("writing to memory doesn't fail")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bundle, BundleError> {
+        let (mut manifest, mut program, mut input, mut expected) = (None, None, None, None);
+        for entry in tar::Archive::new(bytes).entries()? {
+            let mut entry = entry?;
+            let slot = match &*entry.path_bytes() {
+                b"manifest.toml" => &mut manifest,
+                b"program.hny" => &mut program,
+                b"input" => &mut input,
+                b"expected-output" => &mut expected,
+                _ => continue,
+            };
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            *slot = Some(contents);
+        }
+
+        let manifest = manifest.ok_or(BundleError::Missing(MANIFEST))?;
+        let manifest = std::str::from_utf8(&manifest).map_err(|_| BundleError::NotUtf8)?;
+        let program = program.ok_or(BundleError::Missing(PROGRAM))?;
+        Ok(Bundle {
+            manifest: toml::from_str(manifest).map_err(BundleError::Manifest)?,
+            program: String::from_utf8(program).map_err(|_| BundleError::NotUtf8)?,
+            input,
+            expected,
+        })
+    }
+
+    /// Compiles the program and runs it within the manifest's limits, reading
+    /// the bundled input or else `input`.
+    pub fn run(&self, input: &[u8]) -> Result<Execution, CompileError> {
+        let program = Program::parse(&self.program, self.manifest.lenient)?;
+        let input = self.input.as_deref().unwrap_or(input);
+        Ok(limits::execute(&program, input, &self.manifest.limits()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::limits::Exit;
+
+    #[test]
+    fn test_round_trip() {
+        let bundle = Bundle {
+            manifest: Manifest {
+                name: Some("echo".to_string()),
+                max_steps: Some(100),
+                ..Manifest::default()
+            },
+            // ,.,.
+            program: "New Happy Year New New Happy Year New".to_string(),
+            input: Some(b"hi".to_vec()),
+            expected: Some(b"hi".to_vec()),
+        };
+        let bytes = bundle.to_bytes();
+        assert!(Bundle::detect(&bytes));
+        assert_eq!(bytes, bundle.to_bytes());
+        let unpacked = Bundle::from_bytes(&bytes).unwrap();
+        assert_eq!(unpacked, bundle);
+
+        let run = unpacked.run(b"ignored").unwrap();
+        assert_eq!((run.exit, &run.output[..]), (Exit::Finished, &b"hi"[..]));
+    }
+
+    #[test]
+    fn test_missing_program() {
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_size(0);
+        header.set_cksum();
+        archive.append_data(&mut header, MANIFEST, &[][..]).unwrap();
+        let bytes = archive.into_inner().unwrap();
+        assert!(matches!(
+            Bundle::from_bytes(&bytes),
+            Err(BundleError::Missing(PROGRAM))
+        ));
+        assert!(!Bundle::detect(b"Year Happy"));
+    }
+}
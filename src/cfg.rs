@@ -0,0 +1,208 @@
+use std::{collections::BTreeSet, fmt::Write, ops::Range};
+
+use crate::{Op, Program};
+
+/// A straight-line run of ops. Only the last op of a block may jump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub ops: Range<usize>,
+    pub successors: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Block(usize),
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: Target,
+    /// `Some(true)` when taken on a non-zero cell, `Some(false)` on zero, and
+    /// `None` for unconditional fallthrough.
+    pub nonzero: Option<bool>,
+}
+
+/// Basic blocks of a compiled program, plus the op ranges of its loops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    /// `start..end + 1` for each loop, where `start` is its `jz` and `end` its `jnz`.
+    pub loops: Vec<Range<usize>>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(program: &Program) -> ControlFlowGraph {
+        let ops = program.ops();
+
+        // Every loop header starts a block so that loops map onto whole blocks.
+        let mut leaders = BTreeSet::from([0]);
+        let mut loops = Vec::new();
+        for (i, op) in ops.iter().enumerate() {
+            match *op {
+                Op::JumpIfZero(target) => {
+                    leaders.extend([i, i + 1, target + 1]);
+                    loops.push(i..target + 1);
+                }
+                Op::JumpIfNonZero(target) => {
+                    leaders.extend([i + 1, target + 1]);
+                }
+                _ => (),
+            }
+        }
+        leaders.retain(|&leader| leader < ops.len());
+        let starts = leaders.into_iter().collect::<Vec<_>>();
+
+        let block_at = |op: usize| match starts.binary_search(&op) {
+            Ok(block) => Target::Block(block),
+            Err(_) => Target::Exit,
+        };
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(block, &start)| {
+                let end = starts.get(block + 1).copied().unwrap_or(ops.len());
+                let next = block_at(end);
+                let successors = match ops[end - 1] {
+                    Op::JumpIfZero(target) => vec![
+                        Edge {
+                            target: next,
+                            nonzero: Some(true),
+                        },
+                        Edge {
+                            target: block_at(target + 1),
+                            nonzero: Some(false),
+                        },
+                    ],
+                    Op::JumpIfNonZero(target) => vec![
+                        Edge {
+                            target: block_at(target + 1),
+                            nonzero: Some(true),
+                        },
+                        Edge {
+                            target: next,
+                            nonzero: Some(false),
+                        },
+                    ],
+                    _ => vec![Edge {
+                        target: next,
+                        nonzero: None,
+                    }],
+                };
+                BasicBlock {
+                    ops: start..end,
+                    successors,
+                }
+            })
+            .collect();
+
+        ControlFlowGraph { blocks, loops }
+    }
+
+    /// Renders the graph in Graphviz DOT, drawing each loop as a cluster.
+    pub fn to_dot(&self, program: &Program) -> String {
+        let mut out = String::new();
+        out.push_str("digraph cfg {\n");
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        out.push_str("    entry [shape=oval];\n");
+        out.push_str("    exit [shape=oval];\n");
+
+        let ops = program.ops();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for op in block.ops.clone() {
+                let _ = write!(label, "{}: {}\\l", op, ops[op]);
+            }
+            let _ = writeln!(out, "    b{} [label=\"{}\"];", i, label);
+        }
+
+        let name = |target: Target| match target {
+            Target::Block(block) => format!("b{}", block),
+            Target::Exit => "exit".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "    entry -> {};",
+            name(match self.blocks.is_empty() {
+                true => Target::Exit,
+                false => Target::Block(0),
+            })
+        );
+        for (i, block) in self.blocks.iter().enumerate() {
+            for edge in &block.successors {
+                let label = match edge.nonzero {
+                    Some(true) => " [label=\"nonzero\"]",
+                    Some(false) => " [label=\"zero\"]",
+                    None => "",
+                };
+                let _ = writeln!(out, "    b{} -> {}{};", i, name(edge.target), label);
+            }
+        }
+
+        self.write_clusters(&mut out, 0..ops.len(), 1);
+        out.push_str("}\n");
+        out
+    }
+
+    fn write_clusters(&self, out: &mut String, region: Range<usize>, depth: usize) {
+        let mut covered = region.start;
+        for range in &self.loops {
+            if range.start < covered || range.end > region.end || *range == region {
+                continue;
+            }
+            let indent = "    ".repeat(depth);
+            let _ = writeln!(out, "{}subgraph cluster_loop_{} {{", indent, range.start);
+            let _ = writeln!(out, "{}    label=\"loop @{}\";", indent, range.start);
+            for (i, block) in self.blocks.iter().enumerate() {
+                let inner = self
+                    .loops
+                    .iter()
+                    .filter(|inner| inner.start > range.start && inner.end <= range.end)
+                    .any(|inner| inner.contains(&block.ops.start));
+                if range.contains(&block.ops.start) && !inner {
+                    let _ = writeln!(out, "{}    b{};", indent, i);
+                }
+            }
+            self.write_clusters(out, range.clone(), depth + 1);
+            let _ = writeln!(out, "{}}}", indent);
+            covered = range.end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        // + [ - ] .
+        let program =
+            Program::from_str("Year Happy Happy Happy Happy Year New New Year New").unwrap();
+        let cfg = ControlFlowGraph::new(&program);
+        assert_eq!(
+            cfg.blocks.iter().map(|b| b.ops.clone()).collect::<Vec<_>>(),
+            [0..1, 1..2, 2..4, 4..5]
+        );
+        assert_eq!(cfg.loops.len(), 1);
+        assert_eq!(cfg.loops[0], 1..4);
+        assert_eq!(
+            cfg.blocks[1].successors,
+            [
+                Edge {
+                    target: Target::Block(2),
+                    nonzero: Some(true)
+                },
+                Edge {
+                    target: Target::Block(3),
+                    nonzero: Some(false)
+                },
+            ]
+        );
+        assert_eq!(cfg.blocks[3].successors[0].target, Target::Exit);
+
+        let dot = cfg.to_dot(&program);
+        assert!(dot.contains("    b2 -> b2 [label=\"nonzero\"];\n"));
+        assert!(dot.contains("    subgraph cluster_loop_1 {\n        label=\"loop @1\";\n        b1;\n        b2;\n    }\n"));
+    }
+}
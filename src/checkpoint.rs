@@ -0,0 +1,247 @@
+//! Snapshots of a compiled program part way through a run, so runs that take
+//! hours can be saved to disk as they go and resumed after a crash.
+
+use std::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ir::Program, State};
+
+/// Everything needed to carry on running a program from where it was.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// [`Program::fingerprint`] of the program that was running.
+    pub program: u64,
+    /// Index of the op to run next.
+    pub pc: usize,
+    pub steps: u64,
+    pub tape: Vec<u8>,
+    /// Index into `tape` of the current cell.
+    pub pointer: usize,
+    /// Index into `tape` of the cell the program started on.
+    #[serde(default)]
+    pub origin: usize,
+    /// Bytes of stdin the program had read.
+    pub input: u64,
+    /// Whether the program had run to its end, leaving nothing to resume.
+    #[serde(default)]
+    pub finished: bool,
+}
+
+impl Checkpoint {
+    fn new(program: &Program, pc: usize, state: &State) -> Checkpoint {
+        Checkpoint {
+            program: program.fingerprint(),
+            pc,
+            steps: state.steps,
+            tape: state.tape().to_vec(),
+            pointer: state.pointer(),
+            origin: state.origin(),
+            input: state.input.consumed,
+            finished: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResumeError {
+    /// The checkpoint was taken running a different program.
+    ProgramMismatch,
+    /// The run the checkpoint was taken in had finished.
+    Finished,
+    /// The pointer, the origin or the op to run next is out of bounds.
+    Corrupt,
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResumeError::ProgramMismatch => {
+                write!(f, "the checkpoint was taken running a different program")
+            }
+            ResumeError::Finished => write!(f, "the run had already finished"),
+            ResumeError::Corrupt => write!(f, "the checkpoint is corrupt"),
+        }
+    }
+}
+
+impl Error for ResumeError {}
+
+/// Runs `program` on a blank tape, reading stdin and writing stdout, and
+/// calls `save` with a checkpoint every `every` steps, and with a last one
+/// marked finished when the program ends, so the run isn't resumed and its
+/// output written again. Returns the tape as the program left it.
+pub fn run(program: &Program, every: u64, save: impl FnMut(&Checkpoint)) -> State {
+    execute(program, State::new(), 0, every, save)
+}
+
+/// Carries on running `program` from `checkpoint`, as [`run`] does. Stdin is
+/// assumed to be the same input as before, so the bytes the program had
+/// already read are skipped.
+pub fn resume(
+    program: &Program,
+    checkpoint: &Checkpoint,
+    every: u64,
+    save: impl FnMut(&Checkpoint),
+) -> Result<State, ResumeError> {
+    if checkpoint.program != program.fingerprint() {
+        return Err(ResumeError::ProgramMismatch);
+    }
+    if checkpoint.finished {
+        return Err(ResumeError::Finished);
+    }
+    if checkpoint.pointer >= checkpoint.tape.len()
+        || checkpoint.origin >= checkpoint.tape.len()
+        || checkpoint.pc > program.ops().len()
+    {
+        return Err(ResumeError::Corrupt);
+    }
+    let mut state = State::new();
+    state.state = checkpoint.tape.iter().copied().collect();
+    state.index = checkpoint.pointer;
+    state.origin = checkpoint.origin;
+    state.steps = checkpoint.steps;
+    for _ in 0..checkpoint.input {
+        if state.input.next_raw().is_none() {
+            break;
+        }
+    }
+    Ok(execute(program, state, checkpoint.pc, every, save))
+}
+
+fn execute(
+    program: &Program,
+    mut state: State,
+    mut pc: usize,
+    every: u64,
+    mut save: impl FnMut(&Checkpoint),
+) -> State {
+    let every = every.max(1);
+    while let Some(next) = state.execute_from(program, pc, state.steps + every) {
+        pc = next;
+        // What the program wrote before a checkpoint isn't written again
+        // when it is resumed.
+        crate::output::flush();
+        save(&Checkpoint::new(program, pc, &state));
+    }
+    crate::output::flush();
+    save(&Checkpoint {
+        finished: true,
+        ..Checkpoint::new(program, program.ops().len(), &state)
+    });
+    state
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    #[test]
+    fn test_checkpoints() {
+        let program = Program::from_str(&brainfuck_to_hny("++[>+++<-]>").output).unwrap();
+        let mut checkpoints = Vec::new();
+        let state = run(&program, 5, |checkpoint| {
+            checkpoints.push(checkpoint.clone())
+        });
+
+        // 13 ops: add 2, jz, two iterations of five ops ending in jnz, move 1.
+        assert_eq!(state.tape(), &[0, 6]);
+        assert_eq!(checkpoints.len(), 3);
+        assert_eq!(
+            checkpoints[0],
+            Checkpoint {
+                program: program.fingerprint(),
+                pc: 5,
+                steps: 5,
+                tape: vec![2, 3],
+                pointer: 0,
+                origin: 0,
+                input: 0,
+                finished: false,
+            }
+        );
+        assert_eq!(checkpoints[1].steps, 10);
+
+        let mut resumed = Vec::new();
+        let state = resume(&program, &checkpoints[0], 5, |checkpoint| {
+            resumed.push(checkpoint.clone())
+        })
+        .unwrap();
+        assert_eq!(state.tape(), &[0, 6]);
+        assert_eq!(resumed, &checkpoints[1..]);
+
+        let other = Program::from_str(&brainfuck_to_hny("+").output).unwrap();
+        assert_eq!(
+            resume(&other, &checkpoints[0], 5, |_| ()).unwrap_err(),
+            ResumeError::ProgramMismatch
+        );
+        let corrupt = Checkpoint {
+            pointer: 2,
+            ..checkpoints[0].clone()
+        };
+        assert_eq!(
+            resume(&program, &corrupt, 5, |_| ()).unwrap_err(),
+            ResumeError::Corrupt
+        );
+    }
+
+    /// A tape grown left of where the program started is laid out the same
+    /// when the run is resumed.
+    #[test]
+    fn test_origin() {
+        let program = Program::from_str(&brainfuck_to_hny("<<+>>+++").output).unwrap();
+        let mut checkpoints = Vec::new();
+        let state = run(&program, 2, |checkpoint| {
+            checkpoints.push(checkpoint.clone())
+        });
+        assert_eq!((state.tape(), state.origin()), (&[1, 0, 3][..], 2));
+        assert_eq!((checkpoints[0].origin, checkpoints[0].pointer), (2, 0));
+
+        let state = resume(&program, &checkpoints[0], 2, |_| ()).unwrap();
+        assert_eq!((state.tape(), state.origin()), (&[1, 0, 3][..], 2));
+
+        let corrupt = Checkpoint {
+            origin: 3,
+            ..checkpoints[0].clone()
+        };
+        assert_eq!(
+            resume(&program, &corrupt, 2, |_| ()).unwrap_err(),
+            ResumeError::Corrupt
+        );
+    }
+
+    /// The last checkpoint of a run that finished can't be resumed, so its
+    /// output isn't written again.
+    #[test]
+    fn test_finished() {
+        let program = Program::from_str(&brainfuck_to_hny("+++").output).unwrap();
+        let mut checkpoints = Vec::new();
+        run(&program, 100, |checkpoint| {
+            checkpoints.push(checkpoint.clone())
+        });
+        let [last] = &checkpoints[..] else {
+            panic!("expected only the final checkpoint, got {:?}", checkpoints);
+        };
+        assert!(last.finished);
+        assert_eq!((last.pc, last.steps, &last.tape[..]), (1, 1, &[3][..]));
+        assert_eq!(
+            resume(&program, last, 100, |_| ()).unwrap_err(),
+            ResumeError::Finished
+        );
+
+        // Resuming an earlier checkpoint ends in a finished one too.
+        let program = Program::from_str(&brainfuck_to_hny("++[>+++<-]>").output).unwrap();
+        let mut checkpoints = Vec::new();
+        run(&program, 5, |checkpoint| {
+            checkpoints.push(checkpoint.clone())
+        });
+        let mut resumed = Vec::new();
+        resume(&program, &checkpoints[0], 5, |checkpoint| {
+            resumed.push(checkpoint.clone())
+        })
+        .unwrap();
+        assert_eq!(resumed.last(), checkpoints.last());
+        assert!(resumed.last().unwrap().finished);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Write;
+
+use crate::{Op, Program};
+
+/// Runtime shared by every generated program. The tape grows in both
+/// directions, matching the interpreter.
+const RUNTIME: &str = r#"#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static unsigned char *tape;
+static size_t len = 1;
+static size_t p = 0;
+
+static void hny_move(long n) {
+    if (n < 0 && (size_t)-n > p) {
+        size_t grow = (size_t)-n - p;
+        unsigned char *next = calloc(len + grow, 1);
+        if (!next) abort();
+        memcpy(next + grow, tape, len);
+        free(tape);
+        tape = next;
+        len += grow;
+        p = 0;
+    } else {
+        p += n;
+        if (p >= len) {
+            size_t next_len = len * 2 > p + 1 ? len * 2 : p + 1;
+            tape = realloc(tape, next_len);
+            if (!tape) abort();
+            memset(tape + len, 0, next_len - len);
+            len = next_len;
+        }
+    }
+}
+
+static void hny_mul_add(long offset, unsigned char factor) {
+    unsigned char value = tape[p];
+    if (value) {
+        hny_move(offset);
+        tape[p] += value * factor;
+        hny_move(-offset);
+    }
+}
+
+static void hny_input(void) {
+    int c = getchar();
+    if (c != EOF) tape[p] = (unsigned char)c;
+}
+
+"#;
+
+/// Generates a standalone C program equivalent to `program`.
+pub fn generate(program: &Program) -> String {
+    let mut out = String::from(RUNTIME);
+    out.push_str("int main(void) {\n");
+    out.push_str("    tape = calloc(len, 1);\n");
+    out.push_str("    if (!tape) abort();\n");
+
+    let mut depth = 1;
+    for op in program.ops() {
+        if let Op::JumpIfNonZero(_) = op {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+        let _ = match op {
+            Op::Add(n) => writeln!(out, "{}tape[p] += {};", indent, n),
+            Op::Move(n) => writeln!(out, "{}hny_move({});", indent, n),
+            Op::Output => writeln!(out, "{}putchar(tape[p]);", indent),
+            Op::Input => writeln!(out, "{}hny_input();", indent),
+            Op::Set(n) => writeln!(out, "{}tape[p] = {};", indent, n),
+            Op::MulAdd { offset, factor } => {
+                writeln!(out, "{}hny_mul_add({}, {});", indent, offset, factor)
+            }
+            Op::JumpIfZero(_) => writeln!(out, "{}while (tape[p]) {{", indent),
+            Op::JumpIfNonZero(_) => writeln!(out, "{}}}", indent),
+        };
+        if let Op::JumpIfZero(_) = op {
+            depth += 1;
+        }
+    }
+
+    out.push_str("    return 0;\n");
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let program =
+            Program::from_str("Year Happy Happy Happy New Year Year New Happy New New New")
+                .unwrap();
+        let c = generate(&program);
+        assert!(c.ends_with(
+            "    tape[p] += 1;\n    while (tape[p]) {\n        hny_move(1);\n        putchar(tape[p]);\n        hny_move(-1);\n    }\n    return 0;\n}\n"
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt::Write;
+
+use crate::{Op, Program};
+
+const PRELUDE: &str = r#"// Generated by hnyfuck.
+"use strict";
+
+/**
+ * Runs the program. `input` may be a string or a byte array; the output is
+ * returned as a Uint8Array.
+ */
+function hnyfuck(input) {
+    if (typeof input === "string") input = new TextEncoder().encode(input);
+    input = input || new Uint8Array(0);
+    let inputPos = 0;
+    const output = [];
+    let tape = new Uint8Array(1);
+    let p = 0;
+
+    function move(n) {
+        if (p + n < 0) {
+            const grow = -(p + n);
+            const next = new Uint8Array(tape.length + grow);
+            next.set(tape, grow);
+            tape = next;
+            p = 0;
+        } else {
+            p += n;
+            if (p >= tape.length) {
+                const next = new Uint8Array(Math.max(tape.length * 2, p + 1));
+                next.set(tape);
+                tape = next;
+            }
+        }
+    }
+
+    function mulAdd(offset, factor) {
+        const value = tape[p];
+        if (value) {
+            move(offset);
+            tape[p] += value * factor;
+            move(-offset);
+        }
+    }
+
+"#;
+
+const EPILOGUE: &str = r#"    return Uint8Array.from(output);
+}
+
+if (typeof module !== "undefined" && typeof require !== "undefined") {
+    module.exports = hnyfuck;
+    if (require.main === module) {
+        const fs = require("fs");
+        let input;
+        try {
+            input = fs.readFileSync(0);
+        } catch (e) {
+            input = new Uint8Array(0);
+        }
+        process.stdout.write(hnyfuck(input));
+    }
+}
+"#;
+
+/// Generates a JavaScript module equivalent to `program`. It defines a
+/// `hnyfuck(input)` function for browsers and, under Node, exports it and runs
+/// against stdin/stdout when executed directly.
+pub fn generate(program: &Program) -> String {
+    let mut out = String::from(PRELUDE);
+
+    let mut depth = 1;
+    for op in program.ops() {
+        if let Op::JumpIfNonZero(_) = op {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+        let _ = match op {
+            Op::Add(n) => writeln!(out, "{}tape[p] += {};", indent, n),
+            Op::Move(n) => writeln!(out, "{}move({});", indent, n),
+            Op::Output => writeln!(out, "{}output.push(tape[p]);", indent),
+            Op::Input => writeln!(
+                out,
+                "{}if (inputPos < input.length) tape[p] = input[inputPos++];",
+                indent
+            ),
+            Op::Set(n) => writeln!(out, "{}tape[p] = {};", indent, n),
+            Op::MulAdd { offset, factor } => {
+                writeln!(out, "{}mulAdd({}, {});", indent, offset, factor)
+            }
+            Op::JumpIfZero(_) => writeln!(out, "{}while (tape[p]) {{", indent),
+            Op::JumpIfNonZero(_) => writeln!(out, "{}}}", indent),
+        };
+        if let Op::JumpIfZero(_) = op {
+            depth += 1;
+        }
+    }
+
+    out.push_str(EPILOGUE);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let program =
+            Program::from_str("New Happy Happy Happy Year New New Happy New New").unwrap();
+        let js = generate(&program);
+        assert!(js.contains(
+            "    if (inputPos < input.length) tape[p] = input[inputPos++];\n    while (tape[p]) {\n        output.push(tape[p]);\n        if (inputPos < input.length) tape[p] = input[inputPos++];\n    }\n    return Uint8Array.from(output);\n"
+        ));
+    }
+}
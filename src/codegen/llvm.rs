@@ -0,0 +1,378 @@
+use inkwell::{
+    builder::{Builder, BuilderError},
+    context::Context,
+    module::{Linkage, Module},
+    passes::PassBuilderOptions,
+    targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine},
+    values::{FunctionValue, GlobalValue, IntValue, PointerValue},
+    AddressSpace, IntPredicate, OptimizationLevel,
+};
+
+use crate::{Op, Program};
+
+/// Generates LLVM IR equivalent to `program`, optimized with LLVM's `default<O2>`
+/// pipeline for the host target.
+pub fn generate(program: &Program) -> Result<String, String> {
+    let context = Context::create();
+    let module = context.create_module("hnyfuck");
+    let codegen = Codegen::new(&context, &module);
+    codegen.define_move().map_err(|e| e.to_string())?;
+    codegen.define_main(program).map_err(|e| e.to_string())?;
+    module.verify().map_err(|e| e.to_string())?;
+
+    Target::initialize_native(&InitializationConfig::default())?;
+    let triple = TargetMachine::get_default_triple();
+    let target = Target::from_triple(&triple).map_err(|e| e.to_string())?;
+    let machine = target
+        .create_target_machine(
+            &triple,
+            &TargetMachine::get_host_cpu_name().to_string(),
+            &TargetMachine::get_host_cpu_features().to_string(),
+            OptimizationLevel::Default,
+            RelocMode::Default,
+            CodeModel::Default,
+        )
+        .ok_or("could not create a target machine for the host")?;
+    module.set_triple(&triple);
+    module.set_data_layout(&machine.get_target_data().get_data_layout());
+    module
+        .run_passes("default<O2>", &machine, PassBuilderOptions::create())
+        .map_err(|e| e.to_string())?;
+
+    Ok(module.print_to_string().to_string())
+}
+
+/// Builds the module around three globals mirroring the C runtime: the tape
+/// pointer, its length, and the current cell index.
+struct Codegen<'a, 'ctx> {
+    context: &'ctx Context,
+    module: &'a Module<'ctx>,
+    builder: Builder<'ctx>,
+    tape: GlobalValue<'ctx>,
+    len: GlobalValue<'ctx>,
+    p: GlobalValue<'ctx>,
+}
+
+impl<'a, 'ctx> Codegen<'a, 'ctx> {
+    fn new(context: &'ctx Context, module: &'a Module<'ctx>) -> Self {
+        let i8_ptr = context.i8_type().ptr_type(AddressSpace::default());
+        let i64_type = context.i64_type();
+
+        let tape = module.add_global(i8_ptr, None, "tape");
+        tape.set_initializer(&i8_ptr.const_null());
+        let len = module.add_global(i64_type, None, "len");
+        len.set_initializer(&i64_type.const_zero());
+        let p = module.add_global(i64_type, None, "p");
+        p.set_initializer(&i64_type.const_zero());
+        for global in [tape, len, p] {
+            global.set_linkage(Linkage::Internal);
+        }
+
+        let i32_type = context.i32_type();
+        let void = context.void_type();
+        module.add_function(
+            "calloc",
+            i8_ptr.fn_type(&[i64_type.into(), i64_type.into()], false),
+            None,
+        );
+        module.add_function(
+            "realloc",
+            i8_ptr.fn_type(&[i8_ptr.into(), i64_type.into()], false),
+            None,
+        );
+        module.add_function("free", void.fn_type(&[i8_ptr.into()], false), None);
+        module.add_function("putchar", i32_type.fn_type(&[i32_type.into()], false), None);
+        module.add_function("getchar", i32_type.fn_type(&[], false), None);
+
+        Codegen {
+            context,
+            module,
+            builder: context.create_builder(),
+            tape,
+            len,
+            p,
+        }
+    }
+
+    fn function(&self, name: &str) -> FunctionValue<'ctx> {
+        self.module
+            .get_function(name)
+            .expect("declared in Codegen::new")
+    }
+
+    fn load_int(
+        &self,
+        global: GlobalValue<'ctx>,
+        name: &str,
+    ) -> Result<IntValue<'ctx>, BuilderError> {
+        Ok(self
+            .builder
+            .build_load(global.as_pointer_value(), name)?
+            .into_int_value())
+    }
+
+    fn load_tape(&self) -> Result<PointerValue<'ctx>, BuilderError> {
+        Ok(self
+            .builder
+            .build_load(self.tape.as_pointer_value(), "tape")?
+            .into_pointer_value())
+    }
+
+    fn call_ptr(
+        &self,
+        name: &str,
+        args: &[inkwell::values::BasicMetadataValueEnum<'ctx>],
+    ) -> Result<PointerValue<'ctx>, BuilderError> {
+        let value = self.builder.build_call(self.function(name), args, name)?;
+        Ok(value
+            .try_as_basic_value()
+            .basic()
+            .expect("returns a pointer")
+            .into_pointer_value())
+    }
+
+    /// `hny_move(n)`: moves the pointer, growing the tape at either end.
+    fn define_move(&self) -> Result<(), BuilderError> {
+        let i64_type = self.context.i64_type();
+        let function = self.module.add_function(
+            "hny_move",
+            self.context.void_type().fn_type(&[i64_type.into()], false),
+            Some(Linkage::Internal),
+        );
+        let entry = self.context.append_basic_block(function, "entry");
+        let grow_front = self.context.append_basic_block(function, "grow_front");
+        let check_back = self.context.append_basic_block(function, "check_back");
+        let grow_back = self.context.append_basic_block(function, "grow_back");
+        let done = self.context.append_basic_block(function, "done");
+
+        self.builder.position_at_end(entry);
+        let n = function.get_nth_param(0).unwrap().into_int_value();
+        let p = self.load_int(self.p, "p")?;
+        let next = self.builder.build_int_add(p, n, "next")?;
+        let negative = self.builder.build_int_compare(
+            IntPredicate::SLT,
+            next,
+            i64_type.const_zero(),
+            "negative",
+        )?;
+        self.builder
+            .build_conditional_branch(negative, grow_front, check_back)?;
+
+        self.builder.position_at_end(grow_front);
+        let grow = self.builder.build_int_neg(next, "grow")?;
+        let len = self.load_int(self.len, "len")?;
+        let new_len = self.builder.build_int_add(len, grow, "new_len")?;
+        let new_tape = self.call_ptr(
+            "calloc",
+            &[new_len.into(), i64_type.const_int(1, false).into()],
+        )?;
+        let old_tape = self.load_tape()?;
+        let dest = unsafe { self.builder.build_gep(new_tape, &[grow], "dest")? };
+        self.builder.build_memcpy(dest, 1, old_tape, 1, len)?;
+        self.builder
+            .build_call(self.function("free"), &[old_tape.into()], "")?;
+        self.builder
+            .build_store(self.tape.as_pointer_value(), new_tape)?;
+        self.builder
+            .build_store(self.len.as_pointer_value(), new_len)?;
+        self.builder
+            .build_store(self.p.as_pointer_value(), i64_type.const_zero())?;
+        self.builder.build_unconditional_branch(done)?;
+
+        self.builder.position_at_end(check_back);
+        self.builder.build_store(self.p.as_pointer_value(), next)?;
+        let len = self.load_int(self.len, "len")?;
+        let past_end = self
+            .builder
+            .build_int_compare(IntPredicate::SGE, next, len, "past_end")?;
+        self.builder
+            .build_conditional_branch(past_end, grow_back, done)?;
+
+        self.builder.position_at_end(grow_back);
+        let doubled = self
+            .builder
+            .build_int_mul(len, i64_type.const_int(2, false), "doubled")?;
+        let needed = self
+            .builder
+            .build_int_add(next, i64_type.const_int(1, false), "needed")?;
+        let enough =
+            self.builder
+                .build_int_compare(IntPredicate::UGT, doubled, needed, "enough")?;
+        let new_len = self
+            .builder
+            .build_select(enough, doubled, needed, "new_len")?
+            .into_int_value();
+        let old_tape = self.load_tape()?;
+        let new_tape = self.call_ptr("realloc", &[old_tape.into(), new_len.into()])?;
+        let gap = unsafe { self.builder.build_gep(new_tape, &[len], "gap")? };
+        let gap_len = self.builder.build_int_sub(new_len, len, "gap_len")?;
+        self.builder
+            .build_memset(gap, 1, self.context.i8_type().const_zero(), gap_len)?;
+        self.builder
+            .build_store(self.tape.as_pointer_value(), new_tape)?;
+        self.builder
+            .build_store(self.len.as_pointer_value(), new_len)?;
+        self.builder.build_unconditional_branch(done)?;
+
+        self.builder.position_at_end(done);
+        self.builder.build_return(None)?;
+        Ok(())
+    }
+
+    fn cell(&self) -> Result<PointerValue<'ctx>, BuilderError> {
+        let tape = self.load_tape()?;
+        let p = self.load_int(self.p, "p")?;
+        Ok(unsafe { self.builder.build_gep(tape, &[p], "cell")? })
+    }
+
+    fn define_main(&self, program: &Program) -> Result<(), BuilderError> {
+        let i8_type = self.context.i8_type();
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let function = self
+            .module
+            .add_function("main", i32_type.fn_type(&[], false), None);
+        let entry = self.context.append_basic_block(function, "entry");
+
+        self.builder.position_at_end(entry);
+        let one = i64_type.const_int(1, false);
+        let tape = self.call_ptr("calloc", &[one.into(), one.into()])?;
+        self.builder
+            .build_store(self.tape.as_pointer_value(), tape)?;
+        self.builder.build_store(self.len.as_pointer_value(), one)?;
+
+        let mut loops = Vec::new();
+        for op in program.ops() {
+            match *op {
+                Op::Add(n) => {
+                    let cell = self.cell()?;
+                    let value = self.builder.build_load(cell, "value")?.into_int_value();
+                    let sum = self.builder.build_int_add(
+                        value,
+                        i8_type.const_int(n as u64, false),
+                        "sum",
+                    )?;
+                    self.builder.build_store(cell, sum)?;
+                }
+                Op::Move(n) => {
+                    let n = i64_type.const_int(n as u64, true);
+                    self.builder
+                        .build_call(self.function("hny_move"), &[n.into()], "")?;
+                }
+                Op::Output => {
+                    let cell = self.cell()?;
+                    let value = self.builder.build_load(cell, "value")?.into_int_value();
+                    let c = self.builder.build_int_z_extend(value, i32_type, "c")?;
+                    self.builder
+                        .build_call(self.function("putchar"), &[c.into()], "")?;
+                }
+                Op::Set(n) => {
+                    let cell = self.cell()?;
+                    self.builder
+                        .build_store(cell, i8_type.const_int(n as u64, false))?;
+                }
+                Op::MulAdd { offset, factor } => {
+                    let then = self.context.append_basic_block(function, "mul_add");
+                    let next = self.context.append_basic_block(function, "next");
+                    let value = self
+                        .builder
+                        .build_load(self.cell()?, "value")?
+                        .into_int_value();
+                    let zero = self.builder.build_int_compare(
+                        IntPredicate::EQ,
+                        value,
+                        i8_type.const_zero(),
+                        "zero",
+                    )?;
+                    self.builder.build_conditional_branch(zero, next, then)?;
+
+                    self.builder.position_at_end(then);
+                    let there = i64_type.const_int(offset as u64, true);
+                    let back = i64_type.const_int(offset.wrapping_neg() as u64, true);
+                    self.builder
+                        .build_call(self.function("hny_move"), &[there.into()], "")?;
+                    let cell = self.cell()?;
+                    let target = self.builder.build_load(cell, "target")?.into_int_value();
+                    let product = self.builder.build_int_mul(
+                        value,
+                        i8_type.const_int(factor as u64, false),
+                        "product",
+                    )?;
+                    let sum = self.builder.build_int_add(target, product, "sum")?;
+                    self.builder.build_store(cell, sum)?;
+                    self.builder
+                        .build_call(self.function("hny_move"), &[back.into()], "")?;
+                    self.builder.build_unconditional_branch(next)?;
+                    self.builder.position_at_end(next);
+                }
+                Op::Input => {
+                    let store = self.context.append_basic_block(function, "store");
+                    let next = self.context.append_basic_block(function, "next");
+                    let c = self
+                        .builder
+                        .build_call(self.function("getchar"), &[], "c")?
+                        .try_as_basic_value()
+                        .basic()
+                        .expect("returns an int")
+                        .into_int_value();
+                    let eof = self.builder.build_int_compare(
+                        IntPredicate::EQ,
+                        c,
+                        i32_type.const_all_ones(),
+                        "eof",
+                    )?;
+                    self.builder.build_conditional_branch(eof, next, store)?;
+
+                    self.builder.position_at_end(store);
+                    let byte = self.builder.build_int_truncate(c, i8_type, "byte")?;
+                    self.builder.build_store(self.cell()?, byte)?;
+                    self.builder.build_unconditional_branch(next)?;
+                    self.builder.position_at_end(next);
+                }
+                Op::JumpIfZero(_) => {
+                    let cond = self.context.append_basic_block(function, "cond");
+                    let body = self.context.append_basic_block(function, "body");
+                    let exit = self.context.append_basic_block(function, "exit");
+                    self.builder.build_unconditional_branch(cond)?;
+
+                    self.builder.position_at_end(cond);
+                    let value = self
+                        .builder
+                        .build_load(self.cell()?, "value")?
+                        .into_int_value();
+                    let zero = self.builder.build_int_compare(
+                        IntPredicate::EQ,
+                        value,
+                        i8_type.const_zero(),
+                        "zero",
+                    )?;
+                    self.builder.build_conditional_branch(zero, exit, body)?;
+
+                    self.builder.position_at_end(body);
+                    loops.push((cond, exit));
+                }
+                Op::JumpIfNonZero(_) => {
+                    let (cond, exit) = loops.pop().expect("compiled programs have balanced loops");
+                    self.builder.build_unconditional_branch(cond)?;
+                    self.builder.position_at_end(exit);
+                }
+            }
+        }
+
+        self.builder.build_return(Some(&i32_type.const_zero()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let program = Program::from_str("Year Happy Year New").unwrap();
+        let ir = generate(&program).unwrap();
+        assert!(ir.contains("define i32 @main()"));
+        assert!(ir.contains("@putchar(i32 1)"));
+    }
+}
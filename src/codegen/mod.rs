@@ -0,0 +1,7 @@
+//! Source generators for compiling a [`Program`](crate::Program) ahead of time.
+
+pub mod c;
+pub mod js;
+#[cfg(feature = "llvm")]
+pub mod llvm;
+pub mod python;
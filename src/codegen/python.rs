@@ -0,0 +1,107 @@
+use std::fmt::Write;
+
+use crate::{Op, Program};
+
+const PRELUDE: &str = r#"#!/usr/bin/env python3
+# Generated by hnyfuck.
+import sys
+
+# The tape starts as a single zero cell and grows in both directions.
+tape = [0]
+p = 0
+
+
+def move(n):
+    """Move the pointer n cells, adding zero cells when it walks off either end."""
+    global p
+    p += n
+    if p < 0:
+        tape[:0] = [0] * -p
+        p = 0
+    if p >= len(tape):
+        tape.extend([0] * (p + 1 - len(tape)))
+
+
+def mul_add(offset, factor):
+    """Add the current cell times factor to the cell offset steps away."""
+    value = tape[p]
+    if value != 0:
+        move(offset)
+        tape[p] = (tape[p] + value * factor) % 256
+        move(-offset)
+
+
+def read():
+    """Store the next input byte in the current cell, or leave it alone at end of input."""
+    byte = sys.stdin.buffer.read(1)
+    if byte:
+        tape[p] = byte[0]
+
+
+def write():
+    """Print the current cell as a byte."""
+    sys.stdout.buffer.write(bytes([tape[p]]))
+
+
+"#;
+
+/// Generates a Python 3 script equivalent to `program`, written to be read as
+/// well as run.
+pub fn generate(program: &Program) -> String {
+    let mut out = String::from(PRELUDE);
+
+    let ops = program.ops();
+    let mut depth = 0;
+    for (i, op) in ops.iter().enumerate() {
+        if let Op::JumpIfNonZero(_) = op {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+        let _ = match *op {
+            Op::Add(n) if n <= 128 => writeln!(out, "{}tape[p] = (tape[p] + {}) % 256", indent, n),
+            Op::Add(n) => writeln!(
+                out,
+                "{}tape[p] = (tape[p] - {}) % 256",
+                indent,
+                256 - n as u16
+            ),
+            Op::Move(n) => writeln!(out, "{}move({})", indent, n),
+            Op::Output => writeln!(out, "{}write()", indent),
+            Op::Input => writeln!(out, "{}read()", indent),
+            Op::Set(n) => writeln!(out, "{}tape[p] = {}", indent, n),
+            Op::MulAdd { offset, factor } => {
+                writeln!(out, "{}mul_add({}, {})", indent, offset, factor as i8)
+            }
+            Op::JumpIfZero(_) => {
+                let empty = matches!(ops.get(i + 1), Some(Op::JumpIfNonZero(_)));
+                writeln!(out, "{}while tape[p] != 0:", indent).and_then(|_| match empty {
+                    true => writeln!(out, "{}    pass", indent),
+                    false => Ok(()),
+                })
+            }
+            Op::JumpIfNonZero(_) => Ok(()),
+        };
+        if let Op::JumpIfZero(_) = op {
+            depth += 1;
+        }
+    }
+
+    out.push_str("sys.stdout.buffer.flush()\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let program =
+            Program::from_str("Happy Year Happy Happy New New Happy Happy Year Happy New New")
+                .unwrap();
+        let python = generate(&program);
+        assert!(python.ends_with(
+            "tape[p] = (tape[p] - 1) % 256\nwhile tape[p] != 0:\n    pass\nwhile tape[p] != 0:\n    tape[p] = (tape[p] + 1) % 256\nsys.stdout.buffer.flush()\n"
+        ));
+    }
+}
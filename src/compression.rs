@@ -0,0 +1,71 @@
+//! Reading programs stored gzip- or zstd-compressed. Word pairs compress
+//! extremely well, so generated programs are often kept as `.hny.gz` or
+//! `.hny.zst`; the format is told by the magic bytes, not the extension.
+
+use std::io::{self, Read};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression `bytes` start with the magic bytes of, if any.
+    pub fn detect(bytes: &[u8]) -> Option<Compression> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// `bytes` decompressed if they are gzip or zstd, or as they are otherwise.
+pub fn decompress(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match Compression::detect(&bytes) {
+        None => return Ok(bytes),
+        Some(Compression::Gzip) => {
+            flate2::read::MultiGzDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+        }
+        Some(Compression::Zstd) => {
+            ruzstd::decoding::StreamingDecoder::new(&bytes[..])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                .read_to_end(&mut out)?;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    const CODE: &str = "Year Happy Year Happy Year New";
+
+    #[test]
+    fn test_decompress() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(CODE.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(Compression::detect(&gzip), Some(Compression::Gzip));
+        assert_eq!(decompress(gzip).unwrap(), CODE.as_bytes());
+
+        let zstd = ruzstd::encoding::compress_to_vec(
+            CODE.as_bytes(),
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        assert_eq!(Compression::detect(&zstd), Some(Compression::Zstd));
+        assert_eq!(decompress(zstd).unwrap(), CODE.as_bytes());
+
+        assert_eq!(decompress(CODE.into()).unwrap(), CODE.as_bytes());
+        assert!(decompress(vec![0x1f, 0x8b, 0]).is_err());
+    }
+}
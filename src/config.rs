@@ -0,0 +1,122 @@
+//! Config files giving defaults for options the command line leaves out: the
+//! user's, `hnyfuck/config.toml` under `$XDG_CONFIG_HOME` or else `~/.config`,
+//! and a project's, `.hnyfuck.toml` in the program's directory or the nearest
+//! one above it. A project's settings win over the user's.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::FormatStyle;
+
+/// The name of a project's config file.
+pub const PROJECT_FILE: &str = ".hnyfuck.toml";
+
+/// How `run` executes a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Engine {
+    /// Interpret loops until they get hot, then compile them to IR ops and
+    /// run those.
+    #[default]
+    Tiered,
+    /// Interpret every loop.
+    Interpreter,
+}
+
+/// Defaults from a config file, with `None` for those it doesn't set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub lenient: Option<bool>,
+    /// Optimization level, 0 to 2.
+    pub optimize: Option<u8>,
+    pub engine: Option<Engine>,
+    /// Loop iterations to interpret before compiling a loop.
+    pub tier_threshold: Option<u64>,
+    /// The style `fmt` lays programs out in, as a `[fmt]` table.
+    pub fmt: Option<FormatStyle>,
+}
+
+impl Config {
+    /// Reads a config from TOML, e.g. `lenient = true`.
+    pub fn from_toml(input: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(input)
+    }
+
+    /// Where the user's config file is, whether or not it exists, or `None`
+    /// if neither `$XDG_CONFIG_HOME` nor `$HOME` is set.
+    pub fn user_path() -> Option<PathBuf> {
+        Some(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("config.toml"))
+    }
+
+    /// The project config file for the program at `program`: the
+    /// [`PROJECT_FILE`] in its directory or the nearest one above.
+    pub fn project_path(program: &Path) -> Option<PathBuf> {
+        let program = std::path::absolute(program).ok()?;
+        program
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join(PROJECT_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// This config's settings, falling back to `fallback`'s where it has
+    /// none. A `[fmt]` table is taken whole.
+    pub fn or(self, fallback: Config) -> Config {
+        Config {
+            lenient: self.lenient.or(fallback.lenient),
+            optimize: self.optimize.or(fallback.optimize),
+            engine: self.engine.or(fallback.engine),
+            tier_threshold: self.tier_threshold.or(fallback.tier_threshold),
+            fmt: self.fmt.or(fallback.fmt),
+        }
+    }
+}
+
+/// hnyfuck's directory under the XDG base directory `variable` names, or
+/// under `fallback` in the home directory if it isn't set.
+pub(crate) fn xdg_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(variable)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            let home = std::env::var_os("HOME").filter(|dir| !dir.is_empty())?;
+            Some(PathBuf::from(home).join(fallback))
+        })?;
+    Some(base.join("hnyfuck"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Layout;
+
+    #[test]
+    fn test_config_from_toml() {
+        let config =
+            Config::from_toml("lenient = true\nengine = \"interpreter\"\n[fmt]\nindent = 2")
+                .unwrap();
+        assert_eq!(config.lenient, Some(true));
+        assert_eq!(config.engine, Some(Engine::Interpreter));
+        assert_eq!(config.optimize, None);
+        let fmt = config.fmt.unwrap();
+        assert_eq!((fmt.indent, fmt.layout), (2, Layout::Code));
+        assert!(Config::from_toml("cell-size = 16").is_err());
+    }
+
+    #[test]
+    fn test_project_path() {
+        let root = std::env::temp_dir().join(format!("hnyfuck-project-{}", std::process::id()));
+        let nested = root.join("src/programs");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(Config::project_path(&nested.join("a.hny")), None);
+
+        std::fs::write(root.join(PROJECT_FILE), "").unwrap();
+        assert_eq!(
+            Config::project_path(&nested.join("a.hny")),
+            Some(root.join(PROJECT_FILE))
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use std::{error::Error, fmt, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{frontend::Frontend, lexer, Instruction};
+
+/// Links byte ranges of a converted program back to the file it came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// Name of the original file, if known.
+    pub source: Option<String>,
+    /// One entry per instruction, ordered by position in the generated text.
+    pub mappings: Vec<Mapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mapping {
+    pub generated: Range<usize>,
+    pub original: Range<usize>,
+}
+
+impl SourceMap {
+    /// Finds the original range of the instruction covering `offset` in the
+    /// generated text.
+    pub fn lookup(&self, offset: usize) -> Option<Range<usize>> {
+        let i = self
+            .mappings
+            .partition_point(|mapping| mapping.generated.end <= offset);
+        let mapping = self.mappings.get(i)?;
+        mapping
+            .generated
+            .contains(&offset)
+            .then(|| mapping.original.clone())
+    }
+}
+
+impl SourceMap {
+    /// A map from `next`'s output back to this map's original, for a
+    /// conversion made in two steps: this one, then `next` on its output.
+    pub fn then(&self, next: SourceMap) -> SourceMap {
+        SourceMap {
+            source: self.source.clone(),
+            mappings: next
+                .mappings
+                .into_iter()
+                .filter_map(|mapping| {
+                    Some(Mapping {
+                        original: self.lookup(mapping.original.start)?,
+                        generated: mapping.generated,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    pub output: String,
+    pub source_map: SourceMap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConvertError {
+    InvalidToken { span: Range<usize> },
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::InvalidToken { span } => {
+                write!(f, "invalid token at bytes {}..{}", span.start, span.end)
+            }
+        }
+    }
+}
+
+impl Error for ConvertError {}
+
+/// The languages programs can be written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Language {
+    /// hnyfuck word pairs
+    #[default]
+    Hny,
+    /// brainfuck symbols
+    Bf,
+    /// Ook! word pairs
+    Ook,
+    /// Blub word pairs
+    Blub,
+    /// 🎉, 🎆 and 🎊 for Happy, New and Year
+    Emoji,
+}
+
+impl Language {
+    /// The word-substitution dialects besides hnyfuck.
+    pub const DIALECTS: [Language; 3] = [Language::Ook, Language::Blub, Language::Emoji];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Hny => "hnyfuck",
+            Language::Bf => "brainfuck",
+            Language::Ook => "Ook!",
+            Language::Blub => "Blub",
+            Language::Emoji => "emoji",
+        }
+    }
+
+    /// The extension programs in the language are saved with.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Language::Hny => "hny",
+            Language::Bf => "bf",
+            Language::Ook => "ook",
+            Language::Blub => "blub",
+            Language::Emoji => "emoji",
+        }
+    }
+
+    /// The frontend for a word-substitution dialect other than hnyfuck.
+    pub fn frontend(self) -> Option<Frontend> {
+        match self {
+            Language::Hny | Language::Bf => None,
+            Language::Ook => Some(Frontend::ook()),
+            Language::Blub => Some(Frontend::blub()),
+            Language::Emoji => Some(Frontend::emoji()),
+        }
+    }
+}
+
+/// The language `source` looks to be written in, and whether it could as
+/// well be another. A program made of nothing but one dialect's words is in
+/// that dialect. Otherwise it is hnyfuck or brainfuck, whichever it has more
+/// instructions in, and ambiguous if it has plenty of the other too. Ties go
+/// to hnyfuck. Punctuation attached to a word, as in `Year, Happy...`, is
+/// read as prose rather than brainfuck.
+pub fn detect_language(source: &str) -> (Language, bool) {
+    for language in Language::DIALECTS {
+        let frontend = language.frontend().expect("dialects have frontends");
+        if frontend.recognizes(source) {
+            return (language, false);
+        }
+    }
+    let pairs = lexer::tokens(source, true).count() / 2;
+    let symbols = source[lexer::shebang_len(source)..]
+        .split_whitespace()
+        .map(|word| {
+            let prose = word.chars().any(char::is_alphanumeric);
+            word.chars()
+                .filter(|&c| Instruction::from_symbol(c).is_some())
+                .filter(|c| !prose || !matches!(c, ',' | '.' | '-' | '+'))
+                .count()
+        })
+        .sum::<usize>();
+    let (language, more, fewer) = match symbols > pairs {
+        true => (Language::Bf, symbols, pairs),
+        false => (Language::Hny, pairs, symbols),
+    };
+    (language, fewer > 0 && fewer * 4 > more)
+}
+
+/// Rewrites `source`, written in `from`, in `to`, by way of word pairs.
+pub fn convert(source: &str, from: Language, to: Language) -> Result<Conversion, ConvertError> {
+    let pairs = match from {
+        Language::Hny => None,
+        Language::Bf => Some(brainfuck_to_hny(source)),
+        dialect => Some(
+            dialect
+                .frontend()
+                .expect("dialects have frontends")
+                .to_hny(source)?,
+        ),
+    };
+    let code = pairs.as_ref().map_or(source, |pairs| &pairs.output);
+    let converted = match to {
+        Language::Hny => None,
+        Language::Bf => Some(hny_to_brainfuck(code)?),
+        dialect => Some(
+            dialect
+                .frontend()
+                .expect("dialects have frontends")
+                .from_hny(code)?,
+        ),
+    };
+    Ok(match (pairs, converted) {
+        (Some(pairs), Some(converted)) => Conversion {
+            source_map: pairs.source_map.then(converted.source_map),
+            output: converted.output,
+        },
+        (Some(conversion), None) | (None, Some(conversion)) => conversion,
+        (None, None) => Conversion {
+            output: source.to_string(),
+            source_map: SourceMap::default(),
+        },
+    })
+}
+
+/// Rewrites brainfuck as word pairs separated by single spaces. Characters
+/// other than the eight commands are comments and are dropped, as is a
+/// leading `#!` line.
+pub fn brainfuck_to_hny(code: &str) -> Conversion {
+    let mut output = String::new();
+    let mut mappings = Vec::new();
+    let start = lexer::shebang_len(code);
+    for (offset, c) in code[start..].char_indices() {
+        let offset = start + offset;
+        let Some(instruction) = Instruction::from_symbol(c) else {
+            continue;
+        };
+        if !output.is_empty() {
+            output.push(' ');
+        }
+        let (first, second) = instruction.pair();
+        let start = output.len();
+        output.push_str(first);
+        output.push(' ');
+        output.push_str(second);
+        mappings.push(Mapping {
+            generated: start..output.len(),
+            original: offset..offset + 1,
+        });
+    }
+
+    Conversion {
+        output,
+        source_map: SourceMap {
+            source: None,
+            mappings,
+        },
+    }
+}
+
+/// Rewrites word pairs as brainfuck. A trailing unpaired word is dropped.
+pub fn hny_to_brainfuck(code: &str) -> Result<Conversion, ConvertError> {
+    let mut output = String::new();
+    let mut mappings = Vec::new();
+    let mut tokens = lexer::tokens(code, false).map(|token| (token.text, token.span));
+    while let (Some((first, first_span)), Some((second, second_span))) =
+        (tokens.next(), tokens.next())
+    {
+        let span = first_span.start..second_span.end;
+        let instruction = Instruction::from_pair((first, second))
+            .ok_or(ConvertError::InvalidToken { span: span.clone() })?;
+        mappings.push(Mapping {
+            generated: output.len()..output.len() + 1,
+            original: span,
+        });
+        output.push(instruction.symbol());
+    }
+
+    Ok(Conversion {
+        output,
+        source_map: SourceMap {
+            source: None,
+            mappings,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::Program;
+
+    fn brainfuck() -> impl Strategy<Value = String> {
+        proptest::collection::vec(proptest::sample::select(&Instruction::ALL[..]), 0..200)
+            .prop_map(|instructions| instructions.into_iter().map(Instruction::symbol).collect())
+    }
+
+    fn hny() -> impl Strategy<Value = String> {
+        let separator = proptest::sample::select(&[" ", "  ", "\n", "\t", " \n  "][..]);
+        proptest::collection::vec(
+            (
+                proptest::sample::select(&Instruction::ALL[..]),
+                separator.clone(),
+                separator,
+            ),
+            0..200,
+        )
+        .prop_map(|pairs| {
+            pairs
+                .into_iter()
+                .map(|(instruction, inner, outer)| {
+                    let (first, second) = instruction.pair();
+                    format!("{}{}{}{}", first, inner, second, outer)
+                })
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn brainfuck_round_trip(code in brainfuck()) {
+            let hny = brainfuck_to_hny(&code).output;
+            prop_assert_eq!(hny_to_brainfuck(&hny).unwrap().output, code);
+        }
+
+        #[test]
+        fn hny_round_trip(code in hny()) {
+            let bf = hny_to_brainfuck(&code).unwrap().output;
+            let round_trip = brainfuck_to_hny(&bf).output;
+            prop_assert!(code.split_whitespace().eq(round_trip.split_whitespace()));
+            prop_assert_eq!(Program::from_str(&round_trip), Program::from_str(&code));
+        }
+    }
+
+    #[test]
+    fn test_brainfuck_to_hny() {
+        let conversion = brainfuck_to_hny("+ comment [-]");
+        assert_eq!(
+            conversion.output,
+            "Year Happy Happy Happy Happy Year New New"
+        );
+        assert_eq!(conversion.source_map.lookup(13), Some(10..11));
+        assert_eq!(conversion.source_map.lookup(10), None);
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language("++[>+<-]"), (Language::Bf, false));
+        assert_eq!(
+            detect_language("#!/usr/bin/env -S hnyfuck --lenient\nYear Happy Year New"),
+            (Language::Hny, false)
+        );
+        assert_eq!(
+            detect_language("Happy New Year, friends. Year New"),
+            (Language::Hny, false)
+        );
+        assert_eq!(
+            detect_language("Year, Happy... Year, New."),
+            (Language::Hny, false)
+        );
+        assert_eq!(
+            detect_language("Happy New Year [friends] Year New"),
+            (Language::Hny, true)
+        );
+        assert_eq!(detect_language(""), (Language::Hny, false));
+        assert_eq!(
+            detect_language("Ook. Ook? Ook! Ook."),
+            (Language::Ook, false)
+        );
+        assert_eq!(detect_language("🎊🎉🎊🎆"), (Language::Emoji, false));
+        assert_eq!(
+            brainfuck_to_hny("#!hnyfuck --lang bf\n+").output,
+            "Year Happy"
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let conversion = convert("+ [-]", Language::Bf, Language::Ook).unwrap();
+        assert_eq!(conversion.output, "Ook. Ook. Ook! Ook? Ook! Ook! Ook? Ook!");
+        assert_eq!(conversion.source_map.lookup(30), Some(4..5));
+        let back = convert(&conversion.output, Language::Ook, Language::Bf).unwrap();
+        assert_eq!(back.output, "+[-]");
+    }
+
+    #[test]
+    fn test_hny_to_brainfuck() {
+        let conversion = hny_to_brainfuck("Year Happy\n  Happy Happy Happy Year New New").unwrap();
+        assert_eq!(conversion.output, "+[-]");
+        assert_eq!(conversion.source_map.lookup(1), Some(13..24));
+        assert_eq!(
+            hny_to_brainfuck("Year Happy Year Year"),
+            Err(ConvertError::InvalidToken { span: 11..20 })
+        );
+    }
+}
@@ -0,0 +1,563 @@
+//! A [Debug Adapter Protocol](https://microsoft.github.io/debug-adapter-protocol/)
+//! server, so VS Code and other editors can debug programs: breakpoints on
+//! word pairs, stepping one instruction at a time and the tape shown as
+//! variables.
+//!
+//! The `launch` request takes the `program` path and optionally `lenient`,
+//! `stopOnEntry` and `input`, a string the program reads in place of stdin,
+//! which carries the protocol.
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    ops::ControlFlow,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, Mutex,
+    },
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    ir::Program,
+    lexer,
+    middleware::{Middleware, Next},
+    HnyFuck, Instruction, State,
+};
+
+/// The only thread, as far as clients are concerned.
+const THREAD: u64 = 1;
+/// `variablesReference` of the tape.
+const TAPE: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchArguments {
+    program: PathBuf,
+    #[serde(default)]
+    lenient: bool,
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    stop_on_entry: bool,
+}
+
+/// A launched program.
+struct Debuggee {
+    path: PathBuf,
+    source: String,
+    lenient: bool,
+    input: String,
+    /// Line and column of each word pair.
+    positions: Vec<(usize, usize)>,
+}
+
+/// What the client asked for.
+enum Action {
+    /// Nothing that changes whether the program runs.
+    Wait,
+    /// Start the launched program.
+    Start,
+    Continue,
+    Step,
+    Pause,
+    Stop,
+}
+
+struct Adapter {
+    replies: Box<dyn Write + Send>,
+    seq: u64,
+    messages: Receiver<Value>,
+    debuggee: Option<Debuggee>,
+    breakpoints: HashSet<usize>,
+    /// Why to stop before the next instruction, if asked to.
+    pause: Option<&'static str>,
+    stepping: bool,
+    /// The word pair and instruction the program is stopped at.
+    stopped: Option<(usize, Instruction)>,
+    /// Output not yet sent.
+    output: Vec<u8>,
+}
+
+impl Adapter {
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(
+            self.replies,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .ok();
+        self.replies.flush().ok();
+    }
+
+    fn respond(&mut self, request: &Value, body: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({"type": "event", "event": event, "body": body}));
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.output.push(byte);
+        if byte == b'\n' {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.output.is_empty() {
+            let output = String::from_utf8_lossy(&std::mem::take(&mut self.output)).into_owned();
+            self.event("output", json!({"category": "stdout", "output": output}));
+        }
+    }
+
+    /// Answers `request`, given the tape if the program is running.
+    fn request(&mut self, request: &Value, state: Option<&State>) -> Action {
+        let arguments = &request["arguments"];
+        let (body, action) = match request["command"].as_str().unwrap_or_default() {
+            "initialize" => (
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsTerminateRequest": true,
+                })),
+                Action::Wait,
+            ),
+            "launch" => (self.launch(arguments), Action::Wait),
+            "setBreakpoints" => (Ok(self.set_breakpoints(arguments)), Action::Wait),
+            "setExceptionBreakpoints" => (Ok(json!({})), Action::Wait),
+            "configurationDone" => match (&self.debuggee, state) {
+                (Some(_), None) => (Ok(json!({})), Action::Start),
+                (None, _) => (
+                    Err("no program has been launched".to_string()),
+                    Action::Wait,
+                ),
+                (Some(_), Some(_)) => (Ok(json!({})), Action::Wait),
+            },
+            "threads" => (
+                Ok(json!({"threads": [{"id": THREAD, "name": "main"}]})),
+                Action::Wait,
+            ),
+            "stackTrace" => (Ok(self.stack_trace()), Action::Wait),
+            "scopes" => (
+                Ok(json!({"scopes": [{
+                    "name": "Tape",
+                    "variablesReference": TAPE,
+                    "expensive": false,
+                }]})),
+                Action::Wait,
+            ),
+            "variables" => (Ok(variables(state)), Action::Wait),
+            "continue" | "next" | "stepIn" | "stepOut" if self.stopped.is_none() => {
+                (Err("the program is not stopped".to_string()), Action::Wait)
+            }
+            "continue" => (Ok(json!({"allThreadsContinued": true})), Action::Continue),
+            "next" | "stepIn" | "stepOut" => (Ok(json!({})), Action::Step),
+            "pause" => (Ok(json!({})), Action::Pause),
+            "disconnect" | "terminate" => (Ok(json!({})), Action::Stop),
+            command => (
+                Err(format!("unsupported request `{}`", command)),
+                Action::Wait,
+            ),
+        };
+        self.respond(request, body);
+        action
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        if self.debuggee.is_some() {
+            return Err("a program has already been launched".to_string());
+        }
+        let arguments = LaunchArguments::deserialize(arguments).map_err(|e| e.to_string())?;
+        let path = &arguments.program;
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+        Program::parse(&source, arguments.lenient)
+            .map_err(|e| format!("Error compiling {}: {}", path.display(), e))?;
+        self.debuggee = Some(Debuggee {
+            path: path.canonicalize().unwrap_or_else(|_| path.clone()),
+            positions: lexer::pair_positions(&source, arguments.lenient),
+            source,
+            lenient: arguments.lenient,
+            input: arguments.input,
+        });
+        if arguments.stop_on_entry {
+            self.pause = Some("entry");
+        }
+        // Breakpoints are only set once the client hears the program has
+        // loaded, so they can be matched to its word pairs.
+        self.event("initialized", json!({}));
+        Ok(json!({}))
+    }
+
+    /// Replaces the breakpoints with those in `arguments`, putting each on
+    /// the word pair at its line and column, or the first on its line.
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let requested = arguments["breakpoints"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let path = arguments["source"]["path"].as_str().map(PathBuf::from);
+        let debuggee = self.debuggee.as_ref().filter(|debuggee| {
+            path.is_none_or(|path| path.canonicalize().unwrap_or(path) == debuggee.path)
+        });
+        self.breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in &requested {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let column = breakpoint["column"].as_u64().map(|column| column as usize);
+            let pair = debuggee.and_then(|debuggee| pair_at(&debuggee.positions, line, column));
+            breakpoints.push(match (pair, debuggee) {
+                (Some(pair), Some(debuggee)) => {
+                    self.breakpoints.insert(pair);
+                    let (line, column) = debuggee.positions[pair];
+                    json!({"verified": true, "line": line, "column": column})
+                }
+                _ => json!({"verified": false, "line": line}),
+            });
+        }
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(&self) -> Value {
+        let frames = match (&self.debuggee, self.stopped) {
+            (Some(debuggee), Some((pair, instruction))) => {
+                let (line, column) = debuggee.positions.get(pair).copied().unwrap_or((0, 0));
+                let (first, second) = instruction.pair();
+                vec![json!({
+                    "id": 1,
+                    "name": format!("{} {} ({})", first, second, instruction.symbol()),
+                    "source": {"path": debuggee.path},
+                    "line": line,
+                    "column": column,
+                })]
+            }
+            _ => Vec::new(),
+        };
+        json!({"stackFrames": frames, "totalFrames": frames.len()})
+    }
+
+    /// Why to stop before running word pair `pair`, if at all.
+    fn reason(&mut self, pair: usize) -> Option<&'static str> {
+        match self.pause.take() {
+            Some(reason) => Some(reason),
+            None if std::mem::take(&mut self.stepping) => Some("step"),
+            None if self.breakpoints.contains(&pair) => Some("breakpoint"),
+            None => None,
+        }
+    }
+
+    /// Stops the program if it is at a breakpoint or was asked to stop, or
+    /// else answers requests that came in while it ran, and answers requests
+    /// until it may go on.
+    fn check(&mut self, instruction: Instruction, pair: usize, state: &State) -> ControlFlow<()> {
+        let mut reason = self.reason(pair);
+        let reason = loop {
+            if let Some(reason) = reason {
+                break reason;
+            }
+            match self.messages.try_recv() {
+                Ok(request) => match self.request(&request, Some(state)) {
+                    Action::Pause => reason = Some("pause"),
+                    Action::Stop => return ControlFlow::Break(()),
+                    _ => {}
+                },
+                Err(TryRecvError::Empty) => return ControlFlow::Continue(()),
+                Err(TryRecvError::Disconnected) => return ControlFlow::Break(()),
+            }
+        };
+
+        self.stopped = Some((pair, instruction));
+        self.flush();
+        self.event(
+            "stopped",
+            json!({"reason": reason, "threadId": THREAD, "allThreadsStopped": true}),
+        );
+        let flow = loop {
+            let Ok(request) = self.messages.recv() else {
+                break ControlFlow::Break(());
+            };
+            match self.request(&request, Some(state)) {
+                Action::Continue => break ControlFlow::Continue(()),
+                Action::Step => {
+                    self.stepping = true;
+                    break ControlFlow::Continue(());
+                }
+                Action::Stop => break ControlFlow::Break(()),
+                _ => {}
+            }
+        };
+        self.stopped = None;
+        flow
+    }
+}
+
+/// The word pair at `column` of `line`, or the first on `line`.
+fn pair_at(positions: &[(usize, usize)], line: usize, column: Option<usize>) -> Option<usize> {
+    let on_line = || (0..positions.len()).filter(move |&pair| positions[pair].0 == line);
+    let containing = column.and_then(|column| {
+        on_line()
+            .take_while(|&pair| positions[pair].1 <= column)
+            .last()
+    });
+    containing.or_else(|| on_line().next())
+}
+
+fn variables(state: Option<&State>) -> Value {
+    let Some(state) = state else {
+        return json!({"variables": []});
+    };
+    let origin = state.origin() as isize;
+    let mut variables = vec![json!({
+        "name": "pointer",
+        "value": (state.pointer() as isize - origin).to_string(),
+        "variablesReference": 0,
+    })];
+    variables.extend(state.tape().iter().enumerate().map(|(i, cell)| {
+        json!({
+            "name": format!("[{}]", i as isize - origin),
+            "value": cell.to_string(),
+            "variablesReference": 0,
+        })
+    }));
+    json!({ "variables": variables })
+}
+
+/// Reads one message, skipping any that aren't JSON, or returns `None` at the
+/// end of the stream.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+        if let Ok(message) = serde_json::from_slice(&body) {
+            return Some(message);
+        }
+    }
+}
+
+struct Handle(Arc<Mutex<Adapter>>);
+
+impl Middleware for Handle {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .check(instruction, next.pair(), next.state())?;
+        next.run(instruction)
+    }
+}
+
+/// Serves a client sending requests on `requests` and reading responses and
+/// events from `replies`, until it disconnects.
+pub fn serve(requests: impl Read + Send + 'static, replies: impl Write + Send + 'static) {
+    let (sender, messages) = mpsc::channel();
+    // Requests are read on their own thread so the running program can be
+    // paused.
+    std::thread::spawn(move || {
+        let mut requests = BufReader::new(requests);
+        while let Some(message) = read_message(&mut requests) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    let adapter = Arc::new(Mutex::new(Adapter {
+        replies: Box::new(replies),
+        seq: 0,
+        messages,
+        debuggee: None,
+        breakpoints: HashSet::new(),
+        pause: None,
+        stepping: false,
+        stopped: None,
+        output: Vec::new(),
+    }));
+
+    loop {
+        let Ok(request) = adapter.lock().unwrap().messages.recv() else {
+            return;
+        };
+        let action = adapter.lock().unwrap().request(&request, None);
+        match action {
+            Action::Stop => return,
+            Action::Start if run(&adapter).is_break() => return,
+            _ => {}
+        }
+    }
+}
+
+/// Runs the launched program, breaking if the client stopped it.
+fn run(adapter: &Arc<Mutex<Adapter>>) -> ControlFlow<()> {
+    let mut hny = {
+        let adapter = adapter.lock().unwrap();
+        let Some(debuggee) = &adapter.debuggee else {
+            return ControlFlow::Continue(());
+        };
+        let mut hny = HnyFuck::parse(&debuggee.source, debuggee.lenient);
+        hny.set_input(Cursor::new(debuggee.input.clone().into_bytes()));
+        hny
+    };
+    let output = adapter.clone();
+    hny.add_output_transform(move |byte| {
+        output.lock().unwrap().write(byte);
+        None
+    });
+    hny.add_middleware(Handle(adapter.clone()));
+    hny.run();
+
+    if hny.halted {
+        return ControlFlow::Break(());
+    }
+    let mut adapter = adapter.lock().unwrap();
+    adapter.flush();
+    adapter.event("exited", json!({"exitCode": 0}));
+    adapter.event("terminated", json!({}));
+    ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    fn send(to: &mut impl Write, seq: u64, command: &str, arguments: Value) {
+        let body =
+            json!({"seq": seq, "type": "request", "command": command, "arguments": arguments})
+                .to_string();
+        write!(to, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+
+    /// A message summed up by its event and reason or its command.
+    fn summary(message: &Value) -> String {
+        match message["type"].as_str().unwrap() {
+            "event" => format!(
+                "{} {}",
+                message["event"].as_str().unwrap(),
+                message["body"]["reason"].as_str().unwrap_or_default()
+            ),
+            _ => message["command"].as_str().unwrap().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_dap() {
+        let code = brainfuck_to_hny("+>++.").output;
+        let path = std::env::temp_dir().join(format!("hnyfuck-dap-{}.hny", std::process::id()));
+        std::fs::write(&path, &code).unwrap();
+        let (line, column) = lexer::pair_positions(&code, false)[3];
+
+        let (requests, mut to_server) = io::pipe().unwrap();
+        let (from_server, replies) = io::pipe().unwrap();
+        let server = std::thread::spawn(move || serve(requests, replies));
+        let breakpoints = json!({
+            "source": {"path": path},
+            "breakpoints": [{"line": line, "column": column + 1}],
+        });
+        send(
+            &mut to_server,
+            1,
+            "initialize",
+            json!({"adapterID": "hnyfuck"}),
+        );
+        send(
+            &mut to_server,
+            2,
+            "launch",
+            json!({"program": path, "stopOnEntry": true}),
+        );
+        send(&mut to_server, 3, "setBreakpoints", breakpoints);
+        send(&mut to_server, 4, "configurationDone", Value::Null);
+        send(&mut to_server, 5, "next", json!({"threadId": THREAD}));
+        send(&mut to_server, 6, "continue", json!({"threadId": THREAD}));
+
+        // Reads messages up to and including the one summed up as `last`.
+        let mut from_server = BufReader::new(from_server);
+        let mut messages = Vec::new();
+        let mut read_until = |last: &str| {
+            while messages.last().map(summary).as_deref() != Some(last) {
+                messages.push(read_message(&mut from_server).unwrap());
+            }
+        };
+        read_until("stopped breakpoint");
+        send(
+            &mut to_server,
+            7,
+            "variables",
+            json!({"variablesReference": TAPE}),
+        );
+        send(&mut to_server, 8, "continue", json!({"threadId": THREAD}));
+        read_until("terminated ");
+        send(&mut to_server, 9, "disconnect", Value::Null);
+        read_until("disconnect");
+        server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            messages.iter().map(summary).collect::<Vec<_>>(),
+            [
+                "initialize",
+                "initialized ",
+                "launch",
+                "setBreakpoints",
+                "configurationDone",
+                "stopped entry",
+                "next",
+                "stopped step",
+                "continue",
+                "stopped breakpoint",
+                "variables",
+                "continue",
+                "output ",
+                "exited ",
+                "terminated ",
+                "disconnect",
+            ]
+        );
+        assert_eq!(
+            messages[3]["body"]["breakpoints"][0],
+            json!({"verified": true, "line": line, "column": column})
+        );
+        let variables = &messages[10]["body"]["variables"];
+        assert_eq!(variables[0]["value"], "1");
+        assert_eq!(variables[2]["value"], "1");
+        assert_eq!(messages[12]["body"]["output"], "\u{2}");
+    }
+}
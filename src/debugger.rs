@@ -0,0 +1,709 @@
+//! An interactive debugger, run as [middleware](crate::middleware) so it sees
+//! every instruction before it executes.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Write},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    lexer,
+    middleware::{Middleware, Next},
+    Instruction, State,
+};
+
+/// How many cells either side of the pointer `tape` shows.
+const TAPE_WINDOW: usize = 8;
+
+const HELP: &str = "\
+commands:
+  s, step [N]   run N instructions (default 1), then pause again
+  c, continue   run until interrupted or a breakpoint
+  b, break COND pause when COND becomes true, e.g. `cell[3] == 10`;
+                without COND, list the breakpoints
+  tp, trace COND[, MSG]
+                print MSG when COND becomes true and carry on, with
+                operands in braces filled in, e.g. `{cell[1]}`;
+                without COND, list the tracepoints
+  d, delete N   remove breakpoint or tracepoint N
+  t, tape       show the cells around the pointer
+  q, quit       stop the program
+  h, help       show this list
+an empty line repeats `step`
+";
+
+/// A condition on the program's state, like `cell[3] == 10` or
+/// `pointer > 100`: two operands compared with `==`, `!=`, `<`, `<=`, `>` or
+/// `>=`. An operand is a number, `cell` for the current cell, `cell[N]` for
+/// the cell N from the one the program started on, `pointer` for how far the
+/// pointer is from there, `steps` for the instructions run so far or `index`
+/// for the word pair about to run, counting from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    left: Operand,
+    comparison: Comparison,
+    right: Operand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Number(i64),
+    Current,
+    Cell(isize),
+    Pointer,
+    Steps,
+    Index,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Longest first, so `<=` isn't read as `<`.
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    ("<=", Comparison::Le),
+    (">=", Comparison::Ge),
+    ("<", Comparison::Lt),
+    (">", Comparison::Gt),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionError {
+    /// No comparison between two operands.
+    NoComparison,
+    /// An operand that isn't a number, `cell`, `cell[N]`, `pointer`,
+    /// `steps` or `index`.
+    UnknownOperand(String),
+    /// A `{` in a tracepoint's message without a `}` after it.
+    UnclosedBrace,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConditionError::NoComparison => {
+                write!(f, "a condition compares two operands, like `cell[3] == 10`")
+            }
+            ConditionError::UnknownOperand(operand) => write!(
+                f,
+                "`{}` isn't a number, `cell`, `cell[N]`, `pointer`, `steps` or `index`",
+                operand
+            ),
+            ConditionError::UnclosedBrace => write!(f, "a `{{` in the message isn't closed"),
+        }
+    }
+}
+
+impl Error for ConditionError {}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, ConditionError> {
+        let (at, symbol, comparison) = COMPARISONS
+            .iter()
+            .filter_map(|&(symbol, comparison)| Some((text.find(symbol)?, symbol, comparison)))
+            .min_by_key(|&(at, symbol, _)| (at, usize::MAX - symbol.len()))
+            .ok_or(ConditionError::NoComparison)?;
+        Ok(Condition {
+            left: Operand::parse(&text[..at])?,
+            comparison,
+            right: Operand::parse(&text[at + symbol.len()..])?,
+        })
+    }
+
+    /// Whether the condition holds of `state` after `steps` instructions,
+    /// with the word pair numbered `index` to run next.
+    pub fn holds(&self, state: &State, steps: u64, index: usize) -> bool {
+        let (left, right) = (
+            self.left.value(state, steps, index),
+            self.right.value(state, steps, index),
+        );
+        match self.comparison {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = COMPARISONS
+            .iter()
+            .find(|&&(_, comparison)| comparison == self.comparison)
+            .map_or("", |&(symbol, _)| symbol);
+        write!(f, "{} {} {}", self.left, symbol, self.right)
+    }
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Operand, ConditionError> {
+        let text = text.trim();
+        let unknown = || ConditionError::UnknownOperand(text.to_string());
+        Ok(match text {
+            "cell" => Operand::Current,
+            "pointer" => Operand::Pointer,
+            "steps" => Operand::Steps,
+            "index" => Operand::Index,
+            _ => match text
+                .strip_prefix("cell[")
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                Some(index) => Operand::Cell(index.trim().parse().map_err(|_| unknown())?),
+                None => Operand::Number(text.parse().map_err(|_| unknown())?),
+            },
+        })
+    }
+
+    fn value(self, state: &State, steps: u64, index: usize) -> i64 {
+        let cell = |index: usize| state.tape().get(index).copied().unwrap_or(0) as i64;
+        let origin = state.origin() as isize;
+        match self {
+            Operand::Number(n) => n,
+            Operand::Current => cell(state.pointer()),
+            // Cells the program hasn't reached yet are blank.
+            Operand::Cell(n) => usize::try_from(origin + n).map_or(0, cell),
+            Operand::Pointer => (state.pointer() as isize - origin) as i64,
+            Operand::Steps => steps.min(i64::MAX as u64) as i64,
+            Operand::Index => index as i64,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Number(n) => write!(f, "{}", n),
+            Operand::Current => write!(f, "cell"),
+            Operand::Cell(n) => write!(f, "cell[{}]", n),
+            Operand::Pointer => write!(f, "pointer"),
+            Operand::Steps => write!(f, "steps"),
+            Operand::Index => write!(f, "index"),
+        }
+    }
+}
+
+/// A condition with a message to print when it becomes true, written
+/// `CONDITION, MESSAGE`. Operands in braces in the message, like
+/// `{cell[1]}`, are filled in with their values. Without a message the
+/// index, steps, pointer and current cell are printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tracepoint {
+    condition: Condition,
+    message: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Operand(Operand),
+}
+
+impl Tracepoint {
+    pub fn parse(text: &str) -> Result<Tracepoint, ConditionError> {
+        let (condition, mut rest) = text.split_once(',').unwrap_or((text, ""));
+        rest = rest.trim();
+        if rest.is_empty() {
+            rest = "index {index}, steps {steps}, pointer {pointer}, cell {cell}";
+        }
+        let mut message = Vec::new();
+        while let Some((text, operand)) = rest.split_once('{') {
+            let (operand, after) = operand
+                .split_once('}')
+                .ok_or(ConditionError::UnclosedBrace)?;
+            message.push(Piece::Text(text.to_string()));
+            message.push(Piece::Operand(Operand::parse(operand)?));
+            rest = after;
+        }
+        message.push(Piece::Text(rest.to_string()));
+        message.retain(|piece| *piece != Piece::Text(String::new()));
+        Ok(Tracepoint {
+            condition: Condition::parse(condition)?,
+            message,
+        })
+    }
+
+    /// The message with the operands in it filled in.
+    pub fn message(&self, state: &State, steps: u64, index: usize) -> String {
+        self.message
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => text.clone(),
+                Piece::Operand(operand) => operand.value(state, steps, index).to_string(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Tracepoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, ", self.condition)?;
+        for piece in &self.message {
+            match piece {
+                Piece::Text(text) => write!(f, "{}", text)?,
+                Piece::Operand(operand) => write!(f, "{{{}}}", operand)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A breakpoint or tracepoint and whether its condition held before the last
+/// instruction, as it's only hit when the condition becomes true.
+struct Breakpoint {
+    number: usize,
+    point: Point,
+    held: bool,
+}
+
+enum Point {
+    Break(Condition),
+    Trace(Tracepoint),
+}
+
+impl Point {
+    fn condition(&self) -> &Condition {
+        match self {
+            Point::Break(condition) => condition,
+            Point::Trace(tracepoint) => &tracepoint.condition,
+        }
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Point::Break(condition) => write!(f, "{}", condition),
+            Point::Trace(tracepoint) => write!(f, "trace {}", tracepoint),
+        }
+    }
+}
+
+/// Pauses the program when asked to and reads commands until told to go on.
+/// Commands are read from stdin and replies written to stderr unless other
+/// streams are given with [`Debugger::with_io`].
+pub struct Debugger {
+    /// Line and column of each word pair.
+    positions: Vec<(usize, usize)>,
+    interrupt: Arc<AtomicBool>,
+    /// Instructions left to run before pausing, if stepping.
+    remaining: Option<u64>,
+    steps: u64,
+    breakpoints: Vec<Breakpoint>,
+    /// The number the next breakpoint set gets.
+    next_breakpoint: usize,
+    commands: Box<dyn BufRead + Send>,
+    replies: Box<dyn Write + Send>,
+}
+
+impl Debugger {
+    /// A debugger for `source`, which is parsed the same way the interpreter
+    /// running it parses it.
+    pub fn new(source: &str, lenient: bool) -> Debugger {
+        Debugger {
+            positions: lexer::pair_positions(source, lenient),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            remaining: None,
+            steps: 0,
+            breakpoints: Vec::new(),
+            next_breakpoint: 1,
+            commands: Box::new(io::BufReader::new(io::stdin())),
+            replies: Box::new(io::stderr()),
+        }
+    }
+
+    /// Reads commands from `commands` and writes replies to `replies`.
+    pub fn with_io(
+        mut self,
+        commands: impl BufRead + Send + 'static,
+        replies: impl Write + Send + 'static,
+    ) -> Debugger {
+        self.commands = Box::new(commands);
+        self.replies = Box::new(replies);
+        self
+    }
+
+    /// Pauses before the first instruction.
+    pub fn pause_at_start(mut self) -> Debugger {
+        self.remaining = Some(0);
+        self
+    }
+
+    /// Pauses whenever `condition` becomes true, as the `break` command does.
+    pub fn with_breakpoint(mut self, condition: Condition) -> Debugger {
+        self.add_breakpoint(Point::Break(condition), false);
+        self
+    }
+
+    /// Prints a message whenever the tracepoint's condition becomes true,
+    /// as the `trace` command does, without pausing.
+    pub fn with_tracepoint(mut self, tracepoint: Tracepoint) -> Debugger {
+        self.add_breakpoint(Point::Trace(tracepoint), false);
+        self
+    }
+
+    fn add_breakpoint(&mut self, point: Point, held: bool) {
+        self.breakpoints.push(Breakpoint {
+            number: self.next_breakpoint,
+            point,
+            held,
+        });
+        self.next_breakpoint += 1;
+    }
+
+    /// A flag that pauses the program before its next instruction when set,
+    /// for example from a signal handler. The debugger clears it on pausing.
+    pub fn interrupt(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    fn paused(&mut self, state: &State, pair: usize) -> bool {
+        // Every condition is checked every step, so each knows whether it
+        // held before, however the program was paused.
+        let mut hit = false;
+        for breakpoint in &mut self.breakpoints {
+            let held = breakpoint.point.condition().holds(state, self.steps, pair);
+            if held && !breakpoint.held {
+                match &breakpoint.point {
+                    Point::Break(condition) => {
+                        hit = true;
+                        writeln!(
+                            self.replies,
+                            "breakpoint {}: {}",
+                            breakpoint.number, condition
+                        )
+                    }
+                    Point::Trace(tracepoint) => writeln!(
+                        self.replies,
+                        "trace {}: {}",
+                        breakpoint.number,
+                        tracepoint.message(state, self.steps, pair)
+                    ),
+                }
+                .ok();
+            }
+            breakpoint.held = held;
+        }
+        if self.interrupt.swap(false, Ordering::Relaxed) || hit {
+            return true;
+        }
+        match &mut self.remaining {
+            Some(0) => true,
+            Some(n) => {
+                *n -= 1;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Reads commands until one resumes the program.
+    fn prompt(&mut self, instruction: Instruction, pair: usize, state: &State) -> ControlFlow<()> {
+        let (first, second) = instruction.pair();
+        let (line, column) = self.positions.get(pair).copied().unwrap_or((0, 0));
+        writeln!(
+            self.replies,
+            "paused at {}:{} after {} steps: {} {} ({})",
+            line,
+            column,
+            self.steps,
+            first,
+            second,
+            instruction.symbol()
+        )
+        .ok();
+        loop {
+            write!(self.replies, "(hnyfuck) ").ok();
+            self.replies.flush().ok();
+            let mut command = String::new();
+            if self.commands.read_line(&mut command).unwrap_or(0) == 0 {
+                // Nothing more to read, so let the program finish.
+                writeln!(self.replies).ok();
+                self.remaining = None;
+                return ControlFlow::Continue(());
+            }
+            let mut words = command.split_whitespace();
+            match (words.next(), words.next()) {
+                (None | Some("s" | "step"), n) => match n.map_or(Ok(1), str::parse::<u64>) {
+                    Ok(n) if n > 0 => {
+                        self.remaining = Some(n - 1);
+                        return ControlFlow::Continue(());
+                    }
+                    _ => writeln!(self.replies, "step takes a positive count").ok(),
+                },
+                (Some("c" | "continue"), _) => {
+                    self.remaining = None;
+                    return ControlFlow::Continue(());
+                }
+                (Some("t" | "tape"), _) => self.show_tape(state),
+                (Some("b" | "break" | "tp" | "trace"), None) => self.list_breakpoints(),
+                (Some(kind @ ("b" | "break" | "tp" | "trace")), Some(_)) => {
+                    let (_, text) = command
+                        .trim()
+                        .split_once(char::is_whitespace)
+                        .unwrap_or_default();
+                    let point = match kind {
+                        "b" | "break" => Condition::parse(text).map(Point::Break),
+                        _ => Tracepoint::parse(text).map(Point::Trace),
+                    };
+                    match point {
+                        Ok(point) => {
+                            // A condition already true is only hit once it
+                            // turns true again.
+                            let held = point.condition().holds(state, self.steps, pair);
+                            let reply = match &point {
+                                Point::Break(condition) => {
+                                    format!("breakpoint {}: {}", self.next_breakpoint, condition)
+                                }
+                                Point::Trace(tracepoint) => {
+                                    format!("tracepoint {}: {}", self.next_breakpoint, tracepoint)
+                                }
+                            };
+                            self.add_breakpoint(point, held);
+                            writeln!(self.replies, "{}", reply).ok()
+                        }
+                        Err(e) => writeln!(self.replies, "{}", e).ok(),
+                    }
+                }
+                (Some("d" | "delete"), n) => {
+                    let before = self.breakpoints.len();
+                    let number = n.and_then(|n| n.parse::<usize>().ok());
+                    self.breakpoints
+                        .retain(|breakpoint| Some(breakpoint.number) != number);
+                    match (number, self.breakpoints.len() < before) {
+                        (_, true) => Some(()),
+                        (Some(number), false) => {
+                            writeln!(self.replies, "no breakpoint or tracepoint {}", number).ok()
+                        }
+                        (None, false) => {
+                            writeln!(self.replies, "delete takes a breakpoint number").ok()
+                        }
+                    }
+                }
+                (Some("q" | "quit"), _) => return ControlFlow::Break(()),
+                (Some("h" | "help"), _) => write!(self.replies, "{}", HELP).ok(),
+                (Some(other), _) => writeln!(
+                    self.replies,
+                    "unknown command `{}`; `help` lists them",
+                    other
+                )
+                .ok(),
+            };
+        }
+    }
+
+    fn list_breakpoints(&mut self) -> Option<()> {
+        if self.breakpoints.is_empty() {
+            return writeln!(self.replies, "no breakpoints").ok();
+        }
+        for breakpoint in &self.breakpoints {
+            writeln!(self.replies, "{}: {}", breakpoint.number, breakpoint.point).ok()?;
+        }
+        Some(())
+    }
+
+    fn show_tape(&mut self, state: &State) -> Option<()> {
+        writeln!(self.replies, "{}", tape_window(state, TAPE_WINDOW)).ok()
+    }
+}
+
+/// The cells within `window` of the pointer, with the pointer's bracketed.
+pub(crate) fn tape_window(state: &State, window: usize) -> String {
+    let (tape, pointer) = (state.tape(), state.pointer());
+    let start = pointer.saturating_sub(window);
+    let end = (pointer + window + 1).min(tape.len());
+    let cells = (start..end)
+        .map(|i| match i == pointer {
+            true => format!("[{}]", tape[i]),
+            false => tape[i].to_string(),
+        })
+        .collect::<Vec<_>>();
+    let before = if start > 0 { "... " } else { "" };
+    let after = if end < tape.len() { " ..." } else { "" };
+    format!("{}{}{}", before, cells.join(" "), after)
+}
+
+impl Middleware for Debugger {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+        if self.paused(next.state(), next.pair()) {
+            self.prompt(instruction, next.pair(), next.state())?;
+        }
+        self.steps += 1;
+        next.run(instruction)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{brainfuck_to_hny, HnyFuck};
+
+    /// A `Write` whose contents can be read after it is boxed.
+    #[derive(Clone, Default)]
+    pub(crate) struct Shared(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_debugger() {
+        let code = brainfuck_to_hny("+++>++[-]").output;
+        let replies = Shared::default();
+        let debugger = Debugger::new(&code, false)
+            .with_io(&b"step 4\ntape\nbogus\n\ntape\nquit\n"[..], replies.clone())
+            .pause_at_start();
+        let mut hny = HnyFuck::from_str(&code);
+        hny.add_middleware(debugger);
+        hny.run();
+
+        let replies = String::from_utf8(replies.0.lock().unwrap().clone()).unwrap();
+        let prompts = replies.lines().collect::<Vec<_>>();
+        assert_eq!(
+            prompts,
+            [
+                "paused at 1:1 after 0 steps: Year Happy (+)",
+                "(hnyfuck) paused at 1:43 after 4 steps: Year Happy (+)",
+                "(hnyfuck) 3 [0]",
+                "(hnyfuck) unknown command `bogus`; `help` lists them",
+                "(hnyfuck) paused at 1:54 after 5 steps: Year Happy (+)",
+                "(hnyfuck) 3 [1]",
+                "(hnyfuck) ",
+            ]
+        );
+        assert_eq!(hny.state.tape(), &[3, 1]);
+    }
+
+    #[test]
+    fn test_condition() {
+        let condition = Condition::parse(" cell[1]>=cell ").unwrap();
+        assert_eq!(condition.to_string(), "cell[1] >= cell");
+        assert_eq!(
+            Condition::parse("pointer <= -2").unwrap().to_string(),
+            "pointer <= -2"
+        );
+        assert_eq!(
+            Condition::parse("cell = 3"),
+            Err(ConditionError::NoComparison)
+        );
+        assert_eq!(
+            Condition::parse("cel[1] == 3"),
+            Err(ConditionError::UnknownOperand("cel[1]".to_string()))
+        );
+
+        // Cells and the pointer count from the cell the program started on.
+        let mut hny = HnyFuck::from_str(&brainfuck_to_hny("<+>>++>").output);
+        hny.run();
+        let holds = |text: &str| Condition::parse(text).unwrap().holds(hny.state(), 5, 9);
+        assert!(holds("cell[-1] == 1") && holds("cell[1] == 2") && holds("cell[0] == 0"));
+        assert!(holds("pointer == 2") && holds("cell == 0") && holds("cell[99] == 0"));
+        assert!(holds("steps > 4") && holds("steps != 4") && !holds("steps < 5"));
+        assert!(holds("cell[1] > cell[-1]") && !holds("cell[-5] != 0"));
+        assert!(holds("index == 9"));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // Cell 1 goes up to 6 and back down to 0, then up to 3.
+        let code = brainfuck_to_hny("++[>+++<-]>[-]+++").output;
+        let replies = Shared::default();
+        let debugger = Debugger::new(&code, false)
+            .with_io(
+                &b"continue\ntape\nbreak cell == 2\nbreak\ncontinue\ndelete 1\ndelete 1\nc\n"[..],
+                replies.clone(),
+            )
+            .with_breakpoint(Condition::parse("cell[1] == 3").unwrap());
+        let mut hny = HnyFuck::from_str(&code);
+        hny.add_middleware(debugger);
+        hny.run();
+
+        let replies = String::from_utf8(replies.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            replies.lines().collect::<Vec<_>>(),
+            [
+                "breakpoint 1: cell[1] == 3",
+                "paused at 1:77 after 7 steps: Happy New (<)",
+                // Only once cell 1 has left 3 and come back.
+                "(hnyfuck) breakpoint 1: cell[1] == 3",
+                "paused at 1:138 after 24 steps: New New (])",
+                "(hnyfuck) 0 [3]",
+                "(hnyfuck) breakpoint 2: cell == 2",
+                "(hnyfuck) 1: cell[1] == 3",
+                "2: cell == 2",
+                "(hnyfuck) breakpoint 2: cell == 2",
+                "paused at 1:138 after 26 steps: New New (])",
+                "(hnyfuck) (hnyfuck) no breakpoint or tracepoint 1",
+                "(hnyfuck) breakpoint 2: cell == 2",
+                "paused at 1:168 after 33 steps: Year Happy (+)",
+                "(hnyfuck) ",
+            ]
+        );
+        assert_eq!(hny.state.tape(), &[0, 3]);
+    }
+
+    #[test]
+    fn test_tracepoints() {
+        let tracepoint = Tracepoint::parse("index == 4 , cell 1 is {cell[1]}").unwrap();
+        assert_eq!(tracepoint.to_string(), "index == 4, cell 1 is {cell[1]}");
+        assert_eq!(
+            Tracepoint::parse("steps > 1").unwrap().to_string(),
+            "steps > 1, index {index}, steps {steps}, pointer {pointer}, cell {cell}"
+        );
+        assert_eq!(
+            Tracepoint::parse("cell == 1, {cell"),
+            Err(ConditionError::UnclosedBrace)
+        );
+        assert_eq!(
+            Tracepoint::parse("cell == 1, {tape}"),
+            Err(ConditionError::UnknownOperand("tape".to_string()))
+        );
+
+        // The `>` starting the loop body is the word pair numbered 4.
+        let code = brainfuck_to_hny("+++[>+<-]").output;
+        let replies = Shared::default();
+        let debugger = Debugger::new(&code, false)
+            .with_io(&b""[..], replies.clone())
+            .with_tracepoint(tracepoint)
+            .with_tracepoint(Tracepoint::parse("cell[1] == 2").unwrap());
+        let mut hny = HnyFuck::from_str(&code);
+        hny.add_middleware(debugger);
+        hny.run();
+
+        let replies = String::from_utf8(replies.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            replies.lines().collect::<Vec<_>>(),
+            [
+                "trace 1: cell 1 is 0",
+                "trace 1: cell 1 is 1",
+                "trace 2: index 6, steps 11, pointer 1, cell 2",
+                "trace 1: cell 1 is 2",
+            ]
+        );
+        assert_eq!(hny.state.tape(), &[0, 3]);
+    }
+}
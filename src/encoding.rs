@@ -0,0 +1,208 @@
+//! Base64 for a program's input and output, so binary data can pass through
+//! shells and JSON without being mangled as text.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Read, Write},
+};
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    DecodeError, Engine,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::transform::Transform;
+
+/// Standard base64, written padded and read with or without padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// How bytes are written as text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    /// As they are.
+    #[default]
+    Raw,
+    /// In base64, ignoring whitespace when reading it.
+    Base64,
+}
+
+impl Encoding {
+    /// The bytes `text` encodes.
+    pub fn decode(self, text: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            Encoding::Raw => Ok(text.to_vec()),
+            Encoding::Base64 => {
+                let text = text
+                    .iter()
+                    .copied()
+                    .filter(|byte| !byte.is_ascii_whitespace())
+                    .collect::<Vec<_>>();
+                BASE64.decode(text)
+            }
+        }
+    }
+
+    /// `bytes` as text. Raw bytes that aren't UTF-8 are replaced.
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Raw => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Base64 => BASE64.encode(bytes),
+        }
+    }
+}
+
+/// Reads the bytes base64 read from the reader it wraps encodes, decoding a
+/// group of four characters at a time so interactive input isn't held up.
+#[derive(Debug)]
+pub struct Base64Decoder<R> {
+    inner: BufReader<R>,
+    /// Characters of a group not yet complete.
+    group: Vec<u8>,
+    decoded: VecDeque<u8>,
+}
+
+impl<R: Read> Base64Decoder<R> {
+    pub fn new(inner: R) -> Self {
+        Base64Decoder {
+            inner: BufReader::new(inner),
+            group: Vec::with_capacity(4),
+            decoded: VecDeque::new(),
+        }
+    }
+
+    fn decode_group(&mut self) -> io::Result<()> {
+        let bytes = BASE64
+            .decode(&self.group)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.decoded.extend(bytes);
+        self.group.clear();
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Base64Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.decoded.is_empty() {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                if self.group.is_empty() {
+                    return Ok(0);
+                }
+                self.decode_group()?;
+                continue;
+            }
+            if byte[0].is_ascii_whitespace() {
+                continue;
+            }
+            self.group.push(byte[0]);
+            if self.group.len() == 4 {
+                self.decode_group()?;
+            }
+        }
+        let n = buf.len().min(self.decoded.len());
+        for (slot, byte) in buf.iter_mut().zip(self.decoded.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+/// Writes bytes to `out` in base64 instead of passing them on, three bytes
+/// at a time. The last, padded group and a newline are written when the
+/// encoder is finished or dropped.
+#[derive(Debug)]
+pub struct Base64Encoder<W: Write> {
+    out: W,
+    group: Vec<u8>,
+    written: bool,
+}
+
+impl<W: Write> Base64Encoder<W> {
+    pub fn new(out: W) -> Self {
+        Base64Encoder {
+            out,
+            group: Vec::with_capacity(3),
+            written: false,
+        }
+    }
+
+    fn write_group(&mut self) -> io::Result<()> {
+        let text = BASE64.encode(&self.group);
+        self.group.clear();
+        self.written = true;
+        self.out.write_all(text.as_bytes())?;
+        self.out.flush()
+    }
+
+    /// Writes out the bytes of a group not yet full, and ends the line.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.group.is_empty() {
+            self.write_group()?;
+        }
+        if std::mem::take(&mut self.written) {
+            self.out.write_all(b"\n")?;
+        }
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Transform for Base64Encoder<W> {
+    fn apply(&mut self, byte: u8) -> Option<u8> {
+        self.group.push(byte);
+        if self.group.len() == 3 {
+            // A failed write costs the output a group, not the program its run.
+            self.write_group().ok();
+        }
+        None
+    }
+}
+
+impl<W: Write> Drop for Base64Encoder<W> {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(Encoding::Base64.encode(b"\x00\xffhi"), "AP9oaQ==");
+        assert_eq!(Encoding::Raw.encode(b"hi\xff"), "hi\u{fffd}");
+        assert_eq!(
+            Encoding::Base64.decode(b"AP9o\naQ\n").unwrap(),
+            b"\x00\xffhi"
+        );
+        assert!(Encoding::Base64.decode(b"A").is_err());
+        assert_eq!(Encoding::Raw.decode(b"AP9o").unwrap(), b"AP9o");
+    }
+
+    #[test]
+    fn test_streaming() {
+        let mut decoded = Vec::new();
+        Base64Decoder::new(&b"AP9o aQ==\nAQ"[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"\x00\xffhi\x01");
+        assert!(Base64Decoder::new(&b"AP9o!"[..])
+            .read_to_end(&mut Vec::new())
+            .is_err());
+
+        let mut encoder = Base64Encoder::new(Vec::new());
+        for &byte in b"\x00\xffhi" {
+            assert_eq!(encoder.apply(byte), None);
+        }
+        assert_eq!(encoder.out, b"AP9o");
+        encoder.finish().unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(encoder.out, b"AP9oaQ==\n");
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+
+use crate::{
+    ir::Program,
+    limits::{self, Exit, Limits},
+};
+
+/// How a program ended on one input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The program halted. `tape` holds the cells from `origin` (the offset
+    /// of the first cell from the starting cell) with zeros trimmed off both
+    /// ends, so tapes that only differ in how far they grew compare equal.
+    Finished {
+        output: Vec<u8>,
+        origin: isize,
+        tape: Vec<u8>,
+    },
+    /// The program was still running after the step limit, having written
+    /// `output` so far.
+    StepLimit { output: Vec<u8> },
+}
+
+/// Runs `program` on `input` without touching stdin or stdout, stopping after
+/// `limit` executed operations. Reading past the end of `input` leaves the cell
+/// unchanged, as the interpreter does at end of file.
+pub fn execute(program: &Program, input: &[u8], limit: u64) -> Outcome {
+    let limits = Limits {
+        steps: limit,
+        ..Limits::NONE
+    };
+    let mut run = limits::execute(program, input, &limits);
+    match run.exit {
+        Exit::Finished => finished(run.output, run.tape.make_contiguous(), run.origin),
+        _ => Outcome::StepLimit { output: run.output },
+    }
+}
+
+/// The outcome of a program that halted leaving `tape`, whose first cell is
+/// `origin` cells from the starting cell.
+pub(crate) fn finished(output: Vec<u8>, tape: &[u8], origin: isize) -> Outcome {
+    let Some(first) = tape.iter().position(|&cell| cell != 0) else {
+        return Outcome::Finished {
+            output,
+            origin: 0,
+            tape: Vec::new(),
+        };
+    };
+    let last = tape.iter().rposition(|&cell| cell != 0).unwrap_or(first);
+    Outcome::Finished {
+        output,
+        origin: origin + first as isize,
+        tape: tape[first..=last].to_vec(),
+    }
+}
+
+/// An input on which two programs behave differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub input: Vec<u8>,
+    pub left: Outcome,
+    pub right: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "programs differ on input \"{}\"",
+            self.input.escape_ascii()
+        )?;
+        writeln!(f, "  first:  {}", describe(&self.left))?;
+        write!(f, "  second: {}", describe(&self.right))
+    }
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Finished {
+            output,
+            origin,
+            tape,
+        } => format!(
+            "output \"{}\", tape {:?} from cell {}",
+            output.escape_ascii(),
+            tape,
+            origin
+        ),
+        Outcome::StepLimit { output } => {
+            format!(
+                "step limit reached after output \"{}\"",
+                output.escape_ascii()
+            )
+        }
+    }
+}
+
+/// The result of comparing two programs over a set of inputs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Inputs tried.
+    pub inputs: usize,
+    /// Inputs on which both programs hit the step limit with the same output,
+    /// so they may still differ later.
+    pub inconclusive: usize,
+    /// The first input the programs disagree on, if any.
+    pub mismatch: Option<Mismatch>,
+}
+
+/// Runs `left` and `right` on every input until they disagree on output or
+/// final tape.
+pub fn check<I>(left: &Program, right: &Program, inputs: I, limit: u64) -> Report
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let mut report = Report::default();
+    for input in inputs {
+        report.inputs += 1;
+        let a = execute(left, &input, limit);
+        let b = execute(right, &input, limit);
+        if a != b {
+            report.mismatch = Some(Mismatch {
+                input,
+                left: a,
+                right: b,
+            });
+            break;
+        }
+        if matches!(a, Outcome::StepLimit { .. }) {
+            report.inconclusive += 1;
+        }
+    }
+    report
+}
+
+/// Every input of at most `max_len` bytes drawn from `alphabet`, shortest
+/// first.
+pub fn exhaustive_inputs(alphabet: &[u8], max_len: usize) -> impl Iterator<Item = Vec<u8>> + '_ {
+    (0..=max_len).flat_map(move |len| {
+        let count = alphabet.len().pow(len as u32);
+        (0..count).map(move |mut n| {
+            let mut input = vec![0; len];
+            for byte in input.iter_mut().rev() {
+                *byte = alphabet[n % alphabet.len()];
+                n /= alphabet.len();
+            }
+            input
+        })
+    })
+}
+
+/// Parses one input per line. The line break is not part of the input;
+/// `\n`, `\t`, `\\` and `\xHH` escapes stand for the bytes they name.
+pub fn parse_inputs(text: &str) -> Result<Vec<Vec<u8>>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| unescape(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// The bytes `line` stands for, with the escapes [`parse_inputs`] reads.
+pub fn unescape(line: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape \\x{}", hex))?;
+                bytes.push(byte);
+            }
+            Some(c) => return Err(format!("invalid escape \\{}", c)),
+            None => return Err("trailing backslash".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    fn program(brainfuck: &str) -> Program {
+        let mut program = Program::from_str(&brainfuck_to_hny(brainfuck).output).unwrap();
+        program.optimize();
+        program
+    }
+
+    #[test]
+    fn test_execute() {
+        let outcome = execute(&program(",+.<<+"), b"a", 100);
+        assert_eq!(
+            outcome,
+            Outcome::Finished {
+                output: b"b".to_vec(),
+                origin: -2,
+                tape: vec![1, 0, 98],
+            }
+        );
+        assert_eq!(
+            execute(&program("+[.]"), b"", 10),
+            Outcome::StepLimit { output: vec![1; 4] }
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let inputs = exhaustive_inputs(b"\0ab", 2).collect::<Vec<_>>();
+        assert_eq!(inputs.len(), 13);
+
+        let report = check(&program(",[-]"), &program(",[+]"), inputs.clone(), 10_000);
+        assert_eq!(report.inputs, 13);
+        assert!(report.mismatch.is_none());
+
+        let report = check(&program(",."), &program(",+-+."), inputs, 10_000);
+        assert_eq!(report.mismatch.unwrap().input, b"");
+    }
+
+    #[test]
+    fn test_parse_inputs() {
+        assert_eq!(
+            parse_inputs("ab\n\\x41\\n\n").unwrap(),
+            [b"ab".to_vec(), b"A\n".to_vec()]
+        );
+        assert!(parse_inputs("\\q").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt::Write;
+
+use crate::{lexer, Instruction};
+
+/// Lists every word pair of `source` next to its brainfuck symbol and what it
+/// does. With `indent`, loop bodies are indented one level per loop.
+pub fn explain(source: &str, indent: bool) -> String {
+    let mut out = String::new();
+    let mut tokens = lexer::tokens(source, false).map(|token| token.text);
+    let mut depth = 0usize;
+
+    while let Some(first) = tokens.next() {
+        let Some(second) = tokens.next() else {
+            let _ = writeln!(
+                out,
+                "{}{:<12}    unpaired word, ignored",
+                pad(indent, depth),
+                first
+            );
+            break;
+        };
+
+        let pair = format!("{} {}", first, second);
+        let Some(instruction) = Instruction::from_pair((first, second)) else {
+            let _ = writeln!(
+                out,
+                "{}{:<12} ?  not an instruction",
+                pad(indent, depth),
+                pair
+            );
+            continue;
+        };
+
+        if instruction == Instruction::LoopEnd {
+            depth = depth.saturating_sub(1);
+        }
+        let _ = writeln!(
+            out,
+            "{}{:<12} {}  {}",
+            pad(indent, depth),
+            pair,
+            instruction.symbol(),
+            instruction.description()
+        );
+        if instruction == Instruction::LoopStart {
+            depth += 1;
+        }
+    }
+    out
+}
+
+fn pad(indent: bool, depth: usize) -> String {
+    match indent {
+        true => "    ".repeat(depth),
+        false => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let code = "Happy Happy Happy Year New New Happy";
+        assert_eq!(
+            explain(code, true),
+            "Happy Happy  [  start a loop, skipped if the current cell is zero\n    \
+             Happy Year   -  subtract one from the current cell\n\
+             New New      ]  repeat the loop while the current cell is non-zero\n\
+             Happy           unpaired word, ignored\n"
+        );
+        assert!(explain("Year Year", false).starts_with("Year Year    ?  not an instruction"));
+    }
+}
@@ -0,0 +1,463 @@
+//! Opt-in extension instructions. [`EXTENSION`](crate::EXTENSION), `Year
+//! Year`, the one pair no instruction uses, calls the function numbered by
+//! the current cell. A function reads its arguments from the cells to the
+//! right and leaves its result in the current cell, which is 0 if it failed
+//! or its extension isn't enabled, so a program can test for one.
+//!
+//! - 1, `fileio`: opens the file named by the text after the current cell,
+//!   up to a NUL, for reading.
+//! - 2, `fileio`: creates or empties the file so named for writing.
+//! - 3, `fileio`: reads a byte of the file open for reading into the next
+//!   cell, failing at the end of the file.
+//! - 4, `fileio`: writes the next cell to the file open for writing.
+//! - 5, `clock`: writes the seconds since the Unix epoch into the next eight
+//!   cells, most significant byte first.
+//! - 6, `clock`: writes the milliseconds since the program started into the
+//!   next four cells, most significant byte first.
+//! - 7, `fork`: starts a copy of the program from its beginning, on a blank
+//!   tape of its own and with no input, on another thread, giving 1. In the
+//!   copy the first call gives 2 without starting anything, so a program that
+//!   forks before anything else can tell which it is. A program can fork
+//!   once, and its copy can't.
+//! - 8, `fork`: sends the next cell to the other program, failing once it has
+//!   finished.
+//! - 9, `fork`: waits for a byte from the other program and writes it into
+//!   the next cell, failing once it has finished and nothing is left.
+//!
+//! In [deterministic](Extensions::set_deterministic) runs the clock is
+//! driven by the step count instead: it reads the Unix epoch when the program
+//! starts and goes forward a millisecond every thousand steps. Forking fails,
+//! as the two programs' output would interleave differently from run to run.
+//!
+//! Only the interpreter runs extension calls. Programs using them don't
+//! compile, so the server, bundles and compiled targets never run them.
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{tape::Tape, HnyFuck, TokenStream};
+
+/// A set of extension functions.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+pub enum Extension {
+    /// Opening, reading and writing files named on the tape
+    #[serde(rename = "fileio")]
+    #[value(name = "fileio")]
+    FileIo,
+    /// Reading the time
+    Clock,
+    /// Running a copy of the program alongside it and passing bytes between
+    /// the two (experimental)
+    Fork,
+}
+
+const OPEN_READ: u8 = 1;
+const OPEN_WRITE: u8 = 2;
+const READ: u8 = 3;
+const WRITE: u8 = 4;
+const UNIX_TIME: u8 = 5;
+const ELAPSED: u8 = 6;
+const FORK: u8 = 7;
+const SEND: u8 = 8;
+const RECEIVE: u8 = 9;
+
+/// A function a program can call, and the extension providing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Function {
+    pub number: u8,
+    /// Kebab-case name, for listings.
+    pub name: &'static str,
+    pub extension: Extension,
+    pub description: &'static str,
+}
+
+/// Every extension function, by number.
+pub const FUNCTIONS: [Function; 9] = [
+    Function {
+        number: OPEN_READ,
+        name: "open-read",
+        extension: Extension::FileIo,
+        description: "open the file named by the text after the current cell for reading",
+    },
+    Function {
+        number: OPEN_WRITE,
+        name: "open-write",
+        extension: Extension::FileIo,
+        description:
+            "create or empty the file named by the text after the current cell for writing",
+    },
+    Function {
+        number: READ,
+        name: "read",
+        extension: Extension::FileIo,
+        description: "read a byte of the file open for reading into the next cell",
+    },
+    Function {
+        number: WRITE,
+        name: "write",
+        extension: Extension::FileIo,
+        description: "write the next cell to the file open for writing",
+    },
+    Function {
+        number: UNIX_TIME,
+        name: "unix-time",
+        extension: Extension::Clock,
+        description: "write the seconds since the Unix epoch into the next eight cells",
+    },
+    Function {
+        number: ELAPSED,
+        name: "elapsed",
+        extension: Extension::Clock,
+        description: "write the milliseconds since the program started into the next four cells",
+    },
+    Function {
+        number: FORK,
+        name: "fork",
+        extension: Extension::Fork,
+        description: "start a copy of the program on another thread",
+    },
+    Function {
+        number: SEND,
+        name: "send",
+        extension: Extension::Fork,
+        description: "send the next cell to the other program",
+    },
+    Function {
+        number: RECEIVE,
+        name: "receive",
+        extension: Extension::Fork,
+        description: "wait for a byte from the other program and write it into the next cell",
+    },
+];
+
+/// Steps a deterministic clock takes to go forward a millisecond.
+const STEPS_PER_MILLI: u64 = 1000;
+
+/// The extensions a program may call, the files it has open and the copy of
+/// it running alongside.
+#[derive(Debug)]
+pub struct Extensions {
+    enabled: BTreeSet<Extension>,
+    deterministic: bool,
+    started: Instant,
+    reading: Option<BufReader<File>>,
+    writing: Option<BufWriter<File>>,
+    /// The whole program, for a copy to run.
+    pub(crate) program: Option<TokenStream>,
+    /// Whether this is the copy of a program that forked.
+    copy: bool,
+    forked: bool,
+    /// The channel to and from the other program.
+    peer: Option<(Sender<u8>, Receiver<u8>)>,
+    child: Option<JoinHandle<()>>,
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Extensions {
+            enabled: BTreeSet::new(),
+            deterministic: false,
+            started: Instant::now(),
+            reading: None,
+            writing: None,
+            program: None,
+            copy: false,
+            forked: false,
+            peer: None,
+            child: None,
+        }
+    }
+}
+
+/// The same extensions enabled and the same clock. Open files and the
+/// channel to a forked copy stay with the original.
+impl Clone for Extensions {
+    fn clone(&self) -> Self {
+        Extensions {
+            enabled: self.enabled.clone(),
+            deterministic: self.deterministic,
+            started: self.started,
+            reading: None,
+            writing: None,
+            program: self.program.clone(),
+            copy: self.copy,
+            forked: self.forked,
+            peer: None,
+            child: None,
+        }
+    }
+}
+
+impl Drop for Extensions {
+    /// Waits for the copy of the program to finish, after hanging up on it
+    /// so it doesn't wait for bytes that will never come.
+    fn drop(&mut self) {
+        self.peer = None;
+        if let Some(child) = self.child.take() {
+            child.join().ok();
+        }
+    }
+}
+
+impl Extensions {
+    pub fn enable(&mut self, extension: Extension) {
+        self.enabled.insert(extension);
+    }
+
+    pub fn is_enabled(&self, extension: Extension) -> bool {
+        self.enabled.contains(&extension)
+    }
+
+    /// Whether any extension is enabled, and so whether the program may make
+    /// extension calls at all.
+    pub fn is_empty(&self) -> bool {
+        self.enabled.is_empty()
+    }
+
+    /// Makes functions that would read the outside world, such as the
+    /// clock, give the same results on every run.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Calls `function` on the tape with the pointer at `index`, after
+    /// `steps` steps of the program, returning the result for the current
+    /// cell.
+    pub(crate) fn call(&mut self, function: u8, tape: &mut Tape, index: usize, steps: u64) -> u8 {
+        let Some(extension) = FUNCTIONS
+            .iter()
+            .find(|known| known.number == function)
+            .map(|known| known.extension)
+        else {
+            return 0;
+        };
+        if !self.is_enabled(extension) {
+            return 0;
+        }
+        let done = match function {
+            OPEN_READ => self.open(tape, index, false),
+            OPEN_WRITE => self.open(tape, index, true),
+            READ => self.read().map(|byte| write_after(tape, index, &[byte])),
+            WRITE => self.write(next(tape, index)),
+            UNIX_TIME => {
+                let time = self.unix_time(steps).as_secs();
+                write_after(tape, index, &time.to_be_bytes());
+                Some(())
+            }
+            ELAPSED => {
+                let elapsed = self.elapsed(steps).as_millis() as u32;
+                write_after(tape, index, &elapsed.to_be_bytes());
+                Some(())
+            }
+            FORK => return self.fork().unwrap_or(0),
+            SEND => self
+                .peer
+                .as_ref()
+                .and_then(|(sender, _)| sender.send(next(tape, index)).ok()),
+            _ => self
+                .peer
+                .as_ref()
+                .and_then(|(_, receiver)| receiver.recv().ok())
+                .map(|byte| write_after(tape, index, &[byte])),
+        };
+        done.is_some() as u8
+    }
+
+    /// Starts the copy of the program, giving 1, or in the copy gives 2.
+    fn fork(&mut self) -> Option<u8> {
+        if self.deterministic || std::mem::replace(&mut self.forked, true) {
+            return None;
+        }
+        if self.copy {
+            return Some(2);
+        }
+        let program = self.program.clone()?;
+        let enabled = self.enabled.clone();
+        let (to_child, from_parent) = mpsc::channel();
+        let (to_parent, from_child) = mpsc::channel();
+        let child = thread::Builder::new()
+            .name("hnyfuck-fork".to_string())
+            .spawn(move || {
+                let mut hny = HnyFuck::new(program);
+                hny.set_input(io::empty());
+                let extensions = &mut hny.state.extensions;
+                extensions.enabled = enabled;
+                extensions.copy = true;
+                extensions.peer = Some((to_parent, from_parent));
+                hny.run();
+            })
+            .ok()?;
+        self.peer = Some((to_child, from_child));
+        self.child = Some(child);
+        Some(1)
+    }
+
+    fn open(&mut self, tape: &[u8], index: usize, write: bool) -> Option<()> {
+        let name = tape
+            .iter()
+            .skip(index + 1)
+            .take_while(|&&byte| byte != 0)
+            .copied()
+            .collect::<Vec<_>>();
+        let name = String::from_utf8(name)
+            .ok()
+            .filter(|name| !name.is_empty())?;
+        // So a file written can be read back.
+        if let Some(writing) = &mut self.writing {
+            writing.flush().ok()?;
+        }
+        if write {
+            self.writing = Some(BufWriter::new(File::create(name).ok()?));
+        } else {
+            self.reading = Some(BufReader::new(File::open(name).ok()?));
+        }
+        Some(())
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        match self.reading.as_mut()?.read(&mut byte).ok()? {
+            0 => None,
+            _ => Some(byte[0]),
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> Option<()> {
+        self.writing.as_mut()?.write_all(&[byte]).ok()
+    }
+
+    fn unix_time(&self, steps: u64) -> Duration {
+        match self.deterministic {
+            true => self.elapsed(steps),
+            false => SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn elapsed(&self, steps: u64) -> Duration {
+        match self.deterministic {
+            true => Duration::from_millis(steps / STEPS_PER_MILLI),
+            false => self.started.elapsed(),
+        }
+    }
+}
+
+fn next(tape: &[u8], index: usize) -> u8 {
+    tape.get(index + 1).copied().unwrap_or(0)
+}
+
+/// Writes `bytes` into the cells after the one at `index`, growing the tape
+/// if they run off its end.
+fn write_after(tape: &mut Tape, index: usize, bytes: &[u8]) {
+    if tape.len() <= index + bytes.len() {
+        tape.resize(index + bytes.len() + 1);
+    }
+    for (i, &byte) in bytes.iter().enumerate() {
+        tape[index + 1 + i] = byte;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fileio() {
+        let path = std::env::temp_dir().join(format!("hnyfuck-fileio-{}", std::process::id()));
+        let mut tape = Tape::from_iter([OPEN_WRITE]);
+        tape.extend(path.to_str().unwrap().bytes());
+        let mut extensions = Extensions::default();
+        assert_eq!(extensions.call(OPEN_WRITE, &mut tape, 0, 0), 0);
+
+        extensions.enable(Extension::FileIo);
+        assert_eq!(extensions.call(OPEN_WRITE, &mut tape, 0, 0), 1);
+        let mut cells = Tape::from_iter([WRITE, b'h']);
+        assert_eq!(extensions.call(WRITE, &mut cells, 0, 0), 1);
+        cells[1] = b'i';
+        assert_eq!(extensions.call(WRITE, &mut cells, 0, 0), 1);
+
+        assert_eq!(extensions.call(OPEN_READ, &mut tape, 0, 0), 1);
+        let mut cells = Tape::from_iter([READ]);
+        assert_eq!(extensions.call(READ, &mut cells, 0, 0), 1);
+        assert_eq!(extensions.call(READ, &mut cells, 0, 0), 1);
+        assert_eq!(&cells[..], [READ, b'i']);
+        assert_eq!(extensions.call(READ, &mut cells, 0, 0), 0);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(extensions.call(OPEN_READ, &mut tape, 0, 0), 0);
+        assert_eq!(extensions.call(99, &mut tape, 0, 0), 0);
+    }
+
+    #[test]
+    fn test_fork() {
+        use crate::{to_source, Instruction::*};
+
+        let call = "Year Year";
+        let code = [
+            // Fork, leaving 1 here in the original and 2 in the copy.
+            to_source(&[Increment; 7]),
+            call.to_string(),
+            // The copy sends 65 and clears the cell.
+            to_source(&[Decrement, LoopStart, ShiftRight]),
+            to_source(&[Increment; 65]),
+            to_source(&[ShiftLeft]),
+            to_source(&[Increment; 7]),
+            call.to_string(),
+            to_source(&[LoopStart, Decrement, LoopEnd, LoopEnd]),
+            // Both wait for a byte, which only the original gets.
+            to_source(&[Increment; 9]),
+            call.to_string(),
+        ]
+        .join(" ");
+        let mut hny = HnyFuck::from_str(&code);
+        hny.enable_extension(Extension::Fork);
+        hny.run();
+        assert_eq!(hny.state.tape(), &[1, 65]);
+
+        let mut hny = HnyFuck::from_str(&code);
+        hny.enable_extension(Extension::Fork);
+        hny.set_deterministic(true);
+        hny.run();
+        // Forking fails, so the original takes the copy's path, sending to
+        // no one.
+        assert_eq!(hny.state.tape(), &[0, 65]);
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut extensions = Extensions::default();
+        extensions.enable(Extension::Clock);
+        let mut tape = Tape::from_iter([UNIX_TIME]);
+        assert_eq!(extensions.call(UNIX_TIME, &mut tape, 0, 0), 1);
+        assert_eq!(tape.len(), 9);
+        assert_ne!(tape[5..], [0; 4]);
+
+        extensions.set_deterministic(true);
+        assert_eq!(extensions.call(UNIX_TIME, &mut tape, 0, 2_500_000), 1);
+        assert_eq!(&tape[..], [UNIX_TIME, 0, 0, 0, 0, 0, 0, 0, 2]);
+        let mut tape = Tape::from_iter([ELAPSED]);
+        assert_eq!(extensions.call(ELAPSED, &mut tape, 0, 300_999), 1);
+        assert_eq!(&tape[..], [ELAPSED, 0, 0, 1, 44]);
+    }
+}
@@ -0,0 +1,143 @@
+//! Downloading programs to run from a URL, for `run --url`. A download is cut
+//! off past a size limit, and can be checked against the SHA-256 its sharer
+//! published, so a changed or tampered snippet is refused rather than run.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+};
+
+use sha2::{Digest, Sha256};
+
+/// The most bytes a download may have unless told otherwise.
+pub const DEFAULT_MAX_BYTES: u64 = 1 << 20;
+
+#[derive(Debug)]
+pub enum FetchError {
+    /// The request failed or the server answered with an error status.
+    Request(ureq::Error),
+    /// The connection broke while reading the body.
+    Read(io::Error),
+    /// The body was longer than the limit, in bytes.
+    TooLarge(u64),
+    /// The body's SHA-256 wasn't the one expected.
+    ChecksumMismatch { expected: String, actual: String },
+    /// The body isn't UTF-8, so can't be a program.
+    NotUtf8,
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Request(e) => write!(f, "{}", e),
+            FetchError::Read(e) => write!(f, "couldn't read the response: {}", e),
+            FetchError::TooLarge(max) => write!(f, "the program is larger than {} bytes", max),
+            FetchError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "the program's SHA-256 is {}, not the expected {}",
+                actual, expected
+            ),
+            FetchError::NotUtf8 => write!(f, "the program isn't valid UTF-8"),
+        }
+    }
+}
+
+impl Error for FetchError {}
+
+/// Downloads the program at `url`, failing if it is longer than `max_bytes`
+/// or, given `sha256` in hex, if its hash differs.
+pub fn fetch(url: &str, max_bytes: u64, sha256: Option<&str>) -> Result<String, FetchError> {
+    let response = ureq::get(url).call().map_err(FetchError::Request)?;
+    let mut body = Vec::new();
+    response
+        .into_body()
+        .into_reader()
+        .take(max_bytes.saturating_add(1))
+        .read_to_end(&mut body)
+        .map_err(FetchError::Read)?;
+    check(body, max_bytes, sha256)
+}
+
+/// Checks a downloaded `body` against the limit and hash [`fetch`] was given.
+fn check(body: Vec<u8>, max_bytes: u64, sha256: Option<&str>) -> Result<String, FetchError> {
+    if body.len() as u64 > max_bytes {
+        return Err(FetchError::TooLarge(max_bytes));
+    }
+    if let Some(expected) = sha256 {
+        let actual = sha256_hex(&body);
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(FetchError::ChecksumMismatch {
+                expected: expected.trim().to_ascii_lowercase(),
+                actual,
+            });
+        }
+    }
+    String::from_utf8(body).map_err(|_| FetchError::NotUtf8)
+}
+
+/// The SHA-256 of `bytes` in lowercase hex, as `sha256sum` prints it.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let body = b"Year Happy Year New".to_vec();
+        let hash = sha256_hex(&body);
+        assert_eq!(
+            check(body.clone(), 19, Some(&hash.to_uppercase())).unwrap(),
+            "Year Happy Year New"
+        );
+        assert!(matches!(
+            check(body.clone(), 18, None),
+            Err(FetchError::TooLarge(18))
+        ));
+        assert!(matches!(
+            check(body, 100, Some(&sha256_hex(b""))),
+            Err(FetchError::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            check(vec![0xff], 100, None),
+            Err(FetchError::NotUtf8)
+        ));
+    }
+
+    #[test]
+    fn test_fetch() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/prog.hny", server.server_addr());
+        let serve = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let request = server.recv().unwrap();
+                let response = match request.url() {
+                    "/prog.hny" => tiny_http::Response::from_string("Year Happy"),
+                    _ => tiny_http::Response::from_string("").with_status_code(404),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        assert_eq!(fetch(&url, 100, None).unwrap(), "Year Happy");
+        let missing = url.replace("prog", "missing");
+        assert!(matches!(
+            fetch(&missing, 100, None),
+            Err(FetchError::Request(_))
+        ));
+        serve.join().unwrap();
+    }
+}
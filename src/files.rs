@@ -0,0 +1,222 @@
+//! The programs a tooling command works on, found from the paths it is
+//! given: files are taken as they are, directories are searched recursively
+//! and glob patterns such as `examples/**/*.hny` are expanded, with include
+//! and exclude patterns picking among the files found.
+
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Component, Path, PathBuf},
+};
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+
+#[derive(Debug)]
+pub enum FilesError {
+    Pattern(globset::Error),
+    Io(PathBuf, io::Error),
+    /// A pattern no file matches.
+    NoMatch(PathBuf),
+}
+
+impl fmt::Display for FilesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilesError::Pattern(e) => write!(f, "{}", e),
+            FilesError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            FilesError::NoMatch(pattern) => write!(f, "no files match {}", pattern.display()),
+        }
+    }
+}
+
+impl Error for FilesError {}
+
+impl From<globset::Error> for FilesError {
+    fn from(e: globset::Error) -> Self {
+        FilesError::Pattern(e)
+    }
+}
+
+/// Which of the files under a directory to take. Patterns are matched
+/// against paths relative to the directory, and `*` matches across `/`, so
+/// `*.hny` picks programs at any depth and `vendor*` skips a whole tree.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    /// Takes files matching any of `include` and none of `exclude`.
+    /// Directories matching `exclude` aren't searched.
+    pub fn new(
+        include: impl IntoIterator<Item = impl AsRef<str>>,
+        exclude: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Filter, FilesError> {
+        let set = |patterns: &mut dyn Iterator<Item = String>| {
+            let mut set = GlobSetBuilder::new();
+            for pattern in patterns {
+                set.add(Glob::new(&pattern)?);
+            }
+            set.build()
+        };
+        Ok(Filter {
+            include: set(&mut include.into_iter().map(|p| p.as_ref().to_string()))?,
+            exclude: set(&mut exclude.into_iter().map(|p| p.as_ref().to_string()))?,
+        })
+    }
+}
+
+/// The files `paths` name, in order and each once. A directory gives the
+/// files under it the filter takes, leaving out hidden ones and not
+/// following symbolic links to directories. A path that doesn't exist and
+/// has any of `*?[{` in it is a pattern, giving the files it matches that
+/// aren't excluded; there `*` stays within a directory and `**` crosses
+/// them. Anything else is taken as it is, whatever the filter says, so a
+/// file named on its own is always processed.
+pub fn expand(paths: &[PathBuf], filter: &Filter) -> Result<Vec<PathBuf>, FilesError> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let keep = |relative: &Path| filter.include.is_match(relative);
+            walk(path, path, &filter.exclude, &keep, &mut files)?;
+        } else if let Some(base) = pattern_base(path) {
+            let pattern = GlobBuilder::new(&path.to_string_lossy())
+                .literal_separator(true)
+                .build()?
+                .compile_matcher();
+            let found = files.len();
+            let keep = |relative: &Path| pattern.is_match(base.join(relative));
+            walk(&base, &base, &filter.exclude, &keep, &mut files)?;
+            if files.len() == found {
+                return Err(FilesError::NoMatch(path.clone()));
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+    Ok(files)
+}
+
+/// The directory to search for a pattern's matches: its components before
+/// the first with a wildcard. `None` if `path` isn't a pattern.
+fn pattern_base(path: &Path) -> Option<PathBuf> {
+    let wild = |text: &str| text.contains(['*', '?', '[', '{']);
+    if path.exists() || !wild(&path.to_string_lossy()) {
+        return None;
+    }
+    Some(
+        path.components()
+            .take_while(|component| match component {
+                Component::Normal(name) => !wild(&name.to_string_lossy()),
+                _ => true,
+            })
+            .collect(),
+    )
+}
+
+/// Adds the files under `dir` that `keep` takes, by their path relative to
+/// `root`, in name order.
+fn walk(
+    root: &Path,
+    dir: &Path,
+    exclude: &GlobSet,
+    keep: &dyn Fn(&Path) -> bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), FilesError> {
+    let io_error = |e| FilesError::Io(dir.to_path_buf(), e);
+    // A pattern like `*.hny` is searched for in the current directory.
+    let read = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let mut entries = std::fs::read_dir(read)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(io_error)?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if exclude.is_match(relative) {
+            continue;
+        }
+        if entry.file_type().map_err(io_error)?.is_dir() {
+            walk(root, &path, exclude, keep, files)?;
+        } else if path.is_file() && keep(relative) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let root = std::env::temp_dir().join(format!("hnyfuck-files-{}", std::process::id()));
+        for file in [
+            "a.hny",
+            "b.bf",
+            "sub/c.hny",
+            "sub/deep/d.hny",
+            "vendor/e.hny",
+            ".hidden/f.hny",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "Year Happy").unwrap();
+        }
+        let found = |paths: &[PathBuf], include: &[&str], exclude: &[&str]| {
+            let filter = Filter::new(include, exclude).unwrap();
+            expand(paths, &filter).map(|files| {
+                files
+                    .iter()
+                    .map(|file| {
+                        file.strip_prefix(&root)
+                            .unwrap()
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        let dir = [root.clone()];
+
+        assert_eq!(
+            found(&dir, &["*.hny"], &[]).unwrap(),
+            ["a.hny", "sub/c.hny", "sub/deep/d.hny", "vendor/e.hny"]
+        );
+        assert_eq!(
+            found(&dir, &["*.hny"], &["vendor", "*/deep"]).unwrap(),
+            ["a.hny", "sub/c.hny"]
+        );
+        assert_eq!(found(&dir, &["*.bf"], &[]).unwrap(), ["b.bf"]);
+
+        // Named files are taken whatever the filter says, and only once.
+        let named = [root.join("b.bf"), root.join("sub/*.hny"), root.join("b.bf")];
+        assert_eq!(
+            found(&named, &["*.hny"], &[]).unwrap(),
+            ["b.bf", "sub/c.hny"]
+        );
+        let recursive = [root.join("**/*.hny")];
+        assert_eq!(
+            found(&recursive, &[], &["vendor"]).unwrap(),
+            ["a.hny", "sub/c.hny", "sub/deep/d.hny"]
+        );
+        assert!(matches!(
+            found(&[root.join("*.txt")], &[], &[]),
+            Err(FilesError::NoMatch(_))
+        ));
+        assert!(Filter::new(["a[b"], [""; 0]).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
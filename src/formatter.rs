@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{lexer, LOOP_END, LOOP_START};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Lines of word pairs with indented loop bodies.
+    #[default]
+    Code,
+    /// Punctuated sentences that read like a greeting. Only runs leniently.
+    Card,
+}
+
+/// Layout options for [`format`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FormatStyle {
+    #[serde(rename = "style")]
+    pub layout: Layout,
+    /// Most word pairs on one line.
+    pub pairs_per_line: usize,
+    /// Longest line, indentation included. A single pair is never split.
+    pub max_width: usize,
+    /// Spaces of indentation per loop level.
+    pub indent: usize,
+    /// Put `Happy Happy` and `New New` on lines of their own.
+    pub loop_lines: bool,
+}
+
+impl Default for FormatStyle {
+    fn default() -> Self {
+        FormatStyle {
+            layout: Layout::Code,
+            pairs_per_line: 8,
+            max_width: 100,
+            indent: 4,
+            loop_lines: true,
+        }
+    }
+}
+
+impl FormatStyle {
+    /// Reads a style from TOML, e.g. `pairs-per-line = 4`. Missing keys keep
+    /// their defaults.
+    pub fn from_toml(input: &str) -> Result<FormatStyle, toml::de::Error> {
+        toml::from_str(input)
+    }
+}
+
+/// Lays out the words of `source` according to `style`. Only whitespace
+/// changes, so the program means exactly what it did before. A `#!` line is
+/// kept as it is.
+pub fn format(source: &str, style: &FormatStyle) -> String {
+    let (shebang, body) = source.split_at(lexer::shebang_len(source));
+    let mut out = shebang.to_string();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out + &match style.layout {
+        Layout::Code => format_code(body, style),
+        Layout::Card => format_card(body, style),
+    }
+}
+
+fn format_code(source: &str, style: &FormatStyle) -> String {
+    let mut out = String::new();
+    let mut line = String::new();
+    let mut pairs = 0;
+    let mut depth = 0usize;
+
+    let mut flush = |line: &mut String, pairs: &mut usize| {
+        if !line.is_empty() {
+            out.push_str(line);
+            out.push('\n');
+            line.clear();
+        }
+        *pairs = 0;
+    };
+
+    let mut tokens = source.split_whitespace();
+    while let Some(first) = tokens.next() {
+        let second = tokens.next();
+        let pair = match second {
+            Some(second) => format!("{} {}", first, second),
+            None => first.to_string(),
+        };
+        let is_start = second.is_some_and(|second| (first, second) == LOOP_START);
+        let is_end = second.is_some_and(|second| (first, second) == LOOP_END);
+
+        if is_end {
+            depth = depth.saturating_sub(1);
+        }
+        let boundary = style.loop_lines && (is_start || is_end);
+        if boundary {
+            flush(&mut line, &mut pairs);
+        }
+
+        let indent = " ".repeat(style.indent * depth);
+        if line.is_empty() {
+            line.push_str(&indent);
+        } else if pairs >= style.pairs_per_line || line.len() + 1 + pair.len() > style.max_width {
+            flush(&mut line, &mut pairs);
+            line.push_str(&indent);
+        } else {
+            line.push(' ');
+        }
+        line.push_str(&pair);
+        pairs += 1;
+
+        if is_start {
+            depth += 1;
+        }
+        if boundary {
+            flush(&mut line, &mut pairs);
+        }
+    }
+    flush(&mut line, &mut pairs);
+    out
+}
+
+/// Longest sentence the card layout writes before breaking it off.
+const MAX_SENTENCE: usize = 7;
+
+/// Writes the keywords of `source` as sentences, wrapped at `max_width`.
+/// Sentences end after each "Happy New Year" with `!`, or with `.` once they
+/// grow long, and longer ones get a comma every third word.
+fn format_card(source: &str, style: &FormatStyle) -> String {
+    let words = lexer::tokens(source, true)
+        .map(|token| token.text)
+        .collect::<Vec<_>>();
+
+    let mut sentences = Vec::new();
+    let mut sentence = Vec::<&str>::new();
+    for (i, word) in words.iter().enumerate() {
+        sentence.push(word);
+        let greeting = sentence.ends_with(&["Happy", "New", "Year"]);
+        if greeting || sentence.len() == MAX_SENTENCE || i + 1 == words.len() {
+            let mut text = String::new();
+            for (j, word) in sentence.iter().enumerate() {
+                if j > 0 {
+                    text.push(' ');
+                }
+                text.push_str(word);
+                if j % 3 == 2 && j + 1 < sentence.len() && sentence.len() > 4 {
+                    text.push(',');
+                }
+            }
+            text.push(if greeting { '!' } else { '.' });
+            sentences.push(text);
+            sentence.clear();
+        }
+    }
+
+    let mut out = String::new();
+    let mut line = String::new();
+    for word in sentences.iter().flat_map(|sentence| sentence.split(' ')) {
+        if !line.is_empty() && line.len() + 1 + word.len() > style.max_width {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// A unified diff turning `source` into `formatted`, headed with `name`, or
+/// `None` when they're the same.
+pub fn diff(name: &str, source: &str, formatted: &str) -> Option<String> {
+    (source != formatted).then(|| {
+        similar::TextDiff::from_lines(source, formatted)
+            .unified_diff()
+            .header(name, name)
+            .to_string()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CODE: &str = "Year Happy Year Happy Happy Happy New Year Year Happy Happy New Happy Year New New Year New";
+
+    #[test]
+    fn test_format_default() {
+        assert_eq!(
+            format(CODE, &FormatStyle::default()),
+            "Year Happy Year Happy\nHappy Happy\n    New Year Year Happy Happy New Happy Year\nNew New\nYear New\n"
+        );
+    }
+
+    #[test]
+    fn test_format_shebang() {
+        assert_eq!(
+            format(
+                "#!/usr/bin/env hnyfuck\nYear Happy   Year New",
+                &FormatStyle::default()
+            ),
+            "#!/usr/bin/env hnyfuck\nYear Happy Year New\n"
+        );
+    }
+
+    #[test]
+    fn test_format_inline_loops() {
+        let style = FormatStyle {
+            layout: Layout::Code,
+            pairs_per_line: 3,
+            max_width: 30,
+            indent: 2,
+            loop_lines: false,
+        };
+        assert_eq!(
+            format(CODE, &style),
+            "Year Happy Year Happy\nHappy Happy New Year\n  Year Happy Happy New\n  Happy Year New New Year New\n"
+        );
+    }
+
+    #[test]
+    fn test_format_card() {
+        let style = FormatStyle {
+            layout: Layout::Card,
+            max_width: 40,
+            ..FormatStyle::default()
+        };
+        let card = format(CODE, &style);
+        assert_eq!(
+            card,
+            "Year Happy Year, Happy Happy Happy, New.\nYear Year Happy, Happy New Happy, Year.\nNew New Year New.\n"
+        );
+        assert_eq!(
+            format("Year Happy New Year Happy New", &style),
+            "Year Happy New Year! Happy New.\n"
+        );
+        assert_eq!(
+            lexer::tokens(&card, true)
+                .map(|t| t.text)
+                .collect::<Vec<_>>(),
+            CODE.split_whitespace().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_style_from_toml() {
+        let style =
+            FormatStyle::from_toml("style = \"card\"\npairs-per-line = 2\nloop-lines = false")
+                .unwrap();
+        assert_eq!(style.layout, Layout::Card);
+        assert_eq!(style.pairs_per_line, 2);
+        assert!(!style.loop_lines);
+        assert_eq!(style.indent, 4);
+        assert!(FormatStyle::from_toml("width = 2").is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let source = "Year Happy   Year New";
+        let formatted = format(source, &FormatStyle::default());
+        assert_eq!(diff("a.hny", &formatted, &formatted), None);
+        assert_eq!(
+            diff("a.hny", source, &formatted).unwrap(),
+            "--- a.hny\n+++ a.hny\n@@ -1 +1 @@\n-Year Happy   Year New\n\\ No newline at end of file\n+Year Happy Year New\n"
+        );
+    }
+}
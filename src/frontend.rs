@@ -0,0 +1,475 @@
+//! Other brainfuck dialects that, like hnyfuck, spell each instruction as a
+//! fixed run of words, such as Ook! (`Ook. Ook?` for `>`). A program in one
+//! is rewritten as word pairs, so it runs through the same interpreter and
+//! compiler as any other. Dialects can also be written in emoji or other
+//! characters without spaces between them, split into what a reader sees as
+//! single characters.
+
+use std::{collections::BTreeMap, error::Error, fmt, ops::Range};
+
+use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    convert::{Conversion, ConvertError, Mapping},
+    lexer::{self, Token, KEYWORDS},
+    Instruction, SourceMap,
+};
+
+/// What a dialect's programs are made of.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Split {
+    /// Words separated by whitespace.
+    #[default]
+    Words,
+    /// Grapheme clusters, whether or not whitespace separates them, so that
+    /// an emoji made of several code points is one token.
+    Graphemes,
+}
+
+impl Split {
+    /// The tokens of `text`, skipping a leading `#!` line.
+    fn tokens(self, text: &str) -> Vec<Token<'_>> {
+        match self {
+            Split::Words => lexer::tokens(text, false).collect(),
+            Split::Graphemes => {
+                let start = lexer::shebang_len(text);
+                text[start..]
+                    .grapheme_indices(true)
+                    .filter(|(_, grapheme)| !grapheme.chars().all(char::is_whitespace))
+                    .map(|(i, grapheme)| Token {
+                        text: grapheme,
+                        span: start + i..start + i + grapheme.len(),
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// What goes between the tokens of one instruction.
+    fn separator(self) -> &'static str {
+        match self {
+            Split::Words => " ",
+            Split::Graphemes => "",
+        }
+    }
+}
+
+/// Whether `a` and `b` are the same token, ignoring the variation selector
+/// that asks for emoji presentation, which keyboards add inconsistently.
+fn same(a: &str, b: &str) -> bool {
+    let visible = |c: &char| *c != '\u{fe0f}';
+    a.chars().filter(visible).eq(b.chars().filter(visible))
+}
+
+/// A dialect as a TOML spec gives it, e.g.
+///
+/// ```toml
+/// name = "xmas"
+///
+/// [instructions]
+/// ">" = "Jingle Bells"
+/// "<" = "Bells Jingle"
+/// "+" = "Ho Ho Ho"
+/// ```
+///
+/// with each instruction under its brainfuck symbol. `split = "graphemes"`
+/// spells instructions with characters rather than words, such as
+/// `"+" = "🎄🎁"`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+struct Spec {
+    name: Option<String>,
+    split: Split,
+    instructions: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpecError {
+    Toml(toml::de::Error),
+    /// A key under `[instructions]` isn't a brainfuck symbol.
+    UnknownInstruction(String),
+    /// An instruction is spelled with no words.
+    EmptySpelling(char),
+    /// Two instructions are spelled the same way.
+    DuplicateSpelling(String),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecError::Toml(e) => write!(f, "{}", e),
+            SpecError::UnknownInstruction(key) => {
+                write!(f, "\"{}\" isn't one of the instructions <>+-.,[]", key)
+            }
+            SpecError::EmptySpelling(symbol) => write!(f, "'{}' is spelled with no words", symbol),
+            SpecError::DuplicateSpelling(words) => {
+                write!(f, "\"{}\" spells two instructions", words)
+            }
+        }
+    }
+}
+
+impl Error for SpecError {}
+
+/// Keyword sets for `--locale`: the words of each language's New Year
+/// greeting standing for Happy, New and Year.
+pub const LOCALES: [(&str, [&str; 3]); 4] = [
+    ("de", ["Frohes", "Neues", "Jahr"]),
+    ("es", ["Feliz", "Nuevo", "Año"]),
+    ("fr", ["Bonne", "Nouvelle", "Année"]),
+    ("ja", ["明けまして", "おめでとう", "ございます"]),
+];
+
+/// A word-substitution dialect: the words spelling each instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frontend {
+    name: String,
+    split: Split,
+    spellings: Vec<(Instruction, Vec<String>)>,
+}
+
+impl Frontend {
+    /// A dialect called `name` spelling each instruction with the
+    /// whitespace-separated words given for it. Instructions left out can't
+    /// be written.
+    pub fn new<'a>(
+        name: impl Into<String>,
+        spellings: impl IntoIterator<Item = (Instruction, &'a str)>,
+    ) -> Frontend {
+        Frontend {
+            name: name.into(),
+            split: Split::Words,
+            spellings: spellings
+                .into_iter()
+                .map(|(instruction, words)| {
+                    (
+                        instruction,
+                        words.split_whitespace().map(String::from).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads a dialect from a TOML spec, named `default_name` unless it has a
+    /// `name`.
+    pub fn from_toml(input: &str, default_name: &str) -> Result<Frontend, SpecError> {
+        let spec: Spec = toml::from_str(input).map_err(SpecError::Toml)?;
+        let mut spellings = Vec::new();
+        for (key, words) in &spec.instructions {
+            let mut chars = key.chars();
+            let instruction = match (chars.next(), chars.next()) {
+                (Some(symbol), None) => Instruction::from_symbol(symbol),
+                _ => None,
+            }
+            .ok_or_else(|| SpecError::UnknownInstruction(key.clone()))?;
+            let words = spec
+                .split
+                .tokens(words)
+                .into_iter()
+                .map(|token| token.text)
+                .collect::<Vec<_>>();
+            if words.is_empty() {
+                return Err(SpecError::EmptySpelling(instruction.symbol()));
+            }
+            let spelled_alike = |other: &Vec<&str>| {
+                other.len() == words.len() && other.iter().zip(&words).all(|(a, b)| same(a, b))
+            };
+            if spellings.iter().any(|(_, other)| spelled_alike(other)) {
+                return Err(SpecError::DuplicateSpelling(
+                    words.join(spec.split.separator()),
+                ));
+            }
+            spellings.push((instruction, words));
+        }
+        Ok(Frontend {
+            name: spec.name.unwrap_or_else(|| default_name.to_string()),
+            split: spec.split,
+            spellings: spellings
+                .into_iter()
+                .map(|(instruction, words)| {
+                    (instruction, words.into_iter().map(String::from).collect())
+                })
+                .collect(),
+        })
+    }
+
+    /// [Ook!](https://www.dangermouse.net/esoteric/ook.html), for orang-utans.
+    pub fn ook() -> Frontend {
+        Frontend::monkey("ook", "Ook")
+    }
+
+    /// Blub, Ook! for fish.
+    pub fn blub() -> Frontend {
+        Frontend::monkey("blub", "Blub")
+    }
+
+    /// hnyfuck itself, Happy, New and Year, for tools taking any dialect.
+    pub fn hnyfuck() -> Frontend {
+        Frontend::greeting("hnyfuck", Split::Words, KEYWORDS)
+    }
+
+    /// hnyfuck in emoji: 🎉 for Happy, 🎆 for New and 🎊 for Year, so that
+    /// `+` is 🎊🎉.
+    pub fn emoji() -> Frontend {
+        Frontend::greeting("emoji", Split::Graphemes, ["🎉", "🎆", "🎊"])
+    }
+
+    /// hnyfuck with the words of another language's New Year greeting, if
+    /// there is a set for `locale` in [`LOCALES`].
+    pub fn locale(locale: &str) -> Option<Frontend> {
+        let (name, words) = LOCALES.iter().find(|(name, _)| *name == locale)?;
+        Some(Frontend::greeting(name, Split::Words, *words))
+    }
+
+    /// hnyfuck with `words` in place of Happy, New and Year.
+    fn greeting(name: &str, split: Split, words: [&str; 3]) -> Frontend {
+        let word = |keyword| {
+            let i = KEYWORDS
+                .iter()
+                .position(|k| *k == keyword)
+                .expect("a keyword");
+            words[i].to_string()
+        };
+        Frontend {
+            name: name.to_string(),
+            split,
+            spellings: Instruction::ALL
+                .into_iter()
+                .map(|instruction| {
+                    let (first, second) = instruction.pair();
+                    (instruction, vec![word(first), word(second)])
+                })
+                .collect(),
+        }
+    }
+
+    /// Ook! with `word` in place of "Ook".
+    fn monkey(name: &str, word: &str) -> Frontend {
+        let spell = |a, b| format!("{}{} {}{}", word, a, word, b);
+        Frontend::new(
+            name,
+            [
+                (Instruction::ShiftRight, spell('.', '?')),
+                (Instruction::ShiftLeft, spell('?', '.')),
+                (Instruction::Increment, spell('.', '.')),
+                (Instruction::Decrement, spell('!', '!')),
+                (Instruction::Output, spell('!', '.')),
+                (Instruction::Input, spell('.', '!')),
+                (Instruction::LoopStart, spell('!', '?')),
+                (Instruction::LoopEnd, spell('?', '!')),
+            ]
+            .iter()
+            .map(|(instruction, words)| (*instruction, words.as_str())),
+        )
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn split(&self) -> Split {
+        self.split
+    }
+
+    /// How the dialect spells `instruction`, if it can.
+    pub fn spelling(&self, instruction: Instruction) -> Option<&[String]> {
+        self.spellings
+            .iter()
+            .find(|(candidate, _)| *candidate == instruction)
+            .map(|(_, words)| &words[..])
+    }
+
+    /// Whether `word` is part of any instruction's spelling.
+    pub fn knows(&self, word: &str) -> bool {
+        self.spellings
+            .iter()
+            .any(|(_, words)| words.iter().any(|known| same(known, word)))
+    }
+
+    /// Whether `source` is made of nothing but this dialect's words, and
+    /// has some.
+    pub fn recognizes(&self, source: &str) -> bool {
+        let tokens = self.split.tokens(source);
+        !tokens.is_empty() && tokens.iter().all(|token| self.knows(token.text))
+    }
+
+    /// Rewrites a program in this dialect as word pairs. Where spellings
+    /// share a beginning the longest that fits is taken. A word that starts
+    /// no instruction is an error, as in hnyfuck.
+    pub fn to_hny(&self, source: &str) -> Result<Conversion, ConvertError> {
+        let tokens = self.split.tokens(source);
+        let mut output = String::new();
+        let mut mappings = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let (instruction, length) = self
+                .spellings
+                .iter()
+                .filter(|(_, words)| {
+                    !words.is_empty()
+                        && tokens.len() - i >= words.len()
+                        && tokens[i..]
+                            .iter()
+                            .zip(words)
+                            .all(|(token, word)| same(token.text, word))
+                })
+                .map(|(instruction, words)| (*instruction, words.len()))
+                .max_by_key(|&(_, length)| length)
+                .ok_or(ConvertError::InvalidToken {
+                    span: tokens[i].span.clone(),
+                })?;
+            if !output.is_empty() {
+                output.push(' ');
+            }
+            let (first, second) = instruction.pair();
+            let start = output.len();
+            output.push_str(&format!("{} {}", first, second));
+            mappings.push(Mapping {
+                generated: start..output.len(),
+                original: tokens[i].span.start..tokens[i + length - 1].span.end,
+            });
+            i += length;
+        }
+        Ok(conversion(output, mappings))
+    }
+
+    /// Rewrites word pairs in this dialect. A trailing unpaired word is
+    /// dropped.
+    pub fn from_hny(&self, code: &str) -> Result<Conversion, ConvertError> {
+        let mut output = String::new();
+        let mut mappings = Vec::new();
+        let mut tokens = lexer::tokens(code, false);
+        while let (Some(first), Some(second)) = (tokens.next(), tokens.next()) {
+            let span: Range<usize> = first.span.start..second.span.end;
+            let words = Instruction::from_pair((first.text, second.text))
+                .and_then(|instruction| self.spelling(instruction))
+                .ok_or(ConvertError::InvalidToken { span: span.clone() })?;
+            if !output.is_empty() {
+                output.push(' ');
+            }
+            let start = output.len();
+            output.push_str(&words.join(self.split.separator()));
+            mappings.push(Mapping {
+                generated: start..output.len(),
+                original: span,
+            });
+        }
+        Ok(conversion(output, mappings))
+    }
+}
+
+fn conversion(output: String, mappings: Vec<Mapping>) -> Conversion {
+    Conversion {
+        output,
+        source_map: SourceMap {
+            source: None,
+            mappings,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hny_to_brainfuck;
+
+    #[test]
+    fn test_ook() {
+        let ook = Frontend::ook();
+        let source = "Ook. Ook. Ook! Ook?\n  Ook! Ook! Ook? Ook! Ook! Ook.";
+        let hny = ook.to_hny(source).unwrap();
+        assert_eq!(hny_to_brainfuck(&hny.output).unwrap().output, "+[-].");
+        assert_eq!(hny.source_map.lookup(0), Some(0..9));
+        assert_eq!(
+            ook.from_hny(&hny.output).unwrap().output.split(' ').count(),
+            10
+        );
+        assert_eq!(
+            ook.to_hny("Ook. Ook. Ook. Blub."),
+            Err(ConvertError::InvalidToken { span: 10..14 })
+        );
+        assert!(ook.to_hny("Ook.").is_err());
+    }
+
+    #[test]
+    fn test_emoji() {
+        let emoji = Frontend::emoji();
+        // +[-]. with a variation selector after one 🎊 and no spaces.
+        let source = "🎊🎉🎉🎉 🎉🎊\u{fe0f}🎆🎆🎊🎆";
+        let hny = emoji.to_hny(source).unwrap();
+        assert_eq!(hny_to_brainfuck(&hny.output).unwrap().output, "+[-].");
+        assert_eq!(
+            emoji.from_hny(&hny.output).unwrap().output,
+            "🎊🎉 🎉🎉 🎉🎊 🎆🎆 🎊🎆"
+        );
+        assert!(emoji.recognizes(source));
+        assert!(!emoji.recognizes(""));
+
+        // A thumbs up with a skin tone is one token, not a thumbs up and a
+        // stray modifier.
+        let thumbs = Frontend::from_toml(
+            "split = \"graphemes\"\n[instructions]\n\"+\" = \"👍🏽\"\n\".\" = \"👍\"",
+            "spec",
+        )
+        .unwrap();
+        let hny = thumbs.to_hny("👍🏽👍").unwrap().output;
+        assert_eq!(hny_to_brainfuck(&hny).unwrap().output, "+.");
+    }
+
+    #[test]
+    fn test_locale() {
+        let spanish = Frontend::locale("es").unwrap();
+        let hny = spanish.to_hny("Año Feliz Año Nuevo").unwrap().output;
+        assert_eq!(hny, "Year Happy Year New");
+        assert_eq!(
+            Frontend::locale("ja")
+                .unwrap()
+                .from_hny(&hny)
+                .unwrap()
+                .output,
+            "ございます 明けまして ございます おめでとう"
+        );
+        assert_eq!(Frontend::locale("xx"), None);
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let spec = "name = \"xmas\"\n[instructions]\n\"+\" = \"Ho Ho Ho\"\n\".\" = \"Jingle\"";
+        let xmas = Frontend::from_toml(spec, "spec").unwrap();
+        assert_eq!(xmas.name(), "xmas");
+        let hny = xmas.to_hny("Ho Ho Ho Jingle").unwrap().output;
+        assert_eq!(hny_to_brainfuck(&hny).unwrap().output, "+.");
+
+        let error = |spec| Frontend::from_toml(spec, "spec").unwrap_err();
+        assert_eq!(
+            error("[instructions]\n\"++\" = \"Ho\""),
+            SpecError::UnknownInstruction("++".to_string())
+        );
+        assert_eq!(
+            error("[instructions]\n\"+\" = \" \""),
+            SpecError::EmptySpelling('+')
+        );
+        assert_eq!(
+            error("[instructions]\n\"+\" = \"Ho  Ho\"\n\"-\" = \"Ho Ho\""),
+            SpecError::DuplicateSpelling("Ho Ho".to_string())
+        );
+    }
+
+    #[test]
+    fn test_longest_spelling() {
+        let frontend = Frontend::new(
+            "test",
+            [
+                (Instruction::Increment, "ho"),
+                (Instruction::Decrement, "ho ho"),
+            ],
+        );
+        let hny = frontend.to_hny("ho ho ho").unwrap().output;
+        assert_eq!(hny_to_brainfuck(&hny).unwrap().output, "-+");
+        assert!(Frontend::blub().knows("Blub!"));
+        assert!(!Frontend::blub().knows("Ook!"));
+    }
+}
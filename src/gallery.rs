@@ -0,0 +1,70 @@
+//! Classic programs shipped with the crate, to try the language without
+//! writing any.
+
+/// A program from the gallery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub source: &'static str,
+}
+
+pub const EXAMPLES: [Example; 4] = [
+    Example {
+        name: "hello",
+        description: "prints a greeting",
+        source: include_str!("../examples/hello.hny"),
+    },
+    Example {
+        name: "cat",
+        description: "copies its input to its output",
+        source: include_str!("../examples/cat.hny"),
+    },
+    Example {
+        name: "fibonacci",
+        description: "prints the Fibonacci numbers below 100",
+        source: include_str!("../examples/fibonacci.hny"),
+    },
+    Example {
+        name: "99-bottles",
+        description: "sings 99 Bottles of Beer",
+        source: include_str!("../examples/99-bottles.hny"),
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|example| example.name == name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{equiv, Program};
+
+    fn output(name: &str, input: &[u8]) -> String {
+        let program = Program::from_str(find(name).unwrap().source).unwrap();
+        match equiv::execute(&program, input, 10_000_000) {
+            equiv::Outcome::Finished { output, .. } => String::from_utf8(output).unwrap(),
+            outcome => panic!("{} did not finish: {:?}", name, outcome),
+        }
+    }
+
+    #[test]
+    fn test_examples() {
+        assert_eq!(output("hello", b""), "Happy New Year!");
+        assert_eq!(output("cat", b"Happy\nNew Year"), "Happy\nNew Year");
+        assert_eq!(
+            output("fibonacci", b""),
+            "1, 1, 2, 3, 5, 8, 13, 21, 34, 55, 89"
+        );
+
+        let song = output("99-bottles", b"");
+        assert!(song.starts_with(
+            "99 bottles of beer on the wall, 99 bottles of beer.\n\
+             Take one down and pass it around, 98 bottles of beer on the wall.\n\n"
+        ));
+        assert!(song.contains("\n\n1 bottle of beer on the wall, 1 bottle of beer.\n"));
+        assert!(song.ends_with("buy some more, 99 bottles of beer on the wall.\n"));
+        assert_eq!(song.lines().filter(|line| line.is_empty()).count(), 99);
+    }
+}
@@ -0,0 +1,192 @@
+//! Editor grammars generated from a dialect's spellings, so highlighting and
+//! folding follow whatever words a program is written in. For now that's
+//! [tree-sitter](https://tree-sitter.github.io): a `grammar.js` to generate a
+//! parser from, and the queries editors read to highlight and fold with it.
+
+use std::fmt::Write;
+
+use crate::{frontend::Frontend, frontend::Split, Instruction};
+
+/// The files of a tree-sitter grammar, by their path in its repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeSitter {
+    pub name: String,
+    /// `grammar.js`.
+    pub grammar: String,
+    /// `queries/highlights.scm`.
+    pub highlights: String,
+    /// `queries/folds.scm`.
+    pub folds: String,
+}
+
+impl TreeSitter {
+    /// Each file's path and contents.
+    pub fn files(&self) -> [(&'static str, &str); 3] {
+        [
+            ("grammar.js", &self.grammar),
+            ("queries/highlights.scm", &self.highlights),
+            ("queries/folds.scm", &self.folds),
+        ]
+    }
+}
+
+/// Names the parser `tree-sitter-NAME` is built as: letters, digits and
+/// underscores, not starting with a digit.
+fn grammar_name(dialect: &str) -> String {
+    let name = dialect
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '_',
+        })
+        .collect::<String>();
+    match name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        true => format!("hnyfuck_{}", name),
+        false => name,
+    }
+}
+
+fn rule_name(instruction: Instruction) -> String {
+    instruction.name().replace('-', "_")
+}
+
+/// A grammar for programs written in `frontend`'s words, with `extension`,
+/// if given, as the pair calling an extension function. Instructions the
+/// dialect can't spell are left out, and loops too unless it spells both
+/// ends.
+pub fn tree_sitter(frontend: &Frontend, extension: Option<(&str, &str)>) -> TreeSitter {
+    let name = grammar_name(frontend.name());
+    let literal = |word: &str| serde_json::to_string(word).expect("strings serialize");
+    let sequence = |words: &[&str]| match words {
+        [word] => literal(word),
+        words => format!(
+            "seq({})",
+            words
+                .iter()
+                .map(|word| literal(word))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+
+    let mut rules = Vec::new();
+    for instruction in Instruction::ALL {
+        if let Some(words) = frontend.spelling(instruction) {
+            let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+            rules.push((rule_name(instruction), sequence(&words)));
+        }
+    }
+    if let Some((first, second)) = extension {
+        rules.push(("extension_call".to_string(), sequence(&[first, second])));
+    }
+    let loops = [Instruction::LoopStart, Instruction::LoopEnd]
+        .into_iter()
+        .all(|bracket| frontend.spelling(bracket).is_some());
+    let items = rules
+        .iter()
+        .map(|(rule, _)| rule.as_str())
+        .filter(|rule| !loops || !matches!(*rule, "loop_start" | "loop_end"))
+        .map(|rule| format!("$.{}", rule))
+        .chain(loops.then(|| "$.loop".to_string()))
+        .collect::<Vec<_>>();
+
+    let mut grammar = String::new();
+    writeln!(
+        grammar,
+        "// Generated by hnyfuck {}; edit the dialect, not this file.",
+        env!("CARGO_PKG_VERSION")
+    )
+    .ok();
+    writeln!(grammar, "module.exports = grammar({{").ok();
+    writeln!(grammar, "  name: '{}',", name).ok();
+    writeln!(grammar, "  extras: $ => [/\\s/],").ok();
+    if frontend.split() == Split::Words {
+        // Keywords only match whole words, so "Happyish" isn't "Happy".
+        writeln!(grammar, "  word: $ => $._word,").ok();
+    }
+    writeln!(grammar, "  rules: {{").ok();
+    writeln!(
+        grammar,
+        "    program: $ => seq(optional($.shebang), repeat($._item)),"
+    )
+    .ok();
+    writeln!(grammar, "    _item: $ => choice({}),", items.join(", ")).ok();
+    if loops {
+        writeln!(
+            grammar,
+            "    loop: $ => seq($.loop_start, repeat($._item), $.loop_end),"
+        )
+        .ok();
+    }
+    for (rule, body) in &rules {
+        writeln!(grammar, "    {}: $ => {},", rule, body).ok();
+    }
+    writeln!(grammar, "    shebang: $ => token(prec(1, /#![^\\n]*/)),").ok();
+    if frontend.split() == Split::Words {
+        writeln!(grammar, "    _word: $ => /[^\\s]+/,").ok();
+    }
+    writeln!(grammar, "  }},").ok();
+    writeln!(grammar, "}});").ok();
+
+    let mut highlights = String::new();
+    for (rule, _) in &rules {
+        let capture = match rule.as_str() {
+            "loop_start" | "loop_end" => "@punctuation.bracket",
+            "output" | "input" => "@function.builtin",
+            "extension_call" => "@function.call",
+            _ => "@operator",
+        };
+        writeln!(highlights, "({}) {}", rule, capture).ok();
+    }
+    writeln!(highlights, "(shebang) @keyword.directive").ok();
+    writeln!(highlights, "(ERROR) @error").ok();
+
+    let folds = match loops {
+        true => "(loop) @fold\n".to_string(),
+        false => String::new(),
+    };
+    TreeSitter {
+        name,
+        grammar,
+        highlights,
+        folds,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EXTENSION;
+
+    #[test]
+    fn test_tree_sitter() {
+        let hnyfuck = tree_sitter(&Frontend::hnyfuck(), Some(EXTENSION));
+        assert_eq!(hnyfuck.name, "hnyfuck");
+        assert!(hnyfuck
+            .grammar
+            .contains("    shift_left: $ => seq(\"Happy\", \"New\"),\n"));
+        assert!(hnyfuck
+            .grammar
+            .contains("    extension_call: $ => seq(\"Year\", \"Year\"),\n"));
+        assert!(hnyfuck.grammar.contains("  word: $ => $._word,\n"));
+        assert!(hnyfuck
+            .highlights
+            .contains("(loop_start) @punctuation.bracket\n"));
+        assert_eq!(hnyfuck.folds, "(loop) @fold\n");
+
+        let emoji = tree_sitter(&Frontend::emoji(), None);
+        assert!(emoji
+            .grammar
+            .contains("    increment: $ => seq(\"🎊\", \"🎉\"),\n"));
+        assert!(!emoji.grammar.contains("word:") && !emoji.grammar.contains("extension"));
+
+        let partial = Frontend::new("2 \"quoted\"", [(Instruction::Output, "say")]);
+        let partial = tree_sitter(&partial, None);
+        assert_eq!(partial.name, "hnyfuck_2__quoted_");
+        assert!(partial
+            .grammar
+            .contains("    _item: $ => choice($.output),\n"));
+        assert!(partial.grammar.contains("    output: $ => \"say\",\n"));
+        assert_eq!(partial.folds, "");
+    }
+}
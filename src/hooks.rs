@@ -0,0 +1,63 @@
+//! Callbacks on every cell the interpreter reads or writes, for watchpoints,
+//! heatmaps and the like. Only built with the `hooks` feature, so the
+//! interpreter pays nothing for them otherwise.
+
+use std::fmt;
+
+/// Watches tape accesses. `index` is the cell's offset from the starting
+/// cell, negative to its left. Any `FnMut(index, old, new)` is a hook, called
+/// with `old == new` for reads.
+pub trait MemoryHook {
+    /// Called when an instruction looks at a cell without changing it:
+    /// output, loop tests, and the source of a `muladd`.
+    fn read(&mut self, _index: isize, _value: u8) {}
+    /// Called when a cell is written, even with the value it already had.
+    fn write(&mut self, _index: isize, _old: u8, _new: u8) {}
+}
+
+impl<F: FnMut(isize, u8, u8)> MemoryHook for F {
+    fn read(&mut self, index: isize, value: u8) {
+        self(index, value, value)
+    }
+
+    fn write(&mut self, index: isize, old: u8, new: u8) {
+        self(index, old, new)
+    }
+}
+
+pub(crate) struct Hook(pub(crate) Box<dyn MemoryHook + Send>);
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hook")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{brainfuck_to_hny, HnyFuck};
+
+    #[test]
+    fn test_memory_hook() {
+        // Track the last value written to each cell. Tiering is on, so the
+        // loop is compiled after its first iteration and still watched.
+        let code = brainfuck_to_hny("<+++[->++<]>>+").output;
+        let tape = Arc::new(Mutex::new(BTreeMap::new()));
+        let mut hny = HnyFuck::from_str(&code);
+        hny.set_tier_threshold(Some(1));
+        let cells = tape.clone();
+        hny.set_memory_hook(move |index, _, new| {
+            cells.lock().unwrap().insert(index, new);
+        });
+        hny.run();
+        assert_eq!(
+            *tape.lock().unwrap(),
+            BTreeMap::from([(-1, 0), (0, 6), (1, 1)])
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fmt,
+};
+
+use crate::{
+    lexer,
+    pass::PassManager,
+    ranges::{Interval, Ranges},
+    Instruction,
+};
+
+/// A compiled instruction. Runs of `+`/`-` and `<`/`>` are folded together and
+/// loops are resolved to jump targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add(u8),
+    Move(isize),
+    Output,
+    Input,
+    Set(u8),
+    /// Adds the current cell times `factor` to the cell at `offset`, leaving
+    /// the pointer where it is. Does nothing when the current cell is zero.
+    MulAdd {
+        offset: isize,
+        factor: u8,
+    },
+    /// Jumps past the matching `JumpIfNonZero` when the current cell is zero.
+    JumpIfZero(usize),
+    /// Jumps back past the matching `JumpIfZero` when the current cell is non-zero.
+    JumpIfNonZero(usize),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Op::Add(n) => write!(f, "add {}", n as i8),
+            Op::Move(n) => write!(f, "move {}", n),
+            Op::Output => write!(f, "out"),
+            Op::Input => write!(f, "in"),
+            Op::Set(n) => write!(f, "set {}", n),
+            Op::MulAdd { offset, factor } => write!(f, "muladd {} {}", offset, factor as i8),
+            Op::JumpIfZero(target) => write!(f, "jz {}", target),
+            Op::JumpIfNonZero(target) => write!(f, "jnz {}", target),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    InvalidToken { pair: usize },
+    UnmatchedLoopStart { pair: usize },
+    UnmatchedLoopEnd { pair: usize },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::InvalidToken { pair } => write!(f, "invalid token at pair {}", pair),
+            CompileError::UnmatchedLoopStart { pair } => {
+                write!(f, "unmatched loop start at pair {}", pair)
+            }
+            CompileError::UnmatchedLoopEnd { pair } => {
+                write!(f, "unmatched loop end at pair {}", pair)
+            }
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    ops: Vec<Op>,
+}
+
+impl Program {
+    /// Compiles a sequence of words. A trailing unpaired word is ignored, as
+    /// the interpreter does.
+    pub fn compile<'a>(tokens: impl IntoIterator<Item = &'a str>) -> Result<Program, CompileError> {
+        trace_span!(tracing::Level::DEBUG, "compile");
+        let mut ops = Vec::new();
+        let mut loops = Vec::new();
+        let mut tokens = tokens.into_iter();
+        let mut pair = 0;
+
+        while let (Some(first), Some(second)) = (tokens.next(), tokens.next()) {
+            let instruction = Instruction::from_pair((first, second))
+                .ok_or(CompileError::InvalidToken { pair })?;
+            match instruction {
+                Instruction::Increment => push_add(&mut ops, 1),
+                Instruction::Decrement => push_add(&mut ops, u8::MAX),
+                Instruction::ShiftRight => push_move(&mut ops, 1),
+                Instruction::ShiftLeft => push_move(&mut ops, -1),
+                Instruction::Output => ops.push(Op::Output),
+                Instruction::Input => ops.push(Op::Input),
+                Instruction::LoopStart => {
+                    loops.push((pair, ops.len()));
+                    ops.push(Op::JumpIfZero(0));
+                }
+                Instruction::LoopEnd => {
+                    let (_, start) = loops.pop().ok_or(CompileError::UnmatchedLoopEnd { pair })?;
+                    ops[start] = Op::JumpIfZero(ops.len());
+                    ops.push(Op::JumpIfNonZero(start));
+                }
+            }
+            pair += 1;
+        }
+
+        trace_event!(
+            tracing::Level::DEBUG,
+            pairs = pair,
+            ops = ops.len(),
+            "compiled"
+        );
+        match loops.pop() {
+            Some((pair, _)) => Err(CompileError::UnmatchedLoopStart { pair }),
+            None => Ok(Program { ops }),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &str) -> Result<Program, CompileError> {
+        Program::parse(input, false)
+    }
+
+    pub fn parse(input: &str, lenient: bool) -> Result<Program, CompileError> {
+        Program::compile(lexer::tokens(input, lenient).map(|token| token.text))
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// A hash of the ops, stable across builds and platforms, to tell whether
+    /// two programs are the same.
+    pub fn fingerprint(&self) -> u64 {
+        // FNV-1a over the listing.
+        self.to_string()
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    /// The ops, for [`Pass`](crate::pass::Pass)es to rewrite. Every
+    /// `JumpIfZero` must still be matched by a `JumpIfNonZero` pointing back
+    /// at it once the pass is done.
+    pub fn ops_mut(&mut self) -> &mut Vec<Op> {
+        &mut self.ops
+    }
+
+    /// Rewrites clear loops like `[-]` into `set 0`, and loops that move the
+    /// current cell into others like `[->++<]` into `muladd`s followed by `set 0`.
+    pub fn optimize(&mut self) {
+        self.ops = rewrite_loops(&self.ops, |_, body| simplify_loop(body));
+    }
+
+    /// Unrolls loops that [`Ranges`] proves run a fixed number of times into
+    /// plain adds, e.g. `++++[>+<--]` into `>++<`. This assumes the program
+    /// starts on a blank tape, so unlike [`optimize`](Program::optimize) it
+    /// must not be used on fragments.
+    pub fn fold_counted_loops(&mut self) {
+        let ranges = Ranges::new(self);
+        let program = self.clone();
+        self.ops = rewrite_loops(&program.ops, |start, body| {
+            match ranges.trip_count(&program, start)? {
+                0 => None,
+                count => unroll(body, count),
+            }
+        });
+    }
+
+    /// Removes ops whose effects can never be observed: loops entered on a
+    /// cell [`Ranges`] proves zero, sets of the value a cell already holds,
+    /// and writes overwritten before anything reads them. Like
+    /// [`fold_counted_loops`](Program::fold_counted_loops) this assumes a
+    /// blank tape at the start.
+    pub fn eliminate_dead_code(&mut self) {
+        // Each removal can make others possible, so repeat until none is left.
+        loop {
+            let before = self.ops.clone();
+            self.eliminate_dead_code_once();
+            if self.ops == before {
+                break;
+            }
+        }
+    }
+
+    fn eliminate_dead_code_once(&mut self) {
+        let ranges = Ranges::new(self);
+        self.ops = rewrite_loops(&self.ops, |start, _| match ranges.current(start) {
+            Some(current) if current != Interval::ZERO => None,
+            _ => Some(Vec::new()),
+        });
+
+        let ranges = Ranges::new(self);
+        let redundant = self.ops.iter().enumerate().map(|(i, op)| match *op {
+            Op::Set(n) => ranges.current(i) == Some(Interval::constant(n)),
+            _ => false,
+        });
+        self.ops = remove(&self.ops, redundant.collect());
+
+        self.ops = remove(&self.ops, dead_stores(&self.ops));
+    }
+
+    /// Runs the ops before the first loop, input or output at compile time and
+    /// replaces them with a `set` of each cell they leave non-zero, then a
+    /// move to where they leave the pointer. Assumes a blank tape at the start.
+    pub fn fold_constant_prefix(&mut self) {
+        let end = self
+            .ops
+            .iter()
+            .position(|op| {
+                !matches!(
+                    op,
+                    Op::Add(_) | Op::Move(_) | Op::Set(_) | Op::MulAdd { .. }
+                )
+            })
+            .unwrap_or(self.ops.len());
+
+        let mut tape = BTreeMap::<isize, u8>::new();
+        let mut pointer = 0;
+        for op in &self.ops[..end] {
+            let cell = tape.get(&pointer).copied().unwrap_or(0);
+            match *op {
+                Op::Add(n) => {
+                    tape.insert(pointer, cell.wrapping_add(n));
+                }
+                Op::Move(n) => pointer += n,
+                Op::Set(n) => {
+                    tape.insert(pointer, n);
+                }
+                Op::MulAdd { offset, factor } => {
+                    let target = tape.entry(pointer + offset).or_insert(0);
+                    *target = target.wrapping_add(cell.wrapping_mul(factor));
+                }
+                _ => unreachable!("the prefix has no loops or I/O"),
+            }
+        }
+        tape.retain(|_, value| *value != 0);
+
+        // Visit the cells from whichever end leaves the shorter walk to the
+        // final pointer.
+        let mut cells = tape.into_iter().collect::<Vec<_>>();
+        if let (Some(&(lo, _)), Some(&(hi, _))) = (cells.first(), cells.last()) {
+            let left_first = lo.abs() + (pointer - hi).abs();
+            let right_first = hi.abs() + (pointer - lo).abs();
+            if right_first < left_first {
+                cells.reverse();
+            }
+        }
+        let mut prefix = Vec::new();
+        let mut at = 0;
+        let walk = cells
+            .into_iter()
+            .map(|(offset, value)| (offset, Some(value)))
+            .chain([(pointer, None)]);
+        for (offset, value) in walk {
+            if offset != at {
+                push_move(&mut prefix, offset - at);
+            }
+            prefix.extend(value.map(Op::Set));
+            at = offset;
+        }
+
+        if prefix.len() < end {
+            self.ops.splice(..end, prefix.clone());
+            let shift = end - prefix.len();
+            for op in &mut self.ops[prefix.len()..] {
+                match op {
+                    Op::JumpIfZero(target) | Op::JumpIfNonZero(target) => *target -= shift,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Runs the built-in passes of optimization `level`: none at 0, [`optimize`]
+    /// at 1, and at 2 also [`fold_counted_loops`], [`fold_constant_prefix`] and
+    /// [`eliminate_dead_code`], which assume the program is run from the start
+    /// on a blank tape. See [`PassManager`] to pick passes by name.
+    ///
+    /// [`optimize`]: Program::optimize
+    /// [`fold_counted_loops`]: Program::fold_counted_loops
+    /// [`fold_constant_prefix`]: Program::fold_constant_prefix
+    /// [`eliminate_dead_code`]: Program::eliminate_dead_code
+    pub fn optimize_level(&mut self, level: u8) {
+        PassManager::new(level).run(self);
+    }
+}
+
+/// Copies `ops` without the ones marked in `dead`, relinking jumps and
+/// folding together the ops that become neighbours.
+fn remove(ops: &[Op], dead: Vec<bool>) -> Vec<Op> {
+    let mut kept = Vec::new();
+    let mut loops = Vec::new();
+    for (op, dead) in ops.iter().zip(dead) {
+        match *op {
+            _ if dead => continue,
+            Op::JumpIfZero(_) => loops.push(kept.len()),
+            Op::JumpIfNonZero(_) => {
+                let start = loops.pop().expect("compiled programs have balanced loops");
+                kept[start] = Op::JumpIfZero(kept.len());
+                kept.push(Op::JumpIfNonZero(start));
+                continue;
+            }
+            _ => (),
+        }
+        kept.push(*op);
+    }
+    rewrite_loops(&kept, |_, _| None)
+}
+
+/// Marks writes whose value is overwritten by a `set` before anything reads
+/// it. Only straight-line runs are considered: every cell counts as read at a
+/// loop boundary and at the end of the program.
+fn dead_stores(ops: &[Op]) -> Vec<bool> {
+    let mut dead = vec![false; ops.len()];
+    let mut overwritten = HashSet::new();
+    // Offset from where the pointer is at the end of the current run.
+    let mut pointer = 0isize;
+
+    for (i, op) in ops.iter().enumerate().rev() {
+        match *op {
+            Op::JumpIfZero(_) | Op::JumpIfNonZero(_) => {
+                overwritten.clear();
+                pointer = 0;
+            }
+            Op::Move(n) => pointer -= n,
+            Op::Set(_) => dead[i] = !overwritten.insert(pointer),
+            Op::Add(_) => dead[i] = overwritten.contains(&pointer),
+            // End of input leaves the cell as it was, so input does not
+            // overwrite it.
+            Op::Output | Op::Input => {
+                overwritten.remove(&pointer);
+            }
+            Op::MulAdd { offset, .. } => {
+                dead[i] = overwritten.contains(&(pointer + offset));
+                if !dead[i] {
+                    overwritten.remove(&pointer);
+                    overwritten.remove(&(pointer + offset));
+                }
+            }
+        }
+    }
+    dead
+}
+
+/// Copies `ops`, offering every loop to `replace` with the index of its `jz`
+/// and its body. Loops it returns ops for are replaced by them; the others are
+/// kept and their bodies rewritten in turn. Adds, moves and sets are folded
+/// together as they are copied.
+fn rewrite_loops(ops: &[Op], mut replace: impl FnMut(usize, &[Op]) -> Option<Vec<Op>>) -> Vec<Op> {
+    let mut out = Vec::new();
+    let mut loops = Vec::new();
+    let mut i = 0;
+    while let Some(&op) = ops.get(i) {
+        match op {
+            Op::JumpIfZero(end) => match replace(i, &ops[i + 1..end]) {
+                Some(replacement) => {
+                    for op in replacement {
+                        match op {
+                            Op::Add(n) => push_add(&mut out, n),
+                            Op::Move(n) => push_move(&mut out, n),
+                            Op::Set(n) => push_set(&mut out, n),
+                            op => out.push(op),
+                        }
+                    }
+                    i = end;
+                }
+                None => {
+                    loops.push(out.len());
+                    out.push(Op::JumpIfZero(0));
+                }
+            },
+            Op::JumpIfNonZero(_) => {
+                let start = loops.pop().expect("compiled programs have balanced loops");
+                out[start] = Op::JumpIfZero(out.len());
+                out.push(Op::JumpIfNonZero(start));
+            }
+            Op::Add(n) => push_add(&mut out, n),
+            Op::Move(n) => push_move(&mut out, n),
+            Op::Set(n) => push_set(&mut out, n),
+            op => out.push(op),
+        }
+        i += 1;
+    }
+    out
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, op) in self.ops.iter().enumerate() {
+            writeln!(f, "{:>6}  {}", i, op)?;
+        }
+        Ok(())
+    }
+}
+
+fn simplify_loop(body: &[Op]) -> Option<Vec<Op>> {
+    if let [Op::Add(n)] = body {
+        // An odd step visits every value, so the loop always reaches zero.
+        return (n % 2 == 1).then(|| vec![Op::Set(0)]);
+    }
+
+    let mut offset = 0;
+    let mut deltas = Vec::<(isize, u8)>::new();
+    for op in body {
+        match *op {
+            Op::Add(n) => match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, delta)) => *delta = delta.wrapping_add(n),
+                None => deltas.push((offset, n)),
+            },
+            Op::Move(n) => offset += n,
+            _ => return None,
+        }
+    }
+    if offset != 0 || !deltas.contains(&(0, u8::MAX)) {
+        return None;
+    }
+
+    deltas.sort_by_key(|(offset, _)| *offset);
+    let mut replacement = deltas
+        .into_iter()
+        .filter(|&(offset, factor)| offset != 0 && factor != 0)
+        .map(|(offset, factor)| Op::MulAdd { offset, factor })
+        .collect::<Vec<_>>();
+    replacement.push(Op::Set(0));
+    Some(replacement)
+}
+
+/// The adds of a loop body that runs `count` times, ending with the current
+/// cell cleared, or `None` if the body does more than add and move.
+fn unroll(body: &[Op], count: u32) -> Option<Vec<Op>> {
+    let mut offset = 0;
+    let mut deltas = Vec::<(isize, u8)>::new();
+    for op in body {
+        match *op {
+            Op::Add(n) => match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, delta)) => *delta = delta.wrapping_add(n),
+                None => deltas.push((offset, n)),
+            },
+            Op::Move(n) => offset += n,
+            _ => return None,
+        }
+    }
+
+    let mut unrolled = Vec::new();
+    for (offset, delta) in deltas.into_iter().filter(|&(offset, _)| offset != 0) {
+        unrolled.push(Op::Move(offset));
+        unrolled.push(Op::Add(delta.wrapping_mul(count as u8)));
+        unrolled.push(Op::Move(-offset));
+    }
+    unrolled.push(Op::Set(0));
+    Some(unrolled)
+}
+
+fn push_add(ops: &mut Vec<Op>, n: u8) {
+    match ops.last_mut() {
+        Some(Op::Set(m)) => *m = m.wrapping_add(n),
+        Some(Op::Add(m)) => {
+            *m = m.wrapping_add(n);
+            if *m == 0 {
+                ops.pop();
+            }
+        }
+        _ => ops.push(Op::Add(n)),
+    }
+}
+
+fn push_set(ops: &mut Vec<Op>, n: u8) {
+    match ops.last_mut() {
+        Some(Op::Add(_)) | Some(Op::Set(_)) => *ops.last_mut().unwrap() = Op::Set(n),
+        _ => ops.push(Op::Set(n)),
+    }
+}
+
+fn push_move(ops: &mut Vec<Op>, n: isize) {
+    match ops.last_mut() {
+        Some(Op::Move(m)) => {
+            *m += n;
+            if *m == 0 {
+                ops.pop();
+            }
+        }
+        _ => ops.push(Op::Move(n)),
+    }
+}
@@ -0,0 +1,115 @@
+use std::ops::Range;
+
+/// The only words that make up instructions.
+pub const KEYWORDS: [&str; 3] = ["Happy", "New", "Year"];
+
+/// A word of the program and its byte range in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub span: Range<usize>,
+}
+
+/// Whether `c` can be part of a word in lenient mode. Apostrophes are kept so
+/// that "Happy's" is one word, not the keyword "Happy" followed by "s".
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\''
+}
+
+/// Length of the `#!` line `source` starts with, if any, newline included,
+/// so a program can be made executable with `#!/usr/bin/env hnyfuck`.
+pub fn shebang_len(source: &str) -> usize {
+    if !source.starts_with("#!") {
+        return 0;
+    }
+    source.find('\n').map_or(source.len(), |end| end + 1)
+}
+
+/// Splits `source` into words. In lenient mode words are separated by
+/// punctuation as well as whitespace ("Year!", "Happy,New", "(Happy)") and
+/// words that are not keywords are skipped, so programs can be written as
+/// prose. A leading `#!` line is skipped in either mode.
+pub fn tokens(source: &str, lenient: bool) -> impl Iterator<Item = Token<'_>> {
+    let body = &source[shebang_len(source)..];
+    let words: Box<dyn Iterator<Item = &str>> = match lenient {
+        true => Box::new(body.split(|c| !is_word_char(c))),
+        false => Box::new(body.split_whitespace()),
+    };
+    words
+        .filter(move |word| !word.is_empty() && (!lenient || KEYWORDS.contains(word)))
+        .map(move |text| {
+            let start = text.as_ptr() as usize - source.as_ptr() as usize;
+            Token {
+                text,
+                span: start..start + text.len(),
+            }
+        })
+}
+
+/// One-based line and column of the byte at `offset`.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rfind('\n').map_or(offset, |i| offset - i - 1) + 1;
+    (line, column)
+}
+
+/// Line and column of each word pair in `source`.
+pub(crate) fn pair_positions(source: &str, lenient: bool) -> Vec<(usize, usize)> {
+    tokens(source, lenient)
+        .step_by(2)
+        .map(|token| line_col(source, token.span.start))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        let source = "Happy New Year! Dear friend, Happy,";
+        let strict = tokens(source, false).map(|t| t.text).collect::<Vec<_>>();
+        assert_eq!(
+            strict,
+            ["Happy", "New", "Year!", "Dear", "friend,", "Happy,"]
+        );
+
+        let lenient = tokens(source, true).collect::<Vec<_>>();
+        assert_eq!(
+            lenient.iter().map(|t| t.text).collect::<Vec<_>>(),
+            ["Happy", "New", "Year", "Happy"]
+        );
+        assert_eq!(lenient[2].span, 10..14);
+
+        let spaced = "Happy\u{3000}New\u{a0}\tYear\u{85}Year";
+        assert!(tokens(spaced, false)
+            .map(|t| t.text)
+            .eq(spaced.split_whitespace()));
+    }
+
+    #[test]
+    fn test_shebang() {
+        let source = "#!/usr/bin/env hnyfuck Happy\nYear Happy";
+        assert_eq!(shebang_len(source), 29);
+        let strict = tokens(source, false).collect::<Vec<_>>();
+        assert_eq!(
+            strict.iter().map(|t| t.text).collect::<Vec<_>>(),
+            ["Year", "Happy"]
+        );
+        assert_eq!(strict[0].span, 29..33);
+        assert_eq!(tokens("#!hnyfuck", false).count(), 0);
+        assert_eq!(shebang_len(" #!Year"), 0);
+    }
+
+    #[test]
+    fn test_tokens_punctuation() {
+        let source = "\"Happy,New Year!\" (Year) Happy's New-Year; Happy?";
+        let lenient = tokens(source, true).collect::<Vec<_>>();
+        assert_eq!(
+            lenient.iter().map(|t| t.text).collect::<Vec<_>>(),
+            ["Happy", "New", "Year", "Year", "New", "Year", "Happy"]
+        );
+        assert_eq!(lenient[1].span, 7..10);
+    }
+}
@@ -0,0 +1,1679 @@
+use core::panic;
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+#[macro_use]
+mod trace;
+
+pub mod analyze;
+pub mod ast;
+pub mod audit;
+pub mod bundle;
+pub mod cfg;
+pub mod checkpoint;
+pub mod codegen;
+pub mod compression;
+pub mod config;
+pub mod convert;
+pub mod dap;
+pub mod debugger;
+pub mod encoding;
+pub mod equiv;
+pub mod explain;
+pub mod extension;
+#[cfg(feature = "net")]
+pub mod fetch;
+pub mod files;
+pub mod formatter;
+pub mod frontend;
+pub mod gallery;
+pub mod grammar;
+#[cfg(feature = "hooks")]
+pub mod hooks;
+pub mod ir;
+pub mod lexer;
+pub mod limits;
+pub mod lint;
+pub mod middleware;
+pub mod newline;
+pub mod obfuscate;
+pub mod output;
+pub mod pass;
+pub mod poll;
+pub mod postmortem;
+pub mod profile;
+pub mod progress;
+pub mod ranges;
+pub mod repl;
+pub mod rpc;
+pub mod sandbox;
+pub mod server;
+pub mod spec;
+pub mod specialize;
+pub mod stats;
+pub mod steg;
+pub mod superopt;
+pub mod tape;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod tier;
+pub mod transform;
+pub mod verify;
+
+pub use convert::{brainfuck_to_hny, hny_to_brainfuck, SourceMap};
+pub use explain::explain;
+pub use formatter::{format, FormatStyle, Layout};
+pub use ir::{CompileError, Op, Program};
+pub use limits::{Limits, RunOutcome};
+pub use obfuscate::obfuscate;
+pub use profile::{hot_loops, HotLoop};
+pub use progress::Progress;
+pub use stats::{LoopStats, OpStats, ProgramStats, RunStats};
+pub use tier::DEFAULT_TIER_THRESHOLD;
+
+use extension::{Extension, Extensions};
+#[cfg(feature = "hooks")]
+use hooks::{Hook, MemoryHook};
+use limits::Exit;
+use middleware::{Chain, Middleware};
+use output::Flush;
+use progress::Reporter;
+use tape::Tape;
+use tier::Tiering;
+use transform::{Transform, Transforms};
+
+pub const SHIFT_LEFT: (&str, &str) = ("Happy", "New");
+pub const SHIFT_RIGHT: (&str, &str) = ("New", "Year");
+pub const INCREMENT: (&str, &str) = ("Year", "Happy");
+pub const DECREMENT: (&str, &str) = ("Happy", "Year");
+pub const OUTPUT: (&str, &str) = ("Year", "New");
+pub const INPUT: (&str, &str) = ("New", "Happy");
+pub const LOOP_START: (&str, &str) = ("Happy", "Happy");
+pub const LOOP_END: (&str, &str) = ("New", "New");
+/// Calls an [extension](extension) function, when extensions are enabled.
+pub const EXTENSION: (&str, &str) = ("Year", "Year");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Instruction {
+    ShiftLeft,
+    ShiftRight,
+    Increment,
+    Decrement,
+    Output,
+    Input,
+    LoopStart,
+    LoopEnd,
+}
+
+impl Instruction {
+    pub const ALL: [Instruction; 8] = [
+        Instruction::ShiftLeft,
+        Instruction::ShiftRight,
+        Instruction::Increment,
+        Instruction::Decrement,
+        Instruction::Output,
+        Instruction::Input,
+        Instruction::LoopStart,
+        Instruction::LoopEnd,
+    ];
+
+    pub fn from_pair(pair: (&str, &str)) -> Option<Instruction> {
+        match pair {
+            SHIFT_LEFT => Some(Instruction::ShiftLeft),
+            SHIFT_RIGHT => Some(Instruction::ShiftRight),
+            INCREMENT => Some(Instruction::Increment),
+            DECREMENT => Some(Instruction::Decrement),
+            OUTPUT => Some(Instruction::Output),
+            INPUT => Some(Instruction::Input),
+            LOOP_START => Some(Instruction::LoopStart),
+            LOOP_END => Some(Instruction::LoopEnd),
+            _ => None,
+        }
+    }
+
+    pub fn from_symbol(symbol: char) -> Option<Instruction> {
+        Instruction::ALL
+            .into_iter()
+            .find(|instruction| instruction.symbol() == symbol)
+    }
+
+    pub fn pair(self) -> (&'static str, &'static str) {
+        match self {
+            Instruction::ShiftLeft => SHIFT_LEFT,
+            Instruction::ShiftRight => SHIFT_RIGHT,
+            Instruction::Increment => INCREMENT,
+            Instruction::Decrement => DECREMENT,
+            Instruction::Output => OUTPUT,
+            Instruction::Input => INPUT,
+            Instruction::LoopStart => LOOP_START,
+            Instruction::LoopEnd => LOOP_END,
+        }
+    }
+
+    pub fn symbol(self) -> char {
+        match self {
+            Instruction::ShiftLeft => '<',
+            Instruction::ShiftRight => '>',
+            Instruction::Increment => '+',
+            Instruction::Decrement => '-',
+            Instruction::Output => '.',
+            Instruction::Input => ',',
+            Instruction::LoopStart => '[',
+            Instruction::LoopEnd => ']',
+        }
+    }
+
+    /// Kebab-case name, for listings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Instruction::ShiftLeft => "shift-left",
+            Instruction::ShiftRight => "shift-right",
+            Instruction::Increment => "increment",
+            Instruction::Decrement => "decrement",
+            Instruction::Output => "output",
+            Instruction::Input => "input",
+            Instruction::LoopStart => "loop-start",
+            Instruction::LoopEnd => "loop-end",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Instruction::ShiftLeft => "move the pointer one cell left",
+            Instruction::ShiftRight => "move the pointer one cell right",
+            Instruction::Increment => "add one to the current cell",
+            Instruction::Decrement => "subtract one from the current cell",
+            Instruction::Output => "print the current cell as a byte",
+            Instruction::Input => "read a byte into the current cell",
+            Instruction::LoopStart => "start a loop, skipped if the current cell is zero",
+            Instruction::LoopEnd => "repeat the loop while the current cell is non-zero",
+        }
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Writes `instructions` as word pairs on one line.
+pub(crate) fn to_source(instructions: &[Instruction]) -> String {
+    let mut out = instructions
+        .iter()
+        .map(|instruction| {
+            let (first, second) = instruction.pair();
+            format!("{} {}", first, second)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    out.push('\n');
+    out
+}
+
+pub fn from_brainfuck(code: &str) -> HnyFuck {
+    let hny_code = code
+        .chars()
+        .map(|c| match c {
+            '>' => SHIFT_RIGHT,
+            '<' => SHIFT_LEFT,
+            '+' => INCREMENT,
+            '-' => DECREMENT,
+            '.' => OUTPUT,
+            ',' => INPUT,
+            '[' => LOOP_START,
+            ']' => LOOP_END,
+            _ => panic!("Invalid character"),
+        })
+        .map(|(a, b)| format!("{} {}", a, b))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    HnyFuck::new(TokenStream::from_str(&hny_code))
+}
+
+/// The words of a program, as byte ranges into its text rather than copies,
+/// so tokenizing allocates only the list of ranges and a loop body is a view
+/// of its program's words.
+#[derive(Debug, Clone)]
+pub struct TokenStream {
+    /// The program's text, shared between copies and loop bodies, so a copy
+    /// of a program is cheap to take.
+    source: Arc<String>,
+    /// Byte range in `source` of each token, shared likewise.
+    spans: Arc<Vec<Range<usize>>>,
+    /// Index into `spans` of the next token.
+    position: usize,
+    /// Index into `spans` past the last token of this stream.
+    end: usize,
+    offset: usize,
+}
+
+impl TokenStream {
+    pub fn new() -> TokenStream {
+        TokenStream::with_offset(0)
+    }
+
+    /// Creates an empty stream whose first token sits at `offset` in the
+    /// original program, so positions stay absolute inside loop bodies.
+    pub fn with_offset(offset: usize) -> TokenStream {
+        TokenStream {
+            source: Arc::default(),
+            spans: Arc::default(),
+            position: 0,
+            end: 0,
+            offset,
+        }
+    }
+
+    pub fn push(&mut self, token: &str) {
+        let spans = Arc::make_mut(&mut self.spans);
+        spans.truncate(self.end);
+        let source = Arc::make_mut(&mut self.source);
+        let start = source.len();
+        source.push_str(token);
+        spans.push(start..source.len());
+        self.end += 1;
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &str) -> TokenStream {
+        TokenStream::parse(input, false)
+    }
+
+    /// Tokenizes `input`, skipping prose around the keywords when `lenient`.
+    pub fn parse(input: &str, lenient: bool) -> TokenStream {
+        trace_span!(tracing::Level::DEBUG, "parse", lenient);
+        let spans = lexer::tokens(input, lenient)
+            .map(|token| token.span)
+            .collect::<Vec<_>>();
+        TokenStream {
+            source: Arc::new(input.to_string()),
+            end: spans.len(),
+            spans: Arc::new(spans),
+            position: 0,
+            offset: 0,
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<&str> {
+        match index < self.end {
+            true => Some(&self.source[self.spans[index].clone()]),
+            false => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&str> {
+        let index = self.position;
+        if index >= self.end {
+            return None;
+        }
+        self.position += 1;
+        self.offset += 1;
+        self.get(index)
+    }
+
+    pub fn next2(&mut self) -> Option<(&str, &str)> {
+        let index = self.position;
+        let taken = (self.end - index).min(2);
+        self.position += taken;
+        self.offset += taken;
+        Some((self.get(index)?, self.get(index + 1)?))
+    }
+
+    pub fn peek(&self) -> Option<&str> {
+        self.get(self.position)
+    }
+
+    pub fn peekn(&self, n: usize) -> Option<&str> {
+        self.get(self.position + n)
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = &str> {
+        self.spans[self.position..self.end]
+            .iter()
+            .map(|span| &self.source[span.clone()])
+    }
+
+    /// Index of the next token within the original program.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Takes the body of the loop whose start was just read, up to its end
+    /// or, if it has none, the end of the stream, as a view of the same
+    /// words.
+    fn take_loop_body(&mut self) -> TokenStream {
+        let mut body = TokenStream {
+            end: self.position,
+            ..self.clone()
+        };
+        let mut depth = 1;
+        while let Some(pair) = self.next2() {
+            match pair {
+                LOOP_START => depth += 1,
+                LOOP_END => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => (),
+            }
+            body.end = self.position;
+        }
+        body
+    }
+}
+
+impl Default for TokenStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct InputStream {
+    /// The program's input, or `None` for stdin, which is only opened once the
+    /// program reads it.
+    reader: Option<io::Bytes<Box<dyn BufRead + Send>>>,
+    transforms: Transforms,
+    /// Bytes read so far, transformed away or not.
+    consumed: u64,
+}
+
+impl InputStream {
+    fn new() -> InputStream {
+        InputStream {
+            reader: None,
+            transforms: Transforms::default(),
+            consumed: 0,
+        }
+    }
+
+    fn set_reader(&mut self, input: impl Read + Send + 'static) {
+        let reader: Box<dyn BufRead + Send> = Box::new(io::BufReader::new(input));
+        self.reader = Some(reader.bytes());
+    }
+
+    /// The next byte of input, before any transforms.
+    fn next_raw(&mut self) -> Option<u8> {
+        if self.reader.is_none() {
+            self.set_reader(io::stdin());
+        }
+        let byte = self.reader.as_mut()?.next()?.ok()?;
+        self.consumed += 1;
+        Some(byte)
+    }
+
+    /// The next byte of input to survive the transforms.
+    fn next(&mut self) -> Option<u8> {
+        loop {
+            let byte = self.next_raw()?;
+            if let Some(byte) = self.transforms.apply(byte) {
+                return Some(byte);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for InputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InputStream")
+            .field("transforms", &self.transforms)
+            .field("consumed", &self.consumed)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct State {
+    state: Tape,
+    index: usize,
+    input: InputStream,
+    output_transforms: Transforms,
+    steps: u64,
+    /// The word pair running, as [`Progress::pair`].
+    pair: usize,
+    /// The step count at which to call `reporter` next.
+    next_report: u64,
+    reporter: Option<Reporter>,
+    /// How many cells the tape has grown to the left of the starting cell.
+    origin: usize,
+    extensions: Extensions,
+    limits: Limits,
+    /// Set when a limit stops the program.
+    exit: Option<Exit>,
+    /// Bytes the program has written, before output transforms.
+    written: u64,
+    flush: Flush,
+    #[cfg(feature = "hooks")]
+    hook: Option<Hook>,
+}
+
+impl State {
+    pub fn new() -> State {
+        State {
+            state: Tape::new(),
+            index: 0,
+            input: InputStream::new(),
+            output_transforms: Transforms::default(),
+            steps: 0,
+            pair: 0,
+            next_report: u64::MAX,
+            reporter: None,
+            origin: 0,
+            extensions: Extensions::default(),
+            limits: Limits::NONE,
+            exit: None,
+            written: 0,
+            flush: Flush::default(),
+            #[cfg(feature = "hooks")]
+            hook: None,
+        }
+    }
+
+    /// Calls `hook` on every cell read and write from now on.
+    #[cfg(feature = "hooks")]
+    pub fn set_memory_hook(&mut self, hook: impl MemoryHook + Send + 'static) {
+        self.hook = Some(Hook(Box::new(hook)));
+    }
+
+    /// Writes `args` on a fresh tape, each followed by a NUL, and starts the
+    /// program on the blank cell after them, so it can find them by walking
+    /// left.
+    fn load_args<A: AsRef<[u8]>>(&mut self, args: &[A]) {
+        self.state.clear();
+        for arg in args {
+            self.state.extend(arg.as_ref().iter().copied());
+            self.state.push_back(0);
+        }
+        self.state.push_back(0);
+        self.index = self.state.len() - 1;
+        self.origin = self.index;
+    }
+
+    /// Puts `cells` on a fresh tape, from the cell the program starts on
+    /// rightward.
+    fn load_tape(&mut self, cells: &[u8]) {
+        self.state.clear();
+        self.state.extend(cells.iter().copied());
+        if self.state.is_empty() {
+            self.state.push_back(0);
+        }
+        self.index = 0;
+        self.origin = 0;
+    }
+
+    /// The cells of the tape, from the leftmost the program has visited.
+    pub fn tape(&self) -> &[u8] {
+        &self.state
+    }
+
+    /// Index into [`tape`](State::tape) of the current cell.
+    pub fn pointer(&self) -> usize {
+        self.index
+    }
+
+    /// Index into [`tape`](State::tape) of the cell the program started on.
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    fn set_progress(&mut self, reporter: Reporter) {
+        self.next_report = self.steps.saturating_add(reporter.interval());
+        self.reporter = Some(reporter);
+    }
+
+    /// Whether the program may go on, which it may until a limit stops it.
+    fn running(&mut self) -> bool {
+        if self.exit.is_none() && self.steps >= self.limits.steps {
+            self.exit = Some(Exit::StepLimit);
+        }
+        self.exit.is_none()
+    }
+
+    /// Whether the tape may grow to `cells` cells, stopping the program if
+    /// not.
+    fn has_room(&mut self, cells: usize) -> bool {
+        if cells > self.limits.cells {
+            self.exit = Some(Exit::TapeLimit);
+        }
+        self.exit.is_none()
+    }
+
+    fn tick(&mut self) {
+        self.steps += 1;
+        if self.steps >= self.next_report {
+            self.report();
+        }
+    }
+
+    #[cold]
+    fn report(&mut self) {
+        if let Some(reporter) = &mut self.reporter {
+            self.next_report = reporter.report(Progress {
+                steps: self.steps,
+                pair: self.pair,
+                tape: &self.state,
+                pointer: self.index,
+            });
+        }
+    }
+
+    #[cfg(feature = "hooks")]
+    fn on_read(&mut self, value: u8) {
+        if let Some(Hook(hook)) = &mut self.hook {
+            hook.read(self.index as isize - self.origin as isize, value);
+        }
+    }
+
+    #[cfg(not(feature = "hooks"))]
+    fn on_read(&mut self, _: u8) {}
+
+    #[cfg(feature = "hooks")]
+    fn on_write(&mut self, old: u8, new: u8) {
+        if let Some(Hook(hook)) = &mut self.hook {
+            hook.write(self.index as isize - self.origin as isize, old, new);
+        }
+    }
+
+    #[cfg(not(feature = "hooks"))]
+    fn on_write(&mut self, _: u8, _: u8) {}
+
+    fn grow_left(&mut self) {
+        if !self.has_room(self.state.len() + 1) {
+            return;
+        }
+        self.state.push_front(0);
+        self.origin += 1;
+    }
+
+    fn shift_left(&mut self) {
+        match self.index {
+            0 => self.grow_left(),
+            _ => self.index -= 1,
+        }
+    }
+
+    fn shiht_right(&mut self) {
+        match self.index {
+            i if i == self.state.len() - 1 => {
+                if !self.has_room(self.state.len() + 1) {
+                    return;
+                }
+                self.state.push_back(0);
+                self.index += 1;
+            }
+            _ => self.index += 1,
+        }
+    }
+
+    fn shift(&mut self, n: isize) {
+        if n < 0 {
+            let n = n.unsigned_abs();
+            if n > self.index && !self.has_room(self.state.len() + n - self.index) {
+                return;
+            }
+            if n > self.index {
+                for _ in self.index..n {
+                    self.grow_left();
+                }
+                self.index = 0;
+            } else {
+                self.index -= n;
+            }
+        } else {
+            if !self.has_room(self.state.len().max(self.index + n as usize + 1)) {
+                return;
+            }
+            self.index += n as usize;
+            if self.index >= self.state.len() {
+                self.state.resize(self.index + 1);
+            }
+        }
+    }
+
+    fn add(&mut self, n: u8) {
+        if let Some(cell) = self.state.get_mut(self.index) {
+            let (old, new) = (*cell, cell.wrapping_add(n));
+            *cell = new;
+            self.on_write(old, new);
+        }
+    }
+
+    fn set(&mut self, n: u8) {
+        if let Some(cell) = self.state.get_mut(self.index) {
+            let old = *cell;
+            *cell = n;
+            self.on_write(old, n);
+        }
+    }
+
+    fn mul_add(&mut self, offset: isize, factor: u8) {
+        let value = self.state.get(self.index).copied().unwrap_or(0);
+        self.on_read(value);
+        if value != 0 {
+            // Walk there and back so the tape grows exactly as the loop would have.
+            self.shift(offset);
+            if self.exit.is_some() {
+                return;
+            }
+            self.add(value.wrapping_mul(factor));
+            self.shift(-offset);
+        }
+    }
+
+    fn increment(&mut self) {
+        self.add(1);
+    }
+
+    fn decrement(&mut self) {
+        self.add(u8::MAX);
+    }
+
+    fn output(&mut self) {
+        if self.written >= self.limits.output as u64 {
+            self.exit = Some(Exit::OutputLimit);
+            return;
+        }
+        if let Some(&cell) = self.state.get(self.index) {
+            self.written += 1;
+            if let Some(byte) = self.output_transforms.apply(cell) {
+                trace_event!(tracing::Level::TRACE, byte, "output");
+                output::write(byte, self.flush);
+            }
+            self.on_read(cell);
+        }
+    }
+
+    fn input(&mut self) {
+        let byte = self.input.next();
+        trace_event!(tracing::Level::TRACE, byte, "input");
+        if let Some(byte) = byte {
+            self.set(byte);
+        }
+    }
+
+    /// Calls the extension function the current cell numbers.
+    fn call_extension(&mut self) {
+        let function = self.state.get(self.index).copied().unwrap_or(0);
+        let result = self
+            .extensions
+            .call(function, &mut self.state, self.index, self.steps);
+        trace_event!(tracing::Level::TRACE, function, result, "extension");
+        self.on_read(function);
+        self.set(result);
+    }
+
+    fn cond(&mut self) -> bool {
+        let value = self.state.get(self.index).copied().unwrap_or(0);
+        self.on_read(value);
+        value != 0
+    }
+
+    /// Executes one instruction. Loop brackets are left to the caller.
+    fn step(&mut self, instruction: Instruction) {
+        if !self.running() {
+            return;
+        }
+        self.tick();
+        match instruction {
+            Instruction::ShiftLeft => self.shift_left(),
+            Instruction::ShiftRight => self.shiht_right(),
+            Instruction::Increment => self.increment(),
+            Instruction::Decrement => self.decrement(),
+            Instruction::Output => self.output(),
+            Instruction::Input => self.input(),
+            Instruction::LoopStart | Instruction::LoopEnd => (),
+        }
+    }
+
+    /// Runs a compiled program against this tape.
+    pub fn execute(&mut self, program: &Program) {
+        self.execute_from(program, 0, u64::MAX);
+    }
+
+    /// Runs `program` from op `pc` to the end, returning `None`, or until
+    /// the step count reaches `until`, returning the op to go on from.
+    fn execute_from(&mut self, program: &Program, mut pc: usize, until: u64) -> Option<usize> {
+        trace_span!(tracing::Level::TRACE, "execute", ops = program.ops().len());
+        let ops = program.ops();
+        while let Some(op) = ops.get(pc) {
+            if self.steps >= until {
+                return Some(pc);
+            }
+            if !self.running() {
+                return None;
+            }
+            self.tick();
+            match *op {
+                Op::Add(n) => self.add(n),
+                Op::Move(n) => self.shift(n),
+                Op::Output => self.output(),
+                Op::Input => self.input(),
+                Op::Set(n) => self.set(n),
+                Op::MulAdd { offset, factor } => self.mul_add(offset, factor),
+                Op::JumpIfZero(target) => {
+                    if !self.cond() {
+                        pc = target;
+                    }
+                }
+                Op::JumpIfNonZero(target) => {
+                    if self.cond() {
+                        pc = target;
+                    }
+                }
+            }
+            pc += 1;
+        }
+        None
+    }
+}
+
+/// A copy of the tape, the pointer and the counts, to run on separately.
+/// What was attached from outside isn't copied: the copy reads stdin and
+/// writes stdout, untransformed and unwatched, as a new state does.
+impl Clone for State {
+    fn clone(&self) -> Self {
+        State {
+            state: self.state.clone(),
+            index: self.index,
+            input: InputStream {
+                consumed: self.input.consumed,
+                ..InputStream::new()
+            },
+            output_transforms: Transforms::default(),
+            steps: self.steps,
+            pair: self.pair,
+            next_report: u64::MAX,
+            reporter: None,
+            origin: self.origin,
+            extensions: self.extensions.clone(),
+            limits: self.limits,
+            exit: self.exit,
+            written: self.written,
+            flush: self.flush,
+            #[cfg(feature = "hooks")]
+            hook: None,
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An interpreter for one program.
+///
+/// Interpreters are [`Send`] and share nothing with each other, so a worker
+/// pool can run one per thread. Only stdin and stdout are shared, so workers
+/// should give each program its own input with [`set_input`](HnyFuck::set_input)
+/// and take its output with an output transform that passes nothing on.
+/// Stdin isn't touched until a program without other input reads.
+#[derive(Debug)]
+pub struct HnyFuck {
+    stream: TokenStream,
+    state: State,
+    stats: Option<RunStats>,
+    tiering: Option<Tiering>,
+    middleware: Chain,
+    /// Set when a middleware stops the program.
+    halted: bool,
+}
+
+/// A copy of the interpreter, sharing the program and its compiled loops and
+/// with its own copy of the tape, for running down both sides of a decision
+/// or trying something out without losing where it was. As with a copy of
+/// [`State`], the input, transforms, middleware and callbacks attached to the
+/// original aren't copied.
+impl Clone for HnyFuck {
+    fn clone(&self) -> Self {
+        HnyFuck {
+            stream: self.stream.clone(),
+            state: self.state.clone(),
+            stats: self.stats.clone(),
+            tiering: self.tiering.clone(),
+            middleware: Chain::default(),
+            halted: self.halted,
+        }
+    }
+}
+
+impl HnyFuck {
+    pub fn new(stream: TokenStream) -> Self {
+        Self {
+            stream,
+            state: State::new(),
+            stats: None,
+            tiering: Some(Tiering::new(DEFAULT_TIER_THRESHOLD)),
+            middleware: Chain::default(),
+            halted: false,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &str) -> Self {
+        Self::new(TokenStream::from_str(input))
+    }
+
+    pub fn parse(input: &str, lenient: bool) -> Self {
+        Self::new(TokenStream::parse(input, lenient))
+    }
+
+    /// Sets how many iterations a loop is interpreted a word pair at a time
+    /// before it is compiled to IR ops, which are run from then on; `None`
+    /// keeps every loop on word pairs.
+    pub fn set_tier_threshold(&mut self, threshold: Option<u64>) {
+        self.tiering = threshold.map(Tiering::new);
+    }
+
+    /// Turns on per-opcode and per-loop counters for subsequent runs. Loops are
+    /// never compiled while stats are collected, so every instruction is counted.
+    pub fn enable_stats(&mut self) {
+        self.stats.get_or_insert_with(RunStats::new);
+    }
+
+    pub fn stats(&self) -> Option<&RunStats> {
+        self.stats.as_ref()
+    }
+
+    /// Calls `hook` on every cell the program reads or writes.
+    #[cfg(feature = "hooks")]
+    pub fn set_memory_hook(&mut self, hook: impl MemoryHook + Send + 'static) {
+        self.state.set_memory_hook(hook);
+    }
+
+    /// Reads the program's input from `input` instead of stdin.
+    pub fn set_input(&mut self, input: impl Read + Send + 'static) {
+        self.state.input.set_reader(input);
+    }
+
+    /// Writes `args` at the start of the tape before the program runs, each
+    /// followed by a NUL, with the pointer starting on the cell after the
+    /// last. Cells to the left of where the program starts are negative in
+    /// dumps and the debugger.
+    pub fn set_args<A: AsRef<[u8]>>(&mut self, args: &[A]) {
+        self.state.load_args(args);
+    }
+
+    /// Lets the program call the functions of `extension` with
+    /// [`EXTENSION`]. Loops calling them are never compiled.
+    pub fn enable_extension(&mut self, extension: Extension) {
+        if extension == Extension::Fork {
+            self.state.extensions.program = Some(self.stream.clone());
+        }
+        self.state.extensions.enable(extension);
+    }
+
+    /// Makes extension functions that read the outside world, such as the
+    /// clock, give the same results every run.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.state.extensions.set_deterministic(deterministic);
+    }
+
+    /// Starts the program on a tape holding `cells`, from the cell the pointer
+    /// starts on rightward, such as one saved from an earlier run.
+    pub fn set_tape(&mut self, cells: &[u8]) {
+        self.state.load_tape(cells);
+    }
+
+    /// Keeps the tape in a sparse file created at `path` and mapped into
+    /// memory, rather than in memory itself, for programs using more cells
+    /// than there is memory for. The file is removed as soon as it's mapped.
+    /// Only possible on Linux.
+    pub fn set_tape_file(&mut self, path: &Path) -> io::Result<()> {
+        self.state.state.map_to(path)
+    }
+
+    /// Stops the program once it would take more steps, tape cells or bytes
+    /// of output than `limits` allows, with [`RunOutcome::exit`] saying which.
+    /// Steps and output count from when the interpreter was created.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.state.limits = limits;
+    }
+
+    /// The interpreter's tape.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Passes every byte read from stdin through `transform`, after any added
+    /// before it.
+    pub fn add_input_transform(&mut self, transform: impl Transform + Send + 'static) {
+        self.state.input.transforms.push(transform);
+    }
+
+    /// Sets when what the program writes to stdout is flushed. Whatever
+    /// the setting, it is flushed when the run ends.
+    pub fn set_flush(&mut self, flush: Flush) {
+        self.state.flush = flush;
+    }
+
+    /// Passes every byte written to stdout through `transform`, after any
+    /// added before it.
+    pub fn add_output_transform(&mut self, transform: impl Transform + Send + 'static) {
+        self.state.output_transforms.push(transform);
+    }
+
+    /// Calls `callback` every `interval` steps, counting each instruction the
+    /// interpreter runs and each operation of a compiled loop as a step.
+    pub fn set_progress(
+        &mut self,
+        interval: u64,
+        callback: impl FnMut(Progress<'_>) + Send + 'static,
+    ) {
+        self.state.set_progress(Reporter::new(interval, callback));
+    }
+
+    /// Wraps the interpreter in `middleware`, inside any added before it.
+    pub fn add_middleware(&mut self, middleware: impl Middleware + Send + 'static) {
+        self.middleware.push(middleware);
+    }
+
+    /// Executes `instruction`, the word pair at index `pair`.
+    fn dispatch(&mut self, instruction: Instruction, pair: usize) {
+        self.state.pair = pair;
+        if self.middleware.is_empty() {
+            self.state.step(instruction);
+        } else if self
+            .middleware
+            .run(instruction, &mut self.state, pair)
+            .is_break()
+        {
+            self.halted = true;
+        }
+    }
+
+    /// Runs the program to its end, or until a limit or a middleware stops
+    /// it, returning what the run used.
+    pub fn run(&mut self) -> RunOutcome {
+        let state = &mut self.state;
+        state.exit = None;
+        let (steps, read, written) = (state.steps, state.input.consumed, state.written);
+        self.interpret();
+        output::flush();
+        let state = &self.state;
+        RunOutcome {
+            exit: state.exit.unwrap_or(Exit::Finished),
+            halted: self.halted,
+            steps: state.steps - steps,
+            peak_cells: state.state.len(),
+            bytes_read: state.input.consumed - read,
+            bytes_written: state.written - written,
+        }
+    }
+
+    fn interpret(&mut self) {
+        while let Some(pair) = self.stream.next2() {
+            let (decoded, extension) = (Instruction::from_pair(pair), pair == EXTENSION);
+            if self.halted {
+                trace_event!(tracing::Level::DEBUG, "halted by middleware");
+                break;
+            }
+            if self.state.exit.is_some() {
+                trace_event!(tracing::Level::DEBUG, exit = ?self.state.exit, "stopped by a limit");
+                break;
+            }
+            let started = self.stats.as_ref().map(|_| Instant::now());
+            let instruction = match decoded {
+                Some(instruction) => instruction,
+                None if extension && !self.state.extensions.is_empty() => {
+                    if !self.state.running() {
+                        break;
+                    }
+                    self.state.tick();
+                    self.state.call_extension();
+                    continue;
+                }
+                None => {
+                    self.state.pair = self.stream.offset() / 2 - 1;
+                    panic!("Invalid token")
+                }
+            };
+            let pair = self.stream.offset() / 2 - 1;
+            self.dispatch(instruction, pair);
+            match instruction {
+                Instruction::LoopStart => {
+                    let start = pair;
+                    let token_stream = self.stream.take_loop_body();
+                    self.run_loop(start, token_stream, started);
+                    continue;
+                }
+                Instruction::LoopEnd => panic!("Invalid token"),
+                _ => (),
+            }
+
+            if let (Some(stats), Some(started)) = (self.stats.as_mut(), started) {
+                stats.record(instruction, started.elapsed());
+            }
+        }
+    }
+
+    fn run_loop(&mut self, start: usize, token_stream: TokenStream, started: Option<Instant>) {
+        trace_span!(tracing::Level::TRACE, "loop", start);
+        let state = std::mem::take(&mut self.state);
+
+        let mut nest = Self {
+            stream: token_stream.clone(),
+            state,
+            stats: self.stats.take(),
+            tiering: self.tiering.take(),
+            middleware: std::mem::take(&mut self.middleware),
+            halted: self.halted,
+        };
+
+        if let (Some(stats), Some(started)) = (nest.stats.as_mut(), started) {
+            stats.record(Instruction::LoopStart, started.elapsed());
+            stats.enter_loop(start);
+        }
+        let executed = nest.stats.as_ref().map_or(0, RunStats::instructions);
+
+        // A panic within the loop, such as at a token that isn't an
+        // instruction, leaves the tape with the interpreter as it unwinds,
+        // so it can still be looked at.
+        let looped = std::panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            nest.iterate(start, &token_stream)
+        }));
+
+        if let (Some(stats), Some(started)) = (nest.stats.as_mut(), started) {
+            let executed = stats.instructions() - executed;
+            stats.exit_loop(start, executed, started.elapsed());
+        }
+
+        self.state = nest.state;
+        self.stats = nest.stats;
+        self.tiering = nest.tiering;
+        self.middleware = nest.middleware;
+        self.halted = nest.halted;
+        if let Err(panic) = looped {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    /// Runs the iterations of the loop starting at pair `start`, whose body
+    /// is `token_stream`.
+    fn iterate(&mut self, start: usize, token_stream: &TokenStream) {
+        while self.state.cond() && !self.halted && self.state.exit.is_none() {
+            if self.stats.is_none() && self.middleware.is_empty() {
+                let compiled = self
+                    .tiering
+                    .as_mut()
+                    .and_then(|tiering| tiering.compiled(start, token_stream));
+                if let Some(program) = compiled {
+                    self.state.pair = start;
+                    self.state.execute(&program);
+                    break;
+                }
+            }
+
+            self.interpret();
+            if self.halted || self.state.exit.is_some() {
+                break;
+            }
+            self.dispatch(Instruction::LoopEnd, self.stream.offset() / 2);
+
+            let check = self.stats.as_ref().map(|_| Instant::now());
+
+            self.stream = token_stream.clone();
+
+            if let (Some(stats), Some(check)) = (self.stats.as_mut(), check) {
+                stats.record(Instruction::LoopEnd, check.elapsed());
+                stats.iterate_loop(start);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    use proptest::prelude::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_increment() {
+        let mut hny = from_brainfuck("+++++");
+        hny.run();
+        assert_eq!(hny.state.state[0], 5);
+    }
+
+    #[test]
+    fn test_decrement() {
+        let mut hny = from_brainfuck("+++++-----");
+        hny.run();
+        assert_eq!(hny.state.state[0], 0);
+    }
+
+    #[test]
+    fn test_shift_left_right() {
+        let mut hny = from_brainfuck("+++++>+++++<");
+        dbg!(&hny);
+        hny.run();
+        assert_eq!(hny.state.state[0], 5);
+        assert_eq!(hny.state.state[1], 5);
+    }
+
+    #[test]
+    fn test_args() {
+        // Walk back over the last argument and copy its first byte right.
+        let mut hny = from_brainfuck("<<[<]>[->>>>+<<<<]");
+        hny.set_args(&["a", "bc"]);
+        assert_eq!(hny.state.pointer(), 5);
+        hny.run();
+        assert_eq!(hny.state.tape(), &[b'a', 0, 0, b'c', 0, 0, b'b']);
+        assert_eq!(hny.state.origin(), 5);
+    }
+
+    #[test]
+    fn test_extension() {
+        // Call function 9, which no extension has, and then function 3 in a
+        // loop, which fails with no file open to read.
+        let call = "Year Year";
+        let code = format!(
+            "{} {} {} {} {} {}",
+            to_source(&[Instruction::Increment; 9]),
+            call,
+            to_source(&[Instruction::Increment; 3]),
+            to_source(&[Instruction::LoopStart]),
+            call,
+            to_source(&[Instruction::LoopEnd]),
+        );
+        let mut hny = HnyFuck::from_str(&code);
+        hny.enable_extension(Extension::FileIo);
+        hny.run();
+        assert_eq!(hny.state.tape(), &[0]);
+        assert_eq!(hny.state.steps, 16);
+    }
+
+    #[test]
+    fn test_set_tape() {
+        let mut hny = from_brainfuck("<+>>+");
+        hny.set_tape(&[1, 2]);
+        hny.run();
+        assert_eq!(hny.state().tape(), &[1, 1, 3]);
+        assert_eq!(hny.state().origin(), 1);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut hny = from_brainfuck("+++++[>+++++<-]");
+        hny.run();
+        assert_eq!(hny.state.state[0], 0);
+        assert_eq!(hny.state.state[1], 25);
+    }
+
+    #[test]
+    fn test_token_stream() {
+        let mut stream = TokenStream::parse("Happy Happy New Year New New Happy", false);
+        assert_eq!(stream.next2(), Some(LOOP_START));
+        let mut body = stream.take_loop_body();
+        assert!(Arc::ptr_eq(&body.source, &stream.source));
+        assert_eq!(body.tokens().collect::<Vec<_>>(), ["New", "Year"]);
+        assert_eq!((body.offset(), stream.offset()), (2, 6));
+        assert_eq!(stream.next2(), None);
+
+        body.push("Happy");
+        assert_eq!(body.tokens().collect::<Vec<_>>(), ["New", "Year", "Happy"]);
+        assert_eq!(stream.source.as_str(), "Happy Happy New Year New New Happy");
+    }
+
+    #[test]
+    fn test_state_increment() {
+        let mut state = State::new();
+        state.increment();
+        assert_eq!(state.state[0], 1);
+    }
+
+    #[test]
+    fn test_state_decrement() {
+        let mut state = State::new();
+        state.increment();
+        state.decrement();
+        assert_eq!(state.state[0], 0);
+    }
+
+    #[test]
+    fn test_state_shift_left() {
+        let mut state = State::new();
+        state.increment();
+        state.shift_left();
+        assert_eq!(state.state[0], 0);
+        assert_eq!(state.state[1], 1);
+    }
+
+    #[test]
+    fn test_state_shift_right() {
+        let mut state = State::new();
+        state.increment();
+        state.shiht_right();
+        state.increment();
+        assert_eq!(state.state[0], 1);
+        assert_eq!(state.state[1], 1);
+    }
+
+    #[test]
+    fn test_state_cond() {
+        let mut state = State::new();
+        assert!(!state.cond());
+        state.increment();
+        assert!(state.cond());
+    }
+
+    #[test]
+    fn happy_new_year() {
+        let code =
+            "Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Happy Happy New Year Year Happy New Year Year Happy Year Happy Year Happy New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Happy New Happy New Happy New Happy New Happy Year New New New Year New Year New Year Year Happy Year Happy Year New New Year Happy Year Happy Year Happy Year Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Happy New Happy New Year Happy Year Happy Year New New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Happy New Happy New Year New New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Happy Year Happy Year Happy Year Happy Year Year New New Year Happy Year Happy Year Happy Year Happy Year Happy Year Year New Happy New Happy New Year Happy Year New";
+
+        let mut hny = HnyFuck::new(TokenStream::from_str(code));
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let written = output.clone();
+        hny.add_output_transform(move |byte| {
+            written.lock().unwrap().push(byte);
+            None
+        });
+        hny.run();
+        assert_eq!(*output.lock().unwrap(), b"Happy New Year!");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut hny = from_brainfuck("++[>+++[>+<-]<-]");
+        hny.enable_stats();
+        hny.run();
+
+        let stats = hny.stats().unwrap();
+        assert_eq!(stats.op(Instruction::LoopStart).count, 3);
+        assert_eq!(stats.op(Instruction::LoopEnd).count, 8);
+
+        let outer = stats.loop_stats(2).unwrap();
+        assert_eq!(outer.entries, 1);
+        assert_eq!(outer.iterations, 2);
+
+        let inner = stats.loop_stats(7).unwrap();
+        assert_eq!(inner.entries, 2);
+        assert_eq!(inner.iterations, 6);
+        assert_eq!(inner.instructions, 6 * 5);
+        assert_eq!(hny.state.state[2], 6);
+    }
+
+    #[test]
+    fn test_progress() {
+        // 49 instructions, brackets included, all interpreted. Each report
+        // comes just before the instruction it names runs.
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let mut hny = from_brainfuck("++[>+++[>+<-]<-]");
+        hny.set_tier_threshold(None);
+        let seen = reports.clone();
+        hny.set_progress(16, move |progress| {
+            let cells = progress.tape.to_vec();
+            seen.lock()
+                .unwrap()
+                .push((progress.steps, progress.pair, cells, progress.pointer));
+        });
+        hny.run();
+        assert_eq!(
+            *reports.lock().unwrap(),
+            [
+                (16, 10, vec![2, 2, 2], 2),
+                (32, 8, vec![1, 3, 3], 1),
+                (48, 14, vec![1, 0, 6], 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_pool() {
+        fn assert_send<T: Send>() {}
+        assert_send::<HnyFuck>();
+
+        // Each worker gets its own interpreter, input and output.
+        let workers = (b'a'..b'e')
+            .map(|byte| {
+                std::thread::spawn(move || {
+                    let mut hny = from_brainfuck(",[.[-],]");
+                    hny.set_input(io::Cursor::new(vec![byte; 3]));
+                    let output = Arc::new(Mutex::new(Vec::new()));
+                    let written = output.clone();
+                    hny.add_output_transform(move |byte| {
+                        written.lock().unwrap().push(byte);
+                        None
+                    });
+                    hny.run();
+                    let output = output.lock().unwrap().clone();
+                    output
+                })
+            })
+            .collect::<Vec<_>>();
+        let outputs = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(outputs, [b"aaa", b"bbb", b"ccc", b"ddd"]);
+    }
+
+    #[test]
+    fn test_clone() {
+        // Adds the byte read to the argument's first byte and writes it.
+        let mut hny = from_brainfuck(",<<[->>+<<]>>.");
+        hny.set_args(&["A"]);
+        let mut copy = hny.clone();
+        assert!(Arc::ptr_eq(&hny.stream.spans, &copy.stream.spans));
+
+        let outputs = [&mut hny, &mut copy].map(|hny| {
+            let output = Arc::new(Mutex::new(Vec::new()));
+            let written = output.clone();
+            hny.add_output_transform(move |byte| {
+                written.lock().unwrap().push(byte);
+                None
+            });
+            output
+        });
+        hny.set_input(io::Cursor::new([1]));
+        copy.set_input(io::Cursor::new([2]));
+        hny.run();
+        copy.run();
+        assert_eq!(*outputs[0].lock().unwrap(), b"B");
+        assert_eq!(*outputs[1].lock().unwrap(), b"C");
+        assert_eq!(hny.state.tape(), &[0, 0, b'B']);
+
+        let state = hny.state.clone();
+        assert_eq!((state.tape(), state.pointer()), (hny.state.tape(), 2));
+        assert_eq!(state.steps, hny.state.steps);
+    }
+
+    #[test]
+    fn test_run_outcome() {
+        let limits = Limits {
+            steps: 100,
+            cells: 3,
+            output: 2,
+        };
+        let run = |code: &str, threshold| {
+            let mut hny = from_brainfuck(code);
+            hny.set_tier_threshold(threshold);
+            hny.set_limits(limits);
+            hny.set_input(io::Cursor::new(*b"ab"));
+            hny.add_output_transform(|_| None);
+            hny.run()
+        };
+        assert_eq!(
+            run(",+.>,.<<", None),
+            RunOutcome {
+                exit: Exit::Finished,
+                halted: false,
+                steps: 8,
+                peak_cells: 3,
+                bytes_read: 2,
+                bytes_written: 2,
+            }
+        );
+        for threshold in [None, Some(2)] {
+            let outcome = run("+[]", threshold);
+            assert_eq!((outcome.exit, outcome.steps), (Exit::StepLimit, 100));
+            let outcome = run(">+>+>+", threshold);
+            assert_eq!((outcome.exit, outcome.peak_cells), (Exit::TapeLimit, 3));
+            let outcome = run("+[.]", threshold);
+            assert_eq!(
+                (outcome.exit, outcome.bytes_written),
+                (Exit::OutputLimit, 2)
+            );
+        }
+    }
+
+    #[test]
+    fn test_hot_loops() {
+        let code = "Year Happy Year Happy Happy Happy New Year\nYear Happy Year Happy Year Happy Happy Happy New Year Year Happy Happy New Happy Year New New Happy New Happy Year New New";
+        let mut hny = HnyFuck::from_str(code);
+        hny.enable_stats();
+        hny.run();
+
+        let loops = hot_loops(code, false, hny.stats().unwrap(), 1);
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].start, 7);
+        assert_eq!(loops[0].stats.iterations, 6);
+        assert_eq!(loops[0].brainfuck, "[>+<-]");
+        assert_eq!((loops[0].line, loops[0].column), (2, 34));
+        assert_eq!(
+            &code[loops[0].span.clone()],
+            "Happy Happy New Year Year Happy Happy New Happy Year New New"
+        );
+    }
+
+    /// Loops test their cell when they're reached, as in brainfuck, so a
+    /// loop reached on a zero cell never runs, in the interpreter or
+    /// compiled.
+    #[test]
+    fn test_loop_skipped_on_zero() {
+        for threshold in [None, Some(0)] {
+            let mut hny = from_brainfuck("[>+<]>+");
+            hny.set_tier_threshold(threshold);
+            hny.run();
+            assert_eq!(hny.state.state, [0, 1]);
+
+            // The inner loop is reached on a zero cell every time the hot
+            // outer loop goes round, so it is skipped in the compiled loop
+            // as well.
+            let mut hny = from_brainfuck("+++[>[>+<]<-]");
+            hny.set_tier_threshold(threshold);
+            hny.run();
+            assert_eq!(hny.state.state, [0, 0]);
+        }
+    }
+
+    /// Cells wrap around in both directions, in the interpreter and in
+    /// compiled loops.
+    #[test]
+    fn test_wrapping() {
+        for threshold in [None, Some(0)] {
+            let mut hny = from_brainfuck("-");
+            hny.set_tier_threshold(threshold);
+            hny.run();
+            assert_eq!(hny.state.state, [255]);
+
+            let mut hny = from_brainfuck(&format!("{}>+++[>-<-]", "+".repeat(256)));
+            hny.set_tier_threshold(threshold);
+            hny.run();
+            assert_eq!(hny.state.state, [0, 0, 253]);
+
+            let mut hny = from_brainfuck("++++++++[>++++++++++++++++++++++++++++++++<-]");
+            hny.set_tier_threshold(threshold);
+            hny.run();
+            assert_eq!(hny.state.state, [0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_tiering() {
+        let code = "++++[>++++++[>+++<-]<-]>>[<<+>>-]<<<[-]";
+        for threshold in [None, Some(0), Some(3)] {
+            let mut hny = from_brainfuck(code);
+            hny.set_tier_threshold(threshold);
+            hny.run();
+            assert_eq!(hny.state.state, [0, 72, 0, 0]);
+            assert_eq!(hny.state.index, 0);
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let program = Program::from_str(
+            "Year Happy Year Happy Happy Happy New Year Happy New New Year Happy Year New New",
+        )
+        .unwrap();
+        assert_eq!(
+            program.ops(),
+            [
+                Op::Add(2),
+                Op::JumpIfZero(4),
+                Op::Move(1),
+                Op::Add(255),
+                Op::JumpIfNonZero(1),
+            ]
+        );
+        assert_eq!(
+            Program::from_str("Happy Happy"),
+            Err(CompileError::UnmatchedLoopStart { pair: 0 })
+        );
+        assert_eq!(
+            Program::from_str("Year Happy New New"),
+            Err(CompileError::UnmatchedLoopEnd { pair: 1 })
+        );
+        assert_eq!(
+            Program::from_str("Year Year"),
+            Err(CompileError::InvalidToken { pair: 0 })
+        );
+    }
+
+    #[test]
+    fn test_optimize() {
+        // +++[->++>+++<<]>+[+]
+        let code = "Year Happy Year Happy Year Happy Happy Happy Happy Year New Year Year Happy Year Happy New Year Year Happy Year Happy Year Happy Happy New Happy New New New New Year Year Happy Happy Happy Year Happy New New";
+        let mut program = Program::from_str(code).unwrap();
+        program.optimize();
+        assert_eq!(
+            program.ops(),
+            [
+                Op::Add(3),
+                Op::MulAdd {
+                    offset: 1,
+                    factor: 2
+                },
+                Op::MulAdd {
+                    offset: 2,
+                    factor: 3
+                },
+                Op::Set(0),
+                Op::Move(1),
+                Op::Set(0),
+            ]
+        );
+        assert_eq!(
+            program.to_string(),
+            "     0  add 3\n     1  muladd 1 2\n     2  muladd 2 3\n     3  set 0\n     4  move 1\n     5  set 0\n"
+        );
+
+        let mut state = State::new();
+        state.execute(&program);
+        assert_eq!(state.state, [0, 0, 9]);
+    }
+
+    #[test]
+    fn test_fold_counted_loops() {
+        let code = brainfuck_to_hny("++++[>+++<--]>[.-]").output;
+        let mut program = Program::from_str(&code).unwrap();
+        program.optimize();
+        program.fold_counted_loops();
+        assert_eq!(
+            program.ops(),
+            [
+                Op::Add(4),
+                Op::Move(1),
+                Op::Add(6),
+                Op::Move(-1),
+                Op::Set(0),
+                Op::Move(1),
+                Op::JumpIfZero(9),
+                Op::Output,
+                Op::Add(255),
+                Op::JumpIfNonZero(6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eliminate_dead_code() {
+        let code = brainfuck_to_hny("[-]+++>++<[-]>[<+>-]<.").output;
+        let mut program = Program::from_str(&code).unwrap();
+        program.optimize();
+        program.eliminate_dead_code();
+        assert_eq!(
+            program.ops(),
+            [
+                Op::Move(1),
+                Op::Add(2),
+                Op::MulAdd {
+                    offset: -1,
+                    factor: 1
+                },
+                Op::Set(0),
+                Op::Move(-1),
+                Op::Output,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dead_code_equivalence() {
+        let programs = [
+            "[-]+++>++<[-]>[<+>-]<.",
+            "++++[>+++<--]>[.-]",
+            ">>,[>+<-]>[<<+>>-]<<.",
+            "+[>]+.<[-]+++.",
+            ",>+<[>[-]<-]>.",
+            "+>,<[-]>[-]<[>.<-]",
+        ];
+        for brainfuck in programs {
+            let code = brainfuck_to_hny(brainfuck).output;
+            let mut o1 = Program::from_str(&code).unwrap();
+            let mut o2 = o1.clone();
+            o1.optimize_level(1);
+            o2.optimize_level(2);
+            let inputs = equiv::exhaustive_inputs(b"\0\x01\x07", 2);
+            let report = equiv::check(&o1, &o2, inputs, 10_000);
+            assert_eq!(report.mismatch, None, "{}", brainfuck);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn optimization_levels_agree(program in any::<Program>(), input in any::<Vec<u8>>()) {
+            let mut optimized = program.clone();
+            optimized.optimize_level(2);
+            let plain = equiv::execute(&program, &input, 10_000);
+            let fast = equiv::execute(&optimized, &input, 10_000);
+            if let (equiv::Outcome::Finished { .. }, equiv::Outcome::Finished { .. }) = (&plain, &fast) {
+                prop_assert_eq!(plain, fast);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fold_constant_prefix() {
+        // add 3, move 1, add 2, move -2, add 1, move 2, add -1, out, ...
+        let code = brainfuck_to_hny("+++>++<<+>>-.[-]").output;
+        let mut program = Program::from_str(&code).unwrap();
+        program.optimize();
+        program.fold_constant_prefix();
+        assert_eq!(
+            program.ops(),
+            [
+                Op::Move(-1),
+                Op::Set(1),
+                Op::Move(1),
+                Op::Set(3),
+                Op::Move(1),
+                Op::Set(1),
+                Op::Output,
+                Op::Set(0),
+            ]
+        );
+
+        let code = brainfuck_to_hny("+>+<+[->+<]").output;
+        let mut program = Program::from_str(&code).unwrap();
+        program.fold_constant_prefix();
+        assert_eq!(
+            program.ops()[..4],
+            [Op::Set(2), Op::Move(1), Op::Set(1), Op::Move(-1)]
+        );
+        assert_eq!(program.ops()[4], Op::JumpIfZero(9));
+        assert_eq!(program.ops()[9], Op::JumpIfNonZero(4));
+    }
+}
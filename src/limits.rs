@@ -0,0 +1,226 @@
+//! Running compiled programs on in-memory input with bounds on how long they
+//! run, how much tape they use and how much they write, for hosting programs
+//! you don't trust.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ir::{Op, Program};
+
+/// Bounds on a run. A program that would go past one is stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Operations to execute.
+    pub steps: u64,
+    /// Cells the tape may grow to.
+    pub cells: usize,
+    /// Bytes to write.
+    pub output: usize,
+}
+
+impl Limits {
+    pub const NONE: Limits = Limits {
+        steps: u64::MAX,
+        cells: usize::MAX,
+        output: usize::MAX,
+    };
+
+    /// Each limit the lower of the two.
+    pub fn min(self, other: Limits) -> Limits {
+        Limits {
+            steps: self.steps.min(other.steps),
+            cells: self.cells.min(other.cells),
+            output: self.output.min(other.output),
+        }
+    }
+}
+
+/// Why a run stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Exit {
+    /// The program ran to its end.
+    Finished,
+    StepLimit,
+    TapeLimit,
+    OutputLimit,
+}
+
+/// What a run of the interpreter used, for hosts metering and billing runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RunOutcome {
+    /// Which limit, if any, stopped the program.
+    pub exit: Exit,
+    /// Whether a middleware stopped the program.
+    pub halted: bool,
+    /// Steps taken during the run, as [`Progress::steps`](crate::Progress::steps)
+    /// counts them.
+    pub steps: u64,
+    /// Most cells the tape held, which it still holds, as it never shrinks.
+    pub peak_cells: usize,
+    /// Bytes of input read.
+    pub bytes_read: u64,
+    /// Bytes the program wrote, before any output transforms.
+    pub bytes_written: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub exit: Exit,
+    pub output: Vec<u8>,
+    /// Operations executed.
+    pub steps: u64,
+    pub tape: VecDeque<u8>,
+    /// Offset from the starting cell of the first cell of `tape`.
+    pub origin: isize,
+}
+
+/// Runs `program` on `input` within `limits`, without touching stdin or
+/// stdout. Reading past the end of `input` leaves the cell unchanged, as the
+/// interpreter does at end of file.
+pub fn execute(program: &Program, input: &[u8], limits: &Limits) -> Execution {
+    let mut output = Vec::new();
+    let mut run = stream(
+        program,
+        input.iter().copied(),
+        |byte| output.push(byte),
+        limits,
+    );
+    run.output = output;
+    run
+}
+
+/// Runs `program` as [`execute`] does, but taking each byte of input from
+/// `input` only when the program reads it and passing each byte written to
+/// `output` straight away, for running programs interactively.
+/// [`Execution::output`] is left empty.
+pub fn stream(
+    program: &Program,
+    mut input: impl Iterator<Item = u8>,
+    mut output: impl FnMut(u8),
+    limits: &Limits,
+) -> Execution {
+    let ops = program.ops();
+    let mut run = Execution {
+        exit: Exit::Finished,
+        output: Vec::new(),
+        steps: 0,
+        tape: VecDeque::from([0u8]),
+        origin: 0,
+    };
+    let mut written = 0;
+    let mut index = 0usize;
+    let mut pc = 0;
+
+    // Moves the pointer `n` cells, or returns false if the tape would grow
+    // past the limit.
+    let shift = |run: &mut Execution, index: &mut usize, n: isize| {
+        let target = *index as isize + n;
+        let len = run.tape.len() as isize;
+        let grown = (len - target.min(0)).max(target + 1);
+        if grown as usize > limits.cells {
+            return false;
+        }
+        if target < 0 {
+            for _ in target..0 {
+                run.tape.push_front(0);
+            }
+            run.origin += target;
+            *index = 0;
+        } else {
+            *index = target as usize;
+            if *index >= run.tape.len() {
+                run.tape.resize(*index + 1, 0);
+            }
+        }
+        true
+    };
+
+    while let Some(op) = ops.get(pc) {
+        if run.steps == limits.steps {
+            run.exit = Exit::StepLimit;
+            break;
+        }
+        run.steps += 1;
+        match *op {
+            Op::Add(n) => run.tape[index] = run.tape[index].wrapping_add(n),
+            Op::Move(n) => {
+                if !shift(&mut run, &mut index, n) {
+                    run.exit = Exit::TapeLimit;
+                    break;
+                }
+            }
+            Op::Output => {
+                if written == limits.output {
+                    run.exit = Exit::OutputLimit;
+                    break;
+                }
+                written += 1;
+                output(run.tape[index]);
+            }
+            Op::Input => {
+                if let Some(byte) = input.next() {
+                    run.tape[index] = byte;
+                }
+            }
+            Op::Set(n) => run.tape[index] = n,
+            Op::MulAdd { offset, factor } => {
+                let value = run.tape[index];
+                if value != 0 {
+                    if !shift(&mut run, &mut index, offset) {
+                        run.exit = Exit::TapeLimit;
+                        break;
+                    }
+                    run.tape[index] = run.tape[index].wrapping_add(value.wrapping_mul(factor));
+                    shift(&mut run, &mut index, -offset);
+                }
+            }
+            Op::JumpIfZero(target) => {
+                if run.tape[index] == 0 {
+                    pc = target;
+                }
+            }
+            Op::JumpIfNonZero(target) => {
+                if run.tape[index] != 0 {
+                    pc = target;
+                }
+            }
+        }
+        pc += 1;
+    }
+    run
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    fn run(code: &str, limits: Limits) -> Execution {
+        execute(
+            &Program::from_str(&brainfuck_to_hny(code).output).unwrap(),
+            b"",
+            &limits,
+        )
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            steps: 100,
+            cells: 3,
+            output: 2,
+        };
+        let finished = run("+.>+.<<", limits);
+        assert_eq!(finished.exit, Exit::Finished);
+        assert_eq!(finished.output, [1, 1]);
+        assert_eq!((finished.tape.len(), finished.origin), (3, -1));
+
+        assert_eq!(run("+[]", limits).exit, Exit::StepLimit);
+        assert_eq!(run("+[]", limits).steps, 100);
+        assert_eq!(run(">+>+>", limits).exit, Exit::TapeLimit);
+        assert_eq!(run("...", limits).exit, Exit::OutputLimit);
+        assert_eq!(run("...", limits).output, [0, 0]);
+    }
+}
@@ -0,0 +1,610 @@
+use std::{error::Error, fmt, ops::Range};
+
+use serde_json::json;
+
+use crate::{
+    analyze::{self, Termination},
+    ast::{self, Node},
+    ir::CompileError,
+    lexer, Instruction,
+};
+
+/// The rule syntax errors are reported under by [`Linter::check`].
+pub const SYNTAX: &str = "syntax";
+
+/// How a rule's findings are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Not reported.
+    Allow,
+    Warn,
+    /// Reported as an error; `hnyfuck lint` fails if any are found.
+    Deny,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Warn => write!(f, "warning"),
+            Level::Deny => write!(f, "error"),
+        }
+    }
+}
+
+/// What a rule looks at: the source and its parse tree.
+pub struct Context<'a> {
+    pub source: &'a str,
+    pub lenient: bool,
+    pub nodes: &'a [Node],
+}
+
+/// Something a rule found, before it is given a level and a position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+/// A check run by [`Linter`]. Implement this to add rules of your own. Rules
+/// are shared by the threads checking files in parallel.
+pub trait Rule: Send + Sync {
+    /// Kebab-case name used to refer to the rule in flags and output.
+    fn id(&self) -> &'static str;
+    /// One line saying what the rule looks for.
+    fn description(&self) -> &'static str;
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+    fn check(&self, cx: &Context, findings: &mut Vec<Finding>);
+}
+
+/// A reported finding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub level: Level,
+    pub message: String,
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}[{}]: {}",
+            self.line, self.column, self.level, self.rule, self.message
+        )
+    }
+}
+
+impl Diagnostic {
+    /// The diagnostic as a JSON object, with where it ends as well as where
+    /// it starts, `source` being the text of `file` it was found in.
+    pub fn to_json(&self, file: &str, source: &str) -> serde_json::Value {
+        let (end_line, end_column) = lexer::line_col(source, self.span.end);
+        json!({
+            "file": file,
+            "rule": self.rule,
+            "level": self.level.to_string(),
+            "message": self.message,
+            "line": self.line,
+            "column": self.column,
+            "end_line": end_line,
+            "end_column": end_column,
+            "start": self.span.start,
+            "end": self.span.end,
+        })
+    }
+}
+
+/// How diagnostics are written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiagnosticsFormat {
+    /// One line each, `file:line:column: level[rule]: message`
+    #[default]
+    Text,
+    /// One JSON object each, a line apiece
+    Json,
+    /// One SARIF 2.1.0 log of them all, for code scanning tools
+    Sarif,
+}
+
+/// The level SARIF calls `level`.
+fn sarif_level(level: Level) -> &'static str {
+    match level {
+        Level::Allow => "none",
+        Level::Warn => "warning",
+        Level::Deny => "error",
+    }
+}
+
+/// One-based line and column in UTF-16 code units, SARIF's default, of the
+/// byte at `offset`.
+fn sarif_line_col(source: &str, offset: usize) -> (usize, usize) {
+    let (line, _) = lexer::line_col(source, offset);
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    (line, source[line_start..offset].encode_utf16().count() + 1)
+}
+
+/// A SARIF log of `linter`'s diagnostics in each file, given with its text,
+/// listing every rule so tools can show what one means.
+pub fn sarif<'a>(
+    linter: &Linter,
+    files: impl IntoIterator<Item = (&'a str, &'a str, &'a [Diagnostic])>,
+) -> serde_json::Value {
+    let mut rules = vec![json!({
+        "id": SYNTAX,
+        "shortDescription": { "text": "words that don't parse" },
+        "defaultConfiguration": { "level": sarif_level(Level::Deny) },
+    })];
+    rules.extend(linter.rules().map(|(rule, level)| {
+        json!({
+            "id": rule.id(),
+            "shortDescription": { "text": rule.description() },
+            "defaultConfiguration": { "level": sarif_level(level) },
+        })
+    }));
+
+    let mut results = Vec::new();
+    for (file, source, diagnostics) in files {
+        for diagnostic in diagnostics {
+            let (start_line, start_column) = sarif_line_col(source, diagnostic.span.start);
+            let (end_line, end_column) = sarif_line_col(source, diagnostic.span.end);
+            results.push(json!({
+                "ruleId": diagnostic.rule,
+                "level": sarif_level(diagnostic.level),
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": file },
+                        "region": {
+                            "startLine": start_line,
+                            "startColumn": start_column,
+                            "endLine": end_line,
+                            "endColumn": end_column,
+                            "byteOffset": diagnostic.span.start,
+                            "byteLength": diagnostic.span.len(),
+                        },
+                    },
+                }],
+            }));
+        }
+    }
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "hnyfuck",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownRule(pub String);
+
+impl fmt::Display for UnknownRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown lint rule `{}`", self.0)
+    }
+}
+
+impl Error for UnknownRule {}
+
+/// Runs a set of rules, each at its own level.
+pub struct Linter {
+    rules: Vec<(Box<dyn Rule>, Level)>,
+}
+
+impl Linter {
+    /// A linter with the built-in rules at their default levels.
+    pub fn new() -> Linter {
+        let mut linter = Linter { rules: Vec::new() };
+        linter.add_rule(EmptyLoop);
+        linter.add_rule(DeadLoop);
+        linter.add_rule(ZeroDecrement);
+        linter.add_rule(PointerDrift);
+        linter.add_rule(CancellingPair);
+        linter.add_rule(InfiniteLoop);
+        linter
+    }
+
+    pub fn add_rule(&mut self, rule: impl Rule + 'static) {
+        let level = rule.default_level();
+        self.rules.push((Box::new(rule), level));
+    }
+
+    pub fn set_level(&mut self, id: &str, level: Level) -> Result<(), UnknownRule> {
+        let (_, current) = self
+            .rules
+            .iter_mut()
+            .find(|(rule, _)| rule.id() == id)
+            .ok_or_else(|| UnknownRule(id.to_string()))?;
+        *current = level;
+        Ok(())
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = (&dyn Rule, Level)> {
+        self.rules
+            .iter()
+            .map(|(rule, level)| (rule.as_ref(), *level))
+    }
+
+    /// Diagnostics of every rule not allowed, in source order.
+    pub fn lint(&self, source: &str, lenient: bool) -> Result<Vec<Diagnostic>, CompileError> {
+        let nodes = ast::parse(source, lenient)?;
+        Ok(self.run(source, lenient, &nodes))
+    }
+
+    /// Diagnostics of every syntax error, as errors of the rule `syntax`, and
+    /// of every rule not allowed on what parses around them, in source order.
+    pub fn check(&self, source: &str, lenient: bool) -> Vec<Diagnostic> {
+        let (nodes, errors) = ast::parse_recovering(source, lenient);
+        let mut diagnostics = self.run(source, lenient, &nodes);
+        diagnostics.extend(errors.into_iter().map(|error| {
+            let (line, column) = lexer::line_col(source, error.span.start);
+            Diagnostic {
+                rule: SYNTAX,
+                level: Level::Deny,
+                message: error.to_string(),
+                span: error.span,
+                line,
+                column,
+            }
+        }));
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        diagnostics
+    }
+
+    fn run(&self, source: &str, lenient: bool, nodes: &[Node]) -> Vec<Diagnostic> {
+        let cx = Context {
+            source,
+            lenient,
+            nodes,
+        };
+
+        let mut diagnostics = Vec::new();
+        for (rule, level) in &self.rules {
+            if *level == Level::Allow {
+                continue;
+            }
+            let mut findings = Vec::new();
+            rule.check(&cx, &mut findings);
+            diagnostics.extend(findings.into_iter().map(|finding| {
+                let (line, column) = lexer::line_col(source, finding.span.start);
+                Diagnostic {
+                    rule: rule.id(),
+                    level: *level,
+                    message: finding.message,
+                    span: finding.span,
+                    line,
+                    column,
+                }
+            }));
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        diagnostics
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Calls `f` on every loop, outermost first.
+fn each_loop(nodes: &[Node], f: &mut impl FnMut(&[Node], &Range<usize>)) {
+    for node in nodes {
+        if let Node::Loop(body, span) = node {
+            f(body, span);
+            each_loop(body, f);
+        }
+    }
+}
+
+/// Walks `nodes` knowing whether the current cell is zero, as it is at the
+/// start of the program and after every loop, and calls `f` on each node
+/// with that knowledge. Bodies of loops that cannot be entered are skipped.
+fn walk_zero(nodes: &[Node], zero: bool, untouched: bool, f: &mut impl FnMut(&Node, bool)) {
+    let (mut zero, mut untouched) = (zero, untouched);
+    for node in nodes {
+        f(node, zero);
+        match node {
+            Node::Instruction(Instruction::ShiftLeft | Instruction::ShiftRight, _) => {
+                zero = untouched
+            }
+            Node::Instruction(Instruction::Output, _) => (),
+            Node::Instruction(..) => (zero, untouched) = (false, false),
+            Node::Loop(body, _) => {
+                if !zero {
+                    walk_zero(body, false, false, f);
+                    untouched = false;
+                }
+                zero = true;
+            }
+        }
+    }
+}
+
+struct EmptyLoop;
+
+impl Rule for EmptyLoop {
+    fn id(&self) -> &'static str {
+        "empty-loop"
+    }
+
+    fn description(&self) -> &'static str {
+        "loops with nothing in them, which hang if entered"
+    }
+
+    fn check(&self, cx: &Context, findings: &mut Vec<Finding>) {
+        each_loop(cx.nodes, &mut |body, span| {
+            if body.is_empty() {
+                findings.push(Finding {
+                    span: span.clone(),
+                    message: "empty loop never terminates if entered".to_string(),
+                });
+            }
+        });
+    }
+}
+
+struct DeadLoop;
+
+impl Rule for DeadLoop {
+    fn id(&self) -> &'static str {
+        "dead-loop"
+    }
+
+    fn description(&self) -> &'static str {
+        "loops that start on a cell known to be zero and so never run"
+    }
+
+    fn check(&self, cx: &Context, findings: &mut Vec<Finding>) {
+        walk_zero(cx.nodes, true, true, &mut |node, zero| {
+            if let (Node::Loop(..), true) = (node, zero) {
+                findings.push(Finding {
+                    span: node.span(),
+                    message: "the current cell is always zero here, so this loop never runs"
+                        .to_string(),
+                });
+            }
+        });
+    }
+}
+
+struct ZeroDecrement;
+
+impl Rule for ZeroDecrement {
+    fn id(&self) -> &'static str {
+        "zero-decrement"
+    }
+
+    fn description(&self) -> &'static str {
+        "decrements of a cell known to be zero, which wrap around to 255"
+    }
+
+    fn check(&self, cx: &Context, findings: &mut Vec<Finding>) {
+        walk_zero(cx.nodes, true, true, &mut |node, zero| {
+            if let (Node::Instruction(Instruction::Decrement, span), true) = (node, zero) {
+                findings.push(Finding {
+                    span: span.clone(),
+                    message: "decrementing a zero cell wraps it around to 255".to_string(),
+                });
+            }
+        });
+    }
+}
+
+struct PointerDrift;
+
+impl Rule for PointerDrift {
+    fn id(&self) -> &'static str {
+        "pointer-drift"
+    }
+
+    fn description(&self) -> &'static str {
+        "loops that change cells and end somewhere other than where they started"
+    }
+
+    fn check(&self, cx: &Context, findings: &mut Vec<Finding>) {
+        each_loop(cx.nodes, &mut |body, span| {
+            let mut shift = 0isize;
+            let mut writes = false;
+            for node in body {
+                match node {
+                    Node::Instruction(Instruction::ShiftLeft, _) => shift -= 1,
+                    Node::Instruction(Instruction::ShiftRight, _) => shift += 1,
+                    Node::Instruction(Instruction::Output, _) => (),
+                    _ => writes = true,
+                }
+            }
+            if shift != 0 && writes {
+                findings.push(Finding {
+                    span: span.clone(),
+                    message: format!(
+                        "each iteration moves the pointer {} cell{} {}",
+                        shift.unsigned_abs(),
+                        if shift.unsigned_abs() == 1 { "" } else { "s" },
+                        if shift < 0 { "left" } else { "right" }
+                    ),
+                });
+            }
+        });
+    }
+}
+
+struct CancellingPair;
+
+impl Rule for CancellingPair {
+    fn id(&self) -> &'static str {
+        "cancelling-pair"
+    }
+
+    fn description(&self) -> &'static str {
+        "an instruction immediately undone by the next one"
+    }
+
+    fn check(&self, cx: &Context, findings: &mut Vec<Finding>) {
+        fn check(nodes: &[Node], findings: &mut Vec<Finding>) {
+            for window in nodes.windows(2) {
+                if let [Node::Instruction(a, first), Node::Instruction(b, second)] = window {
+                    let cancels = matches!(
+                        (a, b),
+                        (Instruction::Increment, Instruction::Decrement)
+                            | (Instruction::Decrement, Instruction::Increment)
+                            | (Instruction::ShiftLeft, Instruction::ShiftRight)
+                            | (Instruction::ShiftRight, Instruction::ShiftLeft)
+                    );
+                    if cancels {
+                        findings.push(Finding {
+                            span: first.start..second.end,
+                            message: format!("`{}{}` has no effect", a.symbol(), b.symbol()),
+                        });
+                    }
+                }
+            }
+            for node in nodes {
+                if let Node::Loop(body, _) = node {
+                    check(body, findings);
+                }
+            }
+        }
+        check(cx.nodes, findings);
+    }
+}
+
+struct InfiniteLoop;
+
+impl Rule for InfiniteLoop {
+    fn id(&self) -> &'static str {
+        "infinite-loop"
+    }
+
+    fn description(&self) -> &'static str {
+        "loops that never terminate once entered (see `analyze --termination`)"
+    }
+
+    fn check(&self, cx: &Context, findings: &mut Vec<Finding>) {
+        let Ok(warnings) = analyze::termination(cx.source, cx.lenient) else {
+            return;
+        };
+        findings.extend(
+            warnings
+                .into_iter()
+                .filter(|warning| warning.termination == Termination::Never)
+                .map(|warning| Finding {
+                    span: warning.span,
+                    message: format!("loop never terminates once entered: {}", warning.reason),
+                }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    fn lint(linter: &Linter, brainfuck: &str) -> Vec<(&'static str, Level)> {
+        let source = brainfuck_to_hny(brainfuck).output;
+        linter
+            .lint(&source, false)
+            .unwrap()
+            .into_iter()
+            .map(|diagnostic| (diagnostic.rule, diagnostic.level))
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let linter = Linter::new();
+        assert_eq!(lint(&linter, "+[->+<]>."), []);
+        assert_eq!(
+            lint(&linter, "[-]-+[]"),
+            [
+                ("dead-loop", Level::Warn),
+                ("zero-decrement", Level::Warn),
+                ("cancelling-pair", Level::Warn),
+                ("empty-loop", Level::Warn),
+                ("infinite-loop", Level::Warn),
+            ]
+        );
+        assert_eq!(lint(&linter, ",[>+]"), [("pointer-drift", Level::Warn)]);
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut linter = Linter::new();
+        linter.set_level("dead-loop", Level::Allow).unwrap();
+        linter.set_level("zero-decrement", Level::Deny).unwrap();
+        assert_eq!(lint(&linter, "[-]-"), [("zero-decrement", Level::Deny)]);
+        assert_eq!(
+            linter.set_level("no-such-rule", Level::Deny),
+            Err(UnknownRule("no-such-rule".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let linter = Linter::new();
+        let source = format!("New New {}", brainfuck_to_hny("[-]-[").output);
+        assert!(linter.lint(&source, false).is_err());
+        let diagnostics = linter
+            .check(&source, false)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.rule, diagnostic.level))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            [
+                (SYNTAX, Level::Deny),
+                ("dead-loop", Level::Warn),
+                ("zero-decrement", Level::Warn),
+                ("empty-loop", Level::Warn),
+                (SYNTAX, Level::Deny),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        let linter = Linter::new();
+        let source = format!("Année {}", brainfuck_to_hny("[-]-").output);
+        let diagnostics = linter.check(&source, true);
+        let dead = &diagnostics[0];
+        assert_eq!(dead.rule, "dead-loop");
+
+        // Columns count bytes, where SARIF's count UTF-16 code units.
+        let json = dead.to_json("a.hny", &source);
+        assert_eq!((&json["line"], &json["column"]), (&json!(1), &json!(8)));
+        assert_eq!(json["end_column"], 38);
+        assert_eq!(json["level"], "warning");
+
+        let sarif = sarif(&linter, [("a.hny", source.as_str(), &diagnostics[..])]);
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], SYNTAX);
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), linter.rules().count() + 1);
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "dead-loop");
+        let region = &result["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(
+            (&region["startLine"], &region["startColumn"]),
+            (&json!(1), &json!(7))
+        );
+        assert_eq!(region["byteOffset"], 7);
+        assert_eq!(run["results"].as_array().unwrap().len(), diagnostics.len());
+    }
+}
@@ -1,346 +1,2753 @@
-use core::panic;
 use std::{
-    collections::VecDeque,
-    io::{self, Read},
+    io::{self, IsTerminal, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::Command as Process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
-use clap::Parser;
+use clap::{
+    builder::PossibleValuesParser, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+use clap_complete::Shell;
+#[cfg(feature = "net")]
+use hnyfuck::fetch;
+use hnyfuck::{
+    analyze, ast,
+    bundle::{Bundle, Manifest},
+    cfg::ControlFlowGraph,
+    checkpoint::{self, Checkpoint},
+    codegen,
+    compression::{self, Compression},
+    config::{Config, Engine},
+    convert::{self, detect_language, Language},
+    dap,
+    debugger::{Condition, Debugger, Tracepoint},
+    encoding::{Base64Decoder, Base64Encoder, Encoding},
+    equiv, explain,
+    extension::Extension,
+    files, format, formatter,
+    frontend::{self, Frontend},
+    gallery, grammar, hot_loops, lexer,
+    limits::{Exit, Limits},
+    lint::{self, DiagnosticsFormat, Level, Linter},
+    newline::{Newline, NewlineReader, ToLineFeeds},
+    output::{self, Flush},
+    pass::PassManager,
+    poll::PolledReader,
+    postmortem::{Dump, Trace},
+    ranges::Ranges,
+    repl::{self, Repl},
+    rpc, sandbox, server,
+    spec::Spec,
+    specialize, steg,
+    superopt::{self, Superoptimizer},
+    transform::HexDump,
+    verify::{self, Verification},
+    FormatStyle, HnyFuck, Layout, Program, ProgramStats, Progress, State, DEFAULT_TIER_THRESHOLD,
+    EXTENSION,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+/// Run brainfuck written in Happy, New and Year
 #[derive(Parser)]
+#[command(subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(flatten)]
+    global: Global,
+}
+
+/// Options that apply to every subcommand.
+#[derive(Args, Clone)]
+struct Global {
+    /// Ignore punctuation and words other than Happy, New and Year
+    #[clap(long, global = true)]
+    lenient: bool,
+
+    /// Parse strictly even if the config file asks for --lenient
+    #[clap(long, global = true, conflicts_with = "lenient")]
+    strict: bool,
+
+    /// Optimization level of compiled code: 0 none, 1 loop rewriting, 2 also
+    /// passes that rely on the tape starting blank [default: 1]
+    #[clap(
+        short = 'O',
+        long = "optimize",
+        value_name = "LEVEL",
+        global = true,
+        value_parser = clap::value_parser!(u8).range(0..=2)
+    )]
+    optimize: Option<u8>,
+
+    /// Run an optimization pass even if the level leaves it out (see `passes`)
+    #[clap(long = "enable-pass", value_name = "PASS", global = true)]
+    enable_passes: Vec<String>,
+
+    /// Skip an optimization pass the level would run
+    #[clap(long = "disable-pass", value_name = "PASS", global = true)]
+    disable_passes: Vec<String>,
+
+    /// How to write warnings, errors and timings to stderr: `json` writes one
+    /// object per line, with timings of each phase
+    #[clap(long, value_name = "FORMAT", global = true, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Make runs reproducible: the same program and input give the same
+    /// stdout and stderr every time, on every platform. Leaves out the
+    /// progress line, SIGUSR1 state dumps and timings. Reading past the end of
+    /// input always leaves the cell unchanged
+    #[clap(long, global = true)]
+    deterministic: bool,
+
+    /// Ignore the config files: $XDG_CONFIG_HOME/hnyfuck/config.toml and
+    /// the project's .hnyfuck.toml
+    #[clap(long, global = true)]
+    no_config: bool,
+
+    /// The config files, with the options above already taken from them.
+    #[clap(skip)]
+    config: Config,
+}
+
+impl Global {
+    fn optimize(&self) -> u8 {
+        self.optimize.unwrap_or(1)
+    }
+
+    /// Reads the user's config file and the project's for `program` unless
+    /// --no-config says not to, filling in the options the command line left
+    /// out.
+    fn load_config(&mut self, program: Option<&Path>) {
+        if self.no_config {
+            return;
+        }
+        let user = Config::user_path().filter(|path| path.is_file());
+        let project = program.and_then(Config::project_path);
+        let config = project
+            .iter()
+            .chain(&user)
+            .map(|path| read_config(path))
+            .fold(Config::default(), Config::or);
+        if !self.lenient && !self.strict {
+            self.lenient = config.lenient.unwrap_or(false);
+        }
+        self.optimize = self.optimize.or(config.optimize);
+        self.config = config;
+    }
+}
+
+fn read_config(path: &Path) -> Config {
+    let config = Config::from_toml(&read_source(path))
+        .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e)));
+    if config.optimize.is_some_and(|level| level > 2) {
+        fail(format!(
+            "Error reading {}: optimize must be 0, 1 or 2",
+            path.display()
+        ));
+    }
+    config
+}
+
+impl Command {
+    /// The program the command acts on, or the first of them, whose project
+    /// config applies.
+    fn program(&self) -> Option<&Path> {
+        let path = match self {
+            Command::Run(args) if !args.code => Path::new(args.file.as_deref()?),
+            Command::Pack(PackArgs { file, .. })
+            | Command::Build(BuildArgs { file, .. })
+            | Command::Compile(CompileArgs { file, .. })
+            | Command::Cfg(CfgArgs { file, .. })
+            | Command::Explain(ExplainArgs { file, .. })
+            | Command::Steg(StegCommand::Encode(StegEncodeArgs { file, .. }))
+            | Command::Steg(StegCommand::Decode(StegDecodeArgs { file, .. }))
+            | Command::Obfuscate(ObfuscateArgs { file, .. })
+            | Command::Equiv(EquivArgs { first: file, .. })
+            | Command::Verify(VerifyArgs { file, .. })
+            | Command::Minify(MinifyArgs { file, .. })
+            | Command::Specialize(SpecializeArgs { file, .. })
+            | Command::Analyze(AnalyzeArgs { file, .. })
+            | Command::Stats(StatsArgs { file, .. }) => file,
+            Command::Fmt(FmtArgs { files, .. })
+            | Command::Convert(ConvertArgs { files, .. })
+            | Command::Lint(LintArgs { files, .. })
+            | Command::Pipe(PipeArgs { files }) => files.first()?,
+            _ => return None,
+        };
+        Some(path)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a program (the default when no subcommand is given)
+    Run(Box<RunArgs>),
+    /// Run programs with each one's output feeding the next one's input
+    Pipe(PipeArgs),
+    /// Pack a program with its input and expected output into a .hnypkg bundle
+    Pack(PackArgs),
+    /// Compile a program to a native executable through C
+    Build(BuildArgs),
+    /// Translate a program into source code for another language
+    Compile(CompileArgs),
+    /// Export the control-flow graph of a program as Graphviz DOT
+    Cfg(CfgArgs),
+    /// Print a program with every word pair annotated
+    Explain(ExplainArgs),
+    /// Convert between brainfuck and hnyfuck
+    Convert(ConvertArgs),
+    /// Reformat programs in place
+    Fmt(FmtArgs),
+    /// Hide a program in a cover text, or run one hidden that way
+    #[command(subcommand)]
+    Steg(StegCommand),
+    /// Pad a program with filler so it only runs with --lenient
+    Obfuscate(ObfuscateArgs),
+    /// Check that two programs give the same output and tape on a set of inputs
+    Equiv(EquivArgs),
+    /// Run a program under every engine and check they agree with the interpreter
+    Verify(VerifyArgs),
+    /// Strip a program down to its instructions, shortening straight-line runs
+    Minify(MinifyArgs),
+    /// Bake known input into a program, running as much of it as possible
+    Specialize(SpecializeArgs),
+    /// Report what can be proven about a program without running it
+    Analyze(AnalyzeArgs),
+    /// Check programs for likely mistakes
+    Lint(LintArgs),
+    /// Report instruction counts, loop nesting and other metrics without running
+    Stats(StatsArgs),
+    /// List the optimization passes and whether the options given enable them
+    Passes,
+    /// Print the instruction table, extension functions and semantics
+    Spec(SpecArgs),
+    /// Generate an editor grammar for hnyfuck or another dialect
+    #[command(subcommand)]
+    Grammar(GrammarCommand),
+    /// Carry on a run saved with --checkpoint-every, feeding it the same input
+    Resume(ResumeArgs),
+    /// Serve an HTTP API that runs programs under limits
+    Serve(ServeArgs),
+    /// Answer one request for `serve`, which starts a worker per request
+    #[command(hide = true)]
+    ServeWorker(ServeWorkerArgs),
+    /// Run programs a line at a time against one tape; `:help` lists commands
+    Repl,
+    /// Drive the interpreter with JSON-RPC requests on stdin, one per line
+    Rpc,
+    /// Serve the Debug Adapter Protocol on stdin and stdout, for editors
+    Dap,
+    /// Try the programs that ship with hnyfuck
+    #[command(subcommand)]
+    Examples(ExamplesCommand),
+    /// Print a completion script for a shell
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+    /// Write the manual page in roff
+    Man(ManArgs),
+}
+
+#[derive(Subcommand)]
+enum StegCommand {
+    /// Weave a program into a cover text
+    Encode(StegEncodeArgs),
+    /// Extract a hidden program and run it
+    Decode(StegDecodeArgs),
+}
+
+#[derive(Subcommand)]
+enum GrammarCommand {
+    /// Write a tree-sitter grammar.js, with highlight and fold queries
+    TreeSitter(TreeSitterArgs),
+}
+
+#[derive(Subcommand)]
+enum ExamplesCommand {
+    /// List the examples
+    List,
+    /// Run an example
+    Run { name: String },
+    /// Print an example's source
+    Show { name: String },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    C,
+    Js,
+    Python,
+    #[cfg(feature = "llvm")]
+    #[value(name = "llvm-ir")]
+    LlvmIr,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// The bytes as the program writes them
+    #[default]
+    Raw,
+    /// An xxd-style hex dump, with offsets and the bytes as text
+    Hex,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum SpecFormat {
+    /// Tables for reading
+    #[default]
+    Text,
+    /// One JSON object, for tools
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Emit {
+    /// The optimized instruction listing
+    Ir,
+}
+
+#[derive(Args)]
+struct RunArgs {
     #[clap(short, long)]
     code: bool,
 
-    #[arg()]
-    file: String,
+    /// Print per-opcode and per-loop execution counters to stderr after the run
+    #[clap(long)]
+    stats: bool,
+
+    /// Print the N loops with the most iterations, with their source location
+    #[clap(long, value_name = "N")]
+    hot_loops: Option<usize>,
+
+    /// Loop iterations to interpret before compiling a loop to IR ops
+    /// [default: 100]
+    #[clap(long, value_name = "N")]
+    tier_threshold: Option<u64>,
+
+    /// How to run the program [default: tiered]
+    #[clap(long, value_enum, conflicts_with = "no_tiering")]
+    engine: Option<Engine>,
+
+    /// Interpret every loop without compiling hot ones, as --engine
+    /// interpreter does
+    #[clap(long)]
+    no_tiering: bool,
+
+    /// Language the program is written in [default: guessed from its
+    /// contents]
+    #[clap(long, value_enum)]
+    lang: Option<Language>,
+
+    /// Read the program in the dialect a TOML spec defines, mapping runs of
+    /// words to instructions
+    #[clap(
+        long,
+        value_name = "SPEC",
+        conflicts_with_all = ["lang", "checkpoint_every"]
+    )]
+    frontend: Option<PathBuf>,
+
+    /// Read the program with another language's New Year greeting in place
+    /// of Happy, New and Year
+    #[clap(
+        long,
+        value_parser = PossibleValuesParser::new(frontend::LOCALES.map(|(name, _)| name)),
+        conflicts_with_all = ["lang", "frontend", "checkpoint_every"]
+    )]
+    locale: Option<String>,
+
+    /// Let the program call these extensions' functions with Year Year
+    #[clap(
+        long,
+        value_enum,
+        value_name = "EXTENSION",
+        value_delimiter = ',',
+        conflicts_with_all = ["checkpoint_every", "emit"]
+    )]
+    extensions: Vec<Extension>,
+
+    /// Print an intermediate form of the program instead of running it
+    #[clap(long, value_enum)]
+    emit: Option<Emit>,
+
+    /// Refuse to run programs whose loops nest deeper than N [default: warn
+    /// past 64]
+    #[clap(long, value_name = "N")]
+    max_loop_depth: Option<usize>,
+
+    /// Don't show a status line on stderr when a program runs for long
+    #[clap(long)]
+    no_progress: bool,
+
+    /// Append the state dumps that SIGUSR1 asks for to PATH instead of
+    /// writing them to stderr (Unix only)
+    #[clap(long, value_name = "PATH", conflicts_with = "deterministic")]
+    dump_file: Option<PathBuf>,
+
+    /// If the program fails, past --max-output-bytes, at a token that isn't
+    /// an instruction or writing its output, save its tape and last
+    /// instructions for looking into later. Loops are never compiled while
+    /// recording
+    #[clap(long, conflicts_with = "checkpoint_every")]
+    postmortem: bool,
+
+    /// Where to save the post-mortem dump [default: the program's path with a
+    /// .hnydump extension]
+    #[clap(long, value_name = "PATH", requires = "postmortem")]
+    postmortem_file: Option<PathBuf>,
+
+    /// Run the compiled program, saving its state every N steps (underscores
+    /// allowed, as in 10_000_000) so a crashed run can be picked up again
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = parse_count,
+        conflicts_with_all = ["stats", "hot_loops", "debug", "emit"]
+    )]
+    checkpoint_every: Option<u64>,
+
+    /// Where to save checkpoints [default: the program's path with a .ckpt
+    /// extension]
+    #[clap(long, value_name = "PATH", requires = "checkpoint_every")]
+    checkpoint_file: Option<PathBuf>,
+
+    /// Stop the program with an error once it has written N bytes, rather
+    /// than let it write another
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = parse_count,
+        conflicts_with = "checkpoint_every"
+    )]
+    max_output_bytes: Option<u64>,
+
+    /// Also write the program's output to FILE, byte for byte
+    #[clap(long, value_name = "FILE", conflicts_with = "checkpoint_every")]
+    tee: Option<PathBuf>,
+
+    /// How to show the program's output on stdout
+    #[clap(long, value_enum, default_value_t, conflicts_with = "checkpoint_every")]
+    output_format: OutputFormat,
+
+    /// How the program's input is encoded
+    #[clap(long, value_enum, default_value_t, conflicts_with = "checkpoint_every")]
+    input_encoding: Encoding,
+
+    /// How to encode the program's output on stdout
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["output_format", "checkpoint_every"]
+    )]
+    output_encoding: Encoding,
+
+    /// How the program ends lines, for translating the line feeds in its
+    /// input and back in its output
+    #[clap(long, value_enum, default_value_t, conflicts_with = "checkpoint_every")]
+    newline: Newline,
+
+    /// When the program's output is flushed: every-byte shows prompts at
+    /// once, on-exit is fastest for output no one is watching
+    #[clap(long, value_enum, default_value_t, conflicts_with = "checkpoint_every")]
+    flush: Flush,
+
+    /// Start on the tape saved in FILE, if there is one, and save the tape
+    /// there when the program finishes, so it can keep state between runs
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["args", "checkpoint_every"]
+    )]
+    persist_tape: Option<PathBuf>,
+
+    /// Keep the tape in a sparse file created at FILE, which is removed as
+    /// soon as it's open, instead of in memory, for programs that use more
+    /// cells than there is memory for (Linux only)
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["checkpoint_every", "emit"]
+    )]
+    tape_file: Option<PathBuf>,
+
+    /// Pause in an interactive debugger on Ctrl-C instead of exiting; press
+    /// it twice to exit. Loops are never compiled while debugging
+    #[clap(long)]
+    debug: bool,
+
+    /// Pause in the debugger whenever CONDITION becomes true, such as
+    /// `cell[3] == 10` or `pointer > 100`
+    #[clap(
+        long = "break",
+        value_name = "CONDITION",
+        value_parser = Condition::parse,
+        requires = "debug"
+    )]
+    breakpoints: Vec<Condition>,
+
+    /// Print MESSAGE whenever CONDITION becomes true, filling in operands in
+    /// braces like `{cell[1]}`, and carry on
+    #[clap(
+        long = "tracepoint",
+        value_name = "CONDITION[, MESSAGE]",
+        value_parser = Tracepoint::parse,
+        requires = "debug"
+    )]
+    tracepoints: Vec<Tracepoint>,
+
+    /// Text for the program to read before stdin, with \n, \t, \\ and \xHH
+    /// escapes. Repeat it, or mix it with --input-file, to give input in
+    /// parts, read in order [default: $HNY_INPUT if stdin is a terminal]
+    #[clap(long, value_name = "TEXT", conflicts_with = "checkpoint_every")]
+    input: Vec<String>,
+
+    /// File for the program to read before stdin, as --input
+    #[clap(long, value_name = "FILE", conflicts_with = "checkpoint_every")]
+    input_file: Vec<PathBuf>,
+
+    /// Treat the end of --input and --input-file as the end of input instead
+    /// of going on to read stdin or $HNY_INPUT
+    #[clap(long)]
+    no_stdin: bool,
+
+    /// Text to show on stderr whenever the program waits for input at a
+    /// terminal, such as "? ", with the same escapes as --input
+    #[clap(long, value_name = "TEXT", conflicts_with = "checkpoint_every")]
+    prompt: Option<String>,
+
+    /// Don't wait for input: when the program reads and nothing has been
+    /// typed or sent yet, give it BYTE at once, so it can poll for input
+    #[clap(
+        long,
+        value_name = "BYTE",
+        conflicts_with_all = ["input_encoding", "checkpoint_every"]
+    )]
+    input_default: Option<u8>,
+
+    /// Give up waiting for stdin after TIME, such as 2s or 500ms, so a read
+    /// the input doesn't come for gets the end of input instead, or
+    /// --input-default if given
+    #[clap(
+        long,
+        value_name = "TIME",
+        value_parser = parse_duration,
+        conflicts_with_all = ["input_encoding", "checkpoint_every"]
+    )]
+    input_timeout: Option<Duration>,
+
+    /// --input and --input-file in the order they were given.
+    #[clap(skip)]
+    inputs: Vec<Input>,
+
+    /// Arguments for the program, written to the tape before it starts, each
+    /// followed by a NUL, with the pointer on the cell after the last
+    #[clap(
+        long,
+        value_name = "ARG",
+        num_args = 1..,
+        allow_hyphen_values = true,
+        conflicts_with = "checkpoint_every"
+    )]
+    args: Vec<String>,
+
+    /// Download the program from URL and run it
+    #[cfg(feature = "net")]
+    #[clap(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["file", "code", "checkpoint_every"]
+    )]
+    url: Option<String>,
+
+    /// Refuse to run a download larger than N bytes [default: 1 MiB]
+    #[cfg(feature = "net")]
+    #[clap(long, value_name = "N", value_parser = parse_count, conflicts_with = "file")]
+    max_download_bytes: Option<u64>,
+
+    /// Refuse to run a download unless its SHA-256 is HASH, in hex
+    #[cfg(feature = "net")]
+    #[clap(long, value_name = "HASH", conflicts_with = "file")]
+    sha256: Option<String>,
+
+    #[cfg_attr(not(feature = "net"), arg(required = true))]
+    #[cfg_attr(feature = "net", arg(required_unless_present = "url"))]
+    file: Option<String>,
+}
+
+#[derive(Args)]
+struct PipeArgs {
+    /// The programs, first to last; the first reads stdin and the last writes
+    /// stdout
+    #[arg(required = true, num_args = 2..)]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct PackArgs {
+    file: PathBuf,
+
+    /// File the program reads when the bundle runs [default: stdin]
+    #[clap(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// File holding the output the program should write
+    #[clap(long, value_name = "FILE")]
+    expected: Option<PathBuf>,
+
+    /// Name shown in messages [default: the program's file name]
+    #[clap(long)]
+    name: Option<String>,
+
+    /// Stop the program after N steps
+    #[clap(long, value_name = "N", value_parser = parse_count)]
+    max_steps: Option<u64>,
+
+    /// Stop the program once it uses more than N tape cells
+    #[clap(long, value_name = "N")]
+    max_cells: Option<usize>,
+
+    /// Stop the program once it writes more than N bytes
+    #[clap(long, value_name = "N")]
+    max_output_bytes: Option<usize>,
+
+    /// Path of the bundle to write [default: the program's path with a
+    /// .hnypkg extension]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    file: PathBuf,
+
+    /// Path of the executable to write [default: the program name without extension]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Optimization level passed to the C compiler as -O<LEVEL>
+    #[clap(long, default_value = "2")]
+    opt_level: String,
+
+    /// C compiler to invoke [default: $CC, or cc]
+    #[clap(long)]
+    cc: Option<String>,
+
+    /// Keep the generated C source next to the executable
+    #[clap(long)]
+    keep_c: bool,
+}
+
+#[derive(Args)]
+struct CompileArgs {
+    file: PathBuf,
+
+    #[clap(long, value_enum)]
+    target: Target,
+
+    /// File to write the generated source to [default: stdout]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct CfgArgs {
+    file: PathBuf,
+
+    /// File to write the DOT graph to [default: stdout]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ExplainArgs {
+    file: PathBuf,
+
+    /// Indent loop bodies
+    #[clap(long)]
+    indent: bool,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// Programs, directories to search for them, or glob patterns
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    batch: Batch,
+
+    /// Language to convert into
+    #[clap(long, value_enum)]
+    to: Language,
+
+    /// Language the program is written in [default: guessed from its
+    /// contents]
+    #[clap(long, value_enum)]
+    from: Option<Language>,
+
+    /// File to write the converted program to [default: stdout]
+    #[clap(short, long, conflicts_with = "out_dir")]
+    output: Option<PathBuf>,
+
+    /// Directory to write the converted programs to, under the paths they
+    /// were found at; needed to convert more than one
+    #[clap(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+
+    /// Also write a JSON source map from the output back to the input
+    #[clap(long, value_name = "FILE", conflicts_with = "out_dir")]
+    source_map: Option<PathBuf>,
+}
+
+/// How a command finds programs in directories and glob patterns, and works
+/// through them.
+#[derive(Args)]
+struct Batch {
+    /// Only take files matching GLOB from directories [default: programs
+    /// the command reads, such as *.hny]
+    #[clap(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files and directories matching GLOB
+    #[clap(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Threads to process the files on [default: one per CPU]
+    #[clap(short, long, value_name = "N")]
+    jobs: Option<usize>,
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    /// Programs, directories to search for them, or glob patterns
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    batch: Batch,
+
+    /// TOML file with style options; flags override it
+    #[clap(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Overall layout; `card` output only runs with --lenient
+    #[clap(long, value_enum)]
+    style: Option<Layout>,
+
+    /// Most word pairs on one line
+    #[clap(long, value_name = "N")]
+    pairs_per_line: Option<usize>,
+
+    /// Longest line, indentation included
+    #[clap(long, value_name = "N")]
+    max_width: Option<usize>,
+
+    /// Spaces of indentation per loop level
+    #[clap(long, value_name = "N")]
+    indent: Option<usize>,
+
+    /// Put `Happy Happy` and `New New` on lines of their own
+    #[clap(long, value_name = "BOOL")]
+    loop_lines: Option<bool>,
+
+    /// Don't write anything; print a diff for each file not formatted and
+    /// exit with 1 if there were any
+    #[clap(long)]
+    check: bool,
+}
+
+#[derive(Args)]
+struct StegEncodeArgs {
+    file: PathBuf,
+
+    /// Text to hide the program in
+    #[clap(long, value_name = "FILE")]
+    cover: PathBuf,
+
+    /// File to write the text to [default: stdout]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct StegDecodeArgs {
+    file: PathBuf,
+
+    /// Print the extracted program instead of running it
+    #[clap(long)]
+    print: bool,
+}
+
+#[derive(Args)]
+struct ObfuscateArgs {
+    file: PathBuf,
+
+    /// Chance of inserting filler between two words, from 0 to 1
+    #[clap(long, default_value_t = 0.3)]
+    density: f64,
+
+    /// Seed for the random choices; the same seed gives the same output
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    /// File to write the obfuscated program to [default: stdout]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct EquivArgs {
+    first: PathBuf,
+    second: PathBuf,
+
+    /// File with one input per line; `\n`, `\t`, `\\` and `\xHH` are unescaped
+    #[clap(long, value_name = "FILE")]
+    inputs: Option<PathBuf>,
+
+    /// Also try every input up to N bytes long
+    #[clap(long, value_name = "N")]
+    exhaustive: Option<usize>,
+
+    /// Bytes the exhaustive inputs are made of, escaped like --inputs [default: all bytes]
+    #[clap(long, value_name = "BYTES")]
+    alphabet: Option<String>,
+
+    /// Operations to run per input before giving up on it
+    #[clap(long, value_name = "N", default_value_t = 1_000_000)]
+    step_limit: u64,
+}
+
+#[derive(Args)]
+struct MinifyArgs {
+    file: PathBuf,
+
+    /// Longest replacement the superoptimizer searches for
+    #[clap(long, value_name = "N", default_value_t = 8)]
+    search_length: usize,
+
+    /// File to write the minified program to [default: stdout]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct SpecializeArgs {
+    file: PathBuf,
+
+    /// File holding the start of the program's input [default: none]
+    #[clap(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// Operations to run before leaving the rest to the residual program
+    #[clap(long, value_name = "N", default_value_t = 1_000_000)]
+    step_limit: u64,
+
+    /// File to write the residual program to [default: stdout]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    file: PathBuf,
+
+    /// File holding the program's input [default: none]
+    #[clap(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// Steps to run each engine for before giving up on it
+    #[clap(long, value_name = "N", default_value_t = 100_000_000, value_parser = parse_count)]
+    step_limit: u64,
+}
+
+#[derive(Args)]
+struct ResumeArgs {
+    /// The checkpoint file, which goes on being updated as the run continues
+    checkpoint: PathBuf,
+
+    /// Save the state every N steps [default: as often as before]
+    #[clap(long, value_name = "N", value_parser = parse_count)]
+    checkpoint_every: Option<u64>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Read settings from a TOML file, which the flags below override
+    #[clap(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// [default: 8080]
+    #[clap(long)]
+    port: Option<u16>,
+
+    /// Address to listen on [default: 127.0.0.1]
+    #[clap(long)]
+    host: Option<String>,
+
+    /// Most steps a request may run [default: 10000000]
+    #[clap(long, value_name = "N", value_parser = parse_count)]
+    max_steps: Option<u64>,
+
+    /// Most cells a request's tape may grow to [default: 65536]
+    #[clap(long, value_name = "N")]
+    max_cells: Option<usize>,
+
+    /// Most bytes a request may write [default: 1048576]
+    #[clap(long, value_name = "N")]
+    max_output_bytes: Option<usize>,
+
+    /// Most requests a client may make a minute [default: unlimited]
+    #[clap(long, value_name = "N")]
+    rate_limit: Option<u32>,
+
+    /// Most programs that may run at once [default: unlimited]
+    #[clap(long, value_name = "N")]
+    max_running: Option<usize>,
+
+    /// Most memory each worker may map, in bytes [default: 1073741824]
+    #[clap(long, value_name = "BYTES", value_parser = parse_count)]
+    max_memory: Option<u64>,
+
+    /// Most CPU time each worker may use before it is killed, and longest a
+    /// session may stay open [default: 60]
+    #[clap(long, value_name = "SECONDS")]
+    max_cpu_seconds: Option<u64>,
+
+    /// Append a JSON line to FILE for each program run
+    #[clap(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Rotate the audit log once it reaches this size [default: 104857600]
+    #[clap(long, value_name = "BYTES", value_parser = parse_count)]
+    audit_max_bytes: Option<u64>,
+
+    /// Rotated audit logs to keep [default: 5]
+    #[clap(long, value_name = "N")]
+    audit_keep: Option<usize>,
+
+    /// Run each request in a worker process without sandboxing it, which is
+    /// the only way to serve off Linux
+    #[clap(long)]
+    no_sandbox: bool,
+}
+
+#[derive(Args)]
+struct ServeWorkerArgs {
+    #[clap(long)]
+    no_sandbox: bool,
+
+    #[clap(long, default_value_t = sandbox::DEFAULT_QUOTAS.memory)]
+    max_memory: u64,
+
+    #[clap(long, default_value_t = sandbox::DEFAULT_QUOTAS.cpu_seconds)]
+    max_cpu_seconds: u64,
+}
+
+#[derive(Args)]
+struct ManArgs {
+    /// File to write the page to [default: stdout]
+    #[clap(short, long, conflicts_with = "dir")]
+    output: Option<PathBuf>,
+
+    /// Write a page for every subcommand too, such as hnyfuck-run.1, into DIR
+    #[clap(long, value_name = "DIR")]
+    dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    file: PathBuf,
+}
+
+#[derive(Args)]
+struct TreeSitterArgs {
+    /// Dialect to write the grammar for
+    #[clap(long, value_enum, default_value_t)]
+    lang: Language,
+
+    /// Write the grammar for the dialect a TOML spec defines
+    #[clap(long, value_name = "SPEC", conflicts_with = "lang")]
+    frontend: Option<PathBuf>,
+
+    /// Write the grammar for another language's New Year greeting
+    #[clap(
+        long,
+        value_parser = PossibleValuesParser::new(frontend::LOCALES.map(|(name, _)| name)),
+        conflicts_with_all = ["lang", "frontend"]
+    )]
+    locale: Option<String>,
+
+    /// Write grammar.js and its queries into DIR [default: grammar.js to
+    /// stdout]
+    #[clap(long, value_name = "DIR")]
+    dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct SpecArgs {
+    #[clap(long, value_enum, default_value_t)]
+    format: SpecFormat,
+}
+
+#[derive(Args)]
+struct AnalyzeArgs {
+    file: PathBuf,
+
+    /// Warn about loops that never or may not terminate
+    #[clap(long)]
+    termination: bool,
+
+    /// List the optimized program with the range of the current cell at each op
+    #[clap(long)]
+    ranges: bool,
+}
+
+#[derive(Args)]
+struct LintArgs {
+    /// Programs, directories to search for them, or glob patterns
+    #[arg(required_unless_present = "list")]
+    files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    batch: Batch,
+
+    /// Do not report RULE
+    #[clap(short = 'A', long, value_name = "RULE")]
+    allow: Vec<String>,
+
+    /// Report RULE as a warning
+    #[clap(short = 'W', long, value_name = "RULE")]
+    warn: Vec<String>,
+
+    /// Report RULE as an error and fail if it is found
+    #[clap(short = 'D', long, value_name = "RULE")]
+    deny: Vec<String>,
+
+    /// List the rules and their levels
+    #[clap(long)]
+    list: bool,
+
+    /// How to write the diagnostics to stdout
+    #[clap(long, value_enum, default_value_t)]
+    diagnostics_format: DiagnosticsFormat,
 }
 
 fn main() {
-    let args = Cli::parse();
-    let code = if args.code {
-        args.file
-    } else {
-        std::fs::read_to_string(&args.file).unwrap_or_else(|e| {
-            eprintln!("Error reading file: {}", e);
-            std::process::exit(1);
-        })
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    match &mut cli.command {
+        Some(Command::Run(args)) => args.order_inputs(matches.subcommand_matches("run").unwrap()),
+        None => cli.run.order_inputs(&matches),
+        _ => (),
+    }
+    let mut global = cli.global;
+    let program = match &cli.command {
+        Some(command) => command.program(),
+        None => cli
+            .run
+            .file
+            .as_deref()
+            .filter(|_| !cli.run.code)
+            .map(Path::new),
     };
+    global.load_config(program);
+    LOG_FORMAT.set(global.log_format).ok();
+    DETERMINISTIC.set(global.deterministic).ok();
+    match cli.command {
+        Some(Command::Run(args)) => run(*args, &global),
+        Some(Command::Pipe(args)) => pipe(args, &global),
+        Some(Command::Pack(args)) => pack(args, &global),
+        Some(Command::Build(args)) => build(args, &global),
+        Some(Command::Compile(args)) => compile(args, &global),
+        Some(Command::Cfg(args)) => cfg(args, &global),
+        Some(Command::Convert(args)) => convert(args),
+        Some(Command::Fmt(args)) => fmt(args, &global),
+        Some(Command::Steg(StegCommand::Encode(args))) => steg_encode(args, &global),
+        Some(Command::Steg(StegCommand::Decode(args))) => steg_decode(args, &global),
+        Some(Command::Obfuscate(args)) => obfuscate(args, &global),
+        Some(Command::Equiv(args)) => equiv(args, &global),
+        Some(Command::Verify(args)) => verify(args, &global),
+        Some(Command::Minify(args)) => minify(args, &global),
+        Some(Command::Specialize(args)) => specialize(args, &global),
+        Some(Command::Analyze(args)) => analyze(args, &global),
+        Some(Command::Lint(args)) => lint(args, &global),
+        Some(Command::Stats(args)) => stats(args, &global),
+        Some(Command::Resume(args)) => resume(args, &global),
+        Some(Command::Serve(args)) => serve(args),
+        Some(Command::ServeWorker(args)) => {
+            let quotas = sandbox::Quotas {
+                memory: args.max_memory,
+                cpu_seconds: args.max_cpu_seconds,
+            };
+            if let Err(e) = server::worker((!args.no_sandbox).then_some(&quotas)) {
+                fail(format!("Error answering a request: {}", e));
+            }
+        }
+        Some(Command::Dap) => dap::serve(io::stdin(), io::stdout()),
+        Some(Command::Repl) => repl(&global),
+        Some(Command::Rpc) => rpc::serve(io::BufReader::new(io::stdin()), io::stdout()),
+        Some(Command::Passes) => passes(&global),
+        Some(Command::Spec(args)) => spec(args),
+        Some(Command::Grammar(GrammarCommand::TreeSitter(args))) => tree_sitter(args),
+        Some(Command::Examples(command)) => examples(command),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Cli::command(), "hnyfuck", &mut io::stdout())
+        }
+        Some(Command::Man(args)) => man(args),
+        Some(Command::Explain(args)) => {
+            print!("{}", explain(&read_source(&args.file), args.indent))
+        }
+        None => run(cli.run, &global),
+    }
+}
 
-    let mut hny = HnyFuck::from_str(&code);
-    hny.run();
-}
-
-const SHIFT_LEFT: (&str, &str) = ("Happy", "New");
-const SHIFT_RIGHT: (&str, &str) = ("New", "Year");
-const INCREMENT: (&str, &str) = ("Year", "Happy");
-const DECREMENT: (&str, &str) = ("Happy", "Year");
-const OUTPUT: (&str, &str) = ("Year", "New");
-const INPUT: (&str, &str) = ("New", "Happy");
-const LOOP_START: (&str, &str) = ("Happy", "Happy");
-const LOOP_END: (&str, &str) = ("New", "New");
-
-fn from_brainfuck(code: &str) -> HnyFuck {
-    let hny_code = code
-        .chars()
-        .map(|c| match c {
-            '>' => SHIFT_RIGHT,
-            '<' => SHIFT_LEFT,
-            '+' => INCREMENT,
-            '-' => DECREMENT,
-            '.' => OUTPUT,
-            ',' => INPUT,
-            '[' => LOOP_START,
-            ']' => LOOP_END,
-            _ => panic!("Invalid character"),
-        })
-        .map(|(a, b)| format!("{} {}", a, b))
-        .collect::<Vec<String>>()
-        .join(" ");
+static LOG_FORMAT: OnceLock<LogFormat> = OnceLock::new();
+static DETERMINISTIC: OnceLock<bool> = OnceLock::new();
 
-    HnyFuck::new(TokenStream::from_str(&hny_code))
+#[derive(Clone, Copy)]
+enum Severity {
+    Error,
+    Warning,
+    Note,
+    Info,
 }
 
-#[derive(Debug, Clone)]
-struct TokenStream {
-    tokens: VecDeque<String>,
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Info => "info",
+        }
+    }
 }
 
-impl TokenStream {
-    fn new() -> TokenStream {
-        TokenStream {
-            tokens: VecDeque::new(),
+/// Writes a diagnostic to stderr. Text logs leave out `Info` records and
+/// `fields`; JSON logs write everything as one object.
+fn log(severity: Severity, message: impl std::fmt::Display, fields: serde_json::Value) {
+    output::flush();
+    match LOG_FORMAT.get().copied().unwrap_or(LogFormat::Text) {
+        LogFormat::Text => match severity {
+            Severity::Error => eprintln!("{}", message),
+            Severity::Info => (),
+            _ => eprintln!("{}: {}", severity.name(), message),
+        },
+        LogFormat::Json => {
+            let mut record = serde_json::json!({
+                "level": severity.name(),
+                "message": message.to_string(),
+            });
+            if let serde_json::Value::Object(fields) = fields {
+                record.as_object_mut().unwrap().extend(fields);
+            }
+            eprintln!("{}", record);
         }
     }
+}
 
-    fn push(&mut self, token: String) {
-        self.tokens.push_back(token);
+/// Runs `f`, logging how long it took as `phase` unless runs are to be
+/// deterministic.
+fn timed<T>(phase: &str, f: impl FnOnce() -> T) -> T {
+    if DETERMINISTIC.get() == Some(&true) {
+        return f();
     }
+    let started = Instant::now();
+    let result = f();
+    let ms = started.elapsed().as_secs_f64() * 1000.0;
+    log(
+        Severity::Info,
+        format!("{} took {:.3}ms", phase, ms),
+        serde_json::json!({ "phase": phase, "duration_ms": ms }),
+    );
+    result
+}
 
-    fn from_str(input: &str) -> TokenStream {
-        let mut stream = TokenStream::new();
-        for token in input.split_whitespace() {
-            stream.push(token.to_string());
-        }
-        stream
+fn fail(message: impl std::fmt::Display) -> ! {
+    log(Severity::Error, message, serde_json::Value::Null);
+    std::process::exit(1);
+}
+
+/// The text of the file at `path`, decompressed if it is gzip or zstd.
+fn read_source(path: impl AsRef<Path>) -> String {
+    source_text(read_file(path))
+}
+
+/// [`read_source`] for work on other threads, giving the error instead of
+/// failing, with the path in it.
+fn try_read_source(path: &Path) -> Result<String, String> {
+    let bytes = std::fs::read(path)
+        .and_then(compression::decompress)
+        .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    String::from_utf8(bytes)
+        .map_err(|_| format!("Error reading {}: not valid UTF-8", path.display()))
+}
+
+/// The contents of the file at `path`, decompressed if they are gzip or zstd.
+fn read_file(path: impl AsRef<Path>) -> Vec<u8> {
+    std::fs::read(path)
+        .and_then(compression::decompress)
+        .unwrap_or_else(|e| fail(format!("Error reading file: {}", e)))
+}
+
+fn source_text(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes)
+        .unwrap_or_else(|_| fail("Error reading file: stream did not contain valid UTF-8"))
+}
+
+/// `code`, written in `lang`, as word pairs.
+fn to_hny(name: &str, code: &str, lang: Language) -> String {
+    convert::convert(code, lang, Language::Hny)
+        .unwrap_or_else(|e| fail(format!("Error converting {}: {}", name, e)))
+        .output
+}
+
+/// The environment variable a program's input is taken from when stdin is a
+/// terminal and no other input is given.
+const INPUT_VARIABLE: &str = "HNY_INPUT";
+
+/// Part of a program's input given on the command line.
+enum Input {
+    Text(String),
+    File(PathBuf),
+}
+
+impl RunArgs {
+    /// Fills in [`inputs`](RunArgs::inputs) from the matches `self` was
+    /// parsed from, which know where on the command line each input was.
+    fn order_inputs(&mut self, matches: &clap::ArgMatches) {
+        let at = |id| matches.indices_of(id).into_iter().flatten();
+        let texts = at("input").zip(self.input.drain(..).map(Input::Text));
+        let files = at("input_file").zip(self.input_file.drain(..).map(Input::File));
+        let mut inputs = texts.chain(files).collect::<Vec<_>>();
+        inputs.sort_by_key(|(index, _)| *index);
+        self.inputs = inputs.into_iter().map(|(_, input)| input).collect();
     }
+}
 
-    fn next(&mut self) -> Option<String> {
-        self.tokens.pop_front()
+/// The input `run` gives the program: the inputs given, one after the other,
+/// and then stdin unless `--no-stdin`.
+fn run_input(args: &RunArgs) -> Box<dyn Read + Send> {
+    let mut bytes = Vec::new();
+    for input in &args.inputs {
+        match input {
+            Input::Text(text) => bytes.extend(
+                equiv::unescape(text)
+                    .unwrap_or_else(|e| fail(format!("Error reading --input: {}", e))),
+            ),
+            Input::File(path) => bytes.extend(
+                std::fs::read(path)
+                    .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e))),
+            ),
+        }
+    }
+    if args.inputs.is_empty() {
+        // HNY_INPUT stands in for typing at the terminal, so it goes unread
+        // along with stdin.
+        if args.no_stdin {
+            return Box::new(io::empty());
+        }
+        let Some(text) = std::env::var(INPUT_VARIABLE)
+            .ok()
+            .filter(|text| !text.is_empty() && io::stdin().is_terminal())
+        else {
+            return stdin(args);
+        };
+        bytes = equiv::unescape(&text)
+            .unwrap_or_else(|e| fail(format!("Error reading {}: {}", INPUT_VARIABLE, e)));
+    }
+    let given = io::Cursor::new(bytes);
+    match args.no_stdin {
+        true => Box::new(given),
+        false => Box::new(given.chain(stdin(args))),
     }
+}
 
-    fn next2(&mut self) -> Option<(String, String)> {
-        let first = self.next();
-        let second = self.next();
-        match (first, second) {
-            (Some(f), Some(s)) => Some((f, s)),
-            _ => None,
+/// Stdin, showing `--prompt` before each wait for it at a terminal, and read
+/// without waiting, or waiting only so long, given `--input-default` or
+/// `--input-timeout`.
+fn stdin(args: &RunArgs) -> Box<dyn Read + Send> {
+    let stdin: Box<dyn Read + Send> = match &args.prompt {
+        Some(prompt) if io::stdin().is_terminal() => {
+            Box::new(Prompted(equiv::unescape(prompt).unwrap_or_else(|e| {
+                fail(format!("Error reading --prompt: {}", e))
+            })))
         }
+        _ => Box::new(io::stdin()),
+    };
+    match (args.input_timeout, args.input_default) {
+        (Some(timeout), default) => Box::new(PolledReader::with_timeout(stdin, timeout, default)),
+        (None, Some(default)) => Box::new(PolledReader::new(stdin, default)),
+        (None, None) => stdin,
     }
+}
+
+/// Stdin that writes a prompt to stderr whenever it has to wait for more,
+/// after flushing stdout so the prompt follows what the program wrote.
+struct Prompted(Vec<u8>);
 
-    fn peek(&self) -> Option<&String> {
-        self.tokens.front()
+impl Read for Prompted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        output::flush();
+        io::stderr().write_all(&self.0).ok();
+        io::stdin().read(buf)
     }
+}
+
+/// Input that stops the run with an error when it can't be read, rather than
+/// ending.
+struct Checked<R>(R);
 
-    fn peekn(&self, n: usize) -> Option<&String> {
-        self.tokens.get(n)
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self
+            .0
+            .read(buf)
+            .unwrap_or_else(|e| fail(format!("Error reading input: {}", e))))
     }
 }
 
-#[derive(Debug)]
-struct InputStream {
-    stdin: io::Bytes<io::Stdin>,
+/// What `run` was given to run.
+enum Source {
+    /// A program's name, for messages, and its source.
+    Program(String, String),
+    Bundle(String, Bundle),
 }
 
-impl InputStream {
-    fn new() -> InputStream {
-        InputStream {
-            stdin: io::stdin().bytes(),
+fn run_source(args: &RunArgs) -> Source {
+    #[cfg(feature = "net")]
+    if let Some(url) = &args.url {
+        let max = args.max_download_bytes.unwrap_or(fetch::DEFAULT_MAX_BYTES);
+        let code = fetch::fetch(url, max, args.sha256.as_deref())
+            .unwrap_or_else(|e| fail(format!("Error fetching {}: {}", url, e)));
+        return Source::Program(url.clone(), code);
+    }
+    let file = args.file.clone().unwrap_or_default();
+    if args.code {
+        return Source::Program(file.clone(), file);
+    }
+    let bytes = read_file(&file);
+    if Bundle::detect(&bytes) {
+        let bundle = Bundle::from_bytes(&bytes)
+            .unwrap_or_else(|e| fail(format!("Error reading {}: {}", file, e)));
+        return Source::Bundle(file, bundle);
+    }
+    Source::Program(file, source_text(bytes))
+}
+
+fn run(args: RunArgs, global: &Global) {
+    let (file, code) = match run_source(&args) {
+        Source::Program(file, code) => (file, code),
+        Source::Bundle(file, bundle) => return run_bundle(&file, bundle, global),
+    };
+    let frontend = match (&args.frontend, &args.locale) {
+        (Some(spec), _) => Some(
+            Frontend::from_toml(&read_source(spec), "custom")
+                .unwrap_or_else(|e| fail(format!("Error reading {}: {}", spec.display(), e))),
+        ),
+        (None, Some(locale)) => Frontend::locale(locale),
+        (None, None) => None,
+    };
+    if let Some(frontend) = frontend {
+        let code = frontend
+            .to_hny(&code)
+            .unwrap_or_else(|e| fail(format!("Error converting {}: {}", file, e)))
+            .output;
+        return run_program(args, global, file, code, Language::Hny);
+    }
+    let lang = args.lang.unwrap_or_else(|| {
+        // Only hnyfuck has a lenient mode, and its own extension settles it.
+        let hny = Path::new(&file).extension().is_some_and(|ext| ext == "hny");
+        if global.lenient || !args.code && hny {
+            return Language::Hny;
+        }
+        let (lang, ambiguous) = detect_language(&code);
+        if ambiguous {
+            log(
+                Severity::Warning,
+                format!(
+                    "{} could be hnyfuck or brainfuck; running it as {} (pass --lang to choose)",
+                    file,
+                    lang.name()
+                ),
+                serde_json::json!({ "file": file, "lang": lang.name() }),
+            );
+        }
+        lang
+    });
+    let code = to_hny(&file, &code, lang);
+    run_program(args, global, file, code, lang);
+}
+
+/// Runs `code`, converted to word pairs from `lang`, as `run` was asked.
+fn run_program(args: RunArgs, global: &Global, file: String, code: String, lang: Language) {
+    if let Some(Emit::Ir) = args.emit {
+        print!("{}", compile_code(&file, &code, global));
+        return;
+    }
+
+    check_loop_depth(&code, global.lenient, args.max_loop_depth);
+
+    if let Some(every) = args.checkpoint_every {
+        let path = args.checkpoint_file.unwrap_or_else(|| match args.code {
+            true => PathBuf::from("hnyfuck.ckpt"),
+            false => Path::new(&file).with_extension("ckpt"),
+        });
+        let program = compile_code(&file, &code, global);
+        let file = match args.code {
+            true => file,
+            // Absolute, so the run can be resumed from anywhere.
+            false => std::fs::canonicalize(&file).map_or(file, |path| path.display().to_string()),
+        };
+        let mut saved = CheckpointFile {
+            file,
+            code: args.code,
+            lang,
+            lenient: global.lenient,
+            optimize: global.optimize(),
+            enable_passes: global.enable_passes.clone(),
+            disable_passes: global.disable_passes.clone(),
+            every,
+            checkpoint: None,
+        };
+        timed("run", || {
+            checkpoint::run(&program, every, |checkpoint| {
+                saved.checkpoint = Some(checkpoint.clone());
+                saved.save(&path);
+            })
+        });
+        return;
+    }
+
+    let mut hny = timed("parse", || HnyFuck::parse(&code, global.lenient));
+    let engine = match args.no_tiering {
+        true => Engine::Interpreter,
+        false => args.engine.or(global.config.engine).unwrap_or_default(),
+    };
+    let threshold = args
+        .tier_threshold
+        .or(global.config.tier_threshold)
+        .unwrap_or(DEFAULT_TIER_THRESHOLD);
+    hny.set_tier_threshold((engine == Engine::Tiered).then_some(threshold));
+    if args.stats || args.hot_loops.is_some() {
+        hny.enable_stats();
+    }
+    let mut input = run_input(&args);
+    if args.input_encoding == Encoding::Base64 {
+        input = Box::new(Checked(Base64Decoder::new(input)));
+    }
+    if args.newline != Newline::Lf {
+        input = Box::new(NewlineReader::new(input, args.newline));
+    }
+    hny.set_input(input);
+    if let Some(path) = &args.tape_file {
+        if let Err(e) = hny.set_tape_file(path) {
+            fail(format!("Error creating {}: {}", path.display(), e));
+        }
+    }
+    if !args.args.is_empty() {
+        hny.set_args(&args.args);
+    }
+    if let Some(path) = &args.persist_tape {
+        match std::fs::read(path) {
+            Ok(cells) => hny.set_tape(&cells),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => fail(format!("Error reading {}: {}", path.display(), e)),
         }
     }
+    for &extension in &args.extensions {
+        hny.enable_extension(extension);
+    }
+    hny.set_deterministic(global.deterministic);
+    if args.debug {
+        hny.add_middleware(debugger(&code, global.lenient, &args));
+    }
+    if let Some(max) = args.max_output_bytes {
+        hny.set_limits(Limits {
+            output: usize::try_from(max).unwrap_or(usize::MAX),
+            ..Limits::NONE
+        });
+    }
+    let trace = args.postmortem.then(|| {
+        let trace = Trace::new(POSTMORTEM_EVENTS);
+        hny.add_middleware(trace.recorder());
+        trace
+    });
+    if let Some(path) = &args.tee {
+        // Flushed a line at a time, so little is lost if the run is cut short.
+        let mut file = std::fs::File::create(path)
+            .map(io::LineWriter::new)
+            .unwrap_or_else(|e| fail(format!("Error writing {}: {}", path.display(), e)));
+        let path = path.clone();
+        hny.add_output_transform(move |byte| {
+            file.write_all(&[byte])
+                .unwrap_or_else(|e| fail(format!("Error writing {}: {}", path.display(), e)));
+            Some(byte)
+        });
+    }
+    hny.set_flush(args.flush);
+    if args.newline != Newline::Lf {
+        hny.add_output_transform(ToLineFeeds(args.newline));
+    }
+    if args.output_format == OutputFormat::Hex {
+        hny.add_output_transform(HexDump::new(io::stdout()));
+    }
+    if args.output_encoding == Encoding::Base64 {
+        hny.add_output_transform(Base64Encoder::new(io::stdout()));
+    }
+    let show_progress = !args.debug
+        && !args.no_progress
+        && !global.deterministic
+        && global.log_format == LogFormat::Text
+        && io::stderr().is_terminal();
+    let shown = monitor(
+        &mut hny,
+        show_progress,
+        !global.deterministic,
+        args.dump_file,
+    );
+    let postmortem = |hny: &HnyFuck, error: &str| {
+        let Some(trace) = &trace else { return };
+        let path = args
+            .postmortem_file
+            .clone()
+            .unwrap_or_else(|| match args.code {
+                true => PathBuf::from("hnyfuck.hnydump"),
+                false => Path::new(&file).with_extension("hnydump"),
+            });
+        save_postmortem(&path, Dump::new(&code, error, hny.state(), trace));
+    };
+    let run = timed("run", || {
+        panic::catch_unwind(AssertUnwindSafe(|| hny.run()))
+    });
+    ProgressLine::clear(&shown);
+    let outcome = run.unwrap_or_else(|panic| {
+        // The panic has been reported; the dump follows it.
+        let error = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            (None, None) => "the interpreter panicked".to_string(),
+        };
+        postmortem(&hny, &error);
+        panic::resume_unwind(panic)
+    });
+    if outcome.exit == Exit::OutputLimit {
+        let error = format!(
+            "the program wrote more than the {} bytes --max-output-bytes allows",
+            args.max_output_bytes.unwrap_or_default()
+        );
+        postmortem(&hny, &error);
+        // Exiting skips destructors, so the output transforms are finished
+        // first: the last line of a hex dump, the last, padded base64 group
+        // and the rest of the line --tee holds are still written.
+        drop(hny);
+        fail(format!("error: {}", error));
+    }
+    if let Some(path) = &args.persist_tape {
+        save_tape(path, hny.state());
+    }
 
-    fn next(&mut self) -> Option<u8> {
-        self.stdin.next().and_then(|result| result.ok())
+    if let Some(stats) = hny.stats() {
+        output::flush();
+        if args.stats {
+            eprint!("{}", stats);
+        }
+        if let Some(n) = args.hot_loops {
+            for hot in hot_loops(&code, global.lenient, stats, n) {
+                eprintln!("{}", hot);
+            }
+        }
     }
 }
 
-#[derive(Debug)]
-struct State {
-    state: VecDeque<u8>,
-    index: usize,
-    input: InputStream,
+/// Runs a bundle within its limits, on its input or else stdin, and fails if
+/// it stops short or writes something other than the output it expects.
+fn run_bundle(file: &str, mut bundle: Bundle, global: &Global) {
+    bundle.manifest.lenient |= global.lenient;
+    let name = bundle
+        .manifest
+        .name
+        .clone()
+        .unwrap_or_else(|| file.to_string());
+    let mut input = Vec::new();
+    if bundle.input.is_none() {
+        io::stdin()
+            .read_to_end(&mut input)
+            .unwrap_or_else(|e| fail(format!("Error reading input: {}", e)));
+    }
+    let run = timed("run", || bundle.run(&input))
+        .unwrap_or_else(|e| fail(format!("Error compiling {}: {}", name, e)));
+    let mut stdout = io::stdout();
+    stdout.write_all(&run.output).ok();
+    stdout.flush().ok();
+
+    let limits = bundle.manifest.limits();
+    match run.exit {
+        Exit::Finished => {}
+        Exit::StepLimit => fail(format!(
+            "error: {} ran past the bundle's limit of {} steps",
+            name, limits.steps
+        )),
+        Exit::TapeLimit => fail(format!(
+            "error: {} used more than the bundle's limit of {} cells",
+            name, limits.cells
+        )),
+        Exit::OutputLimit => fail(format!(
+            "error: {} wrote more than the bundle's limit of {} bytes",
+            name, limits.output
+        )),
+    }
+    if bundle
+        .expected
+        .is_some_and(|expected| expected != run.output)
+    {
+        fail(format!(
+            "error: {} didn't write the output its bundle expects",
+            name
+        ));
+    }
+}
+
+/// A checkpoint with what it takes to compile the program it belongs to the
+/// same way again.
+#[derive(Serialize, Deserialize)]
+struct CheckpointFile {
+    /// The program's path, or its source with `code`.
+    file: String,
+    code: bool,
+    #[serde(default)]
+    lang: Language,
+    lenient: bool,
+    optimize: u8,
+    enable_passes: Vec<String>,
+    disable_passes: Vec<String>,
+    /// Steps between checkpoints.
+    every: u64,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl CheckpointFile {
+    /// Writes the file next to `path` and renames it over it, so a crash part
+    /// way leaves the last checkpoint intact. Output so far is flushed first,
+    /// so none of it is lost with the process.
+    fn save(&self, path: &Path) {
+        output::flush();
+        let json = serde_json::to_string(self).expect("checkpoints serialize");
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        std::fs::write(&partial, json)
+            .and_then(|()| std::fs::rename(&partial, path))
+            .unwrap_or_else(|e| fail(format!("Error writing {}: {}", path.display(), e)));
+    }
+}
+
+/// Instructions a post-mortem dump shows the program running before it failed.
+const POSTMORTEM_EVENTS: usize = 64;
+
+/// Saves `dump` to `path`, saying where, or why it couldn't be.
+fn save_postmortem(path: &Path, dump: Dump) {
+    let fields = serde_json::json!({ "file": path });
+    match dump.save(path) {
+        Ok(()) => log(
+            Severity::Note,
+            format!("saved a post-mortem dump to {}", path.display()),
+            fields,
+        ),
+        Err(e) => log(
+            Severity::Error,
+            format!("Error writing {}: {}", path.display(), e),
+            fields,
+        ),
+    }
+}
+
+/// Saves the tape from the cell the program started on rightward, leaving
+/// off the blank cells at the end. Cells left of the start aren't kept.
+fn save_tape(path: &Path, state: &State) {
+    let cells = state.tape()[state.origin()..].iter().copied();
+    let mut cells = cells.collect::<Vec<_>>();
+    let used = cells
+        .iter()
+        .rposition(|&cell| cell != 0)
+        .map_or(0, |last| last + 1);
+    cells.truncate(used);
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    std::fs::write(&partial, cells)
+        .and_then(|()| std::fs::rename(&partial, path))
+        .unwrap_or_else(|e| fail(format!("Error writing {}: {}", path.display(), e)));
 }
 
-impl State {
-    fn new() -> State {
-        let mut state = VecDeque::new();
-        state.push_back(0);
-        State {
-            state,
-            index: 0,
-            input: InputStream::new(),
+fn resume(args: ResumeArgs, global: &Global) {
+    let path = &args.checkpoint;
+    let mut saved = serde_json::from_str::<CheckpointFile>(&read_source(path))
+        .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e)));
+    let checkpoint = saved
+        .checkpoint
+        .take()
+        .unwrap_or_else(|| fail(format!("{} holds no checkpoint", path.display())));
+    let code = match saved.code {
+        true => saved.file.clone(),
+        false => read_source(&saved.file),
+    };
+    let code = to_hny(&saved.file, &code, saved.lang);
+    let options = Global {
+        lenient: saved.lenient,
+        optimize: Some(saved.optimize),
+        enable_passes: saved.enable_passes.clone(),
+        disable_passes: saved.disable_passes.clone(),
+        ..global.clone()
+    };
+    let program = compile_code(&saved.file, &code, &options);
+
+    saved.every = args.checkpoint_every.unwrap_or(saved.every);
+    let every = saved.every;
+    timed("run", || {
+        checkpoint::resume(&program, &checkpoint, every, |checkpoint| {
+            saved.checkpoint = Some(checkpoint.clone());
+            saved.save(path);
+        })
+    })
+    .unwrap_or_else(|e| fail(format!("Error resuming {}: {}", path.display(), e)));
+}
+
+/// Parses a count that may have underscores between digits.
+fn parse_count(count: &str) -> Result<u64, std::num::ParseIntError> {
+    count.replace('_', "").parse()
+}
+
+/// Parses a time such as `2s`, `500ms` or `1m`, in seconds if it has no unit.
+fn parse_duration(time: &str) -> Result<Duration, String> {
+    let split = time
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(time.len());
+    let (number, unit) = time.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("{:?} isn't a time like 2s or 500ms", time))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("unknown unit {:?}; use ms, s or m", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+/// A debugger that pauses on Ctrl-C, reading commands from the terminal so
+/// the program keeps stdin to itself. A second Ctrl-C before the program
+/// pauses, say while it waits for input, exits.
+fn debugger(code: &str, lenient: bool, args: &RunArgs) -> Debugger {
+    let mut debugger = Debugger::new(code, lenient);
+    for condition in &args.breakpoints {
+        debugger = debugger.with_breakpoint(condition.clone());
+    }
+    for tracepoint in &args.tracepoints {
+        debugger = debugger.with_tracepoint(tracepoint.clone());
+    }
+    if let Ok(tty) = std::fs::File::open("/dev/tty") {
+        debugger = debugger.with_io(io::BufReader::new(tty), io::stderr());
+    }
+    let interrupt = debugger.interrupt();
+    ctrlc::set_handler(move || {
+        if interrupt.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
         }
+    })
+    .unwrap_or_else(|e| fail(format!("Error handling Ctrl-C: {}", e)));
+    log(
+        Severity::Note,
+        "press Ctrl-C to pause in the debugger",
+        serde_json::Value::Null,
+    );
+    debugger
+}
+
+/// Steps a program runs before its progress is shown.
+const PROGRESS_THRESHOLD: u64 = 10_000_000;
+/// Steps between checks of whether to redraw the progress line or dump the
+/// state.
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+const PROGRESS_REDRAW: Duration = Duration::from_millis(100);
+
+/// Watches a run through the interpreter's progress reports, drawing the
+/// progress line if `show_progress` and dumping the state on SIGUSR1 if
+/// `dumps`.
+/// Returns whether the line has been drawn, so it can be cleared after.
+fn monitor(
+    hny: &mut HnyFuck,
+    show_progress: bool,
+    dumps: bool,
+    dump_file: Option<PathBuf>,
+) -> Arc<AtomicBool> {
+    let shown = Arc::new(AtomicBool::new(false));
+    let mut line = show_progress.then(|| ProgressLine::new(shown.clone()));
+    let dump = dumps.then(dump_requests).flatten();
+    if line.is_none() && dump.is_none() {
+        return shown;
     }
+    let drawn = shown.clone();
+    hny.set_progress(PROGRESS_INTERVAL, move |progress: Progress| {
+        if let Some(line) = &mut line {
+            line.draw(&progress);
+        }
+        if dump
+            .as_ref()
+            .is_some_and(|requested| requested.swap(false, Ordering::Relaxed))
+        {
+            write_dump(
+                &progress,
+                dump_file.as_deref(),
+                drawn.load(Ordering::Relaxed),
+            );
+        }
+    });
+    shown
+}
+
+/// A status line on stderr, redrawn in place while a long program runs.
+struct ProgressLine {
+    started: Instant,
+    drawn: Instant,
+    shown: Arc<AtomicBool>,
+}
 
-    fn shift_left(&mut self) {
-        match self.index {
-            0 => self.state.push_front(0),
-            _ => self.index -= 1,
+impl ProgressLine {
+    fn new(shown: Arc<AtomicBool>) -> ProgressLine {
+        let started = Instant::now();
+        ProgressLine {
+            started,
+            drawn: started,
+            shown,
         }
     }
 
-    fn shiht_right(&mut self) {
-        match self.index {
-            i if i == self.state.len() - 1 => {
-                self.state.push_back(0);
-                self.index += 1;
-            }
-            _ => self.index += 1,
+    fn draw(&mut self, progress: &Progress) {
+        if progress.steps < PROGRESS_THRESHOLD || self.drawn.elapsed() < PROGRESS_REDRAW {
+            return;
         }
+        self.drawn = Instant::now();
+        let rate = progress.steps as f64 / self.started.elapsed().as_secs_f64();
+        output::flush();
+        eprint!(
+            "\r\x1b[2K{} steps, {} steps/s, {} cells",
+            si(progress.steps as f64),
+            si(rate),
+            progress.tape.len()
+        );
+        self.shown.store(true, Ordering::Relaxed);
     }
 
-    fn increment(&mut self) {
-        if let Some(cell) = self.state.get_mut(self.index) {
-            *cell += 1;
+    fn clear(shown: &AtomicBool) {
+        if shown.load(Ordering::Relaxed) {
+            output::flush();
+            eprint!("\r\x1b[2K");
         }
     }
+}
+
+/// A flag set whenever the process receives SIGUSR1.
+#[cfg(unix)]
+fn dump_requests() -> Option<Arc<AtomicBool>> {
+    let requested = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, requested.clone()).ok()?;
+    Some(requested)
+}
 
-    fn decrement(&mut self) {
-        if let Some(cell) = self.state.get_mut(self.index) {
-            *cell -= 1;
+#[cfg(not(unix))]
+fn dump_requests() -> Option<Arc<AtomicBool>> {
+    None
+}
+
+/// Writes the step count, position and non-zero cells as one line, appending
+/// to `path` or else to stderr, over the progress line if it is `shown`.
+fn write_dump(progress: &Progress, path: Option<&Path>, shown: bool) {
+    let cells = progress
+        .tape
+        .iter()
+        .enumerate()
+        .filter(|(_, &cell)| cell != 0)
+        .map(|(i, cell)| format!("{}={}", i, cell))
+        .collect::<Vec<_>>();
+    let dump = format!(
+        "step {}, pair {}, pointer {}, cells: {}\n",
+        progress.steps,
+        progress.pair,
+        progress.pointer,
+        cells.join(" ")
+    );
+    match path {
+        Some(path) => std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(dump.as_bytes()))
+            .unwrap_or_else(|e| {
+                log(
+                    Severity::Warning,
+                    format!("could not write a dump to {}: {}", path.display(), e),
+                    serde_json::Value::Null,
+                )
+            }),
+        None => {
+            output::flush();
+            let clear = if shown { "\r\x1b[2K" } else { "" };
+            eprint!("{}{}", clear, dump);
         }
     }
+}
+
+/// Formats `n` with an SI prefix, such as `12.3M`.
+fn si(n: f64) -> String {
+    match ["", "k", "M", "G", "T"]
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(i, _)| n >= 1000f64.powi(*i as i32))
+    {
+        Some((0, _)) | None => format!("{:.0}", n),
+        Some((i, prefix)) => format!("{:.1}{}", n / 1000f64.powi(i as i32), prefix),
+    }
+}
 
-    fn output(&mut self) {
-        if let Some(cell) = self.state.get(self.index) {
-            print!("{}", *cell as char);
+fn check_loop_depth(code: &str, lenient: bool, max: Option<usize>) {
+    let Ok(Some(depth)) = analyze::loop_depth(code, lenient) else {
+        return;
+    };
+    let limit = max.unwrap_or(analyze::DEFAULT_LOOP_DEPTH_WARNING);
+    if depth.depth <= limit {
+        return;
+    }
+    let message = format!(
+        "{}:{}: loops nest {} deep, more than {}",
+        depth.line, depth.column, depth.depth, limit
+    );
+    let fields = serde_json::json!({
+        "line": depth.line,
+        "column": depth.column,
+        "depth": depth.depth,
+        "limit": limit,
+    });
+    match max {
+        Some(_) => {
+            log(Severity::Error, format!("error: {}", message), fields);
+            std::process::exit(1);
         }
+        None => log(
+            Severity::Warning,
+            format!(
+                "{}; the interpreter may be slow or overflow its stack (see --max-loop-depth)",
+                message
+            ),
+            fields,
+        ),
     }
+}
 
-    fn input(&mut self) {
-        if let Some(cell) = self.state.get_mut(self.index) {
-            if let Some(byte) = self.input.next() {
-                *cell = byte;
+fn pack(args: PackArgs, global: &Global) {
+    let program = read_source(&args.file);
+    compile_code(&args.file.display().to_string(), &program, global);
+    let name = args.name.or_else(|| {
+        let stem = args.file.file_stem()?.to_str()?;
+        Some(stem.trim_end_matches(".hny").to_string())
+    });
+    let bundle = Bundle {
+        manifest: Manifest {
+            name,
+            lenient: global.lenient,
+            max_steps: args.max_steps,
+            max_cells: args.max_cells,
+            max_output_bytes: args.max_output_bytes,
+        },
+        program,
+        input: args.input.map(read_file),
+        expected: args.expected.map(read_file),
+    };
+    let output = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension("hnypkg"));
+    std::fs::write(&output, bundle.to_bytes())
+        .unwrap_or_else(|e| fail(format!("Error writing {}: {}", output.display(), e)));
+}
+
+/// Runs each program on its own thread, passing its output through a pipe to
+/// the next as it is written. Once the last finishes, so does the process,
+/// as a shell pipeline ends when its last command does.
+fn pipe(args: PipeArgs, global: &Global) {
+    let sources = args
+        .files
+        .iter()
+        .map(|path| {
+            let code = read_source(path);
+            compile_code(&path.display().to_string(), &code, global);
+            code
+        })
+        .collect::<Vec<_>>();
+    let last = sources.len() - 1;
+    let mut input = None;
+    let mut stages = Vec::new();
+    for (i, code) in sources.into_iter().enumerate() {
+        let (reader, writer) = match i == last {
+            true => (None, None),
+            false => {
+                let (reader, writer) =
+                    io::pipe().unwrap_or_else(|e| fail(format!("Error creating a pipe: {}", e)));
+                (Some(reader), Some(writer))
             }
-        }
+        };
+        let lenient = global.lenient;
+        let stage_input = input.take();
+        stages.push(std::thread::spawn(move || {
+            let mut hny = HnyFuck::parse(&code, lenient);
+            if let Some(stage_input) = stage_input {
+                hny.set_input(stage_input);
+            }
+            if let Some(mut writer) = writer {
+                // A stage after this one finishing early closes the pipe, after
+                // which output goes nowhere.
+                hny.add_output_transform(move |byte| {
+                    writer.write_all(&[byte]).ok();
+                    None
+                });
+            }
+            hny.run();
+        }));
+        input = reader;
+    }
+    let last = stages.pop().expect("pipe takes at least two programs");
+    if last.join().is_err() {
+        std::process::exit(101);
+    }
+    output::flush();
+}
+
+fn compile_code(name: &str, code: &str, global: &Global) -> Program {
+    let mut program = timed("parse", || Program::parse(code, global.lenient))
+        .unwrap_or_else(|e| fail_syntax(name, code, e, global));
+    let manager = pass_manager(global);
+    timed("optimize", || manager.run(&mut program));
+    program
+}
+
+/// Fails with `first`, the first syntax error in `code`, after reporting
+/// every one where there are more.
+fn fail_syntax(name: &str, code: &str, first: impl std::fmt::Display, global: &Global) -> ! {
+    let (_, mut errors) = ast::parse_recovering(code, global.lenient);
+    errors.sort_by_key(|error| error.span.start);
+    if errors.len() <= 1 {
+        fail(format!("Error compiling {}: {}", name, first));
     }
+    for error in &errors {
+        let (line, column) = lexer::line_col(code, error.span.start);
+        log(
+            Severity::Error,
+            format!("{}:{}:{}: {}", name, line, column, error),
+            serde_json::json!({ "file": name, "line": line, "column": column }),
+        );
+    }
+    fail(format!(
+        "Error compiling {}: {} syntax errors",
+        name,
+        errors.len()
+    ))
+}
 
-    fn cond(&self) -> bool {
-        self.state.get(self.index).map_or(false, |cell| *cell != 0)
+fn pass_manager(global: &Global) -> PassManager {
+    let mut manager = PassManager::new(global.optimize());
+    let enable = global.enable_passes.iter().map(|name| (name, true));
+    let disable = global.disable_passes.iter().map(|name| (name, false));
+    for (name, enabled) in enable.chain(disable) {
+        manager
+            .set_enabled(name, enabled)
+            .unwrap_or_else(|e| fail(e));
     }
+    manager
 }
 
-#[derive(Debug)]
-struct HnyFuck {
-    stream: TokenStream,
-    state: State,
+fn stats(args: StatsArgs, global: &Global) {
+    let stats = ProgramStats::new(&read_source(&args.file), global.lenient)
+        .unwrap_or_else(|e| fail(format!("Error compiling {}: {}", args.file.display(), e)));
+    print!("{}", stats);
 }
 
-impl HnyFuck {
-    fn new(stream: TokenStream) -> Self {
-        Self {
-            stream,
-            state: State::new(),
-        }
+fn spec(args: SpecArgs) {
+    let spec = Spec::new();
+    match args.format {
+        SpecFormat::Text => print!("{}", spec),
+        SpecFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&spec).expect("the spec serializes")
+        ),
     }
+}
 
-    fn from_str(input: &str) -> Self {
-        Self::new(TokenStream::from_str(input))
+fn tree_sitter(args: TreeSitterArgs) {
+    let (frontend, extension) = match (&args.frontend, &args.locale, args.lang) {
+        (Some(spec), _, _) => (
+            Frontend::from_toml(&read_source(spec), "custom")
+                .unwrap_or_else(|e| fail(format!("Error reading {}: {}", spec.display(), e))),
+            None,
+        ),
+        (None, Some(locale), _) => (Frontend::locale(locale).expect("a known locale"), None),
+        (None, None, Language::Hny) => (Frontend::hnyfuck(), Some(EXTENSION)),
+        (None, None, lang) => match lang.frontend() {
+            Some(frontend) => (frontend, None),
+            None => fail(format!(
+                "{} has no words to write a grammar for",
+                lang.name()
+            )),
+        },
+    };
+    let grammar = grammar::tree_sitter(&frontend, extension);
+    let Some(dir) = args.dir else {
+        print!("{}", grammar.grammar);
+        return;
+    };
+    for (path, contents) in grammar.files() {
+        let path = dir.join(path);
+        path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&path, contents))
+            .unwrap_or_else(|e| fail(format!("Error writing {}: {}", path.display(), e)));
     }
+}
 
-    fn run(&mut self) {
-        while let Some((first, second)) = self.stream.next2() {
-            match (first.as_str(), second.as_str()) {
-                SHIFT_LEFT => self.state.shift_left(),
-                SHIFT_RIGHT => self.state.shiht_right(),
-                INCREMENT => self.state.increment(),
-                DECREMENT => self.state.decrement(),
-                OUTPUT => self.state.output(),
-                INPUT => self.state.input(),
-                LOOP_START => {
-                    let mut token_stream = TokenStream::new();
-                    let mut depth = 1;
-                    while let Some((token1, token2)) = self.stream.next2() {
-                        match (token1.as_str(), token2.as_str()) {
-                            LOOP_START => depth += 1,
-                            LOOP_END => {
-                                depth -= 1;
-                                if depth == 0 {
-                                    break;
-                                }
-                            }
-                            _ => (),
-                        }
-                        token_stream.push(token1);
-                        token_stream.push(token2);
-                    }
+fn passes(global: &Global) {
+    for (pass, enabled) in pass_manager(global).passes() {
+        let status = if enabled { "on" } else { "off" };
+        println!(
+            "{:<20} -O{} {:<3} {}",
+            pass.name(),
+            pass.level(),
+            status,
+            pass.description()
+        );
+    }
+}
 
-                    let state = std::mem::replace(&mut self.state, State::new());
+fn compile_source(path: &Path, global: &Global) -> Program {
+    compile_code(&path.display().to_string(), &read_source(path), global)
+}
 
-                    let mut nest = Self {
-                        stream: token_stream.clone(),
-                        state,
-                    };
+fn build(args: BuildArgs, global: &Global) {
+    let program = compile_source(&args.file, global);
 
-                    while {
-                        nest.run();
+    let output = args
+        .output
+        .unwrap_or_else(|| PathBuf::from(args.file.file_stem().unwrap_or(args.file.as_os_str())));
+    let c_path = if args.keep_c {
+        output.with_extension("c")
+    } else {
+        std::env::temp_dir().join(format!("hnyfuck-{}.c", std::process::id()))
+    };
+    std::fs::write(&c_path, codegen::c::generate(&program))
+        .unwrap_or_else(|e| fail(format!("Error writing {}: {}", c_path.display(), e)));
 
-                        nest.stream = token_stream.clone();
+    let cc = args
+        .cc
+        .or_else(|| std::env::var("CC").ok())
+        .unwrap_or_else(|| "cc".to_string());
+    let status = Process::new(&cc)
+        .arg(format!("-O{}", args.opt_level))
+        .arg("-o")
+        .arg(&output)
+        .arg(&c_path)
+        .status();
 
-                        nest.state.cond()
-                    } {}
+    if !args.keep_c {
+        std::fs::remove_file(&c_path).ok();
+    }
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => fail(format!("{} exited with {}", cc, status)),
+        Err(e) => fail(format!("Error running {}: {}", cc, e)),
+    }
+}
+
+fn compile(args: CompileArgs, global: &Global) {
+    let program = compile_source(&args.file, global);
+    let source = match args.target {
+        Target::C => codegen::c::generate(&program),
+        Target::Js => codegen::js::generate(&program),
+        Target::Python => codegen::python::generate(&program),
+        #[cfg(feature = "llvm")]
+        Target::LlvmIr => codegen::llvm::generate(&program)
+            .unwrap_or_else(|e| fail(format!("Error generating LLVM IR: {}", e))),
+    };
+
+    write_output(args.output, &source);
+}
 
-                    self.state = nest.state;
-                }
-                _ => panic!("Invalid token"),
+fn cfg(args: CfgArgs, global: &Global) {
+    let program = compile_source(&args.file, global);
+    let dot = ControlFlowGraph::new(&program).to_dot(&program);
+    write_output(args.output, &dot);
+}
+
+fn convert(args: ConvertArgs) {
+    let programs = Language::value_variants()
+        .iter()
+        .map(|lang| format!("*.{}", lang.extension()))
+        .collect::<Vec<_>>();
+    let programs = programs.iter().map(String::as_str).collect::<Vec<_>>();
+    let files = expand_files(&args.files, &args.batch, &programs);
+    if files.len() > 1 && args.out_dir.is_none() {
+        fail("Error: converting more than one program needs --out-dir");
+    }
+    let conversions = par_map(&files, |path| {
+        let code = try_read_source(path)?;
+        // Without --from, a program that looks like it is already in the
+        // target language is taken to be in the other of hnyfuck and
+        // brainfuck.
+        let from = args.from.unwrap_or_else(|| match detect_language(&code).0 {
+            lang if lang != args.to => lang,
+            _ if args.to == Language::Hny => Language::Bf,
+            _ => Language::Hny,
+        });
+        let conversion = convert::convert(&code, from, args.to)
+            .map_err(|e| format!("Error converting {}: {}", path.display(), e))?;
+        let output = args.out_dir.as_ref().map(|dir| {
+            // The program's path without any root or `..`, so it stays in
+            // the directory.
+            let relative = path
+                .components()
+                .filter(|component| matches!(component, std::path::Component::Normal(_)))
+                .collect::<PathBuf>();
+            dir.join(relative).with_extension(args.to.extension())
+        });
+        if let Some(output) = &output {
+            if std::path::absolute(output).ok() == std::path::absolute(path).ok() {
+                return Err(format!("Error: {} would be overwritten", path.display()));
             }
         }
+        Ok((output, conversion))
+    });
+    for (path, (output, mut conversion)) in files.iter().zip(conversions) {
+        if let Some(parent) = output.as_ref().and_then(|output| output.parent()) {
+            std::fs::create_dir_all(parent)
+                .unwrap_or_else(|e| fail(format!("Error creating {}: {}", parent.display(), e)));
+        }
+        write_output(output.or(args.output.clone()), &conversion.output);
+
+        if let Some(source_map) = &args.source_map {
+            conversion.source_map.source = Some(path.display().to_string());
+            let json =
+                serde_json::to_string(&conversion.source_map).expect("source maps serialize");
+            std::fs::write(source_map, json)
+                .unwrap_or_else(|e| fail(format!("Error writing {}: {}", source_map.display(), e)));
+        }
+    }
+    if let (Some(dir), true) = (&args.out_dir, is_batch(&args.files, &files)) {
+        log(
+            Severity::Note,
+            format!("converted {} files into {}", files.len(), dir.display()),
+            serde_json::json!({ "files": files.len() }),
+        );
     }
 }
 
-#[cfg(test)]
-mod test {
-    #[allow(unused_imports)]
-    use super::*;
+/// What tooling commands look for in directories without --include.
+const PROGRAMS: &[&str] = &["*.hny"];
 
-    #[test]
-    fn test_increment() {
-        let mut hny = from_brainfuck("+++++");
-        hny.run();
-        assert_eq!(hny.state.state[0], 5);
+/// The files `paths` name, with directories searched for the files `batch`
+/// takes or, without any --include, those matching `programs`. Sets up the
+/// threads to process them on.
+fn expand_files(paths: &[PathBuf], batch: &Batch, programs: &[&str]) -> Vec<PathBuf> {
+    if let Some(jobs) = batch.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .unwrap_or_else(|e| fail(format!("Error starting threads: {}", e)));
     }
+    let include = match batch.include.is_empty() {
+        true => programs.iter().map(|glob| glob.to_string()).collect(),
+        false => batch.include.clone(),
+    };
+    files::Filter::new(include, &batch.exclude)
+        .and_then(|filter| files::expand(paths, &filter))
+        .unwrap_or_else(|e| fail(format!("Error finding programs: {}", e)))
+}
+
+/// `f` of each item, run in parallel but given in the items' order. Fails
+/// with the error of the first item in that order to have one, so a run
+/// reports the same error however its threads were scheduled.
+fn par_map<I: Sync, T: Send>(items: &[I], f: impl Fn(&I) -> Result<T, String> + Sync) -> Vec<T> {
+    let results = items.par_iter().map(&f).collect::<Vec<_>>();
+    results
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| fail(e))
+}
+
+/// Whether a command found more files than the one it was given, so it
+/// should sum up what it did.
+fn is_batch(given: &[PathBuf], found: &[PathBuf]) -> bool {
+    found.len() > 1 || given != found
+}
 
-    #[test]
-    fn test_decrement() {
-        let mut hny = from_brainfuck("+++++-----");
-        hny.run();
-        assert_eq!(hny.state.state[0], 0);
+fn fmt(args: FmtArgs, global: &Global) {
+    let given = args.config.as_ref().map(|path| {
+        FormatStyle::from_toml(&read_source(path))
+            .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e)))
+    });
+    let files = expand_files(&args.files, &args.batch, PROGRAMS);
+    // Files from different projects can be laid out differently.
+    let styles = files
+        .iter()
+        .map(|path| {
+            let style = match (&given, global.no_config) {
+                (Some(style), _) => style.clone(),
+                (None, true) => FormatStyle::default(),
+                (None, false) => Config::project_path(path)
+                    .and_then(|project| read_config(&project).fmt)
+                    .or_else(|| {
+                        let user = Config::user_path().filter(|path| path.is_file())?;
+                        read_config(&user).fmt
+                    })
+                    .unwrap_or_default(),
+            };
+            fmt_style(style, &args)
+        })
+        .collect::<Vec<_>>();
+    let jobs = files.iter().zip(&styles).collect::<Vec<_>>();
+    let formatted = par_map(&jobs, |&(path, style)| {
+        if is_compressed(path) && !args.check {
+            return Err(format!(
+                "Error: {} is compressed and can't be formatted in place",
+                path.display()
+            ));
+        }
+        let source = try_read_source(path)?;
+        let formatted = format(&source, style);
+        Ok((source, formatted))
+    });
+    let mut unformatted = 0;
+    for (path, (source, formatted)) in files.iter().zip(formatted) {
+        if args.check {
+            if let Some(diff) = formatter::diff(&path.to_string_lossy(), &source, &formatted) {
+                print!("{}", diff);
+                unformatted += 1;
+            }
+        } else if formatted != source {
+            std::fs::write(path, formatted)
+                .unwrap_or_else(|e| fail(format!("Error writing {}: {}", path.display(), e)));
+            unformatted += 1;
+        }
+    }
+    if is_batch(&args.files, &files) {
+        let summary = match args.check {
+            true => format!("{} of {} files need formatting", unformatted, files.len()),
+            false => format!("reformatted {} of {} files", unformatted, files.len()),
+        };
+        log(
+            Severity::Note,
+            summary,
+            serde_json::json!({ "files": files.len(), "unformatted": unformatted }),
+        );
     }
+    if args.check && unformatted > 0 {
+        std::process::exit(1);
+    }
+}
 
-    #[test]
-    fn test_shift_left_right() {
-        let mut hny = from_brainfuck("+++++>+++++<");
-        dbg!(&hny);
-        hny.run();
-        assert_eq!(hny.state.state[0], 5);
-        assert_eq!(hny.state.state[1], 5);
+/// Whether the file at `path` starts with the magic bytes of a compression
+/// format.
+fn is_compressed(path: &Path) -> bool {
+    let mut magic = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|file| file.take(4).read_to_end(&mut magic))
+        .is_ok_and(|_| Compression::detect(&magic).is_some())
+}
+
+/// `style` with the options given on the command line.
+fn fmt_style(mut style: FormatStyle, args: &FmtArgs) -> FormatStyle {
+    if let Some(layout) = args.style {
+        style.layout = layout;
+    }
+    if let Some(pairs_per_line) = args.pairs_per_line {
+        style.pairs_per_line = pairs_per_line;
     }
+    if let Some(max_width) = args.max_width {
+        style.max_width = max_width;
+    }
+    if let Some(indent) = args.indent {
+        style.indent = indent;
+    }
+    if let Some(loop_lines) = args.loop_lines {
+        style.loop_lines = loop_lines;
+    }
+    style
+}
 
-    #[test]
-    fn test_loop() {
-        let mut hny = from_brainfuck("+++++[>+++++<-]");
-        hny.run();
-        assert_eq!(hny.state.state[0], 0);
-        assert_eq!(hny.state.state[1], 25);
+fn steg_encode(args: StegEncodeArgs, global: &Global) {
+    let code = read_source(&args.file);
+    compile_code(&args.file.display().to_string(), &code, global);
+    let text = steg::encode(&code, &read_source(&args.cover));
+    write_output(args.output, &text);
+}
+
+fn steg_decode(args: StegDecodeArgs, global: &Global) {
+    let code = steg::decode(&read_source(&args.file));
+    if args.print {
+        println!("{}", code);
+        return;
     }
+    let strict = Global {
+        lenient: false,
+        ..global.clone()
+    };
+    compile_code(&args.file.display().to_string(), &code, &strict);
+    HnyFuck::from_str(&code).run();
+}
 
-    #[test]
-    fn test_state_increment() {
-        let mut state = State::new();
-        state.increment();
-        assert_eq!(state.state[0], 1);
+fn obfuscate(args: ObfuscateArgs, global: &Global) {
+    if !(0.0..=1.0).contains(&args.density) {
+        fail("--density must be between 0 and 1");
     }
+    let code = read_source(&args.file);
+    compile_code(&args.file.display().to_string(), &code, global);
+    let obfuscated = hnyfuck::obfuscate(&code, args.density, args.seed);
+    write_output(args.output, &obfuscated);
+}
+
+fn equiv(args: EquivArgs, global: &Global) {
+    let first = compile_source(&args.first, global);
+    let second = compile_source(&args.second, global);
 
-    #[test]
-    fn test_state_decrement() {
-        let mut state = State::new();
-        state.increment();
-        state.decrement();
-        assert_eq!(state.state[0], 0);
+    let mut inputs = match &args.inputs {
+        Some(path) => equiv::parse_inputs(&read_source(path))
+            .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e))),
+        None if args.exhaustive.is_none() => vec![Vec::new()],
+        None => Vec::new(),
+    };
+    if let Some(max_len) = args.exhaustive {
+        let alphabet = match &args.alphabet {
+            Some(alphabet) => equiv::parse_inputs(alphabet)
+                .ok()
+                .and_then(|lines| lines.into_iter().next())
+                .unwrap_or_else(|| fail("Invalid --alphabet")),
+            None => (0..=u8::MAX).collect(),
+        };
+        inputs.extend(equiv::exhaustive_inputs(&alphabet, max_len));
     }
 
-    #[test]
-    fn test_state_shift_left() {
-        let mut state = State::new();
-        state.increment();
-        state.shift_left();
-        assert_eq!(state.state[0], 0);
-        assert_eq!(state.state[1], 1);
+    let report = equiv::check(&first, &second, inputs, args.step_limit);
+    if let Some(mismatch) = report.mismatch {
+        println!("{}", mismatch);
+        std::process::exit(1);
+    }
+    println!("equivalent on {} inputs", report.inputs);
+    if report.inconclusive > 0 {
+        println!(
+            "{} inputs hit the step limit in both programs and are inconclusive",
+            report.inconclusive
+        );
     }
+}
 
-    #[test]
-    fn test_state_shift_right() {
-        let mut state = State::new();
-        state.increment();
-        state.shiht_right();
-        state.increment();
-        assert_eq!(state.state[0], 1);
-        assert_eq!(state.state[1], 1);
+fn verify(args: VerifyArgs, global: &Global) {
+    let input = match &args.input {
+        Some(path) => std::fs::read(path)
+            .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e))),
+        None => Vec::new(),
+    };
+    let verification = verify::verify(
+        &read_source(&args.file),
+        global.lenient,
+        &input,
+        args.step_limit,
+    )
+    .unwrap_or_else(|e| fail(format!("Error compiling {}: {}", args.file.display(), e)));
+    match verification {
+        Verification::Agreed => println!("all {} engines agree", verify::ENGINES.len()),
+        Verification::Diverged(divergence) => fail(divergence),
+        Verification::Inconclusive => {
+            log(
+                Severity::Error,
+                format!(
+                    "the interpreter was still running after {} steps, leaving nothing to compare",
+                    args.step_limit
+                ),
+                serde_json::Value::Null,
+            );
+            std::process::exit(2);
+        }
     }
+}
+
+fn serve(args: ServeArgs) {
+    let mut settings = match &args.config {
+        Some(path) => server::Settings::from_toml(&read_source(path))
+            .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e))),
+        None => server::Settings::default(),
+    };
+    if let Some(host) = args.host {
+        settings.host = host;
+    }
+    if let Some(port) = args.port {
+        settings.port = port;
+    }
+    if let Some(max_steps) = args.max_steps {
+        settings.max_steps = max_steps;
+    }
+    if let Some(max_cells) = args.max_cells {
+        settings.max_cells = max_cells;
+    }
+    if let Some(max_output_bytes) = args.max_output_bytes {
+        settings.max_output_bytes = max_output_bytes;
+    }
+    if let Some(rate_limit) = args.rate_limit {
+        settings.rate_limit = Some(rate_limit);
+    }
+    if let Some(max_running) = args.max_running {
+        settings.max_running = Some(max_running);
+    }
+    if let Some(max_memory) = args.max_memory {
+        settings.max_memory = max_memory;
+    }
+    if let Some(max_cpu_seconds) = args.max_cpu_seconds {
+        settings.max_cpu_seconds = max_cpu_seconds;
+    }
+    if let Some(audit_log) = args.audit_log {
+        settings.audit_log = Some(audit_log);
+    }
+    if let Some(audit_max_bytes) = args.audit_max_bytes {
+        settings.audit_max_bytes = audit_max_bytes;
+    }
+    if let Some(audit_keep) = args.audit_keep {
+        settings.audit_keep = audit_keep;
+    }
+    if settings.rate_limit == Some(0) || settings.max_running == Some(0) {
+        fail("The rate limit and the most programs running must be positive");
+    }
+
+    if !cfg!(target_os = "linux") && !args.no_sandbox {
+        fail("Requests can only be sandboxed on Linux; pass --no-sandbox to serve without it");
+    }
+    let program = std::env::current_exe()
+        .unwrap_or_else(|e| fail(format!("Error finding the hnyfuck executable: {}", e)));
+    let quotas = settings.quotas();
+    let mut worker_args = vec![
+        "serve-worker".to_string(),
+        format!("--max-memory={}", quotas.memory),
+        format!("--max-cpu-seconds={}", quotas.cpu_seconds),
+    ];
+    if args.no_sandbox {
+        worker_args.push("--no-sandbox".to_string());
+    }
+    let config = server::Config {
+        limits: settings.limits(),
+        runner: server::Runner::Worker {
+            program,
+            args: worker_args,
+        },
+        rate_limit: settings.rate_limit,
+        max_running: settings.max_running,
+        // A session takes no longer than its worker may.
+        session_timeout: Duration::from_secs(quotas.cpu_seconds),
+        idle_timeout: server::DEFAULT_IDLE_TIMEOUT,
+        audit: settings.audit(),
+    };
+    let (host, port) = (settings.host, settings.port);
+    log(
+        Severity::Note,
+        format!("listening on http://{}:{}/run", host, port),
+        serde_json::json!({ "host": host, "port": port }),
+    );
+    if let Err(e) = server::serve((host.as_str(), port), config) {
+        fail(format!("Error serving on {}:{}: {}", host, port, e));
+    }
+}
+
+fn minify(args: MinifyArgs, global: &Global) {
+    let code = read_source(&args.file);
+    compile_code(&args.file.display().to_string(), &code, global);
+    let superoptimizer = Superoptimizer::new(args.search_length);
+    let minified = superopt::minify(&code, global.lenient, &superoptimizer)
+        .unwrap_or_else(|e| fail(format!("Error minifying {}: {}", args.file.display(), e)));
+    write_output(args.output, &minified);
+}
+
+fn analyze(args: AnalyzeArgs, global: &Global) {
+    let code = read_source(&args.file);
+    let all = !args.termination && !args.ranges;
+
+    if all || args.termination {
+        let warnings = analyze::termination(&code, global.lenient)
+            .unwrap_or_else(|e| fail(format!("Error analyzing {}: {}", args.file.display(), e)));
+        for warning in warnings {
+            println!("{}:{}", args.file.display(), warning);
+        }
+    }
+    if all || args.ranges {
+        let program = compile_code(&args.file.display().to_string(), &code, global);
+        print!("{}", Ranges::new(&program).listing(&program));
+    }
+}
 
-    #[test]
-    fn test_state_cond() {
-        let mut state = State::new();
-        assert!(!state.cond());
-        state.increment();
-        assert!(state.cond());
+fn lint(args: LintArgs, global: &Global) {
+    let mut linter = Linter::new();
+    for (rules, level) in [
+        (&args.allow, Level::Allow),
+        (&args.warn, Level::Warn),
+        (&args.deny, Level::Deny),
+    ] {
+        for rule in rules {
+            linter.set_level(rule, level).unwrap_or_else(|e| fail(e));
+        }
+    }
+
+    if args.list {
+        for (rule, level) in linter.rules() {
+            println!("{:<16} {:<8} {}", rule.id(), level, rule.description());
+        }
+        return;
+    }
+
+    let files = expand_files(&args.files, &args.batch, PROGRAMS);
+    let (mut errors, mut warnings) = (0, 0);
+    let mut checked = Vec::new();
+    let results = par_map(&files, |path| {
+        let source = try_read_source(path)?;
+        let diagnostics = linter.check(&source, global.lenient);
+        Ok((source, diagnostics))
+    });
+    for (path, (source, diagnostics)) in files.iter().zip(results) {
+        let file = path.display().to_string();
+        for diagnostic in &diagnostics {
+            match diagnostic.level {
+                Level::Deny => errors += 1,
+                _ => warnings += 1,
+            }
+            match args.diagnostics_format {
+                DiagnosticsFormat::Text => println!("{}:{}", file, diagnostic),
+                DiagnosticsFormat::Json => println!("{}", diagnostic.to_json(&file, &source)),
+                DiagnosticsFormat::Sarif => (),
+            }
+        }
+        checked.push((file, source, diagnostics));
+    }
+    if args.diagnostics_format == DiagnosticsFormat::Sarif {
+        let files = checked
+            .iter()
+            .map(|(file, source, diagnostics)| (file.as_str(), source.as_str(), &diagnostics[..]));
+        let log = lint::sarif(&linter, files);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&log).expect("logs serialize")
+        );
+    }
+    if is_batch(&args.files, &files) {
+        log(
+            Severity::Note,
+            format!(
+                "checked {} files: {} errors, {} warnings",
+                files.len(),
+                errors,
+                warnings
+            ),
+            serde_json::json!({ "files": files.len(), "errors": errors, "warnings": warnings }),
+        );
+    }
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn specialize(args: SpecializeArgs, global: &Global) {
+    let code = read_source(&args.file);
+    compile_code(&args.file.display().to_string(), &code, global);
+    let input = match &args.input {
+        Some(path) => std::fs::read(path)
+            .unwrap_or_else(|e| fail(format!("Error reading {}: {}", path.display(), e))),
+        None => Vec::new(),
+    };
+    let specialization = specialize::specialize(&code, global.lenient, &input, args.step_limit)
+        .unwrap_or_else(|e| fail(format!("Error specializing {}: {}", args.file.display(), e)));
+    if !specialization.finished && specialization.consumed < input.len() {
+        log(
+            Severity::Note,
+            format!(
+                "the residual program reads its input from byte {} of {} on",
+                specialization.consumed,
+                args.input.unwrap_or_default().display()
+            ),
+            serde_json::json!({ "consumed": specialization.consumed }),
+        );
     }
+    write_output(args.output, &specialization.program);
+}
 
-    #[test]
-    fn happy_new_year() {
-        let code =
-            "Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Happy Happy New Year Year Happy New Year Year Happy Year Happy Year Happy New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Happy New Happy New Happy New Happy New Happy Year New New New Year New Year New Year Year Happy Year Happy Year New New Year Happy Year Happy Year Happy Year Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Happy New Happy New Year Happy Year Happy Year New New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Happy New Happy New Year New New Year Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year Happy Year New Happy Year Happy Year Happy Year Happy Year Year New New Year Happy Year Happy Year Happy Year Happy Year Happy Year Year New Happy New Happy New Year Happy Year New";
+fn examples(command: ExamplesCommand) {
+    let find = |name: &str| {
+        gallery::find(name).unwrap_or_else(|| {
+            fail(format!(
+                "No example named `{}`; see `hnyfuck examples list`",
+                name
+            ))
+        })
+    };
+    match command {
+        ExamplesCommand::List => {
+            for example in &gallery::EXAMPLES {
+                println!("{:<12} {}", example.name, example.description);
+            }
+        }
+        ExamplesCommand::Run { name } => {
+            HnyFuck::from_str(find(&name).source).run();
+        }
+        ExamplesCommand::Show { name } => print!("{}", find(&name).source),
+    }
+}
+
+fn repl(global: &Global) {
+    let mut repl = Repl::new(global.lenient);
+    if io::stdin().is_terminal() {
+        repl = repl
+            .with_editor(repl::history_path())
+            .unwrap_or_else(|e| fail(format!("Error setting up line editing: {}", e)));
+    }
+    repl.run();
+}
+
+fn man(args: ManArgs) {
+    let command = Cli::command().name("hnyfuck");
+    if let Some(dir) = args.dir {
+        clap_mangen::generate_to(command, &dir)
+            .unwrap_or_else(|e| fail(format!("Error writing to {}: {}", dir.display(), e)));
+        return;
+    }
+    let mut page = Vec::new();
+    clap_mangen::Man::new(command)
+        .render(&mut page)
+        .expect("rendering to memory succeeds");
+    write_output(args.output, &String::from_utf8_lossy(&page));
+}
 
-        let mut hny = HnyFuck::new(TokenStream::from_str(code));
-        hny.run();
+fn write_output(path: Option<PathBuf>, contents: &str) {
+    match path {
+        Some(path) => std::fs::write(&path, contents)
+            .unwrap_or_else(|e| fail(format!("Error writing {}: {}", path.display(), e))),
+        None => print!("{}", contents),
     }
 }
@@ -0,0 +1,176 @@
+use std::{fmt, ops::ControlFlow, time::Duration};
+
+use crate::{Instruction, State};
+
+/// Wraps the execution of each instruction by the interpreter, like a tower
+/// layer: do something before or after calling `next.run`, call it with a
+/// different instruction, skip it, or return [`ControlFlow::Break`] to stop
+/// the program. Loop brackets pass through the chain too, `Happy Happy` once
+/// when a loop is reached and `New New` after every iteration, but changing
+/// or skipping them does nothing.
+///
+/// Loops are never compiled while any middleware is installed, so every
+/// instruction goes through the chain.
+pub trait Middleware {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()>;
+}
+
+/// The rest of the chain, ending in the interpreter itself.
+pub struct Next<'a> {
+    middleware: &'a mut [Box<dyn Middleware + Send>],
+    state: &'a mut State,
+    pair: usize,
+}
+
+impl Next<'_> {
+    /// The tape as it is before the instruction runs.
+    pub fn state(&self) -> &State {
+        self.state
+    }
+
+    /// Index of the word pair being executed within the program.
+    pub fn pair(&self) -> usize {
+        self.pair
+    }
+
+    pub fn run(self, instruction: Instruction) -> ControlFlow<()> {
+        match self.middleware.split_first_mut() {
+            Some((first, rest)) => first.handle(
+                instruction,
+                Next {
+                    middleware: rest,
+                    state: self.state,
+                    pair: self.pair,
+                },
+            ),
+            None => {
+                self.state.step(instruction);
+                ControlFlow::Continue(())
+            }
+        }
+    }
+}
+
+/// Middleware in the order instructions pass through it.
+#[derive(Default)]
+pub(crate) struct Chain(Vec<Box<dyn Middleware + Send>>);
+
+impl Chain {
+    pub(crate) fn push(&mut self, middleware: impl Middleware + Send + 'static) {
+        self.0.push(Box::new(middleware));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn run(
+        &mut self,
+        instruction: Instruction,
+        state: &mut State,
+        pair: usize,
+    ) -> ControlFlow<()> {
+        Next {
+            middleware: &mut self.0,
+            state,
+            pair,
+        }
+        .run(instruction)
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Chain({} middleware)", self.0.len())
+    }
+}
+
+/// Stops the program once it has executed `limit` instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Meter {
+    pub limit: u64,
+    pub executed: u64,
+}
+
+impl Meter {
+    pub fn new(limit: u64) -> Meter {
+        Meter { limit, executed: 0 }
+    }
+}
+
+impl Middleware for Meter {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+        if self.executed == self.limit {
+            return ControlFlow::Break(());
+        }
+        self.executed += 1;
+        next.run(instruction)
+    }
+}
+
+/// Sleeps before each instruction, to watch a program run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttle(pub Duration);
+
+impl Middleware for Throttle {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+        std::thread::sleep(self.0);
+        next.run(instruction)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{brainfuck_to_hny, HnyFuck};
+
+    /// Records every instruction that reaches it.
+    struct Record(Arc<Mutex<Vec<Instruction>>>);
+
+    impl Middleware for Record {
+        fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+            self.0.lock().unwrap().push(instruction);
+            next.run(instruction)
+        }
+    }
+
+    /// Turns every decrement into an increment.
+    struct Flip;
+
+    impl Middleware for Flip {
+        fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+            match instruction {
+                Instruction::Decrement => next.run(Instruction::Increment),
+                instruction => next.run(instruction),
+            }
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        use Instruction::*;
+
+        let code = brainfuck_to_hny("++[-]").output;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut hny = HnyFuck::from_str(&code);
+        hny.add_middleware(Record(seen.clone()));
+        hny.run();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [Increment, Increment, LoopStart, Decrement, LoopEnd, Decrement, LoopEnd]
+        );
+
+        // With decrements flipped the loop counts up through 256 values, and
+        // the meter only lets the first 10 instructions through.
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut hny = HnyFuck::from_str(&code);
+        hny.add_middleware(Meter::new(10));
+        hny.add_middleware(Flip);
+        hny.add_middleware(Record(seen.clone()));
+        hny.run();
+        assert_eq!(seen.lock().unwrap().len(), 10);
+        assert!(!seen.lock().unwrap().contains(&Decrement));
+    }
+}
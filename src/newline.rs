@@ -0,0 +1,136 @@
+//! Translating newlines between the terminal's convention, a line feed, and
+//! the one a program was written for, as programs written for Windows or old
+//! Macs expect carriage returns in their input and write them in their
+//! output.
+
+use std::io::{self, BufReader, Read};
+
+use serde::{Deserialize, Serialize};
+
+use crate::transform::Transform;
+
+/// How a program ends its lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Newline {
+    /// A line feed, 10.
+    #[default]
+    Lf,
+    /// A carriage return and a line feed, 13 then 10.
+    Crlf,
+    /// A carriage return, 13.
+    Cr,
+}
+
+/// Reads input with its newlines, line feeds or carriage return and line feed
+/// pairs, turned into the program's.
+#[derive(Debug)]
+pub struct NewlineReader<R> {
+    inner: BufReader<R>,
+    newline: Newline,
+    /// Whether the last byte read was a carriage return.
+    after_cr: bool,
+    /// A line feed still to pass on after a carriage return put in front.
+    pending: Option<u8>,
+}
+
+impl<R: Read> NewlineReader<R> {
+    pub fn new(inner: R, newline: Newline) -> Self {
+        NewlineReader {
+            inner: BufReader::new(inner),
+            newline,
+            after_cr: false,
+            pending: None,
+        }
+    }
+
+    fn next(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.take() {
+            return Ok(Some(byte));
+        }
+        loop {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            let after_cr = std::mem::replace(&mut self.after_cr, byte[0] == b'\r');
+            return Ok(Some(match (self.newline, byte[0]) {
+                (Newline::Crlf, b'\n') if !after_cr => {
+                    self.pending = Some(b'\n');
+                    b'\r'
+                }
+                (Newline::Cr, b'\n') if after_cr => continue,
+                (Newline::Cr, b'\n') => b'\r',
+                (_, byte) => byte,
+            }));
+        }
+    }
+}
+
+impl<R: Read> Read for NewlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.next()? {
+                Some(byte) => buf[n] = byte,
+                None => break,
+            }
+            n += 1;
+            // Hand over a line as soon as it ends, for interactive input.
+            if self.inner.buffer().is_empty() && self.pending.is_none() {
+                break;
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Turns the program's newlines in its output into line feeds. A program
+/// ending its lines with both is taken to write carriage returns only before
+/// line feeds, so drops them all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToLineFeeds(pub Newline);
+
+impl Transform for ToLineFeeds {
+    fn apply(&mut self, byte: u8) -> Option<u8> {
+        match (self.0, byte) {
+            (Newline::Crlf, b'\r') => None,
+            (Newline::Cr, b'\r') => Some(b'\n'),
+            (_, byte) => Some(byte),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(input: &[u8], newline: Newline) -> Vec<u8> {
+        let mut out = Vec::new();
+        NewlineReader::new(input, newline)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_newline_reader() {
+        assert_eq!(read(b"a\nb\r\nc", Newline::Lf), b"a\nb\r\nc");
+        assert_eq!(read(b"a\nb\r\nc\n", Newline::Crlf), b"a\r\nb\r\nc\r\n");
+        assert_eq!(read(b"a\nb\r\nc\r", Newline::Cr), b"a\rb\rc\r");
+    }
+
+    #[test]
+    fn test_to_line_feeds() {
+        let translate = |newline, output: &[u8]| {
+            let mut transform = ToLineFeeds(newline);
+            output
+                .iter()
+                .filter_map(|&byte| transform.apply(byte))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(translate(Newline::Lf, b"a\r\n"), b"a\r\n");
+        assert_eq!(translate(Newline::Crlf, b"a\r\nb\r\n"), b"a\nb\n");
+        assert_eq!(translate(Newline::Cr, b"a\rb\r"), b"a\nb\n");
+    }
+}
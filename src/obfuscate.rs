@@ -0,0 +1,111 @@
+use crate::{lexer, Instruction};
+
+/// Words mixed into obfuscated programs. None of them is a keyword, so they
+/// are skipped in lenient mode.
+const FILLER: [&str; 16] = [
+    "Dear", "friends", "wishing", "you", "joy", "and", "peace", "in", "the", "coming", "months",
+    "cheers", "to", "all", "bright", "days",
+];
+
+/// Instruction pairs that leave the tape as it was.
+const CANCELLING: [[Instruction; 2]; 4] = [
+    [Instruction::Increment, Instruction::Decrement],
+    [Instruction::Decrement, Instruction::Increment],
+    [Instruction::ShiftRight, Instruction::ShiftLeft],
+    [Instruction::ShiftLeft, Instruction::ShiftRight],
+];
+
+const LINE_WIDTH: usize = 80;
+
+/// A xorshift generator, so the same seed always gives the same output.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// Pads the keywords of `source` with filler words and with instruction pairs
+/// that cancel out. Between any two words, something is inserted with
+/// probability `density`; cancelling pairs only go between instructions. The
+/// result behaves like `source` when run in lenient mode.
+pub fn obfuscate(source: &str, density: f64, seed: u64) -> String {
+    let mut rng = Rng::new(seed);
+    let mut words = Vec::new();
+
+    for (i, token) in lexer::tokens(source, true).enumerate() {
+        if rng.chance(density) {
+            match i % 2 == 0 && rng.chance(0.5) {
+                true => {
+                    for instruction in CANCELLING[rng.below(CANCELLING.len())] {
+                        let (first, second) = instruction.pair();
+                        words.extend([first, second]);
+                    }
+                }
+                false => words.push(FILLER[rng.below(FILLER.len())]),
+            }
+        }
+        words.push(token.text);
+    }
+
+    let mut out = String::new();
+    let mut line = 0;
+    for word in words {
+        if line > 0 && line + 1 + word.len() > LINE_WIDTH {
+            out.push('\n');
+            line = 0;
+        } else if line > 0 {
+            out.push(' ');
+            line += 1;
+        }
+        out.push_str(word);
+        line += word.len();
+    }
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::Program;
+
+    #[test]
+    fn test_obfuscate() {
+        let source =
+            "Year Happy Year Happy Happy Happy Happy Year New Year Year Happy Happy New New New";
+        let obfuscated = obfuscate(source, 0.5, 7);
+
+        assert_ne!(obfuscated.trim(), source);
+        assert_eq!(obfuscated, obfuscate(source, 0.5, 7));
+        assert!(obfuscated.lines().all(|line| line.len() <= LINE_WIDTH));
+
+        let mut expected = Program::from_str(source).unwrap();
+        let mut actual = Program::parse(&obfuscated, true).unwrap();
+        expected.optimize();
+        actual.optimize();
+        assert_eq!(actual.ops(), expected.ops());
+    }
+
+    #[test]
+    fn test_zero_density() {
+        let source = "Year Happy Year New";
+        assert_eq!(obfuscate(source, 0.0, 1), "Year Happy Year New\n");
+    }
+}
@@ -0,0 +1,62 @@
+//! Writing programs' output to stdout, and when it is flushed: after every
+//! byte, so a prompt shows before the program waits for input, at the end of
+//! each line, as a terminal expects, or only when a run ends, for programs
+//! that write a lot and are watched by no one.
+
+use std::{
+    io::{self, Write},
+    sync::{Mutex, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// When a program's output reaches stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flush {
+    /// As soon as each byte is written.
+    EveryByte,
+    /// At the end of each line.
+    #[default]
+    OnNewline,
+    /// When the run ends, or a buffer fills.
+    OnExit,
+}
+
+/// Most output held back before it's written out whatever the mode.
+const CAPACITY: usize = 64 * 1024;
+
+/// Output not yet written to stdout. There's one for the process, as there's
+/// one stdout, so it can be flushed from wherever the process exits.
+static PENDING: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Writes `byte` to stdout, flushing as `flush` says. Like `print!`, panics
+/// if stdout can't be written, so a program writing to a closed pipe stops.
+pub(crate) fn write(byte: u8, flush: Flush) {
+    let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+    pending.push(byte);
+    let due = match flush {
+        Flush::EveryByte => true,
+        Flush::OnNewline => byte == b'\n',
+        Flush::OnExit => false,
+    };
+    if due || pending.len() >= CAPACITY {
+        if let Err(e) = write_out(&mut pending) {
+            panic!("failed printing to stdout: {}", e);
+        }
+    }
+}
+
+/// Writes out the output held back and flushes stdout. Runs do this when
+/// they end; call it before exiting in the middle of one. Errors are
+/// ignored, as there's nowhere left to write them.
+pub fn flush() {
+    write_out(&mut PENDING.lock().unwrap_or_else(PoisonError::into_inner)).ok();
+}
+
+fn write_out(pending: &mut Vec<u8>) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let written = stdout.write_all(pending).and_then(|()| stdout.flush());
+    pending.clear();
+    written
+}
@@ -0,0 +1,254 @@
+use std::{error::Error, fmt};
+
+use crate::ir::Program;
+
+/// Whether a pass changed the program it ran on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Changed {
+    No,
+    Yes,
+}
+
+impl From<bool> for Changed {
+    fn from(changed: bool) -> Self {
+        match changed {
+            true => Changed::Yes,
+            false => Changed::No,
+        }
+    }
+}
+
+/// A transformation of a [`Program`] run by [`PassManager`]. Implement this to
+/// add optimizations of your own; [`Program::ops_mut`] gives access to the IR.
+pub trait Pass {
+    /// Kebab-case name used to refer to the pass in flags and output.
+    fn name(&self) -> &'static str;
+    /// One line saying what the pass does.
+    fn description(&self) -> &'static str;
+    /// The lowest optimization level that runs the pass.
+    fn level(&self) -> u8 {
+        1
+    }
+    fn run(&self, program: &mut Program) -> Changed;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPass(pub String);
+
+impl fmt::Display for UnknownPass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown pass `{}`", self.0)
+    }
+}
+
+impl Error for UnknownPass {}
+
+/// Runs a sequence of passes in order, each of which can be turned off.
+pub struct PassManager {
+    passes: Vec<(Box<dyn Pass>, bool)>,
+}
+
+impl PassManager {
+    /// The built-in passes, enabled if they belong to optimization `level`.
+    pub fn new(level: u8) -> PassManager {
+        let mut manager = PassManager { passes: Vec::new() };
+        manager.add_pass(SimplifyLoops);
+        manager.add_pass(FoldCountedLoops);
+        manager.add_pass(FoldConstantPrefix);
+        manager.add_pass(EliminateDeadCode);
+        for (pass, enabled) in &mut manager.passes {
+            *enabled = pass.level() <= level;
+        }
+        manager
+    }
+
+    /// Adds `pass`, enabled, to run after the passes already added.
+    pub fn add_pass(&mut self, pass: impl Pass + 'static) {
+        self.passes.push((Box::new(pass), true));
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), UnknownPass> {
+        let (_, current) = self
+            .passes
+            .iter_mut()
+            .find(|(pass, _)| pass.name() == name)
+            .ok_or_else(|| UnknownPass(name.to_string()))?;
+        *current = enabled;
+        Ok(())
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = (&dyn Pass, bool)> {
+        self.passes
+            .iter()
+            .map(|(pass, enabled)| (pass.as_ref(), *enabled))
+    }
+
+    /// Runs every enabled pass once, in order.
+    pub fn run(&self, program: &mut Program) -> Changed {
+        trace_span!(tracing::Level::DEBUG, "optimize");
+        let mut changed = Changed::No;
+        for (pass, _) in self.passes.iter().filter(|(_, enabled)| *enabled) {
+            trace_span!(tracing::Level::DEBUG, "pass", name = pass.name());
+            let result = pass.run(program);
+            trace_event!(tracing::Level::DEBUG, changed = ?result, ops = program.ops().len());
+            if result == Changed::Yes {
+                changed = Changed::Yes;
+            }
+        }
+        changed
+    }
+}
+
+/// Runs `f` on `program`, reporting whether its ops changed.
+fn track(program: &mut Program, f: impl FnOnce(&mut Program)) -> Changed {
+    let before = program.ops().to_vec();
+    f(program);
+    Changed::from(program.ops() != before)
+}
+
+struct SimplifyLoops;
+
+impl Pass for SimplifyLoops {
+    fn name(&self) -> &'static str {
+        "simplify-loops"
+    }
+
+    fn description(&self) -> &'static str {
+        "rewrite clear and multiply loops into set and muladd"
+    }
+
+    fn run(&self, program: &mut Program) -> Changed {
+        track(program, Program::optimize)
+    }
+}
+
+struct FoldCountedLoops;
+
+impl Pass for FoldCountedLoops {
+    fn name(&self) -> &'static str {
+        "fold-counted-loops"
+    }
+
+    fn description(&self) -> &'static str {
+        "unroll loops that run a known number of times into adds"
+    }
+
+    fn level(&self) -> u8 {
+        2
+    }
+
+    fn run(&self, program: &mut Program) -> Changed {
+        track(program, Program::fold_counted_loops)
+    }
+}
+
+struct FoldConstantPrefix;
+
+impl Pass for FoldConstantPrefix {
+    fn name(&self) -> &'static str {
+        "fold-constant-prefix"
+    }
+
+    fn description(&self) -> &'static str {
+        "run the ops before the first loop or I/O at compile time"
+    }
+
+    fn level(&self) -> u8 {
+        2
+    }
+
+    fn run(&self, program: &mut Program) -> Changed {
+        track(program, Program::fold_constant_prefix)
+    }
+}
+
+struct EliminateDeadCode;
+
+impl Pass for EliminateDeadCode {
+    fn name(&self) -> &'static str {
+        "eliminate-dead-code"
+    }
+
+    fn description(&self) -> &'static str {
+        "remove loops that never run, redundant sets and dead stores"
+    }
+
+    fn level(&self) -> u8 {
+        2
+    }
+
+    fn run(&self, program: &mut Program) -> Changed {
+        track(program, Program::eliminate_dead_code)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{brainfuck_to_hny, ir::Op};
+
+    /// Doubles every add, to check that passes of our own run.
+    struct DoubleAdds;
+
+    impl Pass for DoubleAdds {
+        fn name(&self) -> &'static str {
+            "double-adds"
+        }
+
+        fn description(&self) -> &'static str {
+            "double every add"
+        }
+
+        fn run(&self, program: &mut Program) -> Changed {
+            let mut changed = Changed::No;
+            for op in program.ops_mut() {
+                if let Op::Add(n) = op {
+                    *n = n.wrapping_mul(2);
+                    changed = Changed::Yes;
+                }
+            }
+            changed
+        }
+    }
+
+    #[test]
+    fn test_pass_manager() {
+        let code = brainfuck_to_hny("+++>[-]<.").output;
+        let program = Program::from_str(&code).unwrap();
+
+        let mut o1 = program.clone();
+        assert_eq!(PassManager::new(1).run(&mut o1), Changed::Yes);
+        assert_eq!(
+            o1.ops(),
+            [
+                Op::Add(3),
+                Op::Move(1),
+                Op::Set(0),
+                Op::Move(-1),
+                Op::Output
+            ]
+        );
+        assert_eq!(PassManager::new(0).run(&mut o1), Changed::No);
+
+        let mut manager = PassManager::new(0);
+        manager.add_pass(DoubleAdds);
+        manager.set_enabled("simplify-loops", true).unwrap();
+        let mut custom = program.clone();
+        manager.run(&mut custom);
+        assert_eq!(
+            custom.ops(),
+            [
+                Op::Add(6),
+                Op::Move(1),
+                Op::Set(0),
+                Op::Move(-1),
+                Op::Output
+            ]
+        );
+
+        assert_eq!(
+            manager.set_enabled("no-such-pass", false),
+            Err(UnknownPass("no-such-pass".to_string()))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Input that doesn't block the program for long: a thread reads ahead, and
+//! a read that finds nothing has arrived gets a default byte, so a program can
+//! poll for keys instead of stopping until one is pressed, or after a timeout
+//! gets the end of input, so one left waiting doesn't wait forever.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+/// Reads what the reader it wraps delivers within a time, or else a default
+/// byte or an error.
+#[derive(Debug)]
+pub struct PolledReader {
+    /// What the reading thread has read, with an empty chunk at the end of
+    /// input.
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    pending: VecDeque<u8>,
+    timeout: Duration,
+    default: Option<u8>,
+    ended: bool,
+}
+
+impl PolledReader {
+    /// Starts reading `inner` on a thread of its own, giving `default` for
+    /// each read that finds no input waiting.
+    pub fn new(inner: impl Read + Send + 'static, default: u8) -> Self {
+        Self::with_timeout(inner, Duration::ZERO, Some(default))
+    }
+
+    /// Starts reading `inner` on a thread of its own, waiting up to `timeout`
+    /// for input on each read before giving `default` or, without one,
+    /// failing with [`io::ErrorKind::TimedOut`], which the interpreter takes
+    /// as the end of input.
+    pub fn with_timeout(
+        mut inner: impl Read + Send + 'static,
+        timeout: Duration,
+        default: Option<u8>,
+    ) -> Self {
+        let (sender, chunks) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                let chunk = match inner.read(&mut buf) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    chunk => chunk.map(|n| buf[..n].to_vec()),
+                };
+                let last = !matches!(&chunk, Ok(chunk) if !chunk.is_empty());
+                if sender.send(chunk).is_err() || last {
+                    break;
+                }
+            }
+        });
+        PolledReader {
+            chunks,
+            pending: VecDeque::new(),
+            timeout,
+            default,
+            ended: false,
+        }
+    }
+}
+
+impl Read for PolledReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() && !self.ended {
+            match self.chunks.recv_timeout(self.timeout) {
+                Ok(Ok(chunk)) if chunk.is_empty() => self.ended = true,
+                Ok(Ok(chunk)) => self.pending.extend(chunk),
+                Ok(Err(e)) => {
+                    self.ended = true;
+                    return Err(e);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let Some(default) = self.default else {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    };
+                    buf[0] = default;
+                    return Ok(1);
+                }
+                Err(RecvTimeoutError::Disconnected) => self.ended = true,
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        for (slot, byte) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::mpsc::Sender, time::Duration};
+
+    use super::*;
+
+    /// Input that arrives only as the test sends it.
+    struct Typed(Receiver<Vec<u8>>);
+
+    impl Read for Typed {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let typed = self.0.recv().unwrap_or_default();
+            buf[..typed.len()].copy_from_slice(&typed);
+            Ok(typed.len())
+        }
+    }
+
+    fn typed() -> (Sender<Vec<u8>>, PolledReader) {
+        let (keys, typed) = mpsc::channel();
+        (keys, PolledReader::new(Typed(typed), b'?'))
+    }
+
+    /// The next byte that isn't the default, waiting a while for it.
+    fn next_typed(reader: &mut PolledReader) -> Option<u8> {
+        for _ in 0..1000 {
+            let mut byte = [0];
+            match reader.read(&mut byte).unwrap() {
+                0 => return None,
+                _ if byte[0] != b'?' => return Some(byte[0]),
+                _ => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        panic!("the input never arrived");
+    }
+
+    #[test]
+    fn test_polled_reader() {
+        let (keys, mut reader) = typed();
+        let mut byte = [0];
+        assert_eq!(reader.read(&mut byte).unwrap(), 1);
+        assert_eq!(byte, *b"?");
+
+        keys.send(b"ab".to_vec()).unwrap();
+        assert_eq!(next_typed(&mut reader), Some(b'a'));
+        assert_eq!(next_typed(&mut reader), Some(b'b'));
+        drop(keys);
+        assert_eq!(next_typed(&mut reader), None);
+        assert_eq!(reader.read(&mut byte).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (keys, typed) = mpsc::channel();
+        let timeout = Duration::from_millis(20);
+        let mut reader = PolledReader::with_timeout(Typed(typed), timeout, None);
+        let mut byte = [0];
+        let error = reader.read(&mut byte).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        keys.send(b"a".to_vec()).unwrap();
+        let read = std::iter::repeat_with(|| reader.read(&mut byte))
+            .find(|read| !matches!(read, Err(e) if e.kind() == io::ErrorKind::TimedOut));
+        assert_eq!(read.unwrap().unwrap(), 1);
+        assert_eq!(byte, *b"a");
+    }
+}
@@ -0,0 +1,204 @@
+//! Post-mortem dumps of runs that fail, whether a limit stopped them, they
+//! reached a token that isn't an instruction or their output couldn't be
+//! written: the program's hash, where it stopped, the tape and the last
+//! instructions it ran, saved as JSON in a `.hnydump` file to look into
+//! after the process is gone.
+
+use std::{
+    collections::VecDeque,
+    io,
+    ops::ControlFlow,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::source_hash,
+    middleware::{Middleware, Next},
+    Instruction, State,
+};
+
+/// An instruction as it was about to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Steps taken before it.
+    pub steps: u64,
+    /// Index of its word pair.
+    pub pair: usize,
+    /// Its brainfuck symbol.
+    pub instruction: char,
+    /// The current cell, counted from the cell the program started on.
+    pub pointer: isize,
+    /// The current cell's value.
+    pub cell: u8,
+}
+
+/// The last instructions a run got to, most recent last, kept by the
+/// [`Recorder`]s made from it.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    events: Arc<Mutex<VecDeque<Event>>>,
+    capacity: usize,
+}
+
+impl Trace {
+    /// Keeps the last `capacity` instructions.
+    pub fn new(capacity: usize) -> Trace {
+        Trace {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Middleware adding every instruction that reaches it to the trace.
+    pub fn recorder(&self) -> Recorder {
+        Recorder(self.clone())
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.lock().iter().copied().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Event>> {
+        // A run that panicked holding the lock left the trace whole.
+        self.events.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Adds each instruction to a [`Trace`] before passing it on.
+#[derive(Debug)]
+pub struct Recorder(Trace);
+
+impl Middleware for Recorder {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+        let state = next.state();
+        let event = Event {
+            steps: state.steps,
+            pair: next.pair(),
+            instruction: instruction.symbol(),
+            pointer: state.pointer() as isize - state.origin() as isize,
+            cell: state.tape().get(state.pointer()).copied().unwrap_or(0),
+        };
+        {
+            let mut events = self.0.lock();
+            if events.len() == self.0.capacity {
+                events.pop_front();
+            }
+            if self.0.capacity > 0 {
+                events.push_back(event);
+            }
+        }
+        next.run(instruction)
+    }
+}
+
+/// Where a failed run stopped and what led up to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dump {
+    /// [`source_hash`] of the program, to tell which one the dump is of.
+    pub program: String,
+    /// What went wrong.
+    pub error: String,
+    pub steps: u64,
+    /// Index of the word pair the program stopped at.
+    pub pair: usize,
+    /// The tape, from the leftmost cell the program visited.
+    pub tape: Vec<u8>,
+    /// Index into `tape` of the current cell.
+    pub pointer: usize,
+    /// Index into `tape` of the cell the program started on.
+    pub origin: usize,
+    /// The last instructions the program got to, oldest first.
+    pub recent: Vec<Event>,
+}
+
+impl Dump {
+    /// A dump of `state`, left by running `source` until `error` stopped it.
+    pub fn new(source: &str, error: impl Into<String>, state: &State, trace: &Trace) -> Dump {
+        Dump {
+            program: source_hash(source),
+            error: error.into(),
+            steps: state.steps,
+            pair: state.pair,
+            tape: state.tape().to_vec(),
+            pointer: state.pointer(),
+            origin: state.origin(),
+            recent: trace.events(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string(self).expect("dumps serialize");
+        std::fs::write(path, json + "\n")
+    }
+
+    pub fn load(path: &Path) -> io::Result<Dump> {
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::*;
+    use crate::{brainfuck_to_hny, limits::Exit, limits::Limits, HnyFuck};
+
+    #[test]
+    fn test_trace() {
+        let trace = Trace::new(3);
+        let mut hny = HnyFuck::from_str(&brainfuck_to_hny("+>++<-").output);
+        hny.add_middleware(trace.recorder());
+        hny.run();
+        let recent = trace
+            .events()
+            .iter()
+            .map(|event| (event.instruction, event.pointer, event.cell))
+            .collect::<Vec<_>>();
+        assert_eq!(recent, [('+', 1, 1), ('<', 1, 2), ('-', 0, 1)]);
+        assert_eq!(trace.events()[2].steps, 5);
+    }
+
+    #[test]
+    fn test_dump() {
+        // A limit stops the program.
+        let code = brainfuck_to_hny("+++[>+<-]>.").output;
+        let trace = Trace::new(4);
+        let mut hny = HnyFuck::from_str(&code);
+        hny.add_middleware(trace.recorder());
+        hny.set_limits(Limits {
+            steps: 10,
+            ..Limits::NONE
+        });
+        assert_eq!(hny.run().exit, Exit::StepLimit);
+        let dump = Dump::new(&code, "step limit", hny.state(), &trace);
+        assert_eq!((dump.steps, dump.pair, dump.pointer), (10, 5, 1));
+        assert_eq!(dump.tape, [2, 1]);
+        assert_eq!(dump.recent.len(), 4);
+        assert_eq!(dump.recent[3].instruction, '+');
+
+        let path = std::env::temp_dir().join(format!("hnyfuck-{}.hnydump", std::process::id()));
+        dump.save(&path).unwrap();
+        assert_eq!(Dump::load(&path).unwrap(), dump);
+        std::fs::remove_file(&path).unwrap();
+
+        // A call with no extensions enabled panics inside a loop, and the
+        // tape is left as the program got it to.
+        let code = format!(
+            "{} Year Year {}",
+            brainfuck_to_hny("++[>+<-").output,
+            brainfuck_to_hny("]").output
+        );
+        let trace = Trace::new(2);
+        let mut hny = HnyFuck::from_str(&code);
+        hny.add_middleware(trace.recorder());
+        let run = panic::catch_unwind(AssertUnwindSafe(|| hny.run()));
+        assert!(run.is_err());
+        let dump = Dump::new(&code, "Invalid token", hny.state(), &trace);
+        assert_eq!((dump.pair, dump.tape), (7, vec![1, 1]));
+        assert_eq!(dump.recent.last().unwrap().instruction, '-');
+    }
+}
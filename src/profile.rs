@@ -0,0 +1,91 @@
+use std::{fmt, ops::Range};
+
+use crate::{lexer, Instruction, LoopStats, RunStats};
+
+/// A loop from a profiled run, attributed back to the program text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotLoop {
+    /// Pair index of the loop's `Happy Happy`.
+    pub start: usize,
+    pub stats: LoopStats,
+    /// Byte range of the whole loop, `Happy Happy` through `New New`.
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+    /// The loop rewritten as brainfuck.
+    pub brainfuck: String,
+}
+
+/// Returns the `n` loops with the most iterations, busiest first. Ties go to
+/// the loop that executed more instructions. `lenient` must match how the
+/// program was tokenized for the run.
+pub fn hot_loops(source: &str, lenient: bool, stats: &RunStats, n: usize) -> Vec<HotLoop> {
+    let tokens = lexer::tokens(source, lenient)
+        .map(|token| (token.text, token.span))
+        .collect::<Vec<_>>();
+
+    let mut loops = stats.loops().collect::<Vec<_>>();
+    loops.sort_by(|(a_start, a), (b_start, b)| {
+        (b.iterations, b.instructions, a_start).cmp(&(a.iterations, a.instructions, b_start))
+    });
+
+    loops
+        .into_iter()
+        .take(n)
+        .filter_map(|(start, stats)| {
+            let mut brainfuck = String::new();
+            let mut depth = 0;
+            let mut end = None;
+            for chunk in tokens[start * 2..].chunks(2) {
+                let [(first, _), (second, span)] = chunk else {
+                    break;
+                };
+                let instruction = Instruction::from_pair((first, second))?;
+                brainfuck.push(instruction.symbol());
+                match instruction {
+                    Instruction::LoopStart => depth += 1,
+                    Instruction::LoopEnd => depth -= 1,
+                    _ => (),
+                }
+                if depth == 0 {
+                    end = Some(span.end);
+                    break;
+                }
+            }
+            let span = tokens[start * 2].1.start..end?;
+            let (line, column) = lexer::line_col(source, span.start);
+            Some(HotLoop {
+                start,
+                stats,
+                span,
+                line,
+                column,
+                brainfuck,
+            })
+        })
+        .collect()
+}
+
+impl fmt::Display for HotLoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MAX_SNIPPET: usize = 60;
+
+        let snippet = if self.brainfuck.len() > MAX_SNIPPET {
+            format!("{}...", &self.brainfuck[..MAX_SNIPPET])
+        } else {
+            self.brainfuck.clone()
+        };
+        write!(
+            f,
+            "{}:{} (bytes {}..{}) iterations={} instructions={} cost={:?}\n    {}",
+            self.line,
+            self.column,
+            self.span.start,
+            self.span.end,
+            self.stats.iterations,
+            self.stats.instructions,
+            self.stats.cost,
+            snippet
+        )
+    }
+}
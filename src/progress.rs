@@ -0,0 +1,50 @@
+use std::fmt;
+
+/// How far a run has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress<'a> {
+    /// Instructions interpreted plus operations run in compiled loops.
+    pub steps: u64,
+    /// Index of the word pair running, or of the start of the loop running
+    /// compiled.
+    pub pair: usize,
+    /// The tape, from the leftmost cell the program has visited.
+    pub tape: &'a [u8],
+    /// Index into `tape` of the current cell.
+    pub pointer: usize,
+}
+
+/// Calls a callback every `interval` steps.
+pub(crate) struct Reporter {
+    interval: u64,
+    callback: Box<dyn FnMut(Progress<'_>) + Send>,
+}
+
+impl Reporter {
+    pub(crate) fn new(
+        interval: u64,
+        callback: impl FnMut(Progress<'_>) + Send + 'static,
+    ) -> Reporter {
+        Reporter {
+            interval: interval.max(1),
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Reports `progress` and returns the step count to report at next.
+    pub(crate) fn report(&mut self, progress: Progress<'_>) -> u64 {
+        let steps = progress.steps;
+        (self.callback)(progress);
+        steps.saturating_add(self.interval)
+    }
+
+    pub(crate) fn interval(&self) -> u64 {
+        self.interval
+    }
+}
+
+impl fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Reporter(every {} steps)", self.interval)
+    }
+}
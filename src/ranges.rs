@@ -0,0 +1,410 @@
+use std::{collections::BTreeMap, fmt, fmt::Write};
+
+use crate::{Op, Program};
+
+/// Loop rounds after which bounds that keep moving are pushed to their limit,
+/// so the analysis of a loop settles quickly.
+const WIDEN_AFTER: usize = 2;
+
+/// The values a cell may hold, `lo` through `hi` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: u8,
+    pub hi: u8,
+}
+
+impl Interval {
+    pub const ANY: Interval = Interval { lo: 0, hi: u8::MAX };
+    pub const ZERO: Interval = Interval::constant(0);
+
+    pub const fn constant(n: u8) -> Interval {
+        Interval { lo: n, hi: n }
+    }
+
+    /// The single value of the interval, if it has only one.
+    pub fn value(self) -> Option<u8> {
+        (self.lo == self.hi).then_some(self.lo)
+    }
+
+    pub fn contains(self, n: u8) -> bool {
+        (self.lo..=self.hi).contains(&n)
+    }
+
+    fn join(self, other: Interval) -> Interval {
+        Interval {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// Adds `n` to every value. An interval that then straddles the wrap
+    /// from 255 to 0 becomes `ANY`.
+    fn add(self, n: u8) -> Interval {
+        let (lo, hi) = (self.lo.wrapping_add(n), self.hi.wrapping_add(n));
+        match lo <= hi {
+            true => Interval { lo, hi },
+            false => Interval::ANY,
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.value(), *self == Interval::ANY) {
+            (Some(n), _) => write!(f, "{}", n),
+            (None, true) => write!(f, "any"),
+            (None, false) => write!(f, "{}..={}", self.lo, self.hi),
+        }
+    }
+}
+
+/// What is known about the tape at one point. Cells are keyed by their offset
+/// from `anchor`, a position on the tape that is the start of the program
+/// (anchor 0) or wherever the pointer was at the head of a loop that moves it
+/// by an unknown amount. Cells missing from `cells` are in `rest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    anchor: usize,
+    pointer: isize,
+    cells: BTreeMap<isize, Interval>,
+    rest: Interval,
+}
+
+impl State {
+    fn start() -> State {
+        State {
+            anchor: 0,
+            pointer: 0,
+            cells: BTreeMap::new(),
+            rest: Interval::ZERO,
+        }
+    }
+
+    /// A state that knows nothing, anchored at the current pointer.
+    fn unknown(anchor: usize) -> State {
+        State {
+            anchor,
+            pointer: 0,
+            cells: BTreeMap::new(),
+            rest: Interval::ANY,
+        }
+    }
+
+    fn get(&self, offset: isize) -> Interval {
+        self.cells
+            .get(&(self.pointer + offset))
+            .copied()
+            .unwrap_or(self.rest)
+    }
+
+    fn set(&mut self, offset: isize, value: Interval) {
+        match value == self.rest {
+            true => self.cells.remove(&(self.pointer + offset)),
+            false => self.cells.insert(self.pointer + offset, value),
+        };
+    }
+
+    fn step(mut self, op: Op) -> State {
+        match op {
+            Op::Add(n) => self.set(0, self.get(0).add(n)),
+            Op::Move(n) => self.pointer += n,
+            Op::Output => (),
+            Op::Input => self.set(0, Interval::ANY),
+            Op::Set(n) => self.set(0, Interval::constant(n)),
+            Op::MulAdd { offset, factor } => {
+                let value = self.get(0);
+                let target = self.get(offset);
+                if value != Interval::ZERO {
+                    let added = match (value.value(), target.value()) {
+                        (Some(v), Some(t)) => {
+                            Interval::constant(t.wrapping_add(v.wrapping_mul(factor)))
+                        }
+                        _ => Interval::ANY,
+                    };
+                    let result = match value.contains(0) {
+                        true => added.join(target),
+                        false => added,
+                    };
+                    self.set(offset, result);
+                }
+            }
+            Op::JumpIfZero(_) | Op::JumpIfNonZero(_) => unreachable!("loops are walked as a whole"),
+        }
+        self
+    }
+
+    /// Narrows the state to the case where the current cell is (non-)zero,
+    /// or returns `None` if that cannot happen.
+    fn assume(mut self, nonzero: bool) -> Option<State> {
+        let current = self.get(0);
+        match nonzero {
+            true if current == Interval::ZERO => None,
+            true if current.lo == 0 => {
+                self.set(0, Interval { lo: 1, ..current });
+                Some(self)
+            }
+            true => Some(self),
+            false if !current.contains(0) => None,
+            false => {
+                self.set(0, Interval::ZERO);
+                Some(self)
+            }
+        }
+    }
+
+    fn keys<'a>(&'a self, other: &'a State) -> impl Iterator<Item = isize> + 'a {
+        self.cells.keys().chain(other.cells.keys()).copied()
+    }
+}
+
+/// Joins the states of two paths that meet. When they disagree on where the
+/// pointer is, the result knows nothing and is anchored afresh at `anchor`.
+fn join(a: Option<State>, b: Option<State>, anchor: usize) -> Option<State> {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        (a, b) => return a.or(b),
+    };
+    if (a.anchor, a.pointer) != (b.anchor, b.pointer) {
+        return Some(State::unknown(anchor));
+    }
+    let mut joined = State {
+        rest: a.rest.join(b.rest),
+        cells: BTreeMap::new(),
+        ..a.clone()
+    };
+    for key in a.keys(&b) {
+        let value = a.cells.get(&key).copied().unwrap_or(a.rest);
+        let other = b.cells.get(&key).copied().unwrap_or(b.rest);
+        if value.join(other) != joined.rest {
+            joined.cells.insert(key, value.join(other));
+        }
+    }
+    Some(joined)
+}
+
+/// Like `next`, but with every bound that moved since `previous` pushed out
+/// to its limit, so a cell counting down ends at 0 and one counting up at 255.
+fn widen(previous: &Option<State>, next: Option<State>) -> Option<State> {
+    let (Some(previous), Some(mut next)) = (previous, next.clone()) else {
+        return next;
+    };
+    if (previous.anchor, previous.pointer) != (next.anchor, next.pointer) {
+        return Some(next);
+    }
+    let bounds = |old: Interval, new: Interval| Interval {
+        lo: if new.lo < old.lo { 0 } else { new.lo },
+        hi: if new.hi > old.hi { u8::MAX } else { new.hi },
+    };
+    let rest = bounds(previous.rest, next.rest);
+    for key in previous.keys(&next).collect::<Vec<_>>() {
+        let old = previous.cells.get(&key).copied().unwrap_or(previous.rest);
+        let new = next.cells.get(&key).copied().unwrap_or(next.rest);
+        match bounds(old, new) {
+            widened if widened == rest => next.cells.remove(&key),
+            widened => next.cells.insert(key, widened),
+        };
+    }
+    next.rest = rest;
+    Some(next)
+}
+
+/// The range of values of each cell before each op of a program, found by
+/// running the program on intervals instead of bytes from a blank tape.
+#[derive(Debug, Clone)]
+pub struct Ranges {
+    states: Vec<Option<State>>,
+}
+
+impl Ranges {
+    pub fn new(program: &Program) -> Ranges {
+        let mut ranges = Ranges {
+            states: vec![None; program.ops().len()],
+        };
+        ranges.walk(program.ops(), 0, program.ops().len(), Some(State::start()));
+        ranges
+    }
+
+    fn walk(
+        &mut self,
+        ops: &[Op],
+        mut pc: usize,
+        end: usize,
+        mut state: Option<State>,
+    ) -> Option<State> {
+        while pc < end {
+            self.states[pc] = state.clone();
+            let Op::JumpIfZero(close) = ops[pc] else {
+                state = state.map(|state| state.step(ops[pc]));
+                pc += 1;
+                continue;
+            };
+
+            let entry = state;
+            let mut head = entry.clone();
+            for round in 0.. {
+                let body = self.walk(
+                    ops,
+                    pc + 1,
+                    close,
+                    head.clone().and_then(|s| s.assume(true)),
+                );
+                self.states[close] = body.clone();
+                let mut next = join(entry.clone(), body, pc + 1);
+                if round >= WIDEN_AFTER {
+                    next = widen(&head, next);
+                }
+                if next == head {
+                    break;
+                }
+                head = next;
+            }
+            state = head.and_then(|s| s.assume(false));
+            pc = close + 1;
+        }
+        state
+    }
+
+    /// Whether any run reaches op `op`.
+    pub fn reachable(&self, op: usize) -> bool {
+        self.states[op].is_some()
+    }
+
+    /// The values the current cell may hold before op `op`, or `None` if the
+    /// op is unreachable.
+    pub fn current(&self, op: usize) -> Option<Interval> {
+        self.cell(op, 0)
+    }
+
+    /// The values of the cell `offset` cells from the pointer before op `op`.
+    pub fn cell(&self, op: usize, offset: isize) -> Option<Interval> {
+        self.states[op].as_ref().map(|state| state.get(offset))
+    }
+
+    /// Offsets from the pointer of the cells known to be zero before op `op`
+    /// among those the program has touched so far, and whether all others are
+    /// known to be zero too.
+    pub fn known_zero(&self, op: usize) -> (Vec<isize>, bool) {
+        let Some(state) = &self.states[op] else {
+            return (Vec::new(), false);
+        };
+        let zero = state
+            .cells
+            .iter()
+            .filter(|(_, value)| **value == Interval::ZERO)
+            .map(|(offset, _)| offset - state.pointer)
+            .collect();
+        (zero, state.rest == Interval::ZERO)
+    }
+
+    /// How many times the loop starting at op `start` runs, if it always runs
+    /// the same number of times: its body only adds and moves, comes back to
+    /// where it started, and it is always entered with the same value.
+    /// `Some(0)` means the loop is never entered.
+    pub fn trip_count(&self, program: &Program, start: usize) -> Option<u32> {
+        let Op::JumpIfZero(close) = program.ops()[start] else {
+            return None;
+        };
+        let entry = self.current(start)?.value()?;
+        if entry == 0 {
+            return Some(0);
+        }
+
+        let mut offset = 0;
+        let mut delta = 0u8;
+        for op in &program.ops()[start + 1..close] {
+            match *op {
+                Op::Add(n) if offset == 0 => delta = delta.wrapping_add(n),
+                Op::Add(_) => (),
+                Op::Move(n) => offset += n,
+                _ => return None,
+            }
+        }
+        if offset != 0 {
+            return None;
+        }
+        (1..=256u32).find(|&k| entry.wrapping_add(delta.wrapping_mul(k as u8)) == 0)
+    }
+
+    /// The program listing with the current cell's range before each op that
+    /// is reached, and at each loop the cells known to be zero and how many
+    /// times it runs when that is fixed.
+    pub fn listing(&self, program: &Program) -> String {
+        let mut out = String::new();
+        for (i, op) in program.ops().iter().enumerate() {
+            let _ = write!(out, "{:>6}  {:<16}", i, op.to_string());
+            let Some(current) = self.current(i) else {
+                let _ = writeln!(out, "unreachable");
+                continue;
+            };
+            let _ = write!(out, "cell {}", current);
+            if let Op::JumpIfZero(_) = op {
+                let (zero, rest) = self.known_zero(i);
+                let zero = zero.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+                let _ = match (zero.is_empty(), rest) {
+                    (true, true) => write!(out, ", zero: everywhere untouched"),
+                    (true, false) => Ok(()),
+                    (false, true) => {
+                        write!(out, ", zero: {} and everywhere untouched", zero.join(" "))
+                    }
+                    (false, false) => write!(out, ", zero: {}", zero.join(" ")),
+                };
+                match self.trip_count(program, i) {
+                    Some(0) => out.push_str(", never entered"),
+                    Some(k) => {
+                        let _ = write!(out, ", runs {} times", k);
+                    }
+                    None => (),
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    fn program(brainfuck: &str) -> Program {
+        let mut program = Program::from_str(&brainfuck_to_hny(brainfuck).output).unwrap();
+        program.optimize();
+        program
+    }
+
+    #[test]
+    fn test_ranges() {
+        // add 3, jz, out, add -1, move 1, add 2, move -1, jnz, move 1, out
+        let program = program("+++[.->++<]>.");
+        let ranges = Ranges::new(&program);
+
+        assert_eq!(ranges.current(1), Some(Interval::constant(3)));
+        assert_eq!(ranges.current(2), Some(Interval { lo: 1, hi: 3 }));
+        assert_eq!(ranges.cell(1, 1), Some(Interval::ZERO));
+        assert_eq!(ranges.cell(8, 0), Some(Interval::ZERO));
+        assert_eq!(ranges.current(9), Some(Interval::ANY));
+        assert_eq!(ranges.known_zero(1), (vec![], true));
+    }
+
+    #[test]
+    fn test_trip_count() {
+        // add 4, jz, move 1, add 1, move -1, add -2, jnz, move 1, set 0, ...
+        let program = program("++++[>+<--]>[+]<[-].");
+        let ranges = Ranges::new(&program);
+        let out = program.ops().len() - 1;
+
+        assert_eq!(ranges.trip_count(&program, 1), Some(2));
+        assert_eq!(ranges.current(out), Some(Interval::ZERO));
+        assert_eq!(ranges.cell(out, 1), Some(Interval::ZERO));
+    }
+
+    #[test]
+    fn test_unbalanced_loop() {
+        let program = program("+[>]+.");
+        let ranges = Ranges::new(&program);
+        let out = program.ops().len() - 1;
+        assert_eq!(ranges.current(out), Some(Interval::constant(1)));
+        assert_eq!(ranges.cell(out, 1), Some(Interval::ANY));
+    }
+}
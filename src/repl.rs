@@ -0,0 +1,525 @@
+//! An interactive prompt that runs each line entered against the same tape,
+//! so state can be built up a piece at a time. Lines starting with `:` are
+//! commands to the prompt itself. An entry that opens more loops than it
+//! closes carries on over the following lines until they are closed.
+//!
+//! At a terminal, [`Repl::with_editor`] gives the prompt line editing,
+//! history kept between sessions, and Tab completion of keywords and
+//! commands.
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    fs,
+    io::{self, BufRead, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use rustyline::{
+    completion::Completer,
+    error::ReadlineError,
+    history::DefaultHistory,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Context, Editor, Helper, Highlighter, Hinter,
+};
+
+use crate::{
+    config::xdg_dir,
+    debugger::tape_window,
+    equiv::unescape,
+    ir::Program,
+    lexer::{self, KEYWORDS},
+    HnyFuck, State, LOOP_END, LOOP_START,
+};
+
+const PROMPT: &str = "hny> ";
+/// The prompt for the lines of an entry after the first.
+const CONTINUATION: &str = "...> ";
+
+/// The commands, for completion.
+const COMMANDS: [&str; 8] = [
+    ":tape", ":reset", ":load", ":save", ":script", ":set", ":help", ":quit",
+];
+const SETTINGS: [&str; 2] = ["lenient", "input"];
+
+/// How many cells either side of the pointer `:tape` shows.
+const TAPE_WINDOW: usize = 8;
+
+const HELP: &str = "\
+commands:
+  :tape              show the cells around the pointer
+  :reset             start again with a blank tape
+  :load FILE         run a program from FILE against the tape
+  :save FILE         write what has run since the last :reset to FILE
+  :script FILE       carry out the lines of FILE as if typed
+  :set lenient BOOL  skip words other than Happy, New and Year
+  :set input TEXT    what `New Happy` reads next; \\n, \\t, \\\\ and \\xHH escape
+  :help              show this list
+  :quit              leave
+anything else is run as a program
+";
+
+/// Reads lines from stdin and writes replies and program output to stdout
+/// unless other streams are given with [`Repl::with_io`].
+pub struct Repl {
+    state: State,
+    /// Shared with the editor, which needs it to tell where entries end.
+    lenient: Rc<Cell<bool>>,
+    /// Programs run since the tape was last reset, for `:save`.
+    history: Vec<String>,
+    /// Input queued with `:set input`.
+    input: Arc<Mutex<VecDeque<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>,
+    lines: Lines,
+    replies: Box<dyn Write>,
+}
+
+/// Where lines come from.
+enum Lines {
+    /// A stream the prompt is written out before reading from.
+    Stream(Box<dyn BufRead>),
+    Editor {
+        editor: Box<Editor<LineHelper, DefaultHistory>>,
+        /// The file to keep history in.
+        history: Option<PathBuf>,
+    },
+    /// Lines of a script, echoed after the prompt as they are read.
+    Script(VecDeque<String>),
+}
+
+impl Repl {
+    pub fn new(lenient: bool) -> Repl {
+        let mut repl = Repl {
+            state: State::new(),
+            lenient: Rc::new(Cell::new(lenient)),
+            history: Vec::new(),
+            input: Arc::default(),
+            output: Arc::default(),
+            lines: Lines::Stream(Box::new(io::BufReader::new(io::stdin()))),
+            replies: Box::new(io::stdout()),
+        };
+        repl.reset();
+        repl
+    }
+
+    /// Reads lines from `commands` and writes replies to `replies`.
+    pub fn with_io(
+        mut self,
+        commands: impl BufRead + 'static,
+        replies: impl Write + 'static,
+    ) -> Repl {
+        self.lines = Lines::Stream(Box::new(commands));
+        self.replies = Box::new(replies);
+        self
+    }
+
+    /// Reads lines from the terminal with an editor, loading history from
+    /// and saving it to `history` if given.
+    pub fn with_editor(mut self, history: Option<PathBuf>) -> rustyline::Result<Repl> {
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(LineHelper {
+            lenient: self.lenient.clone(),
+        }));
+        if let Some(path) = &history {
+            match editor.load_history(path) {
+                Err(ReadlineError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+        self.lines = Lines::Editor {
+            editor: Box::new(editor),
+            history,
+        };
+        Ok(self)
+    }
+
+    /// The tape as it stands.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Prompts for lines until `:quit` or the end of input.
+    pub fn run(&mut self) {
+        self.run_lines();
+    }
+
+    /// Carries out lines until `:quit` or the end of input, returning whether
+    /// it was `:quit`.
+    fn run_lines(&mut self) -> bool {
+        while let Some(line) = self.read_line(PROMPT) {
+            let line = line.trim();
+            let reply = match line.strip_prefix(':') {
+                Some(command) => match self.command(command) {
+                    Some(reply) => reply,
+                    None => return true,
+                },
+                None if line.is_empty() => Ok(String::new()),
+                None => {
+                    let entry = self.read_entry(line.to_string());
+                    self.execute(entry)
+                }
+            };
+            match reply {
+                // Output that doesn't end a line would run into the prompt.
+                Ok(reply) if !reply.is_empty() && !reply.ends_with('\n') => {
+                    writeln!(self.replies, "{}", reply).ok()
+                }
+                Ok(reply) => write!(self.replies, "{}", reply).ok(),
+                Err(e) => writeln!(self.replies, "error: {}", e).ok(),
+            };
+        }
+        false
+    }
+
+    /// `first` and the lines after it until its loops are closed or the input
+    /// ends.
+    fn read_entry(&mut self, mut entry: String) -> String {
+        while unfinished(&entry, self.lenient.get()) {
+            let Some(line) = self.read_line(CONTINUATION) else {
+                break;
+            };
+            entry.push('\n');
+            entry.push_str(line.trim_end());
+        }
+        entry
+    }
+
+    /// The next line, or `None` at the end of input.
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        match &mut self.lines {
+            Lines::Stream(commands) => {
+                write!(self.replies, "{}", prompt).ok();
+                self.replies.flush().ok();
+                let mut line = String::new();
+                if commands.read_line(&mut line).unwrap_or(0) == 0 {
+                    writeln!(self.replies).ok();
+                    return None;
+                }
+                Some(line)
+            }
+            Lines::Editor { editor, history } => loop {
+                match editor.readline(prompt) {
+                    Ok(line) => {
+                        if !line.trim().is_empty() {
+                            editor.add_history_entry(line.as_str()).ok();
+                            if let Some(path) = history {
+                                save_history(editor, path);
+                            }
+                        }
+                        return Some(line);
+                    }
+                    // Ctrl-C abandons the line, as in a shell.
+                    Err(ReadlineError::Interrupted) => continue,
+                    Err(_) => return None,
+                }
+            },
+            Lines::Script(lines) => {
+                let line = lines.pop_front()?;
+                writeln!(self.replies, "{}{}", prompt, line).ok();
+                Some(line)
+            }
+        }
+    }
+
+    /// Carries out a command, returning what to reply or `None` to quit.
+    fn command(&mut self, command: &str) -> Option<Result<String, String>> {
+        let (name, argument) = command
+            .trim()
+            .split_once(char::is_whitespace)
+            .map_or((command.trim(), ""), |(name, argument)| {
+                (name, argument.trim())
+            });
+        let reply = match (name, argument) {
+            ("tape" | "t", _) => Ok(format!("{}\n", tape_window(&self.state, TAPE_WINDOW))),
+            ("reset", _) => {
+                self.reset();
+                Ok(String::new())
+            }
+            ("load", "") | ("save", "") => Err(format!(":{} takes a file", name)),
+            ("load", path) => fs::read_to_string(path)
+                .map_err(|e| format!("reading {}: {}", path, e))
+                .and_then(|code| self.execute(code)),
+            ("save", path) => self.save(Path::new(path)).map(|_| String::new()),
+            ("script", "") => Err(":script takes a file".to_string()),
+            ("script", path) => match fs::read_to_string(path) {
+                Ok(script) => {
+                    let lines = script.lines().map(str::to_string).collect();
+                    let outer = std::mem::replace(&mut self.lines, Lines::Script(lines));
+                    let quit = self.run_lines();
+                    self.lines = outer;
+                    if quit {
+                        return None;
+                    }
+                    Ok(String::new())
+                }
+                Err(e) => Err(format!("reading {}: {}", path, e)),
+            },
+            ("set", setting) => self.set(setting).map(|_| String::new()),
+            ("help" | "h", _) => Ok(HELP.to_string()),
+            ("quit" | "q", _) => return None,
+            (other, _) => Err(format!("unknown command `:{}`; `:help` lists them", other)),
+        };
+        Some(reply)
+    }
+
+    fn set(&mut self, setting: &str) -> Result<(), String> {
+        let (name, value) = setting
+            .split_once(char::is_whitespace)
+            .unwrap_or((setting, ""));
+        match name {
+            "lenient" => self.lenient.set(match value.trim() {
+                "on" | "true" => true,
+                "off" | "false" => false,
+                _ => return Err("lenient is `on` or `off`".to_string()),
+            }),
+            "input" => {
+                let input = unescape(value.trim_start())?;
+                *self.input.lock().unwrap() = input.into();
+                // Drops whatever the tape's reader had buffered of the last.
+                self.attach_input();
+            }
+            _ => return Err(format!("unknown setting `{}`", name)),
+        }
+        Ok(())
+    }
+
+    /// A blank tape reading from the queued input and writing to the output
+    /// buffer.
+    fn reset(&mut self) {
+        self.state = State::new();
+        self.history.clear();
+        self.attach_input();
+        let output = self.output.clone();
+        self.state.output_transforms.push(move |byte| {
+            output.lock().unwrap().push(byte);
+            None
+        });
+    }
+
+    fn attach_input(&mut self) {
+        let input = Queued(self.input.clone());
+        self.state.input.set_reader(input);
+    }
+
+    /// Runs `code` against the tape, returning its output.
+    fn execute(&mut self, code: String) -> Result<String, String> {
+        let lenient = self.lenient.get();
+        Program::parse(&code, lenient).map_err(|e| e.to_string())?;
+        let mut hny = HnyFuck::parse(&code, lenient);
+        hny.state = std::mem::take(&mut self.state);
+        hny.run();
+        self.state = hny.state;
+        self.history.push(code);
+        Ok(
+            String::from_utf8_lossy(&std::mem::take(&mut *self.output.lock().unwrap()))
+                .into_owned(),
+        )
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let mut code = self.history.join("\n");
+        code.push('\n');
+        fs::write(path, code).map_err(|e| format!("writing {}: {}", path.display(), e))
+    }
+}
+
+/// Where history is kept between sessions: `hnyfuck/history` under
+/// `$XDG_STATE_HOME` or else `~/.local/state`.
+pub fn history_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_STATE_HOME", ".local/state")?.join("history"))
+}
+
+/// Appends the newest entry to the history file, creating it if need be.
+/// History is a convenience, so failing to keep it isn't an error.
+fn save_history(editor: &mut Editor<LineHelper, DefaultHistory>, path: &Path) {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).ok();
+    }
+    editor.append_history(path).ok();
+}
+
+/// Whether `code` opens more loops than it has closed, so an entry needs
+/// more lines. Closing more than are open ends it, to be reported as an
+/// error.
+fn unfinished(code: &str, lenient: bool) -> bool {
+    let words = lexer::tokens(code, lenient)
+        .map(|token| token.text)
+        .collect::<Vec<_>>();
+    let mut depth = 0;
+    for pair in words.chunks(2) {
+        match (pair[0], pair.get(1).copied().unwrap_or_default()) {
+            LOOP_START => depth += 1,
+            LOOP_END if depth == 0 => return false,
+            LOOP_END => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+/// Completes keywords and commands at the prompt, and carries entries with
+/// open loops on to the next line.
+#[derive(Helper, Highlighter, Hinter)]
+struct LineHelper {
+    lenient: Rc<Cell<bool>>,
+}
+
+impl Validator for LineHelper {
+    fn validate(&self, context: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = context.input();
+        Ok(
+            match !input.trim_start().starts_with(':') && unfinished(input, self.lenient.get()) {
+                true => ValidationResult::Incomplete,
+                false => ValidationResult::Valid(None),
+            },
+        )
+    }
+}
+
+impl Completer for LineHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos))
+    }
+}
+
+/// Where the word before `pos` starts, and the words it could be.
+fn complete(line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let previous = before[..start].split_whitespace().collect::<Vec<_>>();
+    let candidates: &[&str] = match previous.as_slice() {
+        [] if word.starts_with(':') => &COMMANDS,
+        [":set"] => &SETTINGS,
+        [":set", "lenient"] => &["on", "off"],
+        [first, ..] if first.starts_with(':') => &[],
+        _ => &KEYWORDS,
+    };
+    let matches = candidates
+        .iter()
+        .filter(|candidate| {
+            candidate.len() >= word.len() && candidate[..word.len()].eq_ignore_ascii_case(word)
+        })
+        .map(|candidate| candidate.to_string())
+        .collect();
+    (start, matches)
+}
+
+/// Input queued with `:set input`, which ends whenever the queue is empty.
+struct Queued(Arc<Mutex<VecDeque<u8>>>);
+
+impl Read for Queued {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{brainfuck_to_hny, debugger::test::Shared};
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(complete("Year ha", 7), (5, vec!["Happy".to_string()]));
+        assert_eq!(complete("", 0).1, KEYWORDS);
+        assert_eq!(
+            complete(":s", 2),
+            (
+                0,
+                vec![
+                    ":save".to_string(),
+                    ":script".to_string(),
+                    ":set".to_string()
+                ]
+            )
+        );
+        assert_eq!(complete(":set l", 6), (5, vec!["lenient".to_string()]));
+        assert_eq!(complete(":load N", 7).1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_script() {
+        let path = std::env::temp_dir().join(format!("hnyfuck-script-{}.txt", std::process::id()));
+        let loop_body = brainfuck_to_hny("->+<").output;
+        let script = format!(
+            "Year Happy Year Happy\nHappy Happy\n{}\nNew New\n:tape\n",
+            loop_body
+        );
+        fs::write(&path, script).unwrap();
+        let replies = Shared::default();
+        let commands = format!(":script {}\nHappy Happy\nNew Happy\n", path.display());
+        Repl::new(false)
+            .with_io(io::Cursor::new(commands), replies.clone())
+            .run();
+        fs::remove_file(&path).unwrap();
+
+        let replies = String::from_utf8(replies.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            replies.lines().collect::<Vec<_>>(),
+            [
+                // The first prompt is for the line that starts the script.
+                "hny> hny> Year Happy Year Happy",
+                "hny> Happy Happy",
+                format!("...> {}", loop_body).as_str(),
+                "...> New New",
+                "hny> :tape",
+                "[0] 2",
+                // Input ending mid-entry runs what there is.
+                "hny> ...> ...> ",
+                "error: unmatched loop start at pair 0",
+                "hny> ",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repl() {
+        let bf = |code: &str| brainfuck_to_hny(code).output;
+        let path = std::env::temp_dir().join(format!("hnyfuck-repl-{}.hny", std::process::id()));
+        let commands = format!(
+            "{}\n{}\n:tape\n:set input AB\n{}\n:save {}\n:reset\n:tape\n:set input AB\n:load {}\n:tape\n:bogus\n:quit\n",
+            bf("+++"),
+            bf(">++"),
+            bf(",.,."),
+            path.display(),
+            path.display(),
+        );
+        let replies = Shared::default();
+        let mut repl = Repl::new(false).with_io(io::Cursor::new(commands), replies.clone());
+        repl.run();
+        fs::remove_file(path).unwrap();
+
+        let replies = String::from_utf8(replies.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            replies.split("hny> ").collect::<Vec<_>>(),
+            [
+                "",
+                "",
+                "",
+                "3 [2]\n",
+                "",
+                "AB\n",
+                "",
+                "",
+                "[0]\n",
+                "",
+                // The saved session, run again.
+                "AB\n",
+                "3 [66]\n",
+                "error: unknown command `:bogus`; `:help` lists them\n",
+                "",
+            ]
+        );
+        assert_eq!(repl.state().tape(), &[3, 66]);
+    }
+}
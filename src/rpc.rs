@@ -0,0 +1,353 @@
+//! A JSON-RPC 2.0 interface to the interpreter, for editors and other tools
+//! to drive it as a subprocess. Requests and responses are JSON objects, one
+//! per line.
+//!
+//! Methods:
+//!
+//! - `load {code, lenient?, input?}` compiles a program and pauses before its
+//!   first instruction, replacing any program loaded before. The program reads
+//!   `input` rather than stdin, and what it writes is collected instead of
+//!   printed.
+//! - `step {count?}` runs `count` instructions, 1 by default, and
+//!   `continue` runs to the end. Both answer once the program pauses again or
+//!   finishes, with `status` (`paused` or `finished`), `steps`, the output
+//!   written since the last answer and, when paused, the next instruction's
+//!   `pair`, `line`, `column` and `instruction`.
+//! - `read_tape` gives the `cells` of the tape and the `pointer` into them,
+//!   while paused or after the program finishes.
+
+use std::{
+    io::{BufRead, Cursor, Write},
+    ops::ControlFlow,
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    ir::Program,
+    lexer,
+    middleware::{Middleware, Next},
+    HnyFuck, Instruction, State,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// `step`, `continue` or `read_tape` with nothing to act on.
+const NOT_RUNNING: i64 = -32000;
+/// `load` given a program that doesn't compile.
+const COMPILE_ERROR: i64 = -32001;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Call {
+    /// Missing for notifications, which get no response.
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct LoadParams {
+    code: String,
+    #[serde(default)]
+    lenient: bool,
+    #[serde(default)]
+    input: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+struct StepParams {
+    #[serde(default = "one")]
+    count: u64,
+}
+
+fn one() -> u64 {
+    1
+}
+
+type Reply = Result<Value, (i64, String)>;
+
+struct Connection {
+    requests: Box<dyn BufRead + Send>,
+    replies: Box<dyn Write + Send>,
+}
+
+impl Connection {
+    /// The next well-formed call, answering any malformed requests before it,
+    /// or `None` once the requests run out.
+    fn receive(&mut self) -> Option<Call> {
+        loop {
+            let mut line = String::new();
+            if self.requests.read_line(&mut line).unwrap_or(0) == 0 {
+                return None;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value = match serde_json::from_str::<Value>(&line) {
+                Ok(value) => value,
+                Err(e) => {
+                    self.reply(Some(&Value::Null), Err((PARSE_ERROR, e.to_string())));
+                    continue;
+                }
+            };
+            let id = value.get("id").cloned().unwrap_or(Value::Null);
+            match serde_json::from_value(value) {
+                Ok(call) => return Some(call),
+                Err(e) => self.reply(Some(&id), Err((INVALID_REQUEST, e.to_string()))),
+            }
+        }
+    }
+
+    fn reply(&mut self, id: Option<&Value>, reply: Reply) {
+        let Some(id) = id else {
+            return;
+        };
+        let response = match reply {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": code, "message": message},
+            }),
+        };
+        writeln!(self.replies, "{}", response).ok();
+        self.replies.flush().ok();
+    }
+}
+
+fn params<T: for<'de> Deserialize<'de>>(call: &Call) -> Result<T, (i64, String)> {
+    let params = match &call.params {
+        Value::Null => json!({}),
+        params => params.clone(),
+    };
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+/// Answers requests from `requests` on `replies` until the requests run out.
+pub fn serve(requests: impl BufRead + Send + 'static, replies: impl Write + Send + 'static) {
+    let connection = Arc::new(Mutex::new(Connection {
+        requests: Box::new(requests),
+        replies: Box::new(replies),
+    }));
+    let mut finished: Option<HnyFuck> = None;
+    let mut next = None;
+    while let Some(call) = next.take().or_else(|| connection.lock().unwrap().receive()) {
+        let reply = match call.method.as_str() {
+            "load" => match params::<LoadParams>(&call) {
+                Ok(load) => {
+                    (finished, next) = run(&connection, &call, load);
+                    continue;
+                }
+                Err(e) => Err(e),
+            },
+            "read_tape" => match &finished {
+                Some(hny) => Ok(tape(&hny.state)),
+                None => Err((NOT_RUNNING, "no program is loaded".to_string())),
+            },
+            "step" | "continue" => Err((NOT_RUNNING, "no program is running".to_string())),
+            method => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        };
+        connection.lock().unwrap().reply(call.id.as_ref(), reply);
+    }
+}
+
+/// Runs the program `call` loads, answering requests as it goes, and returns
+/// it once it has finished along with any `load` that cut it short.
+fn run(
+    connection: &Arc<Mutex<Connection>>,
+    call: &Call,
+    load: LoadParams,
+) -> (Option<HnyFuck>, Option<Call>) {
+    if let Err(e) = Program::parse(&load.code, load.lenient) {
+        let reply = Err((COMPILE_ERROR, e.to_string()));
+        connection.lock().unwrap().reply(call.id.as_ref(), reply);
+        return (None, None);
+    }
+    let positions = lexer::pair_positions(&load.code, load.lenient);
+    let pairs = positions.len();
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let session = Arc::new(Mutex::new(Session {
+        connection: connection.clone(),
+        positions,
+        output: output.clone(),
+        remaining: Some(0),
+        steps: 0,
+        waiting: None,
+        interrupted: None,
+    }));
+
+    let mut hny = HnyFuck::parse(&load.code, load.lenient);
+    hny.set_input(Cursor::new(load.input.into_bytes()));
+    hny.add_output_transform(move |byte| {
+        output.lock().unwrap().push(byte);
+        None
+    });
+    hny.add_middleware(Handle(session.clone()));
+    connection
+        .lock()
+        .unwrap()
+        .reply(call.id.as_ref(), Ok(json!({ "pairs": pairs })));
+    hny.run();
+
+    let mut session = session.lock().unwrap();
+    if let Some(id) = session.waiting.take() {
+        let result = json!({
+            "status": "finished",
+            "steps": session.steps,
+            "output": session.take_output(),
+        });
+        connection.lock().unwrap().reply(Some(&id), Ok(result));
+    }
+    (Some(hny), session.interrupted.take())
+}
+
+fn tape(state: &State) -> Value {
+    json!({
+        "cells": state.tape(),
+        "pointer": state.pointer(),
+    })
+}
+
+/// The state of a running program, shared between the middleware pausing it
+/// and [`run`].
+struct Session {
+    connection: Arc<Mutex<Connection>>,
+    /// Line and column of each word pair.
+    positions: Vec<(usize, usize)>,
+    output: Arc<Mutex<Vec<u8>>>,
+    /// Instructions left to run before pausing, if stepping.
+    remaining: Option<u64>,
+    steps: u64,
+    /// The id of the `step` or `continue` to answer when the program next
+    /// pauses or finishes.
+    waiting: Option<Value>,
+    /// A `load` received while paused, which stops the program.
+    interrupted: Option<Call>,
+}
+
+impl Session {
+    fn take_output(&mut self) -> String {
+        String::from_utf8_lossy(&std::mem::take(&mut *self.output.lock().unwrap())).into_owned()
+    }
+
+    /// Answers requests until one resumes the program.
+    fn pause(&mut self, instruction: Instruction, pair: usize, state: &State) -> ControlFlow<()> {
+        if let Some(id) = self.waiting.take() {
+            let (line, column) = self.positions.get(pair).copied().unwrap_or((0, 0));
+            let result = json!({
+                "status": "paused",
+                "steps": self.steps,
+                "output": self.take_output(),
+                "pair": pair,
+                "line": line,
+                "column": column,
+                "instruction": instruction.symbol().to_string(),
+            });
+            self.connection.lock().unwrap().reply(Some(&id), Ok(result));
+        }
+        loop {
+            let Some(call) = self.connection.lock().unwrap().receive() else {
+                return ControlFlow::Break(());
+            };
+            let reply = match call.method.as_str() {
+                "step" => match params::<StepParams>(&call) {
+                    Ok(StepParams { count }) if count > 0 => {
+                        self.remaining = Some(count - 1);
+                        self.waiting = call.id;
+                        return ControlFlow::Continue(());
+                    }
+                    Ok(_) => Err((INVALID_PARAMS, "count must be positive".to_string())),
+                    Err(e) => Err(e),
+                },
+                "continue" => {
+                    self.remaining = None;
+                    self.waiting = call.id;
+                    return ControlFlow::Continue(());
+                }
+                "read_tape" => Ok(tape(state)),
+                "load" => {
+                    self.interrupted = Some(call);
+                    return ControlFlow::Break(());
+                }
+                method => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+            };
+            self.connection
+                .lock()
+                .unwrap()
+                .reply(call.id.as_ref(), reply);
+        }
+    }
+}
+
+struct Handle(Arc<Mutex<Session>>);
+
+impl Middleware for Handle {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+        let mut session = self.0.lock().unwrap();
+        match &mut session.remaining {
+            Some(0) => session.pause(instruction, next.pair(), next.state())?,
+            Some(n) => *n -= 1,
+            None => {}
+        }
+        session.steps += 1;
+        drop(session);
+        next.run(instruction)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{brainfuck_to_hny, debugger::test::Shared};
+
+    fn replies(requests: &[Value]) -> Vec<Value> {
+        let requests = requests
+            .iter()
+            .map(|request| format!("{}\n", request))
+            .collect::<String>();
+        let replies = Shared::default();
+        serve(Cursor::new(requests), replies.clone());
+        let replies = String::from_utf8(replies.0.lock().unwrap().clone()).unwrap();
+        replies
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["result"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_rpc() {
+        let code = brainfuck_to_hny(",.>+").output;
+        let call = |id: u64, method: &str, params: Value| json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let replies = replies(&[
+            call(1, "load", json!({"code": code, "input": "A"})),
+            call(2, "step", json!({"count": 2})),
+            call(3, "read_tape", Value::Null),
+            call(4, "continue", Value::Null),
+            call(5, "read_tape", Value::Null),
+        ]);
+        assert_eq!(
+            replies,
+            [
+                json!({"pairs": 4}),
+                json!({
+                    "status": "paused",
+                    "steps": 2,
+                    "output": "A",
+                    "pair": 2,
+                    "line": 1,
+                    "column": 20,
+                    "instruction": ">",
+                }),
+                json!({"cells": [65], "pointer": 0}),
+                json!({"status": "finished", "steps": 4, "output": ""}),
+                json!({"cells": [65, 1], "pointer": 1}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Locks the current process down before it runs programs it doesn't trust,
+//! so that even an interpreter bug an attacker could exploit leaves them
+//! unable to reach the filesystem or the network.
+//!
+//! On Linux, [`enter`] caps the process's memory, CPU time and file sizes,
+//! then applies a Landlock ruleset that grants no filesystem or network access
+//! where the kernel supports one, and finally a seccomp filter that fails
+//! every system call but the few needed to compute and to talk over file
+//! descriptors already open, such as stdin and stdout.
+
+use std::io;
+
+/// Resources a sandboxed process may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quotas {
+    /// Bytes of memory the process may map.
+    pub memory: u64,
+    /// Seconds of CPU time, after which the process is killed.
+    pub cpu_seconds: u64,
+}
+
+pub const DEFAULT_QUOTAS: Quotas = Quotas {
+    memory: 1 << 30,
+    cpu_seconds: 60,
+};
+
+/// Sandboxes the current process for the rest of its life. Nothing undoes
+/// it, and it applies to children too.
+#[cfg(target_os = "linux")]
+pub fn enter(quotas: &Quotas) -> io::Result<()> {
+    // Without this, a process that isn't root can't install either filter.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    linux::restrict_access()?;
+    // After Landlock, which needs a file descriptor past the limit.
+    linux::limit_resources(quotas)?;
+    linux::filter_syscalls()
+}
+
+/// Sandboxes the current process for the rest of its life, which is only
+/// possible on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_: &Quotas) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sandboxing is only supported on Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+
+    use super::Quotas;
+
+    fn check(result: libc::c_long) -> io::Result<libc::c_long> {
+        match result {
+            -1 => Err(io::Error::last_os_error()),
+            result => Ok(result),
+        }
+    }
+
+    pub(super) fn limit_resources(quotas: &Quotas) -> io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_AS, quotas.memory),
+            (libc::RLIMIT_CPU, quotas.cpu_seconds),
+            (libc::RLIMIT_FSIZE, 0),
+            (libc::RLIMIT_CORE, 0),
+            (libc::RLIMIT_NPROC, 0),
+            // Only stdin, stdout and stderr.
+            (libc::RLIMIT_NOFILE, 3),
+        ];
+        for (resource, value) in limits {
+            let limit = libc::rlimit {
+                rlim_cur: value,
+                rlim_max: value,
+            };
+            check(unsafe { libc::setrlimit(resource, &limit) }.into())?;
+        }
+        Ok(())
+    }
+
+    /// `struct landlock_ruleset_attr`.
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+        handled_access_net: u64,
+        scoped: u64,
+    }
+
+    const CREATE_RULESET_VERSION: u32 = 1;
+
+    /// Forbids all filesystem and network access the kernel's Landlock
+    /// version can restrict, doing nothing on kernels without Landlock.
+    pub(super) fn restrict_access() -> io::Result<()> {
+        let null = std::ptr::null::<RulesetAttr>();
+        let abi = match check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                null,
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        }) {
+            Ok(abi) => abi,
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EOPNOTSUPP)) => {
+                return Ok(())
+            }
+            Err(e) => return Err(e),
+        };
+        // Each version can restrict more kinds of access than the last.
+        let fs_rights = match abi {
+            1 => 13,
+            2 => 14,
+            3 | 4 => 15,
+            _ => 16,
+        };
+        let attr = RulesetAttr {
+            handled_access_fs: (1 << fs_rights) - 1,
+            handled_access_net: if abi >= 4 { 0b11 } else { 0 },
+            scoped: if abi >= 6 { 0b11 } else { 0 },
+        };
+        let ruleset = check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        })?;
+        let restricted =
+            check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) });
+        unsafe { libc::close(ruleset as libc::c_int) };
+        restricted.map(drop)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    /// System calls the process may still make: reading and writing the file
+    /// descriptors it has, managing memory and exiting.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const ALLOWED: &[libc::c_long] = &[
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_writev,
+        libc::SYS_close,
+        libc::SYS_brk,
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        libc::SYS_futex,
+        libc::SYS_sched_yield,
+        libc::SYS_clock_gettime,
+        libc::SYS_getrandom,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_sigaltstack,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+    ];
+
+    /// Makes every system call outside [`ALLOWED`] fail with `EPERM`.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub(super) fn filter_syscalls() -> io::Result<()> {
+        let statement = |code: u32, k: u32| libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        // Skips `jt` statements if the loaded value is `k`, else `jf`.
+        let jump = |k: u32, jt: u8, jf: u8| libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        };
+        // Offsets into `struct seccomp_data`.
+        let (nr, arch) = (0, 4);
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let ret = libc::BPF_RET | libc::BPF_K;
+
+        // System call numbers differ between architectures, so calls made as
+        // another one are killed rather than matched against the wrong list.
+        let mut filter = vec![
+            statement(load, arch),
+            jump(AUDIT_ARCH, 1, 0),
+            statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(load, nr),
+        ];
+        for &syscall in ALLOWED {
+            filter.push(jump(syscall as u32, 0, 1));
+            filter.push(statement(ret, libc::SECCOMP_RET_ALLOW));
+        }
+        filter.push(statement(ret, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        let result = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            )
+        };
+        check(result.into()).map(drop)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub(super) fn filter_syscalls() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "system call filtering is only supported on x86_64 and aarch64",
+        ))
+    }
+}
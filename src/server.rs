@@ -0,0 +1,1006 @@
+//! An HTTP API for running programs, to back web playgrounds.
+//!
+//! `POST /run` takes a JSON body like
+//! `{"code": "...", "input": "...", "lenient": false, "limits": {"steps": 1000}}`,
+//! where everything but `code` is optional, and answers with the program's
+//! output, why it stopped and how much it used. Requests can lower the
+//! server's limits but not raise them. With `"input_encoding": "base64"` the
+//! input is read as base64, and with `"output_encoding": "base64"` the output
+//! is given in base64, for programs that read or write binary data.
+//!
+//! `GET /session` opens a WebSocket for running a program interactively. The
+//! client's first message is a request as for `/run`, though its output
+//! encoding is ignored. Every message after it is input, with an empty
+//! message ending the input. The program's output comes back in binary
+//! messages, a line at a time and whenever the program waits for input, and
+//! a last text message gives what `/run` would besides the output, or an
+//! error. A session that has gone on too long, or whose client has been
+//! silent too long, is treated as having reached the end of its input, and
+//! stops holding its place among the programs running.
+//!
+//! Programs run on the thread answering the request, or with
+//! [`Runner::Worker`] in a child process per request, which can be
+//! [sandboxed](crate::sandbox). The server and its workers talk in frames of a
+//! kind byte, a little-endian `u32` length and that many bytes: the server
+//! sends the job, the worker sends output and asks for input as the program
+//! needs it, and the worker sends the response it would have given.
+//!
+//! [`Config`] can cap how often each client may make requests and how many
+//! programs may run at once, which the server refuses past with `429` and
+//! `503` respectively, and can keep an [audit log](crate::audit) of the
+//! programs run.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    error::Error,
+    io::{self, BufReader, Read, Write},
+    net::{IpAddr, ToSocketAddrs},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+use crate::{
+    audit::{self, Audit, AuditLog, Record},
+    encoding::Encoding,
+    ir::Program,
+    limits::{self, Execution, Exit, Limits},
+    sandbox::{self, Quotas},
+};
+
+/// Request bodies are cut off past this many bytes.
+pub const MAX_BODY: u64 = 1 << 20;
+
+/// Output sent over a session is held back until it reaches this many bytes,
+/// ends a line or the program waits for input.
+const SESSION_BUFFER: usize = 4096;
+
+/// Limits on a run the server applies when a request doesn't ask for less.
+pub const DEFAULT_LIMITS: Limits = Limits {
+    steps: 10_000_000,
+    cells: 1 << 16,
+    output: 1 << 20,
+};
+
+/// How long a session waits for the client's next message unless the server
+/// is told otherwise.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Clients the rate limiter remembers before it forgets those that have
+/// waited long enough to be back at their full allowance.
+const MAX_CLIENTS: usize = 10_000;
+
+/// Frames from workers longer than this are refused.
+const MAX_FRAME: usize = 1 << 26;
+
+// Kinds of frame between the server and a worker.
+const JOB: u8 = b'j';
+/// Asks for input when the worker sends it, and carries input, or nothing at
+/// the end of input, when the server does.
+const INPUT: u8 = b'i';
+const OUTPUT: u8 = b'o';
+/// The response: a little-endian `u16` status code and the body.
+const DONE: u8 = b'd';
+
+/// How the server runs programs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Runner {
+    /// On the thread answering the request.
+    InProcess,
+    /// In a new process for each request, started by running `program` with
+    /// `args`, which must call [`worker`].
+    Worker { program: PathBuf, args: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The most a request may use.
+    pub limits: Limits,
+    pub runner: Runner,
+    /// Most requests a client may make a minute, in bursts of up to as many.
+    /// IPv6 clients are counted by /64, which is often one machine's.
+    pub rate_limit: Option<u32>,
+    /// Most programs that may run at once.
+    pub max_running: Option<usize>,
+    /// Longest a session may stay open.
+    pub session_timeout: Duration,
+    /// Longest a session waits for the client's next message.
+    pub idle_timeout: Duration,
+    /// Where to record each program run.
+    pub audit: Option<Audit>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            limits: DEFAULT_LIMITS,
+            runner: Runner::InProcess,
+            rate_limit: None,
+            max_running: None,
+            session_timeout: Duration::from_secs(sandbox::DEFAULT_QUOTAS.cpu_seconds),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            audit: None,
+        }
+    }
+}
+
+/// Settings for `hnyfuck serve`, as read from its config file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    pub host: String,
+    pub port: u16,
+    pub max_steps: u64,
+    pub max_cells: usize,
+    pub max_output_bytes: usize,
+    /// Requests a minute per client.
+    pub rate_limit: Option<u32>,
+    pub max_running: Option<usize>,
+    /// Bytes of memory each worker may map.
+    pub max_memory: u64,
+    pub max_cpu_seconds: u64,
+    /// Where to keep the audit log, if anywhere.
+    pub audit_log: Option<PathBuf>,
+    /// Bytes the audit log may grow to before it is rotated.
+    pub audit_max_bytes: u64,
+    /// Rotated audit logs kept.
+    pub audit_keep: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            max_steps: DEFAULT_LIMITS.steps,
+            max_cells: DEFAULT_LIMITS.cells,
+            max_output_bytes: DEFAULT_LIMITS.output,
+            rate_limit: None,
+            max_running: None,
+            max_memory: sandbox::DEFAULT_QUOTAS.memory,
+            max_cpu_seconds: sandbox::DEFAULT_QUOTAS.cpu_seconds,
+            audit_log: None,
+            audit_max_bytes: Audit::new("").max_bytes,
+            audit_keep: Audit::new("").keep,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads settings from TOML, e.g. `rate-limit = 60`. Missing keys keep
+    /// their defaults.
+    pub fn from_toml(input: &str) -> Result<Settings, toml::de::Error> {
+        toml::from_str(input)
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            steps: self.max_steps,
+            cells: self.max_cells,
+            output: self.max_output_bytes,
+        }
+    }
+
+    pub fn audit(&self) -> Option<Audit> {
+        self.audit_log.as_ref().map(|path| Audit {
+            path: path.clone(),
+            max_bytes: self.audit_max_bytes,
+            keep: self.audit_keep,
+        })
+    }
+
+    pub fn quotas(&self) -> Quotas {
+        Quotas {
+            memory: self.max_memory,
+            cpu_seconds: self.max_cpu_seconds,
+        }
+    }
+}
+
+/// A token bucket per client, each holding up to a minute's requests and
+/// refilling at the same rate.
+#[derive(Debug)]
+struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32) -> RateLimiter {
+        RateLimiter {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a request from `ip`'s allowance at `now`, or says how long until
+    /// it has one.
+    fn allow(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = self.per_minute as f64;
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * capacity / 60.0).min(capacity)
+        };
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_CLIENTS {
+            buckets.retain(|_, bucket| refill(bucket) < capacity);
+        }
+        let bucket = buckets.entry(client(ip)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * 60.0 / capacity,
+            ))
+        }
+    }
+}
+
+/// The address requests from `ip` are counted against.
+fn client(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6((v6.to_bits() & !(u64::MAX as u128)).into()),
+        },
+    }
+}
+
+/// Counts the programs running against [`Config::max_running`].
+#[derive(Debug, Default)]
+struct Running(AtomicUsize);
+
+/// A place among the programs running, given up when dropped.
+struct Permit<'a>(&'a Running);
+
+impl Running {
+    fn acquire(&self, max: usize) -> Option<Permit<'_>> {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| {
+                (running < max).then_some(running + 1)
+            })
+            .ok()
+            .map(|_| Permit(self))
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// What the server shares between the threads answering requests.
+struct Shared {
+    config: Config,
+    limiter: Option<RateLimiter>,
+    running: Running,
+    audit: Option<AuditLog>,
+}
+
+impl Shared {
+    fn new(config: Config) -> io::Result<Shared> {
+        Ok(Shared {
+            limiter: config.rate_limit.map(RateLimiter::new),
+            running: Running::default(),
+            audit: config.audit.clone().map(AuditLog::open).transpose()?,
+            config,
+        })
+    }
+
+    /// Lets a request for `/run` or `/session` through, or returns the status,
+    /// seconds to wait and error to refuse it with.
+    fn admit(&self, request: &Request) -> Result<Option<Permit<'_>>, (u16, u64, String)> {
+        if let (Some(limiter), Some(addr)) = (&self.limiter, request.remote_addr()) {
+            if let Err(wait) = limiter.allow(addr.ip(), Instant::now()) {
+                let seconds = wait.as_secs_f64().ceil() as u64;
+                return Err((429, seconds, "too many requests".to_string()));
+            }
+        }
+        match self.config.max_running {
+            Some(max) => match self.running.acquire(max) {
+                Some(permit) => Ok(Some(permit)),
+                None => Err((503, 1, "too many programs are running".to_string())),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// A request for a worker to answer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Job {
+    /// A `/run` request body, or the first message of a session.
+    body: String,
+    limits: Limits,
+    interactive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct RunRequest {
+    code: String,
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    input_encoding: Encoding,
+    #[serde(default)]
+    output_encoding: Encoding,
+    #[serde(default)]
+    lenient: bool,
+    #[serde(default)]
+    limits: RequestLimits,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+struct RequestLimits {
+    steps: Option<u64>,
+    cells: Option<usize>,
+    output: Option<usize>,
+}
+
+impl RequestLimits {
+    /// The limits asked for, capped at `limits`.
+    fn within(self, limits: &Limits) -> Limits {
+        limits.min(Limits {
+            steps: self.steps.unwrap_or(u64::MAX),
+            cells: self.cells.unwrap_or(usize::MAX),
+            output: self.output.unwrap_or(usize::MAX),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct RunResponse {
+    /// The output in the encoding asked for, with bytes that aren't UTF-8
+    /// replaced if it is raw.
+    output: String,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Stats {
+    exit: Exit,
+    steps: u64,
+    cells: usize,
+    duration_ms: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Answers requests on `addr` until the process is stopped, answering each
+/// on its own thread.
+pub fn serve(addr: impl ToSocketAddrs, config: Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let shared = Shared::new(config)?;
+    listen(Server::http(addr)?, shared);
+    Ok(())
+}
+
+fn listen(server: Server, shared: Shared) {
+    let shared = Arc::new(shared);
+    for request in server.incoming_requests() {
+        let shared = shared.clone();
+        std::thread::spawn(move || respond(request, &shared));
+    }
+}
+
+fn respond(mut request: Request, shared: &Shared) {
+    let _permit = match request.url() {
+        "/run" | "/session" => match shared.admit(&request) {
+            Ok(permit) => permit,
+            Err((status, seconds, message)) => {
+                let (status, body) = error(status, message);
+                let response = json_response(body, status)
+                    .with_header(header("Retry-After", &seconds.to_string()));
+                request.respond(response).ok();
+                return;
+            }
+        },
+        _ => None,
+    };
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Post, "/run") => {
+            let mut body = String::new();
+            match request.as_reader().take(MAX_BODY).read_to_string(&mut body) {
+                Ok(_) => {
+                    let client = request.remote_addr().map(|addr| addr.ip());
+                    dispatch(shared, client, body, false, &mut ())
+                }
+                Err(e) => error(400, e),
+            }
+        }
+        (Method::Get, "/session") => match websocket_key(&request) {
+            Some(key) => return session(request, &key, shared),
+            None => error(426, "connect with a WebSocket"),
+        },
+        (_, "/run") => error(405, "use POST"),
+        (_, "/session") => error(405, "use GET"),
+        _ => error(404, "not found"),
+    };
+    request.respond(json_response(body, status)).ok();
+}
+
+fn json_response(body: String, status: u16) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+        .with_header(header("Access-Control-Allow-Origin", "*"))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("headers are valid")
+}
+
+fn websocket_key(request: &Request) -> Option<String> {
+    let header = |name: &str| {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    };
+    match header("Upgrade") {
+        Some(upgrade) if upgrade.eq_ignore_ascii_case("websocket") => {
+            header("Sec-WebSocket-Key").map(str::to_string)
+        }
+        _ => None,
+    }
+}
+
+/// Parses a request body, decodes its input and compiles its program, or
+/// returns the response to give instead.
+fn load(body: &str) -> Result<(RunRequest, Vec<u8>, Program), (u16, String)> {
+    let request = serde_json::from_str::<RunRequest>(body).map_err(|e| error(400, e))?;
+    let input = request
+        .input_encoding
+        .decode(request.input.as_bytes())
+        .map_err(|e| error(400, format!("bad input: {}", e)))?;
+    let mut program = Program::parse(&request.code, request.lenient).map_err(|e| error(422, e))?;
+    program.optimize();
+    Ok((request, input, program))
+}
+
+/// Runs the program in a `/run` request body, returning the status code and
+/// response body.
+fn run(body: &str, limits: &Limits) -> (u16, String) {
+    let (request, input, program) = match load(body) {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let limits = request.limits.within(limits);
+    let started = Instant::now();
+    let run = limits::execute(&program, &input, &limits);
+    let response = RunResponse {
+        output: request.output_encoding.encode(&run.output),
+        stats: Stats::new(&run, started),
+    };
+    (
+        200,
+        serde_json::to_string(&response).expect("responses serialize"),
+    )
+}
+
+impl Stats {
+    fn new(run: &Execution, started: Instant) -> Stats {
+        Stats {
+            exit: run.exit,
+            steps: run.steps,
+            cells: run.tape.len(),
+            duration_ms: millis(started.elapsed()),
+        }
+    }
+}
+
+/// Where an interactive run gets its input and sends its output.
+trait Terminal {
+    /// The next input, or `None` once input has ended.
+    fn read(&mut self) -> Option<Vec<u8>>;
+    fn write(&mut self, output: Vec<u8>);
+}
+
+/// No input, and output is thrown away.
+impl Terminal for () {
+    fn read(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn write(&mut self, _: Vec<u8>) {}
+}
+
+/// Answers `body` from `client`, interactively through `terminal` or else as
+/// a `/run` request, in a worker if the server uses them, and records it in
+/// the audit log if there is one.
+fn dispatch(
+    shared: &Shared,
+    client: Option<IpAddr>,
+    body: String,
+    interactive: bool,
+    terminal: &mut impl Terminal,
+) -> (u16, String) {
+    let started = Instant::now();
+    let job = Job {
+        body,
+        limits: shared.config.limits,
+        interactive,
+    };
+    let (status, response) = execute(&shared.config, &job, terminal);
+    if let Some(log) = &shared.audit {
+        let request = serde_json::from_str::<RunRequest>(&job.body).ok();
+        let response = serde_json::from_str::<serde_json::Value>(&response).unwrap_or_default();
+        let record = Record {
+            timestamp_ms: audit::now_ms(),
+            client,
+            route: if interactive { "session" } else { "run" },
+            source_hash: request
+                .as_ref()
+                .map(|request| audit::source_hash(&request.code)),
+            limits: request.map(|request| request.limits.within(&job.limits)),
+            status,
+            exit: serde_json::from_value(response["exit"].clone()).ok(),
+            steps: response["steps"].as_u64(),
+            duration_ms: millis(started.elapsed()),
+            error: response["error"].as_str().map(str::to_string),
+        };
+        // A full disk shouldn't stop the server answering.
+        log.write(&record).ok();
+    }
+    (status, response)
+}
+
+fn execute(config: &Config, job: &Job, terminal: &mut impl Terminal) -> (u16, String) {
+    match &config.runner {
+        Runner::InProcess => answer(job, terminal),
+        Runner::Worker { program, args } => {
+            let child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn();
+            let mut child = match child {
+                Ok(child) => child,
+                Err(e) => return error(500, format!("Error starting a worker: {}", e)),
+            };
+            let response = relay(&mut child, job, terminal);
+            child.kill().ok();
+            child.wait().ok();
+            response.unwrap_or_else(|e| error(500, format!("the worker failed: {}", e)))
+        }
+    }
+}
+
+fn answer(job: &Job, terminal: &mut impl Terminal) -> (u16, String) {
+    match job.interactive {
+        true => interact(&job.body, &job.limits, terminal),
+        false => run(&job.body, &job.limits),
+    }
+}
+
+/// Sends `job` to a worker and passes input and output between it and
+/// `terminal` until it responds.
+fn relay(child: &mut Child, job: &Job, terminal: &mut impl Terminal) -> io::Result<(u16, String)> {
+    let (Some(mut to), Some(from)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(io::Error::other("the worker's stdio is not piped"));
+    };
+    let mut from = BufReader::new(from);
+    write_frame(&mut to, JOB, &serde_json::to_vec(job)?)?;
+    loop {
+        match read_frame(&mut from)? {
+            (OUTPUT, output) => terminal.write(output),
+            (INPUT, _) => write_frame(&mut to, INPUT, &terminal.read().unwrap_or_default())?,
+            (DONE, response) if response.len() >= 2 => {
+                let status = u16::from_le_bytes([response[0], response[1]]);
+                return Ok((status, String::from_utf8_lossy(&response[2..]).into_owned()));
+            }
+            (kind, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected frame of kind {}", kind),
+                ))
+            }
+        }
+    }
+}
+
+/// Answers a job from a server using [`Runner::Worker`], reading it from
+/// stdin and responding on stdout. Given `quotas`, the process is
+/// [sandboxed](crate::sandbox) with them before it reads anything.
+pub fn worker(quotas: Option<&Quotas>) -> io::Result<()> {
+    if let Some(quotas) = quotas {
+        sandbox::enter(quotas)?;
+    }
+    let mut pipe = Pipe {
+        from: io::stdin().lock(),
+        to: io::stdout().lock(),
+    };
+    let job = match read_frame(&mut pipe.from)? {
+        (JOB, job) => serde_json::from_slice::<Job>(&job)?,
+        (kind, _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected a job, not a frame of kind {}", kind),
+            ))
+        }
+    };
+    let (status, body) = answer(&job, &mut pipe);
+    let mut response = status.to_le_bytes().to_vec();
+    response.extend_from_slice(body.as_bytes());
+    write_frame(&mut pipe.to, DONE, &response)
+}
+
+/// A worker's end of its pipes to the server.
+struct Pipe<R, W> {
+    from: R,
+    to: W,
+}
+
+impl<R: Read, W: Write> Terminal for Pipe<R, W> {
+    fn read(&mut self) -> Option<Vec<u8>> {
+        write_frame(&mut self.to, INPUT, &[]).ok()?;
+        match read_frame(&mut self.from).ok()? {
+            (INPUT, input) if !input.is_empty() => Some(input),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, output: Vec<u8>) {
+        write_frame(&mut self.to, OUTPUT, &output).ok();
+    }
+}
+
+fn write_frame(to: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    to.write_all(&[kind])?;
+    to.write_all(&(payload.len() as u32).to_le_bytes())?;
+    to.write_all(payload)?;
+    to.flush()
+}
+
+fn read_frame(from: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 5];
+    from.read_exact(&mut header)?;
+    let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+    }
+    let mut payload = vec![0; length];
+    from.read_exact(&mut payload)?;
+    Ok((header[0], payload))
+}
+
+/// Runs the program in the first message of a session, returning the status
+/// code and the last message.
+fn interact(body: &str, limits: &Limits, terminal: &mut impl Terminal) -> (u16, String) {
+    let (request, input, program) = match load(body) {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let limits = request.limits.within(limits);
+    let terminal = RefCell::new(terminal);
+    let mut pending = VecDeque::from(input);
+    let mut ended = false;
+    let buffer = RefCell::new(Vec::new());
+    let flush = |buffer: &mut Vec<u8>| {
+        if !buffer.is_empty() {
+            terminal.borrow_mut().write(std::mem::take(buffer));
+        }
+    };
+    let input = std::iter::from_fn(|| loop {
+        if let Some(byte) = pending.pop_front() {
+            return Some(byte);
+        }
+        if ended {
+            return None;
+        }
+        flush(&mut buffer.borrow_mut());
+        match terminal.borrow_mut().read() {
+            Some(input) => pending.extend(input),
+            None => ended = true,
+        }
+    });
+    let output = |byte| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.push(byte);
+        if byte == b'\n' || buffer.len() >= SESSION_BUFFER {
+            flush(&mut buffer);
+        }
+    };
+
+    let started = Instant::now();
+    let run = limits::stream(&program, input, output, &limits);
+    flush(&mut buffer.borrow_mut());
+    (
+        200,
+        serde_json::to_string(&Stats::new(&run, started)).expect("responses serialize"),
+    )
+}
+
+type Socket = WebSocket<Box<dyn tiny_http::ReadWrite + Send>>;
+
+/// Runs a program interactively over a WebSocket, as the module docs
+/// describe.
+fn session(request: Request, key: &str, shared: &Shared) {
+    let client = request.remote_addr().map(|addr| addr.ip());
+    let accept = derive_accept_key(key.as_bytes());
+    let response = Response::empty(101)
+        .with_header(header("Upgrade", "websocket"))
+        .with_header(header("Connection", "Upgrade"))
+        .with_header(header("Sec-WebSocket-Accept", &accept));
+    let stream = request.upgrade("websocket", response);
+    let mut connection = Connection {
+        socket: Arc::new(Mutex::new(WebSocket::from_raw_socket(
+            stream,
+            Role::Server,
+            None,
+        ))),
+        deadline: Instant::now() + shared.config.session_timeout,
+        idle_timeout: shared.config.idle_timeout,
+        expired: false,
+    };
+
+    let Some(body) = connection.receive() else {
+        return;
+    };
+    let body = String::from_utf8_lossy(&body).into_owned();
+    let (_, last) = dispatch(shared, client, body, true, &mut connection);
+    connection.send(Message::text(last));
+    if !connection.expired {
+        let mut socket = connection.lock();
+        socket.close(None).ok();
+        socket.flush().ok();
+    }
+}
+
+/// A session's WebSocket, read on a thread of its own so that waiting for
+/// the client can time out.
+struct Connection {
+    socket: Arc<Mutex<Socket>>,
+    /// When the session ends, whether or not the program has.
+    deadline: Instant,
+    idle_timeout: Duration,
+    /// Whether waiting for the client timed out. The socket is then left to
+    /// the thread still reading it, which closes it once the client sends
+    /// something or goes.
+    expired: bool,
+}
+
+impl Connection {
+    /// The next text or binary message from the client, or `None` once it
+    /// has gone or kept the session waiting too long.
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if self.expired || left.is_zero() {
+            self.expired = true;
+            return None;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let socket = self.socket.clone();
+        std::thread::spawn(move || {
+            let message = receive(&mut socket.lock().unwrap_or_else(PoisonError::into_inner));
+            sender.send(message).ok();
+        });
+        match receiver.recv_timeout(self.idle_timeout.min(left)) {
+            Ok(message) => message,
+            Err(_) => {
+                self.expired = true;
+                None
+            }
+        }
+    }
+
+    fn send(&mut self, message: Message) {
+        if !self.expired {
+            self.lock().send(message).ok();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Socket> {
+        self.socket.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The next text or binary message from the client, or `None` once it has
+/// gone.
+fn receive(socket: &mut Socket) -> Option<Vec<u8>> {
+    loop {
+        match socket.read().ok()? {
+            Message::Text(text) => return Some(text.as_bytes().to_vec()),
+            Message::Binary(data) => return Some(data.to_vec()),
+            Message::Close(_) => return None,
+            _ => continue,
+        }
+    }
+}
+
+impl Terminal for Connection {
+    /// An empty message ends the input, as does the client going or taking
+    /// too long.
+    fn read(&mut self) -> Option<Vec<u8>> {
+        self.receive().filter(|input| !input.is_empty())
+    }
+
+    fn write(&mut self, output: Vec<u8>) {
+        self.send(Message::binary(output));
+    }
+}
+
+fn error(status: u16, error: impl ToString) -> (u16, String) {
+    let response = ErrorResponse {
+        error: error.to_string(),
+    };
+    (
+        status,
+        serde_json::to_string(&response).expect("responses serialize"),
+    )
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    fn post(body: Value) -> (u16, Value) {
+        let (status, body) = run(&body.to_string(), &DEFAULT_LIMITS);
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        assert_eq!(limiter.allow(ip, start), Ok(()));
+        assert_eq!(limiter.allow(ip, start), Ok(()));
+        assert_eq!(limiter.allow(ip, start), Err(Duration::from_secs(30)));
+        assert_eq!(limiter.allow(IpAddr::from([192, 0, 2, 2]), start), Ok(()));
+        assert_eq!(limiter.allow(ip, start + Duration::from_secs(30)), Ok(()));
+
+        // Addresses in one /64 share an allowance.
+        let v6 = |last: u16| IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, last]);
+        assert_eq!(limiter.allow(v6(1), start), Ok(()));
+        assert_eq!(limiter.allow(v6(2), start), Ok(()));
+        assert!(limiter.allow(v6(3), start).is_err());
+    }
+
+    #[test]
+    fn test_max_running() {
+        let running = Running::default();
+        let first = running.acquire(2).unwrap();
+        let _second = running.acquire(2).unwrap();
+        assert!(running.acquire(2).is_none());
+        drop(first);
+        assert!(running.acquire(2).is_some());
+    }
+
+    #[test]
+    fn test_run() {
+        let code = brainfuck_to_hny(",[.,]").output;
+        let (status, body) = post(serde_json::json!({"code": code, "input": "hi\0"}));
+        assert_eq!(status, 200);
+        assert_eq!(body["output"], "hi");
+        assert_eq!(body["exit"], "finished");
+        assert_eq!(body["cells"], 1);
+
+        let binary = serde_json::json!({
+            "code": code,
+            "input": "/4AA",
+            "input_encoding": "base64",
+            "output_encoding": "base64",
+        });
+        assert_eq!(post(binary).1["output"], "/4A=");
+        let bad = serde_json::json!({"code": code, "input": "!", "input_encoding": "base64"});
+        assert_eq!(post(bad).0, 400);
+
+        let code = brainfuck_to_hny("+[]").output;
+        let (status, body) = post(serde_json::json!({"code": code, "limits": {"steps": 50}}));
+        assert_eq!(status, 200);
+        assert_eq!(body["exit"], "step_limit");
+        assert_eq!(body["steps"], 50);
+
+        let (status, body) = post(serde_json::json!({"code": "Happy Happy"}));
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "unmatched loop start at pair 0");
+        assert_eq!(post(serde_json::json!({"input": ""})).0, 400);
+    }
+
+    #[test]
+    fn test_session() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        std::thread::spawn(move || listen(server, Shared::new(Config::default()).unwrap()));
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}/session", addr)).unwrap();
+
+        let code = brainfuck_to_hny(",[.,]").output;
+        let start = serde_json::json!({"code": code, "input": "a"});
+        socket.send(Message::text(start.to_string())).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::binary(b"a".to_vec()));
+        socket.send(Message::binary(b"bc".to_vec())).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::binary(b"bc".to_vec()));
+        socket.send(Message::binary(b"\0".to_vec())).unwrap();
+        let Message::Text(stats) = socket.read().unwrap() else {
+            panic!("expected the stats");
+        };
+        let stats: Value = serde_json::from_str(&stats).unwrap();
+        assert_eq!(stats["exit"], "finished");
+        assert_eq!(stats.get("output"), None);
+    }
+
+    /// A session that waits too long for its client, or runs too long,
+    /// makes way for another.
+    #[test]
+    fn test_session_timeout() {
+        let serve = |config: Config| {
+            let server = Server::http("127.0.0.1:0").unwrap();
+            let url = format!("ws://{}/session", server.server_addr().to_ip().unwrap());
+            std::thread::spawn(move || listen(server, Shared::new(config).unwrap()));
+            url
+        };
+        let code = brainfuck_to_hny(",[.,]").output;
+        let start = Message::text(serde_json::json!({"code": code}).to_string());
+        let config = Config {
+            max_running: Some(1),
+            idle_timeout: Duration::from_millis(200),
+            ..Config::default()
+        };
+
+        // The first client never sends its request.
+        let url = serve(config.clone());
+        let (_idle, _) = tungstenite::connect(&url).unwrap();
+        assert!(tungstenite::connect(&url).is_err());
+        std::thread::sleep(Duration::from_millis(400));
+        let (mut socket, _) = tungstenite::connect(&url).unwrap();
+        socket.send(start.clone()).unwrap();
+        socket.send(Message::binary(b"a\0".to_vec())).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::binary(b"a".to_vec()));
+        assert!(matches!(socket.read().unwrap(), Message::Text(_)));
+
+        // The next keeps sending input, never ending it, and the server
+        // hangs up on it.
+        let url = serve(Config {
+            session_timeout: Duration::from_millis(1000),
+            ..config
+        });
+        let (mut busy, _) = tungstenite::connect(&url).unwrap();
+        busy.send(start).unwrap();
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(1300) {
+            busy.send(Message::binary(b"b".to_vec())).ok();
+            let elapsed = started.elapsed();
+            if elapsed > Duration::from_millis(300) && elapsed < Duration::from_millis(700) {
+                // Past the idle timeout, but the session goes on.
+                assert!(tungstenite::connect(&url).is_err());
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(tungstenite::connect(&url).is_ok());
+    }
+}
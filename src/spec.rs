@@ -0,0 +1,208 @@
+//! The language as data: the keywords, what each pair of them does, the
+//! extension functions and the rules the interpreter follows where
+//! brainfuck dialects differ, taken from the tables the interpreter itself
+//! uses so tools built on it can't drift from it.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    extension::{Function, FUNCTIONS},
+    lexer::KEYWORDS,
+    Instruction, EXTENSION,
+};
+
+/// Everything a tool needs to read or write the language.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Spec {
+    pub version: &'static str,
+    pub keywords: [&'static str; 3],
+    pub instructions: Vec<InstructionSpec>,
+    /// The pair calling the extension function numbered by the current cell.
+    pub extension_call: [&'static str; 2],
+    pub functions: Vec<FunctionSpec>,
+    pub semantics: Semantics,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstructionSpec {
+    pub name: &'static str,
+    pub words: [&'static str; 2],
+    /// The brainfuck instruction it stands for.
+    pub brainfuck: char,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionSpec {
+    pub number: u8,
+    pub name: &'static str,
+    /// The extension to enable for it, as `--extension` takes it.
+    pub extension: String,
+    pub description: &'static str,
+}
+
+/// The interpreter's answers to the questions brainfuck leaves open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Semantics {
+    pub cell_bits: u32,
+    /// Whether adding past the largest value gives zero, and the reverse.
+    pub cells_wrap: bool,
+    /// Whether moving left of the first cell adds a cell there.
+    pub tape_grows_left: bool,
+    /// Whether a loop reached on a zero cell is skipped, rather than its
+    /// body running once before the cell is tested.
+    pub loops_test_on_entry: bool,
+    /// Whether reading at the end of input leaves the cell as it was.
+    pub eof_unchanged: bool,
+    /// Whether a last word without a pair is ignored rather than an error.
+    pub trailing_word_ignored: bool,
+    /// Whether a first line starting `#!` is skipped.
+    pub shebang: bool,
+}
+
+impl Spec {
+    pub fn new() -> Spec {
+        Spec {
+            version: env!("CARGO_PKG_VERSION"),
+            keywords: KEYWORDS,
+            instructions: Instruction::ALL
+                .into_iter()
+                .map(|instruction| {
+                    let (first, second) = instruction.pair();
+                    InstructionSpec {
+                        name: instruction.name(),
+                        words: [first, second],
+                        brainfuck: instruction.symbol(),
+                        description: instruction.description(),
+                    }
+                })
+                .collect(),
+            extension_call: [EXTENSION.0, EXTENSION.1],
+            functions: FUNCTIONS.iter().map(FunctionSpec::new).collect(),
+            semantics: Semantics {
+                cell_bits: u8::BITS,
+                cells_wrap: true,
+                tape_grows_left: true,
+                loops_test_on_entry: true,
+                eof_unchanged: true,
+                trailing_word_ignored: true,
+                shebang: true,
+            },
+        }
+    }
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FunctionSpec {
+    fn new(function: &Function) -> FunctionSpec {
+        let extension = function
+            .extension
+            .to_possible_value()
+            .expect("no extension is skipped");
+        FunctionSpec {
+            number: function.number,
+            name: function.name,
+            extension: extension.get_name().to_string(),
+            description: function.description,
+        }
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hnyfuck {}", self.version)?;
+        writeln!(f, "keywords: {}", self.keywords.join(" "))?;
+        writeln!(f)?;
+        writeln!(f, "instructions")?;
+        for instruction in &self.instructions {
+            writeln!(
+                f,
+                "  {:<12} {}  {:<12} {}",
+                instruction.words.join(" "),
+                instruction.brainfuck,
+                instruction.name,
+                instruction.description
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "extension functions, called by {} with the number in the current cell",
+            self.extension_call.join(" ")
+        )?;
+        for function in &self.functions {
+            writeln!(
+                f,
+                "  {:<2} {:<7} {:<12} {}",
+                function.number, function.extension, function.name, function.description
+            )?;
+        }
+        writeln!(f)?;
+        let semantics = &self.semantics;
+        writeln!(f, "semantics")?;
+        writeln!(f, "  {:<22} {}", "cell bits", semantics.cell_bits)?;
+        for (name, value) in [
+            ("cells wrap", semantics.cells_wrap),
+            ("tape grows left", semantics.tape_grows_left),
+            ("loops test on entry", semantics.loops_test_on_entry),
+            ("eof keeps cell", semantics.eof_unchanged),
+            ("trailing word ignored", semantics.trailing_word_ignored),
+            ("shebang skipped", semantics.shebang),
+        ] {
+            writeln!(f, "  {:<22} {}", name, if value { "yes" } else { "no" })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{from_brainfuck, HnyFuck};
+
+    fn tape(mut hny: HnyFuck) -> Vec<u8> {
+        hny.set_input(std::io::empty());
+        hny.run();
+        hny.state().tape().to_vec()
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec = Spec::new();
+        for instruction in &spec.instructions {
+            let words = (instruction.words[0], instruction.words[1]);
+            let decoded = Instruction::from_pair(words).unwrap();
+            assert_eq!(decoded.symbol(), instruction.brainfuck);
+        }
+        assert_eq!(spec.functions[0].extension, "fileio");
+
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["instructions"][0]["words"][0], "Happy");
+        assert_eq!(json["semantics"]["cell_bits"], 8);
+    }
+
+    /// The semantics claimed are the interpreter's.
+    #[test]
+    fn test_semantics() {
+        let semantics = Spec::new().semantics;
+        assert_eq!(semantics.cell_bits, 8);
+        assert_eq!(tape(from_brainfuck("-")) == [255], semantics.cells_wrap);
+        let grown = tape(from_brainfuck("<+"));
+        assert_eq!(grown.len() == 2 && grown[0] == 1, semantics.tape_grows_left);
+        let skipped = tape(from_brainfuck("[>+<]"));
+        assert_eq!(skipped == [0], semantics.loops_test_on_entry);
+        assert_eq!(tape(from_brainfuck("+,")) == [1], semantics.eof_unchanged);
+        let trailing = HnyFuck::from_str("Year Happy Year");
+        assert_eq!(tape(trailing) == [1], semantics.trailing_word_ignored);
+        let shebang = HnyFuck::from_str("#!Happy Happy\nYear Happy");
+        assert_eq!(tape(shebang) == [1], semantics.shebang);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    ast::{self, Node},
+    ir::CompileError,
+    Instruction,
+};
+
+/// A program baked against part of its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Specialization {
+    /// The residual program, as word pairs.
+    pub program: String,
+    /// How many bytes of the known input the residual program has used up.
+    /// It expects the rest of its input to start from this byte.
+    pub consumed: usize,
+    /// Whether the whole program ran at specialization time, leaving nothing
+    /// to do but write its output.
+    pub finished: bool,
+}
+
+/// Why execution stopped before the end of the program.
+enum Stop {
+    /// An input would have read past the known input.
+    Input,
+    StepLimit,
+}
+
+#[derive(Clone, Default)]
+struct State {
+    tape: BTreeMap<isize, u8>,
+    pointer: isize,
+    output: Vec<u8>,
+    consumed: usize,
+    steps: u64,
+}
+
+impl State {
+    fn cell(&self) -> u8 {
+        self.tape.get(&self.pointer).copied().unwrap_or(0)
+    }
+
+    fn run(&mut self, nodes: &[Node], input: &[u8], limit: u64) -> Result<(), Stop> {
+        for node in nodes {
+            self.steps += 1;
+            if self.steps > limit {
+                return Err(Stop::StepLimit);
+            }
+            match node {
+                Node::Instruction(Instruction::ShiftLeft, _) => self.pointer -= 1,
+                Node::Instruction(Instruction::ShiftRight, _) => self.pointer += 1,
+                Node::Instruction(Instruction::Increment, _) => {
+                    self.tape.insert(self.pointer, self.cell().wrapping_add(1));
+                }
+                Node::Instruction(Instruction::Decrement, _) => {
+                    self.tape.insert(self.pointer, self.cell().wrapping_sub(1));
+                }
+                Node::Instruction(Instruction::Output, _) => self.output.push(self.cell()),
+                Node::Instruction(Instruction::Input, _) => {
+                    let byte = *input.get(self.consumed).ok_or(Stop::Input)?;
+                    self.tape.insert(self.pointer, byte);
+                    self.consumed += 1;
+                }
+                Node::Instruction(Instruction::LoopStart | Instruction::LoopEnd, _) => {
+                    unreachable!("brackets are parsed into loops")
+                }
+                Node::Loop(body, _) => {
+                    while self.cell() != 0 {
+                        self.run(body, input, limit)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Partially evaluates the program in `source` against `input`, the start of
+/// what it will read. Top-level instructions and loops are run one at a time
+/// until one would read past `input` or the step limit is reached; the
+/// residual program writes the output so far, sets up the tape as it was
+/// left, and continues with the rest of the program. A loop that stops part
+/// way through is undone and left in the residual program.
+pub fn specialize(
+    source: &str,
+    lenient: bool,
+    input: &[u8],
+    limit: u64,
+) -> Result<Specialization, CompileError> {
+    let nodes = ast::parse(source, lenient)?;
+    let mut state = State::default();
+    let mut done = 0;
+    for node in &nodes {
+        let before = state.clone();
+        if state.run(std::slice::from_ref(node), input, limit).is_err() {
+            state = before;
+            break;
+        }
+        done += 1;
+    }
+
+    let mut residual = Vec::new();
+    write_prelude(&mut residual, &state);
+    flatten(&nodes[done..], &mut residual);
+    Ok(Specialization {
+        program: crate::to_source(&residual),
+        consumed: state.consumed,
+        finished: done == nodes.len(),
+    })
+}
+
+/// Writes the output using the starting cell as scratch, then sets each
+/// non-zero cell and moves to where the pointer was left.
+fn write_prelude(instructions: &mut Vec<Instruction>, state: &State) {
+    let mut scratch = 0u8;
+    for &byte in &state.output {
+        add(instructions, byte.wrapping_sub(scratch));
+        instructions.push(Instruction::Output);
+        scratch = byte;
+    }
+
+    let mut tape = state.tape.clone();
+    tape.entry(0).or_insert(0);
+    let mut cells = tape
+        .into_iter()
+        .filter(|&(offset, value)| value != 0 || (offset == 0 && scratch != 0))
+        .collect::<Vec<_>>();
+    if let (Some(&(lo, _)), Some(&(hi, _))) = (cells.first(), cells.last()) {
+        let left_first = lo.abs() + (state.pointer - hi).abs();
+        let right_first = hi.abs() + (state.pointer - lo).abs();
+        if right_first < left_first {
+            cells.reverse();
+        }
+    }
+
+    let mut at = 0;
+    let walk = cells
+        .into_iter()
+        .map(|(offset, value)| (offset, Some(value)))
+        .chain([(state.pointer, None)]);
+    for (offset, value) in walk {
+        let shift = match offset < at {
+            true => Instruction::ShiftLeft,
+            false => Instruction::ShiftRight,
+        };
+        instructions.extend(std::iter::repeat_n(shift, offset.abs_diff(at)));
+        if let Some(value) = value {
+            let from = if offset == 0 { scratch } else { 0 };
+            add(instructions, value.wrapping_sub(from));
+        }
+        at = offset;
+    }
+}
+
+/// Adds `n` to the current cell the short way round.
+fn add(instructions: &mut Vec<Instruction>, n: u8) {
+    let (instruction, count) = match n <= 128 {
+        true => (Instruction::Increment, n),
+        false => (Instruction::Decrement, n.wrapping_neg()),
+    };
+    instructions.extend(std::iter::repeat_n(instruction, count as usize));
+}
+
+fn flatten(nodes: &[Node], instructions: &mut Vec<Instruction>) {
+    for node in nodes {
+        match node {
+            Node::Instruction(instruction, _) => instructions.push(*instruction),
+            Node::Loop(body, _) => {
+                instructions.push(Instruction::LoopStart);
+                flatten(body, instructions);
+                instructions.push(Instruction::LoopEnd);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{brainfuck_to_hny, hny_to_brainfuck};
+
+    fn specialize_bf(brainfuck: &str, input: &[u8]) -> (String, usize, bool) {
+        let source = brainfuck_to_hny(brainfuck).output;
+        let specialization = specialize(&source, false, input, 10_000).unwrap();
+        let residual = hny_to_brainfuck(&specialization.program).unwrap().output;
+        (residual, specialization.consumed, specialization.finished)
+    }
+
+    #[test]
+    fn test_specialize() {
+        // Add the first two input bytes and print the sum.
+        assert_eq!(
+            specialize_bf(",>,[-<+>]<.", &[3, 4]),
+            ("+++++++.".to_string(), 2, true)
+        );
+        // Everything before the read past the known input is baked in.
+        assert_eq!(
+            specialize_bf("+++[>++<-]>.,.", &[]),
+            ("++++++.------>++++++,.".to_string(), 0, false)
+        );
+        // A loop that runs out of input part way through is undone.
+        assert_eq!(
+            specialize_bf(",[.,]", b"ab"),
+            ("+".repeat(97) + "[.,]", 1, false)
+        );
+    }
+}
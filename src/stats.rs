@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use crate::{
+    ast::{self, Node},
+    ir::{CompileError, Op, Program},
+    lexer,
+    ranges::Ranges,
+    Instruction,
+};
+
+/// Execution count and cumulative wall time spent in one opcode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    pub count: u64,
+    pub cost: Duration,
+}
+
+/// Counters for a single loop, identified by the pair index of its `Happy Happy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoopStats {
+    pub entries: u64,
+    pub iterations: u64,
+    /// Instructions executed inside the loop body, nested loops included.
+    pub instructions: u64,
+    pub cost: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunStats {
+    ops: [OpStats; 8],
+    loops: BTreeMap<usize, LoopStats>,
+}
+
+impl RunStats {
+    pub fn new() -> RunStats {
+        RunStats::default()
+    }
+
+    pub fn op(&self, instruction: Instruction) -> OpStats {
+        self.ops[instruction.index()]
+    }
+
+    pub fn ops(&self) -> impl Iterator<Item = (Instruction, OpStats)> + '_ {
+        Instruction::ALL.into_iter().map(|i| (i, self.op(i)))
+    }
+
+    pub fn loop_stats(&self, start: usize) -> Option<LoopStats> {
+        self.loops.get(&start).copied()
+    }
+
+    pub fn loops(&self) -> impl Iterator<Item = (usize, LoopStats)> + '_ {
+        self.loops.iter().map(|(start, stats)| (*start, *stats))
+    }
+
+    /// Total number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.ops.iter().map(|op| op.count).sum()
+    }
+
+    pub(crate) fn record(&mut self, instruction: Instruction, cost: Duration) {
+        let op = &mut self.ops[instruction.index()];
+        op.count += 1;
+        op.cost += cost;
+    }
+
+    pub(crate) fn enter_loop(&mut self, start: usize) {
+        self.loops.entry(start).or_default().entries += 1;
+    }
+
+    pub(crate) fn iterate_loop(&mut self, start: usize) {
+        self.loops.entry(start).or_default().iterations += 1;
+    }
+
+    pub(crate) fn exit_loop(&mut self, start: usize, instructions: u64, cost: Duration) {
+        let stats = self.loops.entry(start).or_default();
+        stats.instructions += instructions;
+        stats.cost += cost;
+    }
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<8} {:>12} {:>14}", "opcode", "count", "cost")?;
+        for (instruction, op) in self.ops() {
+            writeln!(
+                f,
+                "{:<8} {:>12} {:>14?}",
+                instruction.symbol(),
+                op.count,
+                op.cost
+            )?;
+        }
+        writeln!(f, "{:<8} {:>12}", "total", self.instructions())?;
+
+        if !self.loops.is_empty() {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{:<8} {:>12} {:>12} {:>14} {:>14}",
+                "loop", "entries", "iterations", "instructions", "cost"
+            )?;
+            for (start, stats) in self.loops() {
+                writeln!(
+                    f,
+                    "{:<8} {:>12} {:>12} {:>14} {:>14?}",
+                    format!("#{}", start),
+                    stats.entries,
+                    stats.iterations,
+                    stats.instructions,
+                    stats.cost
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Metrics of a program's text, found without running it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramStats {
+    pub words: usize,
+    pub bytes: usize,
+    counts: [u64; 8],
+    pub loops: usize,
+    /// How deeply loops nest; 0 for a program without loops.
+    pub max_depth: usize,
+    pub outputs: usize,
+    /// What the outputs that [`Ranges`] proves always write the same byte
+    /// write, in program order.
+    pub constant_outputs: Vec<u8>,
+}
+
+impl ProgramStats {
+    pub fn new(source: &str, lenient: bool) -> Result<ProgramStats, CompileError> {
+        let mut stats = ProgramStats {
+            words: lexer::tokens(source, lenient).count(),
+            bytes: source.len(),
+            ..ProgramStats::default()
+        };
+        stats.count(&ast::parse(source, lenient)?, 0);
+
+        let mut program = Program::parse(source, lenient)?;
+        program.optimize();
+        let ranges = Ranges::new(&program);
+        for (i, op) in program.ops().iter().enumerate() {
+            if *op == Op::Output {
+                stats.outputs += 1;
+                let value = ranges.current(i).and_then(|interval| interval.value());
+                stats.constant_outputs.extend(value);
+            }
+        }
+        Ok(stats)
+    }
+
+    fn count(&mut self, nodes: &[Node], depth: usize) {
+        self.max_depth = self.max_depth.max(depth);
+        for node in nodes {
+            match node {
+                Node::Instruction(instruction, _) => self.counts[instruction.index()] += 1,
+                Node::Loop(body, _) => {
+                    self.loops += 1;
+                    self.counts[Instruction::LoopStart.index()] += 1;
+                    self.counts[Instruction::LoopEnd.index()] += 1;
+                    self.count(body, depth + 1);
+                }
+            }
+        }
+    }
+
+    pub fn count_of(&self, instruction: Instruction) -> u64 {
+        self.counts[instruction.index()]
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+impl fmt::Display for ProgramStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16} {:>8}", "bytes", self.bytes)?;
+        writeln!(f, "{:<16} {:>8}", "words", self.words)?;
+        writeln!(f, "{:<16} {:>8}", "instructions", self.instructions())?;
+        for instruction in Instruction::ALL {
+            writeln!(
+                f,
+                "  {:<14} {:>8}",
+                instruction.symbol(),
+                self.count_of(instruction)
+            )?;
+        }
+        writeln!(f, "{:<16} {:>8}", "loops", self.loops)?;
+        writeln!(f, "{:<16} {:>8}", "max loop depth", self.max_depth)?;
+        writeln!(
+            f,
+            "{:<16} {:>8} of {} outputs: \"{}\"",
+            "constant output",
+            self.constant_outputs.len(),
+            self.outputs,
+            self.constant_outputs.escape_ascii()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    #[test]
+    fn test_program_stats() {
+        let source = brainfuck_to_hny("++++++++[>++++++++<-]>+.,[>[-]<.-]").output;
+        let stats = ProgramStats::new(&source, false).unwrap();
+        assert_eq!(stats.words, 68);
+        assert_eq!(stats.bytes, source.len());
+        assert_eq!(stats.count_of(Instruction::Increment), 17);
+        assert_eq!(stats.count_of(Instruction::LoopStart), 3);
+        assert_eq!(stats.instructions(), 34);
+        assert_eq!(stats.loops, 3);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.outputs, 2);
+        assert_eq!(stats.constant_outputs, b"A");
+    }
+}
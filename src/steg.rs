@@ -0,0 +1,71 @@
+use crate::lexer;
+
+/// Hides the keywords of `program` among the words of `cover`, spreading them
+/// evenly and keeping the cover's own line breaks. Everything that is not a
+/// keyword of the program is skipped in lenient mode, so the result runs with
+/// `--lenient`. Keywords already in the cover are lowercased so they are not
+/// mistaken for instructions.
+pub fn encode(program: &str, cover: &str) -> String {
+    let keywords = lexer::tokens(program, true)
+        .map(|token| token.text)
+        .collect::<Vec<_>>();
+
+    let mut cover = cover.to_string();
+    for token in lexer::tokens(&cover.clone(), true) {
+        let first = token.span.start..token.span.start + 1;
+        cover.replace_range(first.clone(), &cover[first].to_lowercase());
+    }
+
+    let words = cover.split_whitespace().collect::<Vec<_>>();
+    if words.is_empty() {
+        return keywords.join(" ");
+    }
+
+    let mut out = String::new();
+    let mut rest = cover.as_str();
+    let mut hidden = 0;
+    for (i, word) in words.iter().enumerate() {
+        let end = word.as_ptr() as usize - rest.as_ptr() as usize + word.len();
+        out.push_str(&rest[..end]);
+        rest = &rest[end..];
+
+        let until = (i + 1) * keywords.len() / words.len();
+        for keyword in &keywords[hidden..until] {
+            out.push(' ');
+            out.push_str(keyword);
+        }
+        hidden = until;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Extracts the program hidden in `text` by [`encode`] as plain word pairs.
+pub fn decode(text: &str) -> String {
+    lexer::tokens(text, true)
+        .map(|token| token.text)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let program = "Year Happy Happy Happy Happy Year New New";
+        let cover = "Dear Ann,\n\nA Happy new year to you and yours.\nBest wishes\n";
+        let text = encode(program, cover);
+
+        assert!(text.starts_with("Dear Ann, Year\n\nA Happy"));
+        assert!(text.contains(" happy new"));
+        assert!(text.ends_with("wishes New\n"));
+        assert_eq!(decode(&text), program);
+    }
+
+    #[test]
+    fn test_empty_cover() {
+        assert_eq!(encode("Year Happy", " \n"), "Year Happy");
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use crate::{ir::CompileError, lexer, Instruction};
+
+/// Instructions a straight-line fragment is made of. Fragments never contain
+/// I/O or loops, so their whole effect on the tape is known without running
+/// them.
+const STRAIGHT_LINE: [Instruction; 4] = [
+    Instruction::ShiftLeft,
+    Instruction::ShiftRight,
+    Instruction::Increment,
+    Instruction::Decrement,
+];
+
+/// What a straight-line fragment does: where it leaves the pointer and what it
+/// adds to each cell it changes, relative to where it started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+struct Effect {
+    pointer: isize,
+    deltas: Vec<(isize, u8)>,
+}
+
+impl Effect {
+    fn of(fragment: &[Instruction]) -> Option<Effect> {
+        let mut effect = Effect::default();
+        for &instruction in fragment {
+            effect = effect.then(instruction)?;
+        }
+        Some(effect)
+    }
+
+    fn then(&self, instruction: Instruction) -> Option<Effect> {
+        let mut next = self.clone();
+        let delta = match instruction {
+            Instruction::ShiftLeft => {
+                next.pointer -= 1;
+                return Some(next);
+            }
+            Instruction::ShiftRight => {
+                next.pointer += 1;
+                return Some(next);
+            }
+            Instruction::Increment => 1,
+            Instruction::Decrement => u8::MAX,
+            _ => return None,
+        };
+        match next.deltas.binary_search_by_key(&next.pointer, |(o, _)| *o) {
+            Ok(i) => {
+                next.deltas[i].1 = next.deltas[i].1.wrapping_add(delta);
+                if next.deltas[i].1 == 0 {
+                    next.deltas.remove(i);
+                }
+            }
+            Err(i) => next.deltas.insert(i, (next.pointer, delta)),
+        }
+        Some(next)
+    }
+
+    fn write_out(&self) -> Vec<Instruction> {
+        let (Some(&(lo, _)), Some(&(hi, _))) = (self.deltas.first(), self.deltas.last()) else {
+            return walk(0, self.pointer);
+        };
+        let left_first = lo.abs() + (hi - lo) + (self.pointer - hi).abs();
+        let right_first = hi.abs() + (hi - lo) + (self.pointer - lo).abs();
+        let stops = match left_first <= right_first {
+            true => [0, lo, hi, self.pointer],
+            false => [0, hi, lo, self.pointer],
+        };
+
+        let mut pending = self.deltas.clone();
+        let mut out = Vec::new();
+        let mut at = 0;
+        for stop in stops {
+            let moves = walk(at, stop);
+            for step in std::iter::once(None).chain(moves.into_iter().map(Some)) {
+                if let Some(step) = step {
+                    at += if step == Instruction::ShiftLeft {
+                        -1
+                    } else {
+                        1
+                    };
+                    out.push(step);
+                }
+                if let Some(i) = pending.iter().position(|(offset, _)| *offset == at) {
+                    let (_, delta) = pending.remove(i);
+                    out.extend(match delta < 128 {
+                        true => vec![Instruction::Increment; delta as usize],
+                        false => vec![Instruction::Decrement; 256 - delta as usize],
+                    });
+                }
+            }
+        }
+        out
+    }
+}
+
+fn walk(from: isize, to: isize) -> Vec<Instruction> {
+    match from <= to {
+        true => vec![Instruction::ShiftRight; (to - from) as usize],
+        false => vec![Instruction::ShiftLeft; (from - to) as usize],
+    }
+}
+
+/// Knows the shortest way to write every straight-line effect reachable in at
+/// most `max_len` instructions, found by breadth-first search over effects.
+/// Sequences with the same effect behave the same when extended, so only the
+/// first sequence found for each effect is expanded.
+pub struct Superoptimizer {
+    max_len: usize,
+    shortest: HashMap<Effect, Vec<Instruction>>,
+}
+
+impl Superoptimizer {
+    pub fn new(max_len: usize) -> Superoptimizer {
+        let mut shortest = HashMap::from([(Effect::default(), Vec::new())]);
+        let mut frontier = vec![(Effect::default(), Vec::new())];
+
+        for _ in 0..max_len {
+            let mut next = Vec::new();
+            for (effect, sequence) in &frontier {
+                for instruction in STRAIGHT_LINE {
+                    let effect = effect.then(instruction).expect("straight-line instruction");
+                    if shortest.contains_key(&effect) {
+                        continue;
+                    }
+                    let mut sequence = sequence.clone();
+                    sequence.push(instruction);
+                    shortest.insert(effect.clone(), sequence.clone());
+                    next.push((effect, sequence));
+                }
+            }
+            frontier = next;
+        }
+
+        Superoptimizer { max_len, shortest }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// The shortest sequence with the same effect as `fragment`, if one of at
+    /// most `max_len` instructions exists and is shorter than `fragment`.
+    /// Returns `None` for fragments with I/O or loops.
+    pub fn optimize(&self, fragment: &[Instruction]) -> Option<Vec<Instruction>> {
+        let shortest = self.shortest.get(&Effect::of(fragment)?)?;
+        (shortest.len() < fragment.len()).then(|| shortest.clone())
+    }
+
+    /// Like [`optimize`](Superoptimizer::optimize), but a fragment whose
+    /// effect is too long to search for is written out directly: a walk over
+    /// the changed cells in whichever direction is shorter, adding or
+    /// subtracting whichever is fewer. Always returns an equivalent sequence.
+    fn shorten(&self, fragment: &[Instruction]) -> Vec<Instruction> {
+        if let Some(shortest) = self.optimize(fragment) {
+            return shortest;
+        }
+        match Effect::of(fragment).map(|effect| effect.write_out()) {
+            Some(written) if written.len() < fragment.len() => written,
+            _ => fragment.to_vec(),
+        }
+    }
+}
+
+/// Rewrites `source` without anything but instructions, one line long, with
+/// every straight-line fragment replaced by its shortest form.
+pub fn minify(
+    source: &str,
+    lenient: bool,
+    superoptimizer: &Superoptimizer,
+) -> Result<String, CompileError> {
+    let words = lexer::tokens(source, lenient)
+        .map(|token| token.text)
+        .collect::<Vec<_>>();
+    let instructions = words
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(pair, words)| {
+            Instruction::from_pair((words[0], words[1])).ok_or(CompileError::InvalidToken { pair })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut minified = Vec::new();
+    let mut rest = instructions.as_slice();
+    while !rest.is_empty() {
+        let straight = rest
+            .iter()
+            .position(|instruction| !STRAIGHT_LINE.contains(instruction))
+            .unwrap_or(rest.len());
+        minified.extend(superoptimizer.shorten(&rest[..straight]));
+        minified.extend(rest.get(straight));
+        rest = rest.get(straight + 1..).unwrap_or_default();
+    }
+
+    Ok(crate::to_source(&minified))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{brainfuck_to_hny, hny_to_brainfuck};
+
+    fn instructions(brainfuck: &str) -> Vec<Instruction> {
+        brainfuck
+            .chars()
+            .filter_map(Instruction::from_symbol)
+            .collect()
+    }
+
+    #[test]
+    fn test_optimize() {
+        let superoptimizer = Superoptimizer::new(6);
+        let optimize = |bf| superoptimizer.optimize(&instructions(bf));
+
+        assert_eq!(optimize("+-><"), Some(vec![]));
+        assert_eq!(optimize(">+<<>>"), Some(instructions(">+")));
+        assert_eq!(optimize("+>-<-+").map(|shortest| shortest.len()), Some(4));
+        assert_eq!(optimize("+>-<"), None);
+        assert_eq!(optimize("+."), None);
+    }
+
+    #[test]
+    fn test_minify() {
+        let source = brainfuck_to_hny("++-[>+<-<>]>>><.").output;
+        let minified = minify(&source, false, &Superoptimizer::new(4)).unwrap();
+        assert_eq!(hny_to_brainfuck(&minified).unwrap().output, "+[>+<-]>>.");
+
+        let source = brainfuck_to_hny(">>+>-<<+++<<->>-").output;
+        let minified = minify(&source, false, &Superoptimizer::new(2)).unwrap();
+        assert_eq!(hny_to_brainfuck(&minified).unwrap().output, "<->>++>+>-<<");
+    }
+}
@@ -0,0 +1,355 @@
+//! The cells a program works on, kept in memory or, for tapes too big for
+//! it, in a sparse file mapped into memory, so only the pages a program has
+//! written take up space and the kernel can page them out.
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    path::Path,
+};
+#[cfg(target_os = "linux")]
+use std::{fs::File, io, ptr::NonNull};
+
+/// Cells room is first made for, and the least a tape grows by.
+const INITIAL_CAPACITY: usize = 64;
+
+/// A tape that grows at either end. Room is kept before the first cell as
+/// well as after the last, so growing left is as cheap as growing right.
+pub struct Tape {
+    buffer: Buffer,
+    /// Index into the buffer of the first cell.
+    start: usize,
+    len: usize,
+}
+
+/// Where the cells are kept. Everything outside the cells is zero, so cells
+/// the tape grows into start blank without being written.
+enum Buffer {
+    Memory(Vec<u8>),
+    #[cfg(target_os = "linux")]
+    Mapped(Mapping),
+}
+
+impl Buffer {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Buffer::Memory(bytes) => bytes,
+            #[cfg(target_os = "linux")]
+            Buffer::Mapped(mapping) => mapping.bytes(),
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        match self {
+            Buffer::Memory(bytes) => bytes,
+            #[cfg(target_os = "linux")]
+            Buffer::Mapped(mapping) => mapping.bytes_mut(),
+        }
+    }
+
+    /// Makes room for at least `capacity` bytes, all zero past the old end.
+    fn grow(&mut self, capacity: usize) {
+        match self {
+            Buffer::Memory(bytes) => bytes.resize(capacity, 0),
+            #[cfg(target_os = "linux")]
+            Buffer::Mapped(mapping) => {
+                if let Err(e) = mapping.grow(capacity) {
+                    panic!("Couldn't grow the tape file: {}", e);
+                }
+            }
+        }
+    }
+}
+
+impl Tape {
+    /// A tape of one blank cell, kept in memory.
+    pub fn new() -> Tape {
+        Tape {
+            buffer: Buffer::Memory(vec![0; INITIAL_CAPACITY]),
+            start: 0,
+            len: 1,
+        }
+    }
+
+    /// Moves the tape into a sparse file created at `path`, which is removed
+    /// again at once, so nothing is left behind however the run ends. Only
+    /// possible on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn map_to(&mut self, path: &Path) -> io::Result<()> {
+        let mut mapping = Mapping::create(path, self.start + self.len)?;
+        mapping.bytes_mut()[self.start..self.start + self.len].copy_from_slice(self);
+        self.buffer = Buffer::Mapped(mapping);
+        Ok(())
+    }
+
+    /// Moves the tape into a sparse file, which is only possible on Linux.
+    #[cfg(not(target_os = "linux"))]
+    pub fn map_to(&mut self, _: &Path) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "tape files are only supported on Linux",
+        ))
+    }
+
+    /// Whether the tape is kept in a file rather than in memory.
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self.buffer, Buffer::Memory(_))
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.bytes().len()
+    }
+
+    /// Removes every cell.
+    pub fn clear(&mut self) {
+        let (start, len) = (self.start, self.len);
+        self.buffer.bytes_mut()[start..start + len].fill(0);
+        self.len = 0;
+    }
+
+    pub fn push_back(&mut self, cell: u8) {
+        self.resize(self.len + 1);
+        let last = self.len - 1;
+        self[last] = cell;
+    }
+
+    pub fn push_front(&mut self, cell: u8) {
+        if self.start == 0 {
+            self.make_room_before();
+        }
+        self.start -= 1;
+        self.len += 1;
+        self[0] = cell;
+    }
+
+    pub fn extend(&mut self, cells: impl IntoIterator<Item = u8>) {
+        for cell in cells {
+            self.push_back(cell);
+        }
+    }
+
+    /// Grows the tape to `len` cells, adding blank cells on the right.
+    pub fn resize(&mut self, len: usize) {
+        if len <= self.len {
+            return;
+        }
+        let end = self.start + len;
+        if end > self.capacity() {
+            let capacity = end.max(self.capacity() * 2).max(INITIAL_CAPACITY);
+            self.buffer.grow(capacity);
+        }
+        self.len = len;
+    }
+
+    /// Doubles the buffer and moves the cells to the end of it.
+    fn make_room_before(&mut self) {
+        let room = self.capacity().max(INITIAL_CAPACITY);
+        self.buffer.grow(self.capacity() + room);
+        let (start, len) = (self.start, self.len);
+        let bytes = self.buffer.bytes_mut();
+        bytes.copy_within(start..start + len, start + room);
+        // Only where cells were, so the rest of a file stays unwritten.
+        bytes[start..start + len.min(room)].fill(0);
+        self.start += room;
+    }
+}
+
+/// Copies the cells. A copy of a tape kept in a file is kept in memory.
+impl Clone for Tape {
+    fn clone(&self) -> Self {
+        match &self.buffer {
+            Buffer::Memory(bytes) => Tape {
+                buffer: Buffer::Memory(bytes.clone()),
+                start: self.start,
+                len: self.len,
+            },
+            #[cfg(target_os = "linux")]
+            Buffer::Mapped(_) => self.iter().copied().collect(),
+        }
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Tape::new()
+    }
+}
+
+impl FromIterator<u8> for Tape {
+    fn from_iter<I: IntoIterator<Item = u8>>(cells: I) -> Self {
+        let mut tape = Tape::new();
+        tape.clear();
+        tape.extend(cells);
+        tape
+    }
+}
+
+impl Deref for Tape {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer.bytes()[self.start..self.start + self.len]
+    }
+}
+
+impl DerefMut for Tape {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let (start, len) = (self.start, self.len);
+        &mut self.buffer.bytes_mut()[start..start + len]
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for Tape {
+    fn eq(&self, cells: &[u8; N]) -> bool {
+        **self == *cells
+    }
+}
+
+impl fmt::Debug for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// A file mapped into memory, grown a page at a time at least.
+#[cfg(target_os = "linux")]
+struct Mapping {
+    file: File,
+    pointer: NonNull<u8>,
+    len: usize,
+}
+
+// The mapping is owned by the one `Mapping`, like a `Vec`'s allocation.
+#[cfg(target_os = "linux")]
+unsafe impl Send for Mapping {}
+
+#[cfg(target_os = "linux")]
+impl Mapping {
+    fn create(path: &Path, len: usize) -> io::Result<Mapping> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        std::fs::remove_file(path)?;
+        let len = page_align(len.max(1));
+        file.set_len(len as u64)?;
+        let pointer = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                std::os::fd::AsRawFd::as_raw_fd(&file),
+                0,
+            )
+        };
+        Ok(Mapping {
+            pointer: mapped(pointer)?,
+            file,
+            len,
+        })
+    }
+
+    fn grow(&mut self, len: usize) -> io::Result<()> {
+        let len = page_align(len);
+        if len <= self.len {
+            return Ok(());
+        }
+        // The file grows sparse, reading as zeros until written.
+        self.file.set_len(len as u64)?;
+        let pointer = unsafe {
+            libc::mremap(
+                self.pointer.as_ptr().cast(),
+                self.len,
+                len,
+                libc::MREMAP_MAYMOVE,
+            )
+        };
+        self.pointer = mapped(pointer)?;
+        self.len = len;
+        Ok(())
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.pointer.as_ptr(), self.len) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.pointer.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.pointer.as_ptr().cast(), self.len) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn mapped(pointer: *mut libc::c_void) -> io::Result<NonNull<u8>> {
+    match pointer {
+        libc::MAP_FAILED => Err(io::Error::last_os_error()),
+        pointer => NonNull::new(pointer.cast()).ok_or_else(io::Error::last_os_error),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn page_align(len: usize) -> usize {
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as usize;
+    len.div_ceil(page) * page
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn exercise(tape: &mut Tape) {
+        let first = tape[0];
+        tape.push_back(1);
+        for _ in 0..100 {
+            tape.push_front(0);
+        }
+        tape[0] = 2;
+        tape.resize(10_000);
+        assert_eq!(tape.len(), 10_000);
+        assert_eq!((tape[0], tape[100], tape[101]), (2, first, 1));
+        assert!(tape[102..].iter().all(|&cell| cell == 0));
+
+        tape.clear();
+        tape.extend([3, 4]);
+        tape.push_front(5);
+        assert_eq!(&tape[..], &[5, 3, 4]);
+        tape.resize(5);
+        assert_eq!(&tape[..], &[5, 3, 4, 0, 0]);
+    }
+
+    #[test]
+    fn test_tape() {
+        let mut tape = Tape::new();
+        assert_eq!(&tape[..], &[0]);
+        exercise(&mut tape);
+        assert!(!tape.is_mapped());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mapped() {
+        let path = std::env::temp_dir().join(format!("hnyfuck-tape-{}", std::process::id()));
+        let mut tape = Tape::new();
+        tape[0] = 7;
+        tape.map_to(&path).unwrap();
+        assert!(tape.is_mapped() && !path.exists());
+        assert_eq!(&tape[..], &[7]);
+        exercise(&mut tape);
+
+        tape.resize(1 << 24);
+        tape[(1 << 24) - 1] = 9;
+        assert_eq!(tape[(1 << 24) - 1], 9);
+
+        let copy = tape.clone();
+        assert!(!copy.is_mapped());
+        assert_eq!(&copy[..], &tape[..]);
+    }
+}
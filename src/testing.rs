@@ -0,0 +1,93 @@
+//! Proptest strategies for generating programs, built with the `testing`
+//! feature.
+//!
+//! Generated programs always have matched brackets. Every loop body starts by
+//! decrementing the cell the loop tests and ends back on it, so most loops
+//! count down and stop, though nothing stops the rest of the body from
+//! undoing the decrement. Run them with a step limit, as
+//! [`equiv::execute`](crate::equiv::execute) does.
+
+use proptest::{arbitrary::Arbitrary, prelude::*, strategy::BoxedStrategy};
+
+use crate::{ir::Program, to_source, Instruction};
+
+const STRAIGHT_LINE: [Instruction; 6] = [
+    Instruction::ShiftLeft,
+    Instruction::ShiftRight,
+    Instruction::Increment,
+    Instruction::Decrement,
+    Instruction::Output,
+    Instruction::Input,
+];
+
+/// Instructions of a well-formed program, loops nested at most four deep.
+pub fn instructions() -> impl Strategy<Value = Vec<Instruction>> {
+    let leaf =
+        proptest::sample::select(&STRAIGHT_LINE[..]).prop_map(|instruction| vec![instruction]);
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        let parts = proptest::collection::vec(inner, 0..8).prop_map(|parts| parts.concat());
+        prop_oneof![parts.clone(), parts.prop_map(|body| counted_loop(&body))]
+    })
+}
+
+/// A program's source as word pairs.
+pub fn source() -> impl Strategy<Value = String> {
+    instructions().prop_map(|instructions| to_source(&instructions))
+}
+
+/// A compiled program, not optimized.
+pub fn program() -> impl Strategy<Value = Program> {
+    source().prop_map(|source| Program::from_str(&source).expect("generated programs are valid"))
+}
+
+/// Wraps `body` in a loop that decrements the tested cell first and moves
+/// back to it last.
+fn counted_loop(body: &[Instruction]) -> Vec<Instruction> {
+    let shift = body
+        .iter()
+        .fold(0isize, |shift, instruction| match instruction {
+            Instruction::ShiftLeft => shift - 1,
+            Instruction::ShiftRight => shift + 1,
+            _ => shift,
+        });
+    let back = match shift < 0 {
+        true => Instruction::ShiftRight,
+        false => Instruction::ShiftLeft,
+    };
+
+    let mut instructions = vec![Instruction::LoopStart, Instruction::Decrement];
+    instructions.extend_from_slice(body);
+    instructions.extend(std::iter::repeat_n(back, shift.unsigned_abs()));
+    instructions.push(Instruction::LoopEnd);
+    instructions
+}
+
+impl Arbitrary for Program {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Program>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        program().boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn brackets_match(instructions in instructions()) {
+            let mut depth = 0;
+            for instruction in &instructions {
+                match instruction {
+                    Instruction::LoopStart => depth += 1,
+                    Instruction::LoopEnd => depth -= 1,
+                    _ => (),
+                }
+                prop_assert!(depth >= 0);
+            }
+            prop_assert_eq!(depth, 0);
+        }
+    }
+}
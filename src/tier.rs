@@ -0,0 +1,71 @@
+//! The IR tier: loops start out interpreted a word pair at a time, and once
+//! one has gone round often enough it is compiled to an [`Program`] of
+//! folded ops, which [`State::execute`](crate::State::execute) runs from
+//! then on. This is not a JIT: no machine code is generated, and the ops are
+//! interpreted too, only with runs of instructions folded together and
+//! common loops rewritten. `hnyfuck build` is the way to native code.
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{ir::Program, TokenStream, LOOP_END, LOOP_START};
+
+/// Loop iterations spent in the interpreter before a loop is compiled to IR.
+pub const DEFAULT_TIER_THRESHOLD: u64 = 100;
+
+#[derive(Debug, Clone)]
+enum Tier {
+    Interpreted(u64),
+    Compiled(Arc<Program>),
+    Uncompilable,
+}
+
+/// Tracks how often each loop has iterated and compiles the ones that get
+/// hot to IR.
+#[derive(Debug, Clone)]
+pub(crate) struct Tiering {
+    threshold: u64,
+    loops: HashMap<usize, Tier>,
+}
+
+impl Tiering {
+    pub(crate) fn new(threshold: u64) -> Tiering {
+        Tiering {
+            threshold,
+            loops: HashMap::new(),
+        }
+    }
+
+    /// Counts one iteration of the loop starting at pair `start` and returns
+    /// the compiled loop once it has crossed the threshold.
+    pub(crate) fn compiled(&mut self, start: usize, body: &TokenStream) -> Option<Arc<Program>> {
+        let tier = self.loops.entry(start).or_insert(Tier::Interpreted(0));
+        match tier {
+            Tier::Interpreted(count) if *count >= self.threshold => {
+                let tokens = [LOOP_START.0, LOOP_START.1]
+                    .into_iter()
+                    .chain(body.tokens())
+                    .chain([LOOP_END.0, LOOP_END.1]);
+                *tier = match Program::compile(tokens) {
+                    Ok(mut program) => {
+                        program.optimize();
+                        trace_event!(
+                            tracing::Level::DEBUG,
+                            start,
+                            ops = program.ops().len(),
+                            "compiled hot loop"
+                        );
+                        Tier::Compiled(Arc::new(program))
+                    }
+                    Err(_) => Tier::Uncompilable,
+                };
+                self.compiled(start, body)
+            }
+            Tier::Interpreted(count) => {
+                *count += 1;
+                None
+            }
+            Tier::Compiled(program) => Some(program.clone()),
+            Tier::Uncompilable => None,
+        }
+    }
+}
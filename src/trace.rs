@@ -0,0 +1,86 @@
+//! Wrappers around `tracing` that expand to nothing without the `tracing`
+//! feature, so instrumented code needs no `cfg` of its own.
+
+/// Enters a span until the end of the enclosing block.
+macro_rules! trace_span {
+    ($($span:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!($($span)*).entered();
+    };
+}
+
+macro_rules! trace_event {
+    ($($event:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!($($event)*);
+    };
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    use crate::{brainfuck_to_hny, HnyFuck};
+
+    /// Writes down the spans opened and the messages of the events given to
+    /// it, as `span NAME` and `event MESSAGE`.
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Message(Option<String>);
+
+    impl Visit for Message {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut seen = self.0.lock().unwrap();
+            seen.push(format!("span {}", span.metadata().name()));
+            span::Id::from_u64(seen.len() as u64)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut message = Message(None);
+            event.record(&mut message);
+            if let Some(message) = message.0 {
+                self.0.lock().unwrap().push(format!("event {}", message));
+            }
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn test_instrumentation() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Recorder(seen.clone()), || {
+            let mut hny = HnyFuck::from_str(&brainfuck_to_hny(",[-],").output);
+            hny.set_input(&b"\x02\x03"[..]);
+            hny.run();
+        });
+        let seen = seen.lock().unwrap();
+        for expected in ["span parse", "span loop"] {
+            assert!(seen.iter().any(|seen| seen == expected), "{:?}", seen);
+        }
+        assert_eq!(seen.iter().filter(|seen| *seen == "event input").count(), 2);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+/// Rewrites the bytes a program reads or writes on their way between it and
+/// stdin or stdout. Any `FnMut(u8) -> Option<u8>` is a transform.
+pub trait Transform {
+    /// The byte to pass on, or `None` to drop it.
+    fn apply(&mut self, byte: u8) -> Option<u8>;
+}
+
+impl<F: FnMut(u8) -> Option<u8>> Transform for F {
+    fn apply(&mut self, byte: u8) -> Option<u8> {
+        self(byte)
+    }
+}
+
+/// Upper-cases ASCII letters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Uppercase;
+
+impl Transform for Uppercase {
+    fn apply(&mut self, byte: u8) -> Option<u8> {
+        Some(byte.to_ascii_uppercase())
+    }
+}
+
+/// Rotates ASCII letters 13 places through the alphabet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rot13;
+
+impl Transform for Rot13 {
+    fn apply(&mut self, byte: u8) -> Option<u8> {
+        let base = match byte {
+            b'a'..=b'z' => b'a',
+            b'A'..=b'Z' => b'A',
+            _ => return Some(byte),
+        };
+        Some((byte - base + 13) % 26 + base)
+    }
+}
+
+/// Bytes on a line of a [`HexDump`].
+const HEX_LINE: usize = 16;
+
+/// Writes bytes to `out` as an `xxd`-style hex dump instead of passing them
+/// on: each line an offset, sixteen bytes in hex and the same bytes as text,
+/// with a dot for anything unprintable. A short last line is written when the
+/// dump is finished or dropped.
+#[derive(Debug)]
+pub struct HexDump<W: Write> {
+    out: W,
+    offset: u64,
+    line: Vec<u8>,
+}
+
+impl<W: Write> HexDump<W> {
+    pub fn new(out: W) -> Self {
+        HexDump {
+            out,
+            offset: 0,
+            line: Vec::with_capacity(HEX_LINE),
+        }
+    }
+
+    /// Writes out the bytes of a line not yet full.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let mut text = format!("{:08x}: ", self.offset);
+        for i in 0..HEX_LINE {
+            match self.line.get(i) {
+                Some(byte) => text.push_str(&format!("{:02x}", byte)),
+                None => text.push_str("  "),
+            }
+            if i % 2 == 1 {
+                text.push(' ');
+            }
+        }
+        text.push(' ');
+        text.extend(self.line.iter().map(|&byte| match byte {
+            b' '..=b'~' => byte as char,
+            _ => '.',
+        }));
+        text.push('\n');
+        self.offset += self.line.len() as u64;
+        self.line.clear();
+        self.out.write_all(text.as_bytes())
+    }
+}
+
+impl<W: Write> Transform for HexDump<W> {
+    fn apply(&mut self, byte: u8) -> Option<u8> {
+        self.line.push(byte);
+        if self.line.len() == HEX_LINE {
+            // A failed write costs the dump a line, not the program its run.
+            self.finish().ok();
+        }
+        None
+    }
+}
+
+impl<W: Write> Drop for HexDump<W> {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+/// Transforms applied one after another, in the order they were added.
+#[derive(Default)]
+pub(crate) struct Transforms(Vec<Box<dyn Transform + Send>>);
+
+impl Transforms {
+    pub(crate) fn push(&mut self, transform: impl Transform + Send + 'static) {
+        self.0.push(Box::new(transform));
+    }
+
+    pub(crate) fn apply(&mut self, byte: u8) -> Option<u8> {
+        self.0
+            .iter_mut()
+            .try_fold(byte, |byte, transform| transform.apply(byte))
+    }
+}
+
+impl fmt::Debug for Transforms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Transforms({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_transforms() {
+        let mut transforms = Transforms::default();
+        assert_eq!(transforms.apply(b'x'), Some(b'x'));
+
+        let histogram = Arc::new(Mutex::new([0u64; 256]));
+        let counts = histogram.clone();
+        transforms.push(Rot13);
+        transforms.push(move |byte| {
+            counts.lock().unwrap()[byte as usize] += 1;
+            Some(byte)
+        });
+        transforms.push(|byte| (byte != b'!').then_some(byte));
+        transforms.push(Uppercase);
+
+        let out = b"Hello, world!"
+            .iter()
+            .filter_map(|&byte| transforms.apply(byte))
+            .collect::<Vec<_>>();
+        assert_eq!(out, b"URYYB, JBEYQ");
+        assert_eq!(histogram.lock().unwrap()[b'y' as usize], 3);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let mut dump = HexDump::new(Vec::new());
+        for &byte in b"Hello, world!\n\x00\x01\xff" {
+            assert_eq!(dump.apply(byte), None);
+        }
+        dump.finish().unwrap();
+        dump.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(dump.out.clone()).unwrap(),
+            "00000000: 4865 6c6c 6f2c 2077 6f72 6c64 210a 0001  Hello, world!...\n\
+             00000010: ff                                       .\n"
+        );
+    }
+}
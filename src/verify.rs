@@ -0,0 +1,298 @@
+//! Runs a program under every engine on the same input and checks they agree
+//! with the interpreter, to catch optimizer and tiering bugs.
+
+use std::{
+    fmt,
+    io::Cursor,
+    ops::ControlFlow,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    equiv::{self, Outcome},
+    ir::{CompileError, Program},
+    lexer,
+    middleware::{Meter, Middleware, Next},
+    HnyFuck, Instruction,
+};
+
+/// The engines compared, the interpreter first. `tiered` is the interpreter
+/// compiling every loop after its first iteration; the others run the
+/// compiled program at each optimization level.
+pub const ENGINES: [&str; 5] = ["interpreter", "tiered", "ir -O0", "ir -O1", "ir -O2"];
+
+/// What an engine did differently from the interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Output byte `index` differs, or only one side wrote it. `pair` is the
+    /// word pair that wrote the interpreter's byte, and `position` its line
+    /// and column.
+    Output {
+        index: usize,
+        expected: Option<u8>,
+        actual: Option<u8>,
+        pair: Option<usize>,
+        position: Option<(usize, usize)>,
+    },
+    /// The output matched, but the final tape did not. `cell` is the offset
+    /// of the first cell that differs from the starting cell.
+    Tape {
+        cell: isize,
+        expected: u8,
+        actual: u8,
+    },
+    /// The engine was still running at the step limit.
+    StepLimit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub engine: &'static str,
+    pub difference: Difference,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let byte = |byte: Option<u8>| match byte {
+            Some(byte) => format!("0x{:02x}", byte),
+            None => "nothing".to_string(),
+        };
+        write!(f, "{} diverges from the interpreter: ", self.engine)?;
+        match &self.difference {
+            Difference::Output {
+                index,
+                expected,
+                actual,
+                pair,
+                position,
+            } => {
+                write!(
+                    f,
+                    "output byte {} is {} instead of {}",
+                    index,
+                    byte(*actual),
+                    byte(*expected)
+                )?;
+                match (pair, position) {
+                    (Some(pair), Some((line, column))) => write!(
+                        f,
+                        ", written by instruction {} at {}:{}",
+                        pair, line, column
+                    ),
+                    _ => Ok(()),
+                }
+            }
+            Difference::Tape {
+                cell,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "cell {} of the final tape is {} instead of {}",
+                cell, actual, expected
+            ),
+            Difference::StepLimit => write!(f, "still running at the step limit"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// Every engine wrote the interpreter's output and left its tape.
+    Agreed,
+    /// The first engine, in [`ENGINES`] order, to disagree.
+    Diverged(Divergence),
+    /// The interpreter hit the step limit, leaving nothing to compare with.
+    Inconclusive,
+}
+
+/// Runs `source` under every engine in [`ENGINES`] with `input`. Engines are
+/// stopped after `limit` steps, except `tiered`, whose compiled loops cannot
+/// be, so a miscompiled loop that never ends hangs it.
+pub fn verify(
+    source: &str,
+    lenient: bool,
+    input: &[u8],
+    limit: u64,
+) -> Result<Verification, CompileError> {
+    let program = Program::parse(source, lenient)?;
+    let (reference, writers) = interpret(source, lenient, input, None, Some(limit));
+    let Outcome::Finished { output, .. } = &reference else {
+        return Ok(Verification::Inconclusive);
+    };
+
+    let mut runs = vec![(
+        ENGINES[1],
+        interpret(source, lenient, input, Some(0), None).0,
+    )];
+    for (level, engine) in ENGINES[2..].iter().enumerate() {
+        let mut program = program.clone();
+        program.optimize_level(level as u8);
+        runs.push((engine, equiv::execute(&program, input, limit)));
+    }
+
+    for (engine, outcome) in runs {
+        let difference = match compare(&reference, &outcome) {
+            Some(Difference::Output { index, .. }) => {
+                let pair = (index < output.len()).then(|| writers[index]);
+                Difference::Output {
+                    index,
+                    expected: output.get(index).copied(),
+                    actual: outcome_output(&outcome).get(index).copied(),
+                    pair,
+                    position: pair.and_then(|pair| position(source, lenient, pair)),
+                }
+            }
+            Some(difference) => difference,
+            None => continue,
+        };
+        return Ok(Verification::Diverged(Divergence { engine, difference }));
+    }
+    Ok(Verification::Agreed)
+}
+
+/// Runs the interpreter, returning what it did and the word pair that wrote
+/// each output byte. Metering the run keeps every loop interpreted.
+fn interpret(
+    source: &str,
+    lenient: bool,
+    input: &[u8],
+    tier_threshold: Option<u64>,
+    limit: Option<u64>,
+) -> (Outcome, Vec<usize>) {
+    let mut hny = HnyFuck::parse(source, lenient);
+    hny.set_tier_threshold(tier_threshold);
+    hny.set_input(Cursor::new(input.to_vec()));
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let written = output.clone();
+    hny.add_output_transform(move |byte| {
+        written.lock().unwrap().push(byte);
+        None
+    });
+    let writers = Arc::new(Mutex::new(Vec::new()));
+    if let Some(limit) = limit {
+        hny.add_middleware(Meter::new(limit));
+        hny.add_middleware(Writers(writers.clone()));
+    }
+    hny.run();
+
+    let output = std::mem::take(&mut *output.lock().unwrap());
+    let outcome = match hny.halted {
+        true => Outcome::StepLimit { output },
+        false => {
+            let state = &hny.state;
+            equiv::finished(output, state.tape(), -(state.origin() as isize))
+        }
+    };
+    let writers = std::mem::take(&mut *writers.lock().unwrap());
+    (outcome, writers)
+}
+
+/// Records the word pair of every output instruction.
+struct Writers(Arc<Mutex<Vec<usize>>>);
+
+impl Middleware for Writers {
+    fn handle(&mut self, instruction: Instruction, next: Next<'_>) -> ControlFlow<()> {
+        if instruction == Instruction::Output {
+            self.0.lock().unwrap().push(next.pair());
+        }
+        next.run(instruction)
+    }
+}
+
+fn outcome_output(outcome: &Outcome) -> &[u8] {
+    match outcome {
+        Outcome::Finished { output, .. } | Outcome::StepLimit { output } => output,
+    }
+}
+
+/// The first way `actual` differs from `expected`, which finished. Output
+/// differences have only their index filled in.
+fn compare(expected: &Outcome, actual: &Outcome) -> Option<Difference> {
+    let (expected_output, actual_output) = (outcome_output(expected), outcome_output(actual));
+    if expected_output != actual_output {
+        let index = expected_output
+            .iter()
+            .zip(actual_output)
+            .position(|(a, b)| a != b)
+            .unwrap_or(expected_output.len().min(actual_output.len()));
+        return Some(Difference::Output {
+            index,
+            expected: None,
+            actual: None,
+            pair: None,
+            position: None,
+        });
+    }
+    let (
+        Outcome::Finished {
+            origin: expected_origin,
+            tape: expected_tape,
+            ..
+        },
+        Outcome::Finished { origin, tape, .. },
+    ) = (expected, actual)
+    else {
+        return Some(Difference::StepLimit);
+    };
+    let cell = |origin: isize, tape: &[u8], i: isize| {
+        usize::try_from(i - origin)
+            .ok()
+            .and_then(|i| tape.get(i).copied())
+            .unwrap_or(0)
+    };
+    let start = *expected_origin.min(origin);
+    let end = (expected_origin + expected_tape.len() as isize).max(origin + tape.len() as isize);
+    (start..end).find_map(|i| {
+        let (expected, actual) = (
+            cell(*expected_origin, expected_tape, i),
+            cell(*origin, tape, i),
+        );
+        (expected != actual).then_some(Difference::Tape {
+            cell: i,
+            expected,
+            actual,
+        })
+    })
+}
+
+/// Line and column of word pair `pair` in `source`.
+fn position(source: &str, lenient: bool, pair: usize) -> Option<(usize, usize)> {
+    let token = lexer::tokens(source, lenient).nth(pair * 2)?;
+    Some(lexer::line_col(source, token.span.start))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brainfuck_to_hny;
+
+    #[test]
+    fn test_verify() {
+        let code = brainfuck_to_hny(",[->+>++<<]>.>.<<<+").output;
+        assert_eq!(
+            verify(&code, false, b"\x05", 1000),
+            Ok(Verification::Agreed)
+        );
+        assert_eq!(
+            verify(&brainfuck_to_hny("+[]").output, false, b"", 1000),
+            Ok(Verification::Inconclusive)
+        );
+
+        let interpreted = interpret(&code, false, b"\x05", None, Some(1000));
+        assert_eq!(interpreted.1, [12, 14]);
+        let wrong = Outcome::Finished {
+            output: vec![5, 10],
+            origin: 1,
+            tape: vec![5, 10],
+        };
+        assert_eq!(
+            compare(&interpreted.0, &wrong),
+            Some(Difference::Tape {
+                cell: -1,
+                expected: 1,
+                actual: 0
+            })
+        );
+    }
+}
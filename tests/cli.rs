@@ -0,0 +1,413 @@
+//! Runs the `hnyfuck` binary the way it's used from a shell, for what only
+//! the command line does: flags, files it writes and how it exits.
+
+use std::{
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    thread,
+    time::Duration,
+};
+
+use hnyfuck::brainfuck_to_hny;
+
+fn hnyfuck(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_hnyfuck"));
+    command.arg("--no-config").args(args);
+    command
+}
+
+/// Runs `command` on `input` to the end.
+fn run(command: &mut Command, input: &[u8]) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).ok();
+    child.wait_with_output().unwrap()
+}
+
+/// A path for `name` of the test's own, with nothing there yet.
+fn scratch(name: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::remove_file(&path).ok();
+    path
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_resume() {
+    let code = brainfuck_to_hny("++++++++[>++++++++<-]>+.,.").output;
+    let path = scratch("resume.ckpt");
+    let file = path.to_str().unwrap();
+
+    // Checkpointing every step, the last checkpoint before the program
+    // waits for input is just after it wrote A. Kill it there, as a crash
+    // would.
+    let mut child = hnyfuck(&["run", "--code", &code, "--checkpoint-every", "1"])
+        .args(["--checkpoint-file", file])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut written = [0];
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_exact(&mut written)
+        .unwrap();
+    assert_eq!(written, *b"A");
+    thread::sleep(Duration::from_millis(200));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let resumed = run(&mut hnyfuck(&["resume", file]), b"B");
+    assert!(resumed.status.success(), "{}", stderr(&resumed));
+    assert_eq!(resumed.stdout, b"B");
+
+    // The run has finished, so there's nothing left to resume.
+    let again = run(&mut hnyfuck(&["resume", file]), b"B");
+    assert!(!again.status.success());
+    assert!(again.stdout.is_empty());
+    assert!(stderr(&again).contains("the run had already finished"));
+
+    // Nor is there after a run that finishes without a crash.
+    let finished = run(
+        hnyfuck(&["run", "--code", &code, "--checkpoint-every", "1"])
+            .args(["--checkpoint-file", file]),
+        b"B",
+    );
+    assert_eq!(finished.stdout, b"AB");
+    let again = run(&mut hnyfuck(&["resume", file]), b"B");
+    assert!(!again.status.success());
+    assert!(again.stdout.is_empty());
+}
+
+/// Writes its input back out, stopping at a NUL or the end of input.
+fn cat() -> String {
+    brainfuck_to_hny(",[.[-],]").output
+}
+
+/// `--input` and `--input-file` are read in the order they're given,
+/// however they're mixed, then stdin unless `--no-stdin`.
+#[test]
+fn test_input_order() {
+    let path = scratch("input-order.txt");
+    fs::write(&path, "file ").unwrap();
+    let file = path.to_str().unwrap();
+    let code = cat();
+    for (args, expected) in [
+        (&["--input", "a ", "--input-file", file][..], "a file stdin"),
+        (&["--input-file", file, "--input", "a "], "file a stdin"),
+        (
+            &["--input", "a ", "--input-file", file, "--input", "b "],
+            "a file b stdin",
+        ),
+        (
+            &["--input-file", file, "--input", "a ", "--input-file", file],
+            "file a file stdin",
+        ),
+        (
+            &["--input", "a ", "--no-stdin", "--input-file", file],
+            "a file ",
+        ),
+        (&["--no-stdin"], ""),
+    ] {
+        let output = run(hnyfuck(&["run", "--code", &code]).args(args), b"stdin");
+        assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            expected,
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn test_tee() {
+    let path = scratch("tee.out");
+    let code = cat();
+    let input = b"line one\nbinary \x01\xff\n";
+    let output = run(
+        &mut hnyfuck(&["run", "--code", &code, "--tee", path.to_str().unwrap()]),
+        input,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, input.as_slice());
+    assert_eq!(fs::read(&path).unwrap(), input);
+
+    // A run the output limit stops leaves the same in the file as on stdout,
+    // though it ended partway through a line.
+    let output = run(
+        hnyfuck(&["run", "--code", &at_abc(), "--tee", path.to_str().unwrap()])
+            .args(["--max-output-bytes", "3"]),
+        b"",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"@AB");
+    assert_eq!(fs::read(&path).unwrap(), output.stdout);
+}
+
+/// A program writing forever is stopped after exactly N bytes, with an
+/// error.
+#[test]
+fn test_max_output_bytes() {
+    let code = brainfuck_to_hny("+[.]").output;
+    for max in [0, 1, 1000] {
+        let output = run(
+            hnyfuck(&["run", "--code", &code, "--max-output-bytes"]).arg(max.to_string()),
+            b"",
+        );
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, vec![1; max]);
+        let message = format!("more than the {} bytes --max-output-bytes allows", max);
+        assert!(stderr(&output).contains(&message), "{}", stderr(&output));
+    }
+
+    // A program writing no more than that finishes as usual.
+    let output = run(
+        &mut hnyfuck(&["run", "--code", &cat(), "--max-output-bytes", "3"]),
+        b"abc",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"abc");
+}
+
+/// Writes `@ABC` and finishes.
+fn at_abc() -> String {
+    brainfuck_to_hny(&format!("{}.+.+.+.", "+".repeat(64))).output
+}
+
+/// What the output transforms hold back is still written when the output
+/// limit stops the run.
+#[test]
+fn test_max_output_bytes_finishes_output() {
+    let code = at_abc();
+    let output = run(
+        hnyfuck(&["run", "--code", &code, "--output-format", "hex"])
+            .args(["--max-output-bytes", "3"]),
+        b"",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("00000000: 4041 42{}@AB\n", " ".repeat(34))
+    );
+
+    // Counting up from @ without end, four bytes get through, and the last
+    // is only encoded once the encoder is finished.
+    let code = brainfuck_to_hny(&format!("{}[.+]", "+".repeat(64))).output;
+    let output = run(
+        hnyfuck(&["run", "--code", &code, "--output-encoding", "base64"])
+            .args(["--max-output-bytes", "4"]),
+        b"",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"QEFCQw==\n");
+}
+
+/// Writes `code` to a scratch file named `name` and returns its path.
+fn program(name: &str, code: &str) -> String {
+    let path = scratch(name);
+    fs::write(&path, code).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_pipe() {
+    // Each stage adds one to every byte it reads.
+    let next = program("pipe-next.hny", &brainfuck_to_hny(",[+.[-],]").output);
+    let output = run(&mut hnyfuck(&["pipe", &next, &next]), b"abc");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"cde");
+
+    // Output comes through as it is written, before the input has ended.
+    let cat = program("pipe-cat.hny", &cat());
+    let mut child = hnyfuck(&["pipe", &cat, &cat, &cat])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut line = [0; 6];
+    stdin.write_all(b"first\n").unwrap();
+    stdout.read_exact(&mut line).unwrap();
+    assert_eq!(&line, b"first\n");
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+/// With `--deterministic` the same program and input give byte for byte the
+/// same stdout and stderr, even reading the clock and logging as JSON.
+#[test]
+fn test_deterministic() {
+    // Loops a while, then writes what the clock says: the seconds since the
+    // epoch and the milliseconds since the program started.
+    let code = format!(
+        "{} Year Year {} Year Year {}",
+        brainfuck_to_hny("-[>-[-]<-]+++++").output,
+        brainfuck_to_hny(">.>.>.>.>.>.>.>.<<<<<<<<+").output,
+        brainfuck_to_hny(">.>.>.>.").output
+    );
+    let runs = [(); 2].map(|()| {
+        run(
+            &mut hnyfuck(&[
+                "--deterministic",
+                "--log-format",
+                "json",
+                "run",
+                "--code",
+                &code,
+                "--extensions",
+                "clock",
+            ]),
+            b"",
+        )
+    });
+    assert!(runs[0].status.success(), "{}", stderr(&runs[0]));
+    assert_eq!(runs[0].stdout.len(), 12);
+    assert_eq!(runs[0].stdout, runs[1].stdout);
+    assert_eq!(runs[0].stderr, runs[1].stderr);
+}
+
+/// A terminal for stdin, as `HNY_INPUT` is only read from one. The first
+/// end must stay open while it's used.
+#[cfg(target_os = "linux")]
+fn terminal() -> (std::os::fd::OwnedFd, Stdio) {
+    use std::os::fd::FromRawFd;
+
+    let (mut first, mut second) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut first,
+            &mut second,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0, "{}", std::io::Error::last_os_error());
+    unsafe {
+        (
+            std::os::fd::OwnedFd::from_raw_fd(first),
+            Stdio::from(std::os::fd::OwnedFd::from_raw_fd(second)),
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_input_variable() {
+    let code = cat();
+    for (args, expected) in [
+        (&[][..], "from the variable"),
+        (&["--input", r"given\x00"], "given"),
+        (&["--no-stdin"], ""),
+    ] {
+        let (_terminal, stdin) = terminal();
+        let output = hnyfuck(&["run", "--code", &code])
+            .args(args)
+            .env("HNY_INPUT", r"from the variable\x00")
+            .stdin(stdin)
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            expected,
+            "{:?}",
+            args
+        );
+    }
+
+    // Piped input isn't typed, so the variable is left alone.
+    let output = run(
+        hnyfuck(&["run", "--code", &code]).env("HNY_INPUT", "from the variable"),
+        b"piped",
+    );
+    assert_eq!(output.stdout, b"piped");
+}
+
+/// Each line `--log-format json` writes to stderr as a JSON object.
+fn log_records(output: &Output) -> Vec<serde_json::Value> {
+    stderr(output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", line, e)))
+        .collect()
+}
+
+#[test]
+fn test_json_log() {
+    // Either language could have written this, which is warned about.
+    let file = program("log.txt", "Year Happy +");
+    let output = run(&mut hnyfuck(&["--log-format", "json", "run", &file]), b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let records = log_records(&output);
+    for record in &records {
+        assert!(
+            ["error", "warning", "note", "info"].contains(&record["level"].as_str().unwrap()),
+            "{}",
+            record
+        );
+        assert!(record["message"].is_string(), "{}", record);
+    }
+    let warning = records
+        .iter()
+        .find(|record| record["level"] == "warning")
+        .unwrap();
+    assert_eq!(warning["file"], file);
+    assert_eq!(warning["lang"], "hnyfuck");
+    let phases = records
+        .iter()
+        .filter(|record| record["duration_ms"].is_f64())
+        .map(|record| record["phase"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(phases, ["parse", "run"]);
+
+    let missing = scratch("log-missing.hny");
+    let output = run(
+        &mut hnyfuck(&["--log-format", "json", "run", missing.to_str().unwrap()]),
+        b"",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let records = log_records(&output);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["level"], "error");
+    assert!(records[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("No such file"));
+}
+
+/// Without `--lang`, a lenient program punctuated as prose is still run as
+/// hnyfuck, as is what `fmt --style card` writes.
+#[test]
+fn test_lenient_detection() {
+    let file = program("punctuated.txt", "Year, Happy... Year, New.");
+    let output = run(&mut hnyfuck(&["--lenient", "run", &file]), b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, [1]);
+    assert!(!stderr(&output).contains("could be"), "{}", stderr(&output));
+
+    let code = brainfuck_to_hny(&format!("{}.+.", "+".repeat(65))).output;
+    let file = program("card.hny", &code);
+    let formatted = run(&mut hnyfuck(&["fmt", "--style", "card", &file]), b"");
+    assert!(formatted.status.success(), "{}", stderr(&formatted));
+    assert_ne!(fs::read_to_string(&file).unwrap(), code);
+    let output = run(&mut hnyfuck(&["--lenient", "run", &file]), b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"AB");
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+}
@@ -0,0 +1,171 @@
+//! Runs every `tests/programs/*.hny` through each engine and compares what it
+//! writes with the sibling `.out` file, feeding it the sibling `.in` file if
+//! there is one. To add a regression test, drop a program and its expected
+//! output in that directory.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use hnyfuck::{equiv, Program};
+
+const STEP_LIMIT: u64 = 100_000_000;
+
+/// Something that can run a program: takes the program's path and input and
+/// returns its output, or why it could not run it.
+type Engine = fn(&Path, &[u8]) -> Result<Vec<u8>, String>;
+
+fn engines() -> Vec<(&'static str, Engine)> {
+    let mut engines: Vec<(&'static str, Engine)> = vec![
+        ("interpreter", |path, input| {
+            cli(&["--no-tiering".as_ref(), path.as_os_str()], input)
+        }),
+        ("tiered", |path, input| {
+            cli(
+                &["--tier-threshold".as_ref(), "0".as_ref(), path.as_os_str()],
+                input,
+            )
+        }),
+        ("ir -O0", |path, input| ir(path, input, 0)),
+        ("ir -O1", |path, input| ir(path, input, 1)),
+        ("ir -O2", |path, input| ir(path, input, 2)),
+    ];
+    if available("cc") {
+        engines.push(("c", |path, input| {
+            let binary = scratch(path, "bin");
+            cli(
+                &[
+                    "-O2".as_ref(),
+                    "build".as_ref(),
+                    path.as_os_str(),
+                    "-o".as_ref(),
+                    binary.as_os_str(),
+                ],
+                &[],
+            )?;
+            run(Command::new(&binary), input)
+        }));
+    }
+    if available("node") {
+        engines.push(("js", |path, input| {
+            let script = compile(path, "js")?;
+            run(command("node", &script), input)
+        }));
+    }
+    if available("python3") {
+        engines.push(("python", |path, input| {
+            let script = compile(path, "python")?;
+            run(command("python3", &script), input)
+        }));
+    }
+    engines
+}
+
+#[test]
+fn golden() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "hny"))
+        .collect::<Vec<_>>();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs in {}", dir.display());
+
+    let engines = engines();
+    let mut failures = Vec::new();
+    for program in &programs {
+        let input = fs::read(program.with_extension("in")).unwrap_or_default();
+        let Ok(expected) = fs::read(program.with_extension("out")) else {
+            failures.push(format!("{}: no .out file", program.display()));
+            continue;
+        };
+        for (name, engine) in &engines {
+            match engine(program, &input) {
+                Ok(output) if output == expected => (),
+                Ok(output) => failures.push(format!(
+                    "{} ({}):\n  expected: \"{}\"\n       got: \"{}\"",
+                    program.display(),
+                    name,
+                    expected.escape_ascii(),
+                    output.escape_ascii()
+                )),
+                Err(e) => failures.push(format!("{} ({}): {}", program.display(), name, e)),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
+
+fn ir(path: &Path, input: &[u8], level: u8) -> Result<Vec<u8>, String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut program = Program::from_str(&source).map_err(|e| e.to_string())?;
+    program.optimize_level(level);
+    match equiv::execute(&program, input, STEP_LIMIT) {
+        equiv::Outcome::Finished { output, .. } => Ok(output),
+        equiv::Outcome::StepLimit { .. } => Err("step limit reached".to_string()),
+    }
+}
+
+/// Runs the `hnyfuck` binary with `args`.
+fn cli(args: &[&std::ffi::OsStr], input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_hnyfuck"));
+    command.arg("--no-config").args(args);
+    run(command, input)
+}
+
+/// Compiles `path` for `target` into a scratch file and returns its path.
+fn compile(path: &Path, target: &str) -> Result<PathBuf, String> {
+    let output = scratch(path, target);
+    cli(
+        &[
+            "-O2".as_ref(),
+            "compile".as_ref(),
+            "--target".as_ref(),
+            target.as_ref(),
+            path.as_os_str(),
+            "-o".as_ref(),
+            output.as_os_str(),
+        ],
+        &[],
+    )?;
+    Ok(output)
+}
+
+fn scratch(path: &Path, extension: &str) -> PathBuf {
+    let name = path.file_stem().unwrap().to_string_lossy();
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.{}", name, extension))
+}
+
+fn command(program: &str, arg: &Path) -> Command {
+    let mut command = Command::new(program);
+    command.arg(arg);
+    command
+}
+
+fn run(mut command: Command, input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    child.stdin.take().unwrap().write_all(input).ok();
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    match output.status.success() {
+        true => Ok(output.stdout),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+fn available(tool: &str) -> bool {
+    Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
@@ -0,0 +1,73 @@
+//! Runs the server with sandboxed workers, as `hnyfuck serve` does, and
+//! checks a session runs in a worker that is locked down.
+
+#![cfg(target_os = "linux")]
+
+use std::{fs, net::TcpListener, path::PathBuf, time::Duration};
+
+use hnyfuck::{
+    brainfuck_to_hny,
+    server::{self, Config, Runner, DEFAULT_LIMITS},
+};
+use tungstenite::Message;
+
+/// Pids of this process's children.
+fn children() -> Vec<u32> {
+    let parent = std::process::id().to_string();
+    fs::read_dir("/proc")
+        .unwrap()
+        .filter_map(|entry| {
+            let stat = fs::read_to_string(entry.ok()?.path().join("stat")).ok()?;
+            // The fields after the parenthesized command name start with the
+            // state and then the parent's pid.
+            let (pid, rest) = stat.split_once(" (")?;
+            let fields = rest.rsplit_once(") ")?.1;
+            (fields.split(' ').nth(1)? == parent).then(|| pid.parse().ok())?
+        })
+        .collect()
+}
+
+#[test]
+fn test_sandboxed_session() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = Config {
+        limits: DEFAULT_LIMITS,
+        runner: Runner::Worker {
+            program: PathBuf::from(env!("CARGO_BIN_EXE_hnyfuck")),
+            args: vec!["serve-worker".to_string()],
+        },
+        ..Config::default()
+    };
+    std::thread::spawn(move || server::serve(("127.0.0.1", port), config).unwrap());
+    let url = format!("ws://127.0.0.1:{}/session", port);
+    let mut socket = loop {
+        match tungstenite::connect(&url) {
+            Ok((socket, _)) => break socket,
+            Err(_) => std::thread::sleep(Duration::from_millis(10)),
+        }
+    };
+
+    let code = brainfuck_to_hny(",[.,]").output;
+    let start = serde_json::json!({"code": code, "input": "a"});
+    socket.send(Message::text(start.to_string())).unwrap();
+    assert_eq!(socket.read().unwrap(), Message::binary(b"a".to_vec()));
+
+    // The worker is waiting for more input.
+    let workers = children();
+    assert_eq!(workers.len(), 1);
+    let status = fs::read_to_string(format!("/proc/{}/status", workers[0])).unwrap();
+    assert!(status.contains("NoNewPrivs:\t1"));
+    assert!(status.contains("Seccomp:\t2"));
+
+    socket.send(Message::binary(b"b\0".to_vec())).unwrap();
+    assert_eq!(socket.read().unwrap(), Message::binary(b"b".to_vec()));
+    let Message::Text(stats) = socket.read().unwrap() else {
+        panic!("expected the stats");
+    };
+    let stats: serde_json::Value = serde_json::from_str(&stats).unwrap();
+    assert_eq!(stats["exit"], "finished");
+}